@@ -8,7 +8,6 @@ For reading incoming requests, defer to [hyper::body::Incoming].
 )]
 
 use std::fmt;
-use std::pin::pin;
 use std::pin::Pin;
 
 use bytes::Bytes;
@@ -17,6 +16,13 @@ use http_body_util::combinators::UnsyncBoxBody;
 use http_body_util::BodyExt;
 use http_body_util::{Empty, Full};
 
+pub mod multipart;
+mod replay;
+
+#[cfg(feature = "client")]
+pub(crate) use self::replay::ReplayHandle;
+pub use self::replay::{ReplayBody, ReplayError, DEFAULT_REPLAY_LIMIT};
+
 pub use crate::service::{AdaptCustomBodyExt, AdaptCustomBodyLayer, AdaptCustomBodyService};
 #[cfg(feature = "incoming")]
 pub use crate::service::{AdaptIncomingLayer, AdaptIncomingService};
@@ -62,7 +68,22 @@ impl Body {
         }
     }
 
+    /// Make this body replayable, buffering at most `limit` bytes of data.
+    ///
+    /// Bodies which can already be cloned are returned unchanged. See [`ReplayBody`]
+    /// for more details.
+    pub fn replayable(self, limit: usize) -> Self {
+        if self.try_clone().is_some() {
+            return self;
+        }
+
+        ReplayBody::new(self, limit).into()
+    }
+
     /// Try to clone this body.
+    ///
+    /// Streaming bodies can only be cloned when they are wrapped in a [`ReplayBody`],
+    /// for example with [`Body::replayable`].
     pub fn try_clone(&self) -> Option<Self> {
         match &self.inner {
             InnerBody::Boxed(_) => None,
@@ -74,6 +95,7 @@ impl Body {
             }),
             InnerBody::Http(_) => None,
             InnerBody::HttpSync(_) => None,
+            InnerBody::Replay(body) => body.try_clone().map(Into::into),
 
             #[cfg(feature = "incoming")]
             InnerBody::Incoming(_) => None,
@@ -83,6 +105,25 @@ impl Body {
         }
     }
 
+    /// Returns true if this body can be sent again, see [`Body::try_clone`].
+    #[cfg(feature = "client")]
+    pub(crate) fn is_replayable(&self) -> bool {
+        match &self.inner {
+            InnerBody::Full(_) | InnerBody::Empty => true,
+            InnerBody::Replay(body) => body.is_replayable(),
+            _ => false,
+        }
+    }
+
+    /// A handle to check whether a [`ReplayBody`] can still be replayed.
+    #[cfg(feature = "client")]
+    pub(crate) fn replay_handle(&self) -> Option<ReplayHandle> {
+        match &self.inner {
+            InnerBody::Replay(body) => Some(body.handle()),
+            _ => None,
+        }
+    }
+
     /// Convert this body into a boxed body.
     pub fn as_boxed(self) -> UnsyncBoxBody<Bytes, BoxError> {
         match self.inner {
@@ -93,6 +134,7 @@ impl Body {
             }
             InnerBody::Http(body) => body,
             InnerBody::HttpSync(body) => UnsyncBoxBody::new(body),
+            InnerBody::Replay(body) => UnsyncBoxBody::new(body),
 
             #[cfg(feature = "incoming")]
             InnerBody::Incoming(incoming) => UnsyncBoxBody::new(incoming.map_err(Into::into)),
@@ -149,6 +191,14 @@ impl From<Empty<Bytes>> for Body {
     }
 }
 
+impl From<ReplayBody> for Body {
+    fn from(body: ReplayBody) -> Self {
+        Self {
+            inner: InnerBody::Replay(body),
+        }
+    }
+}
+
 #[cfg(feature = "incoming")]
 impl From<hyper::body::Incoming> for Body {
     fn from(body: hyper::body::Incoming) -> Self {
@@ -226,6 +276,7 @@ enum InnerBody {
     Boxed(#[pin] Pin<Box<dyn http_body::Body<Data = Bytes, Error = BoxError> + Send + 'static>>),
     Http(#[pin] UnsyncBoxBody<Bytes, BoxError>),
     HttpSync(#[pin] BoxBody<Bytes, BoxError>),
+    Replay(#[pin] ReplayBody),
 
     #[cfg(feature = "incoming")]
     Incoming(#[pin] hyper::body::Incoming),
@@ -267,6 +318,7 @@ impl http_body::Body for Body {
             InnerBodyProj::Boxed(body) => poll_frame!(body, cx),
             InnerBodyProj::Http(body) => poll_frame!(body, cx),
             InnerBodyProj::HttpSync(body) => poll_frame!(body, cx),
+            InnerBodyProj::Replay(body) => poll_frame!(body, cx),
             #[cfg(feature = "incoming")]
             InnerBodyProj::Incoming(body) => poll_frame!(body, cx),
 
@@ -282,6 +334,7 @@ impl http_body::Body for Body {
             InnerBody::Boxed(ref body) => body.is_end_stream(),
             InnerBody::Http(ref body) => body.is_end_stream(),
             InnerBody::HttpSync(ref body) => body.is_end_stream(),
+            InnerBody::Replay(ref body) => body.is_end_stream(),
             #[cfg(feature = "incoming")]
             InnerBody::Incoming(ref body) => body.is_end_stream(),
             #[cfg(feature = "axum")]
@@ -296,6 +349,7 @@ impl http_body::Body for Body {
             InnerBody::Boxed(ref body) => body.size_hint(),
             InnerBody::Http(ref body) => body.size_hint(),
             InnerBody::HttpSync(ref body) => body.size_hint(),
+            InnerBody::Replay(ref body) => body.size_hint(),
             #[cfg(feature = "incoming")]
             InnerBody::Incoming(ref body) => body.size_hint(),
             #[cfg(feature = "axum")]
@@ -312,6 +366,7 @@ impl fmt::Debug for InnerBody {
             InnerBody::Boxed(_) => f.debug_struct("Boxed").finish(),
             InnerBody::Http(_) => f.debug_struct("Http").finish(),
             InnerBody::HttpSync(_) => f.debug_struct("HttpSync").finish(),
+            InnerBody::Replay(body) => body.fmt(f),
             #[cfg(feature = "incoming")]
             InnerBody::Incoming(_) => f.debug_struct("Incoming").finish(),
             #[cfg(feature = "axum")]
@@ -0,0 +1,801 @@
+//! Streaming `multipart/form-data` bodies.
+//!
+//! Use [`Form`] to build a multipart body for a request, and [`Multipart`] to read
+//! the parts of an incoming multipart body one at a time, without buffering the
+//! whole body in memory.
+
+use std::fmt;
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::stream::{self, BoxStream, StreamExt as _, TryStreamExt as _};
+use http::header::{HeaderName, HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use http::HeaderMap;
+use http_body::Frame;
+use http_body_util::{BodyExt as _, StreamBody};
+use tokio::io::AsyncReadExt as _;
+
+use super::{Body, BoxError};
+use crate::util::{parameters, random_u64};
+
+/// Maximum size of the headers of a single part.
+const HEADER_LIMIT: usize = 8 * 1024;
+
+/// Size of the chunks read from files.
+const FILE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// A `multipart/form-data` body, built from named parts.
+///
+/// # Example
+/// ```no_run
+/// # use hyperdriver::body::multipart::{Form, Part};
+/// # async fn run() -> std::io::Result<()> {
+/// let form = Form::new()
+///     .text("title", "Holiday")
+///     .part("data", Part::bytes(&b"\x00\x01"[..]).with_content_type("application/octet-stream"))
+///     .file("photo", "photo.jpg")
+///     .await?;
+///
+/// let content_type = form.content_type();
+/// let body = form.into_body();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Form {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Form")
+            .field("boundary", &self.boundary)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Form {
+    /// Create an empty form with a random boundary.
+    pub fn new() -> Self {
+        Self {
+            boundary: random_boundary(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// The boundary which separates the parts of this form.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The `Content-Type` header value for this form, including the boundary.
+    pub fn content_type(&self) -> HeaderValue {
+        HeaderValue::try_from(format!("multipart/form-data; boundary={}", self.boundary))
+            .expect("boundary is a valid header value")
+    }
+
+    /// Add a text field to the form.
+    pub fn text(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    /// Add a part to the form.
+    pub fn part(mut self, name: impl Into<String>, part: Part) -> Self {
+        self.parts.push((name.into(), part));
+        self
+    }
+
+    /// Add a file to the form, which is streamed when the body is sent.
+    ///
+    /// See [`Part::from_path`].
+    pub async fn file(
+        self,
+        name: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let part = Part::from_path(path).await?;
+        Ok(self.part(name, part))
+    }
+
+    /// Convert this form into a streaming body.
+    ///
+    /// Remember to set the `Content-Type` header from [`Form::content_type`].
+    pub fn into_body(self) -> Body {
+        let mut streams: Vec<BoxStream<'static, Result<Bytes, BoxError>>> = Vec::new();
+
+        for (name, part) in self.parts {
+            let header = part.header(&self.boundary, &name);
+            streams.push(stream::once(async move { Ok(header) }).boxed());
+            streams.push(part.body.into_data_stream().boxed());
+            streams.push(stream::once(async { Ok(Bytes::from_static(b"\r\n")) }).boxed());
+        }
+
+        let end = Bytes::from(format!("--{}--\r\n", self.boundary));
+        streams.push(stream::once(async move { Ok(end) }).boxed());
+
+        let frames = stream::iter(streams).flatten().map_ok(Frame::data);
+        Body::new(StreamBody::new(frames))
+    }
+}
+
+/// A single part of a [`Form`].
+pub struct Part {
+    body: Body,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    headers: HeaderMap,
+}
+
+impl fmt::Debug for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("file_name", &self.file_name)
+            .field("content_type", &self.content_type)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+impl Part {
+    fn new(body: Body) -> Self {
+        Self {
+            body,
+            file_name: None,
+            content_type: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Create a text part.
+    pub fn text(value: impl Into<String>) -> Self {
+        Self::new(Body::from(value.into()))
+    }
+
+    /// Create a part from bytes.
+    pub fn bytes(value: impl Into<Bytes>) -> Self {
+        Self::new(Body::from(value.into()))
+    }
+
+    /// Create a part from a body, which is streamed when the form is sent.
+    pub fn stream(body: impl Into<Body>) -> Self {
+        Self::new(body.into())
+    }
+
+    /// Create a part which streams the contents of an open file.
+    pub fn file(file: tokio::fs::File) -> Self {
+        let chunks = stream::try_unfold(file, |mut file| async move {
+            let mut buffer = BytesMut::with_capacity(FILE_CHUNK_SIZE);
+            let n = file.read_buf(&mut buffer).await?;
+            Ok::<_, BoxError>((n > 0).then(|| (Frame::data(buffer.freeze()), file)))
+        });
+
+        Self::new(Body::new(StreamBody::new(chunks))).with_content_type("application/octet-stream")
+    }
+
+    /// Open a file and create a part which streams its contents.
+    ///
+    /// The file name is set from the path, and the content type is set to
+    /// `application/octet-stream`.
+    pub async fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        let part = Self::file(file);
+
+        Ok(match path.file_name() {
+            Some(name) => part.with_file_name(name.to_string_lossy()),
+            None => part,
+        })
+    }
+
+    /// Set the file name of this part.
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Set the content type of this part.
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Add a header to this part.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    fn header(&self, boundary: &str, name: &str) -> Bytes {
+        let mut header = format!(
+            "--{boundary}\r\ncontent-disposition: form-data; name=\"{}\"",
+            escape(name)
+        );
+        if let Some(file_name) = &self.file_name {
+            header.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        header.push_str("\r\n");
+
+        let mut header = header.into_bytes();
+        if let Some(content_type) = &self.content_type {
+            header.extend_from_slice(b"content-type: ");
+            header.extend_from_slice(escape(content_type).as_bytes());
+            header.extend_from_slice(b"\r\n");
+        }
+        for (name, value) in &self.headers {
+            header.extend_from_slice(name.as_str().as_bytes());
+            header.extend_from_slice(b": ");
+            header.extend_from_slice(value.as_bytes());
+            header.extend_from_slice(b"\r\n");
+        }
+        header.extend_from_slice(b"\r\n");
+        header.into()
+    }
+}
+
+/// Escape quotes and newlines in header parameters, as browsers do.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn random_boundary() -> String {
+    format!("{:016x}{:016x}", random_u64(), random_u64())
+}
+
+/// Error reading a multipart body.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The `Content-Type` is not multipart, or has no boundary.
+    InvalidContentType,
+
+    /// The body ended before the closing boundary.
+    Incomplete,
+
+    /// The headers of a part are larger than 8 KiB.
+    HeadersTooLarge,
+
+    /// The headers of a part could not be parsed.
+    InvalidHeader,
+
+    /// A part is larger than the configured limit, in bytes.
+    PartTooLarge(usize),
+
+    /// Error reading the underlying body.
+    Body(BoxError),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::InvalidContentType => f.write_str("invalid multipart content type"),
+            MultipartError::Incomplete => f.write_str("incomplete multipart body"),
+            MultipartError::HeadersTooLarge => f.write_str("multipart headers are too large"),
+            MultipartError::InvalidHeader => f.write_str("invalid multipart header"),
+            MultipartError::PartTooLarge(limit) => {
+                write!(f, "multipart part exceeded the limit of {limit} bytes")
+            }
+            MultipartError::Body(_) => f.write_str("error reading multipart body"),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultipartError::Body(error) => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Headers,
+    Data,
+    Done,
+}
+
+/// A streaming parser for `multipart/form-data` bodies.
+///
+/// Parts are read one at a time with [`Multipart::next_field`]. Data is only
+/// buffered until it is read from the [`Field`], and any data which isn't read
+/// is skipped when moving to the next field.
+///
+/// # Example
+/// ```
+/// # use hyperdriver::body::multipart::Multipart;
+/// async fn upload(request: hyperdriver::body::Request) -> Result<(), Box<dyn std::error::Error>> {
+///     let mut multipart = Multipart::from_request(request)?.with_part_limit(1024 * 1024);
+///     while let Some(mut field) = multipart.next_field().await? {
+///         println!("field {:?}", field.name());
+///         while let Some(chunk) = field.chunk().await? {
+///             println!("  {} bytes", chunk.len());
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Multipart {
+    body: Body,
+    buffer: BytesMut,
+    delimiter: Bytes,
+    state: State,
+    eof: bool,
+    part_limit: Option<usize>,
+    part_read: usize,
+}
+
+impl fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("delimiter", &self.delimiter)
+            .field("state", &self.state)
+            .field("part_limit", &self.part_limit)
+            .finish()
+    }
+}
+
+impl Multipart {
+    /// Create a parser for a body with the given boundary.
+    pub fn new(body: impl Into<Body>, boundary: &str) -> Self {
+        // The leading CRLF lets the first boundary match the same delimiter as the rest.
+        Self {
+            body: body.into(),
+            buffer: BytesMut::from(&b"\r\n"[..]),
+            delimiter: Bytes::from(format!("\r\n--{boundary}")),
+            state: State::Preamble,
+            eof: false,
+            part_limit: None,
+            part_read: 0,
+        }
+    }
+
+    /// Create a parser for a request, using the boundary from its `Content-Type` header.
+    pub fn from_request<B: Into<Body>>(request: http::Request<B>) -> Result<Self, MultipartError> {
+        let boundary = boundary(request.headers())?;
+        Ok(Self::new(request.into_body(), &boundary))
+    }
+
+    /// Limit the size of each part in bytes. Parts are not limited by default.
+    pub fn with_part_limit(mut self, limit: usize) -> Self {
+        self.part_limit = Some(limit);
+        self
+    }
+
+    /// Read the headers of the next field.
+    ///
+    /// Returns `None` after the last field.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_>>, MultipartError> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Data => while self.read_chunk().await?.is_some() {},
+                State::Preamble => match find(&self.buffer, &self.delimiter) {
+                    Some(index) => {
+                        let _ = self.buffer.split_to(index);
+                        if !self.boundary().await? {
+                            // Skip the text which looked like a delimiter.
+                            let _ = self.buffer.split_to(self.delimiter.len());
+                        }
+                    }
+                    None => {
+                        let keep = self.delimiter.len() - 1;
+                        if self.buffer.len() > keep {
+                            let _ = self.buffer.split_to(self.buffer.len() - keep);
+                        }
+                        self.fill().await?;
+                    }
+                },
+                State::Headers => {
+                    let end = if self.buffer.starts_with(b"\r\n") {
+                        Some(0)
+                    } else {
+                        find(&self.buffer, b"\r\n\r\n").map(|index| index + 2)
+                    };
+
+                    match end {
+                        Some(end) => {
+                            let block = self.buffer.split_to(end + 2);
+                            let headers = parse_headers(&block[..end])?;
+                            self.state = State::Data;
+                            self.part_read = 0;
+                            return Ok(Some(Field::new(self, headers)));
+                        }
+                        None if self.buffer.len() > HEADER_LIMIT => {
+                            return Err(MultipartError::HeadersTooLarge)
+                        }
+                        None => self.fill().await?,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the next chunk of data in the current part.
+    async fn read_chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if self.state != State::Data {
+            return Ok(None);
+        }
+
+        loop {
+            let chunk = match find(&self.buffer, &self.delimiter) {
+                Some(0) if self.boundary().await? => return Ok(None),
+                // The delimiter wasn't followed by `--` or a line break, so it is data.
+                Some(0) => self.buffer.split_to(self.delimiter.len()),
+                Some(index) => self.buffer.split_to(index),
+                None => {
+                    // The end of the buffer could be the start of the delimiter.
+                    let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+                    if safe == 0 {
+                        self.fill().await?;
+                        continue;
+                    }
+                    self.buffer.split_to(safe)
+                }
+            };
+
+            self.part_read += chunk.len();
+            if let Some(limit) = self.part_limit {
+                if self.part_read > limit {
+                    self.state = State::Done;
+                    return Err(MultipartError::PartTooLarge(limit));
+                }
+            }
+            return Ok(Some(chunk.freeze()));
+        }
+    }
+
+    /// Consume the boundary at the start of the buffer, and move to the next part's
+    /// headers, or to the end of the body after the closing boundary.
+    ///
+    /// Returns `false`, leaving the buffer unchanged, if the delimiter at the start of
+    /// the buffer isn't followed by `--`, or by optional whitespace and a line break,
+    /// and so isn't a boundary.
+    async fn boundary(&mut self) -> Result<bool, MultipartError> {
+        loop {
+            let rest = &self.buffer[self.delimiter.len()..];
+            if rest.starts_with(b"--") {
+                self.state = State::Done;
+                return Ok(true);
+            }
+
+            let padding = rest
+                .iter()
+                .take_while(|&&b| b == b' ' || b == b'\t')
+                .count();
+            match &rest[padding..] {
+                [b'\r', b'\n', ..] => {
+                    let _ = self.buffer.split_to(self.delimiter.len() + padding + 2);
+                    self.state = State::Headers;
+                    return Ok(true);
+                }
+                [] | [b'-'] | [b'\r'] if padding <= HEADER_LIMIT => self.fill().await?,
+                [] | [b'-'] | [b'\r'] => return Err(MultipartError::HeadersTooLarge),
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Read more data from the body into the buffer.
+    async fn fill(&mut self) -> Result<(), MultipartError> {
+        while !self.eof {
+            match self.body.frame().await {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data() {
+                        self.buffer.extend_from_slice(&data);
+                        return Ok(());
+                    }
+                }
+                Some(Err(error)) => return Err(MultipartError::Body(error)),
+                None => self.eof = true,
+            }
+        }
+
+        self.state = State::Done;
+        Err(MultipartError::Incomplete)
+    }
+}
+
+/// A single field of a [`Multipart`] body.
+#[derive(Debug)]
+pub struct Field<'a> {
+    multipart: &'a mut Multipart,
+    headers: HeaderMap,
+    name: Option<String>,
+    file_name: Option<String>,
+}
+
+impl<'a> Field<'a> {
+    fn new(multipart: &'a mut Multipart, headers: HeaderMap) -> Self {
+        let disposition = headers
+            .get(CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| parameters(value, ';'))
+            .unwrap_or_default();
+
+        let mut name = None;
+        let mut file_name = None;
+        for (key, value) in disposition {
+            match key.as_str() {
+                "name" => name = Some(value),
+                "filename" => file_name = Some(value),
+                _ => {}
+            }
+        }
+
+        Self {
+            multipart,
+            headers,
+            name,
+            file_name,
+        }
+    }
+
+    /// The name of the field, from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The file name of the field, from the `Content-Disposition` header.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// The content type of the field.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// The headers of the field.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Read the next chunk of data in this field.
+    ///
+    /// Returns `None` at the end of the field.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        self.multipart.read_chunk().await
+    }
+
+    /// Read the rest of the data in this field.
+    pub async fn bytes(mut self) -> Result<Bytes, MultipartError> {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+
+    /// Read the rest of the data in this field as UTF-8 text.
+    ///
+    /// Invalid UTF-8 is replaced with the replacement character.
+    pub async fn text(self) -> Result<String, MultipartError> {
+        let data = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+}
+
+/// Get the boundary from a `multipart/*` `Content-Type` header.
+fn boundary(headers: &HeaderMap) -> Result<String, MultipartError> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(MultipartError::InvalidContentType)?;
+
+    let (mime, _) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !mime.trim().to_ascii_lowercase().starts_with("multipart/") {
+        return Err(MultipartError::InvalidContentType);
+    }
+
+    parameters(content_type, ';')
+        .into_iter()
+        .find(|(key, value)| key == "boundary" && !value.is_empty())
+        .map(|(_, value)| value)
+        .ok_or(MultipartError::InvalidContentType)
+}
+
+fn parse_headers(block: &[u8]) -> Result<HeaderMap, MultipartError> {
+    let mut headers = HeaderMap::new();
+    for line in block.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(MultipartError::InvalidHeader)?;
+        let name =
+            HeaderName::from_bytes(&line[..colon]).map_err(|_| MultipartError::InvalidHeader)?;
+        let value = HeaderValue::from_bytes(trim(&line[colon + 1..]))
+            .map_err(|_| MultipartError::InvalidHeader)?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn trim(mut value: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = value {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        value = rest;
+    }
+    while let [rest @ .., last] = value {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        value = rest;
+    }
+    value
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn encode(form: Form) -> (String, Bytes) {
+        let boundary = form.boundary().to_owned();
+        let body = form.into_body().collect().await.unwrap().to_bytes();
+        (boundary, body)
+    }
+
+    /// Split the body into single byte frames, to exercise delimiters split across reads.
+    fn trickle(body: Bytes) -> Body {
+        let frames: Vec<Result<_, BoxError>> = (0..body.len())
+            .map(|i| Ok(Frame::data(body.slice(i..i + 1))))
+            .collect();
+        Body::new(StreamBody::new(stream::iter(frames)))
+    }
+
+    #[tokio::test]
+    async fn encode_form() {
+        let form = Form::new().text("a", "1").part(
+            "b\"",
+            Part::bytes("xyz")
+                .with_file_name("b.txt")
+                .with_content_type("text/plain"),
+        );
+        let (boundary, body) = encode(form).await;
+
+        let expected = format!(
+            "--{boundary}\r\ncontent-disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --{boundary}\r\ncontent-disposition: form-data; name=\"b%22\"; filename=\"b.txt\"\r\n\
+             content-type: text/plain\r\n\r\nxyz\r\n--{boundary}--\r\n"
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &vec![7u8; 40_000]).unwrap();
+
+        let form = Form::new()
+            .text("title", "hello\r\n--not a boundary")
+            .file("upload", file.path())
+            .await
+            .unwrap()
+            .text("empty", "");
+        let (boundary, body) = encode(form).await;
+
+        for body in [Body::from(body.clone()), trickle(body)] {
+            let mut multipart = Multipart::new(body, &boundary);
+
+            let field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name(), Some("title"));
+            assert_eq!(field.text().await.unwrap(), "hello\r\n--not a boundary");
+
+            let field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name(), Some("upload"));
+            assert_eq!(field.file_name(), file.path().file_name().unwrap().to_str());
+            assert_eq!(field.content_type(), Some("application/octet-stream"));
+            assert_eq!(field.bytes().await.unwrap(), vec![7u8; 40_000]);
+
+            let field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name(), Some("empty"));
+            assert_eq!(field.bytes().await.unwrap(), "");
+
+            assert!(multipart.next_field().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn skip_unread_fields() {
+        let form = Form::new().text("a", "skipped").text("b", "read");
+        let (boundary, body) = encode(form).await;
+
+        let mut multipart = Multipart::new(trickle(body), &boundary);
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("a"));
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("b"));
+        assert_eq!(field.text().await.unwrap(), "read");
+        assert!(multipart.next_field().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn preamble_and_part_limit() {
+        let body = "preamble\r\n--XYZ\r\nContent-Disposition: form-data; name=\"a; b\"\r\n\r\n\
+                    0123456789\r\n--XYZ--\r\nepilogue";
+        let request = http::Request::builder()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=\"XYZ\"")
+            .body(Body::from(body))
+            .unwrap();
+
+        let mut multipart = Multipart::from_request(request).unwrap().with_part_limit(4);
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("a; b"));
+        assert!(matches!(
+            field.bytes().await,
+            Err(MultipartError::PartTooLarge(4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn incomplete() {
+        let mut multipart = Multipart::new(Body::from("--XYZ\r\n\r\ndata"), "XYZ");
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert!(matches!(
+            field.bytes().await,
+            Err(MultipartError::Incomplete)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delimiters_must_end_the_line() {
+        let body = "--XYZ \t\r\n\r\nfirst\r\n--XYZZY\r\n--XYZ-\r\n--XYZ\r\n\r\nsecond\r\n--XYZ--";
+
+        for body in [Body::from(body), trickle(Bytes::from(body))] {
+            let mut multipart = Multipart::new(body, "XYZ");
+            let field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.text().await.unwrap(), "first\r\n--XYZZY\r\n--XYZ-");
+
+            let field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.text().await.unwrap(), "second");
+            assert!(multipart.next_field().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn preamble_skips_false_delimiters() {
+        let body = "--XYZW\r\n--XYZ\r\n\r\ndata\r\n--XYZ--";
+        let mut multipart = Multipart::new(Body::from(body), "XYZ");
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.text().await.unwrap(), "data");
+        assert!(multipart.next_field().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_content_type() {
+        let request = http::Request::builder()
+            .header(CONTENT_TYPE, "text/plain; boundary=XYZ")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            Multipart::from_request(request),
+            Err(MultipartError::InvalidContentType)
+        ));
+    }
+}
@@ -0,0 +1,346 @@
+//! Request bodies which can be replayed for retries and redirects.
+
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+
+use super::{Body, BoxError};
+
+/// The default number of bytes buffered by a [`ReplayBody`]: 64 KiB.
+pub const DEFAULT_REPLAY_LIMIT: usize = 64 * 1024;
+
+/// Error returned when a replayed body can no longer be reproduced.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The body was larger than the replay buffer, so the data which was already
+    /// sent has been discarded.
+    Overflow,
+
+    /// The body is still being read by another copy of the request.
+    Busy,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Overflow => f.write_str("request body exceeded the replay buffer"),
+            ReplayError::Busy => f.write_str("request body is being read by another request"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// A body which buffers the data it sends, so that it can be sent again.
+///
+/// Streaming bodies can't normally be cloned with [`Body::try_clone`], so requests
+/// with a streaming body are not retried, and are not resent when following
+/// `307` and `308` redirects. Wrapping the body in a `ReplayBody` tees the data into
+/// a buffer as it is sent, up to a limit in bytes. Every copy of the body shares
+/// that buffer: a copy first replays the data which has already been read, and then
+/// continues reading from the original body.
+///
+/// Once more than `limit` bytes have been read, the buffer is discarded and the
+/// body can no longer be replayed: [`Body::try_clone`] returns `None`, and copies
+/// made earlier fail with [`ReplayError::Overflow`] when they are polled.
+pub struct ReplayBody {
+    shared: Arc<Mutex<Shared>>,
+    source: Option<Box<Body>>,
+    position: usize,
+    consumed: u64,
+    trailers_sent: bool,
+}
+
+struct Shared {
+    source: Option<Box<Body>>,
+    buffer: Vec<Bytes>,
+    buffered: usize,
+    limit: usize,
+    size_hint: SizeHint,
+    trailers: Option<HeaderMap>,
+    complete: bool,
+    overflow: bool,
+}
+
+impl ReplayBody {
+    /// Wrap `body`, buffering at most `limit` bytes of data for replays.
+    pub fn new(body: Body, limit: usize) -> Self {
+        let shared = Shared {
+            size_hint: body.size_hint(),
+            source: Some(Box::new(body)),
+            buffer: Vec::new(),
+            buffered: 0,
+            limit,
+            trailers: None,
+            complete: false,
+            overflow: false,
+        };
+
+        Self {
+            shared: Arc::new(Mutex::new(shared)),
+            source: None,
+            position: 0,
+            consumed: 0,
+            trailers_sent: false,
+        }
+    }
+
+    /// Returns true if this body can still be replayed.
+    pub fn is_replayable(&self) -> bool {
+        !self.shared.lock().unwrap().overflow
+    }
+
+    /// A handle which can check whether this body can still be replayed.
+    #[cfg(feature = "client")]
+    pub(crate) fn handle(&self) -> ReplayHandle {
+        ReplayHandle(self.shared.clone())
+    }
+
+    /// Create a copy of this body which will send the body from the beginning.
+    ///
+    /// Returns `None` if the body has exceeded the replay buffer.
+    pub fn try_clone(&self) -> Option<Self> {
+        if !self.is_replayable() {
+            return None;
+        }
+
+        Some(Self {
+            shared: self.shared.clone(),
+            source: None,
+            position: 0,
+            consumed: 0,
+            trailers_sent: false,
+        })
+    }
+}
+
+/// Checks whether a [`ReplayBody`] can still be replayed, without holding a copy of the body.
+#[cfg(feature = "client")]
+#[derive(Clone)]
+pub(crate) struct ReplayHandle(Arc<Mutex<Shared>>);
+
+#[cfg(feature = "client")]
+impl ReplayHandle {
+    pub(crate) fn is_replayable(&self) -> bool {
+        !self.0.lock().unwrap().overflow
+    }
+}
+
+#[cfg(feature = "client")]
+impl fmt::Debug for ReplayHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReplayHandle").finish()
+    }
+}
+
+impl fmt::Debug for ReplayBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("ReplayBody")
+            .field("buffered", &shared.buffered)
+            .field("limit", &shared.limit)
+            .field("complete", &shared.complete)
+            .field("overflow", &shared.overflow)
+            .finish()
+    }
+}
+
+impl Drop for ReplayBody {
+    fn drop(&mut self) {
+        // Give the original body back, so that another copy can continue reading it.
+        if let Some(source) = self.source.take() {
+            if let Ok(mut shared) = self.shared.lock() {
+                shared.source = Some(source);
+            }
+        }
+    }
+}
+
+impl HttpBody for ReplayBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if this.source.is_none() {
+            let mut shared = this.shared.lock().unwrap();
+            if shared.overflow {
+                return Poll::Ready(Some(Err(ReplayError::Overflow.into())));
+            }
+
+            if let Some(data) = shared.buffer.get(this.position) {
+                let data = data.clone();
+                this.position += 1;
+                this.consumed += data.len() as u64;
+                return Poll::Ready(Some(Ok(Frame::data(data))));
+            }
+
+            if shared.complete {
+                if this.trailers_sent {
+                    return Poll::Ready(None);
+                }
+                this.trailers_sent = true;
+                return Poll::Ready(shared.trailers.clone().map(|t| Ok(Frame::trailers(t))));
+            }
+
+            match shared.source.take() {
+                Some(source) => this.source = Some(source),
+                None => return Poll::Ready(Some(Err(ReplayError::Busy.into()))),
+            }
+        }
+
+        let source = this.source.as_mut().expect("source body is present");
+        let frame = match futures_util::ready!(Pin::new(&mut **source).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+            None => {
+                this.shared.lock().unwrap().complete = true;
+                this.trailers_sent = true;
+                return Poll::Ready(None);
+            }
+        };
+
+        let mut shared = this.shared.lock().unwrap();
+        let frame = match frame.into_data() {
+            Ok(data) => {
+                this.consumed += data.len() as u64;
+                if !shared.overflow {
+                    if shared.buffered + data.len() > shared.limit {
+                        tracing::trace!(
+                            limit = shared.limit,
+                            "request body exceeded replay buffer"
+                        );
+                        shared.overflow = true;
+                        shared.buffer = Vec::new();
+                    } else {
+                        shared.buffered += data.len();
+                        shared.buffer.push(data.clone());
+                        this.position += 1;
+                    }
+                }
+                Frame::data(data)
+            }
+            Err(frame) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    shared.trailers = Some(trailers.clone());
+                    shared.complete = true;
+                    this.trailers_sent = true;
+                }
+                frame
+            }
+        };
+
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if let Some(source) = &self.source {
+            return source.is_end_stream();
+        }
+
+        let shared = self.shared.lock().unwrap();
+        if shared.overflow {
+            return false;
+        }
+
+        shared.complete
+            && self.position >= shared.buffer.len()
+            && (self.trailers_sent || shared.trailers.is_none())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let shared = self.shared.lock().unwrap();
+        let mut hint = SizeHint::new();
+        hint.set_lower(shared.size_hint.lower().saturating_sub(self.consumed));
+        if let Some(upper) = shared.size_hint.upper() {
+            hint.set_upper(upper.saturating_sub(self.consumed));
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http_body_util::BodyExt as _;
+
+    fn stream(chunks: &[&'static str]) -> Body {
+        let frames = chunks
+            .iter()
+            .map(|chunk| Ok::<_, BoxError>(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+            .collect::<Vec<_>>();
+        Body::new(http_body_util::StreamBody::new(futures_util::stream::iter(
+            frames,
+        )))
+    }
+
+    #[tokio::test]
+    async fn replay_after_complete() {
+        let body = Body::from(ReplayBody::new(stream(&["hello", " ", "world"]), 1024));
+        let copy = body.try_clone().expect("replayable body should clone");
+
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "hello world");
+
+        let data = copy.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "hello world");
+    }
+
+    #[tokio::test]
+    async fn replay_after_partial_read() {
+        let mut body = ReplayBody::new(stream(&["hello", " ", "world"]), 1024);
+        let copy = body.try_clone().unwrap();
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "hello");
+        drop(body);
+
+        let data = copy.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "hello world");
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let body = ReplayBody::new(stream(&["hello", " ", "world"]), 8);
+        let copy = body.try_clone().unwrap();
+
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "hello world");
+
+        let error = copy.collect().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ReplayError>(),
+            Some(ReplayError::Overflow)
+        ));
+    }
+
+    #[tokio::test]
+    async fn busy() {
+        let mut body = ReplayBody::new(stream(&["hello", " ", "world"]), 1024);
+        let copy = body.try_clone().unwrap();
+
+        body.frame().await.unwrap().unwrap();
+
+        let error = copy.collect().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ReplayError>(),
+            Some(ReplayError::Busy)
+        ));
+    }
+
+    #[test]
+    fn size_hint() {
+        let body = ReplayBody::new(Body::from("hello world"), 1024);
+        assert_eq!(body.size_hint().exact(), Some(11));
+    }
+}
@@ -0,0 +1,476 @@
+//! Alternative services for the client.
+//!
+//! An [`AltSvcCache`] records the alternatives advertised by `Alt-Svc` response headers,
+//! following [RFC 7838](https://datatracker.ietf.org/doc/html/rfc7838), until their
+//! `ma` (max-age) expires. A new advertisement for an origin replaces the previous one,
+//! and `Alt-Svc: clear` removes it.
+//!
+//! Advertisements are only recorded from `https` origins. The client connects to an
+//! alternative when it is on the same host as the origin (so the origin's certificate
+//! is still verified) and uses a protocol the client speaks (`h2` or `http/1.1`).
+//! Other alternatives are recorded, and can be read with [`AltSvcCache::alternatives`].
+//! If connecting to an alternative fails, the alternatives for that origin are
+//! forgotten, so that later requests go to the origin itself.
+//!
+//! The cache is applied to a client with [`Builder::with_alt_svc`](super::Builder::with_alt_svc)
+//! or [`Builder::with_alt_svc_cache`](super::Builder::with_alt_svc_cache). It holds a
+//! bounded number of origins: when it is full, the origin whose alternatives expire
+//! soonest is evicted.
+//!
+//! With the `serde` feature, the cache can be saved to and loaded from a JSON file.
+
+#[cfg(feature = "serde")]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use http::header::ALT_SVC;
+use http::uri::{Authority, Scheme};
+use http::{HeaderMap, Uri};
+
+use super::expiring::{expires_at, Expires, ExpiringCache};
+
+/// The default maximum number of origins in an [`AltSvcCache`].
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The default freshness of an alternative, when the header has no `ma` parameter.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Protocols the client can use to connect to an alternative.
+const SUPPORTED_PROTOCOLS: &[&str] = &["h2", "http/1.1"];
+
+/// An alternative service advertised for an origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Alternative {
+    protocol: String,
+    host: Option<String>,
+    port: u16,
+    expires: SystemTime,
+}
+
+impl Alternative {
+    /// The ALPN protocol ID of the alternative, e.g. `h2` or `h3`.
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    /// The host of the alternative, or `None` if it is the origin's host.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The port of the alternative.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// When the alternative is no longer fresh.
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+impl Expires for Vec<Alternative> {
+    fn expires(&self) -> SystemTime {
+        self.iter()
+            .map(|alternative| alternative.expires)
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+}
+
+/// A shared cache of alternative services.
+///
+/// Cloning an `AltSvcCache` produces a handle to the same cache, so it can be shared
+/// between clients, or retained to inspect or save it after requests.
+#[derive(Clone)]
+pub struct AltSvcCache {
+    origins: Arc<Mutex<ExpiringCache<Vec<Alternative>>>>,
+}
+
+impl fmt::Debug for AltSvcCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AltSvcCache")
+            .field("origins", &self.origins.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for AltSvcCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl AltSvcCache {
+    /// Create a new, empty cache holding up to [`DEFAULT_CAPACITY`] origins.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new, empty cache holding up to `capacity` origins.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            origins: Arc::new(Mutex::new(ExpiringCache::with_capacity(capacity))),
+        }
+    }
+
+    /// Update the cache from the `Alt-Svc` headers of a response to a request for `uri`.
+    ///
+    /// Headers are ignored unless the response was received over `https`.
+    pub fn store_response(&self, uri: &Uri, headers: &HeaderMap) {
+        self.store_response_at(uri, headers, SystemTime::now());
+    }
+
+    fn store_response_at(&self, uri: &Uri, headers: &HeaderMap, now: SystemTime) {
+        if uri.scheme() != Some(&Scheme::HTTPS) {
+            return;
+        }
+
+        let Some(origin) = origin(uri) else {
+            return;
+        };
+
+        let mut values = headers
+            .get_all(ALT_SVC)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .peekable();
+        if values.peek().is_none() {
+            return;
+        }
+
+        let mut alternatives = Vec::new();
+        for value in values {
+            match parse(value, now) {
+                Some(Advertisement::Clear) => {
+                    self.origins.lock().unwrap().remove(&origin);
+                    return;
+                }
+                Some(Advertisement::Alternatives(parsed)) => alternatives.extend(parsed),
+                None => {}
+            }
+        }
+
+        if alternatives.is_empty() {
+            return;
+        }
+
+        self.origins
+            .lock()
+            .unwrap()
+            .insert(origin, alternatives, now);
+    }
+
+    /// The fresh alternatives advertised for the origin of `uri`.
+    pub fn alternatives(&self, uri: &Uri) -> Vec<Alternative> {
+        let Some(origin) = origin(uri) else {
+            return Vec::new();
+        };
+
+        let now = SystemTime::now();
+        self.origins
+            .lock()
+            .unwrap()
+            .get(&origin)
+            .map(|alternatives| {
+                alternatives
+                    .iter()
+                    .filter(|alternative| alternative.expires > now)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Forget the alternatives for the origin of `uri`. Returns `true` if there were any.
+    pub fn remove(&self, uri: &Uri) -> bool {
+        let Some(origin) = origin(uri) else {
+            return false;
+        };
+
+        self.origins.lock().unwrap().remove(&origin).is_some()
+    }
+
+    /// The URI to connect to instead of `uri`, if there is a usable alternative.
+    ///
+    /// Only the port of the URI is changed, since alternatives are only used on the
+    /// origin's host.
+    pub(crate) fn alternative_uri(&self, uri: &Uri) -> Option<Uri> {
+        if uri.scheme() != Some(&Scheme::HTTPS) {
+            return None;
+        }
+
+        let host = uri.host()?;
+        let port = uri.port_u16().unwrap_or(443);
+        let alternative = self.alternatives(uri).into_iter().find(|alternative| {
+            SUPPORTED_PROTOCOLS.contains(&alternative.protocol.as_str())
+                && alternative
+                    .host
+                    .as_deref()
+                    .map_or(true, |alternative| alternative.eq_ignore_ascii_case(host))
+                && alternative.port != port
+        })?;
+
+        let mut parts = uri.clone().into_parts();
+        parts.authority =
+            Some(Authority::try_from(format!("{host}:{}", alternative.port).as_str()).ok()?);
+        Uri::from_parts(parts).ok()
+    }
+
+    /// Returns `true` if there are no origins in the cache.
+    pub fn is_empty(&self) -> bool {
+        self.origins.lock().unwrap().is_empty()
+    }
+
+    /// Remove all origins from the cache.
+    pub fn clear(&self) {
+        self.origins.lock().unwrap().clear();
+    }
+}
+
+#[cfg(feature = "serde")]
+impl AltSvcCache {
+    /// Load fresh alternatives from JSON, as written by [`AltSvcCache::save_json`].
+    pub fn load_json<R: std::io::Read>(reader: R) -> std::io::Result<Self> {
+        let entries: HashMap<String, Vec<Alternative>> = serde_json::from_reader(reader)?;
+        let cache = Self::new();
+        let now = SystemTime::now();
+        {
+            let mut origins = cache.origins.lock().unwrap();
+            for (origin, mut alternatives) in entries {
+                alternatives.retain(|alternative| alternative.expires > now);
+                if !alternatives.is_empty() {
+                    origins.insert(origin, alternatives, now);
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Save fresh alternatives as JSON.
+    pub fn save_json<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let now = SystemTime::now();
+        let origins = self.origins.lock().unwrap();
+        let entries: HashMap<&String, Vec<&Alternative>> = origins
+            .iter()
+            .map(|(origin, alternatives)| {
+                let fresh = alternatives
+                    .iter()
+                    .filter(|alternative| alternative.expires > now)
+                    .collect::<Vec<_>>();
+                (origin, fresh)
+            })
+            .filter(|(_, alternatives)| !alternatives.is_empty())
+            .collect();
+        serde_json::to_writer(writer, &entries).map_err(std::io::Error::other)
+    }
+
+    /// Load fresh alternatives from a JSON file.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::load_json(std::io::BufReader::new(file))
+    }
+
+    /// Save fresh alternatives to a JSON file, replacing its contents.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.save_json(&mut file)?;
+        std::io::Write::flush(&mut file)
+    }
+}
+
+/// The cache key for the origin of a URI.
+fn origin(uri: &Uri) -> Option<String> {
+    let scheme = uri.scheme_str()?;
+    let host = uri.host()?.to_ascii_lowercase();
+    let port = uri.port_u16().or(match scheme {
+        "https" => Some(443),
+        "http" => Some(80),
+        _ => None,
+    })?;
+    Some(format!("{scheme}://{host}:{port}"))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Advertisement {
+    Clear,
+    Alternatives(Vec<Alternative>),
+}
+
+/// Parse an `Alt-Svc` header value.
+fn parse(value: &str, now: SystemTime) -> Option<Advertisement> {
+    if value.trim() == "clear" {
+        return Some(Advertisement::Clear);
+    }
+
+    let alternatives = split_unquoted(value, ',')
+        .into_iter()
+        .filter_map(|entry| parse_alternative(&entry, now))
+        .collect();
+    Some(Advertisement::Alternatives(alternatives))
+}
+
+fn parse_alternative(entry: &str, now: SystemTime) -> Option<Alternative> {
+    let mut params = split_unquoted(entry, ';').into_iter();
+    let first = params.next()?;
+    let (protocol, authority) = first.split_once('=')?;
+    let protocol = percent_decode(protocol.trim())?;
+    let authority = authority.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    let (host, port) = authority.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = (!host.is_empty()).then(|| host.to_ascii_lowercase());
+
+    let mut max_age = DEFAULT_MAX_AGE;
+    for param in params {
+        if let Some((name, value)) = param.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("ma") {
+                let seconds: u64 = value.trim().trim_matches('"').parse().ok()?;
+                max_age = Duration::from_secs(seconds);
+            }
+        }
+    }
+
+    Some(Alternative {
+        protocol,
+        host,
+        port,
+        expires: expires_at(now, max_age),
+    })
+}
+
+/// Split on `separator`, except inside quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                part.push(c);
+            }
+            _ if c == separator && !quoted => parts.push(std::mem::take(&mut part)),
+            _ => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let high = (input.next()? as char).to_digit(16)?;
+            let low = (input.next()? as char).to_digit(16)?;
+            bytes.push((high * 16 + low) as u8);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::HeaderValue;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ALT_SVC, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn parse_header() {
+        let now = SystemTime::now();
+        let Some(Advertisement::Alternatives(alternatives)) = parse(
+            r#"h3="alt.example.com:443"; ma=60; persist=1, http%2F1.1=":8080", broken"#,
+            now,
+        ) else {
+            panic!("expected alternatives");
+        };
+
+        assert_eq!(alternatives.len(), 2);
+        assert_eq!(alternatives[0].protocol(), "h3");
+        assert_eq!(alternatives[0].host(), Some("alt.example.com"));
+        assert_eq!(alternatives[0].port(), 443);
+        assert_eq!(alternatives[0].expires(), now + Duration::from_secs(60));
+        assert_eq!(alternatives[1].protocol(), "http/1.1");
+        assert_eq!(alternatives[1].host(), None);
+        assert_eq!(alternatives[1].expires(), now + DEFAULT_MAX_AGE);
+
+        assert_eq!(parse(" clear ", now), Some(Advertisement::Clear));
+    }
+
+    #[test]
+    fn alternative_uri() {
+        let cache = AltSvcCache::new();
+        let uri: Uri = "https://example.com/path".parse().unwrap();
+        cache.store_response(
+            &uri,
+            &headers(r#"h3=":443", h2="other.com:8443", h2=":8443""#),
+        );
+        assert_eq!(cache.alternatives(&uri).len(), 3);
+
+        let alternative = cache.alternative_uri(&uri).unwrap();
+        assert_eq!(alternative, "https://example.com:8443/path");
+
+        // Another path on the same origin.
+        let other: Uri = "https://EXAMPLE.com:443/".parse().unwrap();
+        assert!(cache.alternative_uri(&other).is_some());
+
+        cache.store_response(&uri, &headers("clear"));
+        assert!(cache.alternative_uri(&uri).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn ignored_responses() {
+        let cache = AltSvcCache::new();
+        let uri: Uri = "http://example.com/".parse().unwrap();
+        cache.store_response(&uri, &headers(r#"h2=":8443""#));
+        assert!(cache.is_empty());
+        assert!(cache.alternative_uri(&uri).is_none());
+    }
+
+    #[test]
+    fn expiry_and_capacity() {
+        let cache = AltSvcCache::with_capacity(1);
+        let now = SystemTime::now();
+        let a: Uri = "https://a.com/".parse().unwrap();
+        let b: Uri = "https://b.com/".parse().unwrap();
+
+        cache.store_response_at(&a, &headers(r#"h2=":8443"; ma=60"#), now);
+        cache.store_response_at(&b, &headers(r#"h2=":8443"; ma=60"#), now);
+        assert!(cache.alternatives(&a).is_empty());
+        assert_eq!(cache.alternatives(&b).len(), 1);
+
+        let past = now - Duration::from_secs(120);
+        cache.store_response_at(&b, &headers(r#"h2=":8443"; ma=60"#), past);
+        assert!(cache.alternatives(&b).is_empty());
+        assert!(cache.alternative_uri(&b).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load() {
+        let cache = AltSvcCache::new();
+        let uri: Uri = "https://example.com/".parse().unwrap();
+        cache.store_response(&uri, &headers(r#"h2=":8443""#));
+
+        let mut saved = Vec::new();
+        cache.save_json(&mut saved).unwrap();
+
+        let loaded = AltSvcCache::load_json(saved.as_slice()).unwrap();
+        assert_eq!(loaded.alternatives(&uri), cache.alternatives(&uri));
+    }
+}
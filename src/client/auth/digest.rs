@@ -0,0 +1,318 @@
+//! RFC 7616 digest access authentication.
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+
+use http::header::WWW_AUTHENTICATE;
+use http::{HeaderMap, HeaderValue, Method, Uri};
+use md5::Md5;
+use sha2::{Digest as _, Sha256};
+
+use crate::util::parameters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "MD5-SESS" => Some(Algorithm::Md5Sess),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-256-SESS" => Some(Algorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn hash(&self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 | Algorithm::Md5Sess => hex(&Md5::digest(data)),
+            Algorithm::Sha256 | Algorithm::Sha256Sess => hex(&Sha256::digest(data)),
+        }
+    }
+
+    fn is_session(&self) -> bool {
+        matches!(self, Algorithm::Md5Sess | Algorithm::Sha256Sess)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        })
+    }
+}
+
+/// A digest challenge from a `WWW-Authenticate` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    /// Only `auth` quality of protection is supported.
+    qop: bool,
+    pub(super) stale: bool,
+}
+
+impl Challenge {
+    /// Find a supported digest challenge in the response headers.
+    pub(super) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Self::parse)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim_start();
+        let (scheme, params) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let mut realm = None;
+        let mut nonce = None;
+        let mut opaque = None;
+        let mut algorithm = Algorithm::Md5;
+        let mut qop = None;
+        let mut stale = false;
+
+        for (key, value) in parameters(params, ',') {
+            match key.as_str() {
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "opaque" => opaque = Some(value),
+                "algorithm" => algorithm = Algorithm::parse(&value)?,
+                "qop" => qop = Some(value),
+                "stale" => stale = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+
+        let qop = match qop {
+            Some(qop) => {
+                if !qop.split(',').any(|option| option.trim() == "auth") {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+
+        Some(Self {
+            realm: realm?,
+            nonce: nonce?,
+            opaque,
+            algorithm,
+            qop,
+            stale,
+        })
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// A client nonce, from the operating system's random number generator.
+fn cnonce() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => Some(hex(&bytes)),
+        Err(error) => {
+            tracing::warn!(%error, "unable to generate a digest client nonce");
+            None
+        }
+    }
+}
+
+fn response(
+    challenge: &Challenge,
+    username: &str,
+    password: &str,
+    method: &Method,
+    uri: &str,
+    nc: u32,
+    cnonce: &str,
+) -> String {
+    let algorithm = challenge.algorithm;
+
+    let mut ha1 = algorithm.hash(&format!("{username}:{}:{password}", challenge.realm));
+    if algorithm.is_session() {
+        ha1 = algorithm.hash(&format!("{ha1}:{}:{cnonce}", challenge.nonce));
+    }
+    let ha2 = algorithm.hash(&format!("{method}:{uri}"));
+
+    if challenge.qop {
+        algorithm.hash(&format!(
+            "{ha1}:{}:{nc:08x}:{cnonce}:auth:{ha2}",
+            challenge.nonce
+        ))
+    } else {
+        algorithm.hash(&format!("{ha1}:{}:{ha2}", challenge.nonce))
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    challenge: Challenge,
+    nc: u32,
+}
+
+/// Digest credentials, and the most recent challenge for a host.
+pub(super) struct Digest {
+    username: String,
+    password: String,
+    session: Mutex<Option<Session>>,
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Digest")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+impl Digest {
+    pub(super) fn new(username: String, password: String) -> Self {
+        Self {
+            username,
+            password,
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Remember a new challenge, which is answered by later requests.
+    pub(super) fn challenge(&self, challenge: Challenge) {
+        *self.session.lock().unwrap() = Some(Session { challenge, nc: 0 });
+    }
+
+    /// The `Authorization` header for a request, if a challenge has been received.
+    pub(super) fn authorization(&self, method: &Method, uri: &Uri) -> Option<HeaderValue> {
+        let mut session = self.session.lock().unwrap();
+        let session = session.as_mut()?;
+        session.nc = session.nc.wrapping_add(1);
+
+        let challenge = &session.challenge;
+        let uri = uri.path_and_query().map_or("/", |path| path.as_str());
+        let cnonce = cnonce()?;
+        let response = response(
+            challenge,
+            &self.username,
+            &self.password,
+            method,
+            uri,
+            session.nc,
+            &cnonce,
+        );
+
+        let mut header = format!(
+            "Digest username={}, realm={}, uri={}, algorithm={}, nonce={}",
+            quote(&self.username),
+            quote(&challenge.realm),
+            quote(uri),
+            challenge.algorithm,
+            quote(&challenge.nonce),
+        );
+        if challenge.qop {
+            header.push_str(&format!(
+                ", nc={:08x}, cnonce={}, qop=auth",
+                session.nc,
+                quote(&cnonce)
+            ));
+        }
+        header.push_str(&format!(", response={}", quote(&response)));
+        if let Some(opaque) = &challenge.opaque {
+            header.push_str(&format!(", opaque={}", quote(opaque)));
+        }
+
+        let mut value = HeaderValue::try_from(header).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHALLENGE: &str = r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=ALGORITHM, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+
+    /// The example from RFC 7616, section 3.9.1.
+    fn rfc_response(algorithm: &str) -> String {
+        let challenge = Challenge::parse(&CHALLENGE.replace("ALGORITHM", algorithm)).unwrap();
+        response(
+            &challenge,
+            "Mufasa",
+            "Circle of Life",
+            &Method::GET,
+            "/dir/index.html",
+            1,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+    }
+
+    #[test]
+    fn rfc7616_md5() {
+        assert_eq!(rfc_response("MD5"), "8ca523f5e9506fed4657c9700eebdbec");
+    }
+
+    #[test]
+    fn rfc7616_sha256() {
+        assert_eq!(
+            rfc_response("SHA-256"),
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+        );
+    }
+
+    #[test]
+    fn parse_challenges() {
+        let challenge = Challenge::parse(r#"Digest realm="a, b", nonce="n", stale=TRUE"#).unwrap();
+        assert_eq!(challenge.realm, "a, b");
+        assert_eq!(challenge.algorithm, Algorithm::Md5);
+        assert!(!challenge.qop);
+        assert!(challenge.stale);
+
+        assert!(Challenge::parse(r#"Basic realm="a""#).is_none());
+        assert!(Challenge::parse(r#"Digest realm="a", nonce="n", qop="auth-int""#).is_none());
+        assert!(Challenge::parse(r#"Digest realm="a", nonce="n", algorithm=SHA-512"#).is_none());
+    }
+
+    #[test]
+    fn authorization_counts_nonces() {
+        let digest = Digest::new("Mufasa".into(), "Circle of Life".into());
+        let uri: Uri = "http://example.org/dir/index.html".parse().unwrap();
+        assert!(digest.authorization(&Method::GET, &uri).is_none());
+
+        digest.challenge(Challenge::parse(&CHALLENGE.replace("ALGORITHM", "MD5")).unwrap());
+        let first = digest.authorization(&Method::GET, &uri).unwrap();
+        let first = first.to_str().unwrap();
+        assert!(first.starts_with(r#"Digest username="Mufasa", realm="http-auth@example.org""#));
+        assert!(first.contains(r#"uri="/dir/index.html""#));
+        assert!(first.contains("nc=00000001"));
+        assert!(first.contains(r#"opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#));
+
+        let second = digest.authorization(&Method::GET, &uri).unwrap();
+        assert!(second.to_str().unwrap().contains("nc=00000002"));
+    }
+}
@@ -0,0 +1,762 @@
+//! Client authentication, scoped per host.
+//!
+//! The [`AuthLayer`] adds credentials to requests for the scheme and authority they
+//! are configured for, so credentials are never sent to other hosts or ports, or over
+//! `http` when they were configured for `https`, including when following redirects.
+//! Requests which already have an `Authorization` header are sent unchanged.
+//!
+//! Supported credentials are:
+//!
+//! - HTTP Basic authentication, with [`Credentials::basic`]. Basic credentials are
+//!   sent in the clear, so they are only sent over `https`, unless
+//!   [`AuthLayer::allow_basic_over_http`] is set.
+//! - Static bearer tokens, with [`Credentials::bearer`].
+//! - Bearer tokens from a [`TokenProvider`], which are refreshed before they expire,
+//!   with [`Credentials::token`].
+//! - RFC 7616 digest authentication, with [`Credentials::digest`]. Digest
+//!   authentication requires a `401 Unauthorized` round trip to receive the challenge,
+//!   so the request is only resent when its body can be replayed (see
+//!   [`Body::replayable`](crate::Body::replayable)).
+//!
+//! ```
+//! # use hyperdriver::client::auth::{AuthLayer, Credentials};
+//! use http::uri::Scheme;
+//!
+//! let auth = AuthLayer::new()
+//!     .with_credentials(
+//!         Scheme::HTTPS,
+//!         "api.example.com".parse().unwrap(),
+//!         Credentials::basic("user", Some("password")),
+//!     )
+//!     .with_credentials(
+//!         Scheme::HTTP,
+//!         "files.example.com:8080".parse().unwrap(),
+//!         Credentials::digest("user", "password"),
+//!     );
+//!
+//! let client = hyperdriver::Client::build_tcp_http().with_auth(auth).build();
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use base64::Engine as _;
+use futures_util::future::{BoxFuture, Either};
+use http::header::{InvalidHeaderValue, AUTHORIZATION};
+use http::uri::{Authority, Scheme};
+use http::{HeaderValue, StatusCode};
+use tower::{Layer, Service, ServiceExt as _};
+
+use self::digest::{Challenge, Digest};
+use super::Error;
+use crate::util::clone_request;
+use crate::Body;
+
+mod digest;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Tokens are refreshed in the background when they expire within this margin.
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// The value of an `Authorization` header for HTTP Basic authentication.
+pub(crate) fn basic(username: &str, password: Option<&str>) -> HeaderValue {
+    let credentials = format!("{username}:{}", password.unwrap_or_default());
+    let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+    let mut value =
+        HeaderValue::try_from(format!("Basic {encoded}")).expect("base64 is a valid header value");
+    value.set_sensitive(true);
+    value
+}
+
+/// The value of an `Authorization` header for a bearer token.
+pub(crate) fn bearer(token: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+    let mut value = HeaderValue::try_from(format!("Bearer {token}"))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+/// A bearer token, which may expire.
+#[derive(Clone)]
+pub struct Token {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl Token {
+    /// Create a token which doesn't expire.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            expires_at: None,
+        }
+    }
+
+    /// Set when the token expires.
+    pub fn with_expires_at(mut self, expires_at: Instant) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Set how long the token is valid for, from now.
+    pub fn with_expires_in(self, expires_in: Duration) -> Self {
+        self.with_expires_at(Instant::now() + expires_in)
+    }
+
+    /// The token value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// When the token expires, if it does.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    fn expires_within(&self, duration: Duration) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now() + duration)
+    }
+}
+
+/// Provides bearer tokens, for example from an OAuth token endpoint.
+pub trait TokenProvider: fmt::Debug + Send + Sync + 'static {
+    /// Fetch a new token.
+    ///
+    /// This is called when there is no token yet, in the background shortly
+    /// before the current token expires, and when the server rejects the
+    /// current token with `401 Unauthorized`.
+    fn token(&self) -> BoxFuture<'_, Result<Token, BoxError>>;
+}
+
+/// Caches the token from a provider, and refreshes it before it expires.
+#[derive(Debug)]
+struct TokenCache {
+    provider: Arc<dyn TokenProvider>,
+    token: tokio::sync::Mutex<Option<(Token, HeaderValue)>>,
+    refreshing: AtomicBool,
+}
+
+impl TokenCache {
+    async fn authorization(self: &Arc<Self>) -> Result<HeaderValue, BoxError> {
+        let mut current = self.token.lock().await;
+
+        if let Some((token, header)) = &*current {
+            if !token.expires_within(Duration::ZERO) {
+                if token.expires_within(REFRESH_MARGIN)
+                    && !self.refreshing.swap(true, Ordering::AcqRel)
+                {
+                    let cache = self.clone();
+                    tokio::spawn(async move { cache.refresh().await });
+                }
+                return Ok(header.clone());
+            }
+        }
+
+        let token = self.provider.token().await?;
+        let header = bearer(token.value())?;
+        *current = Some((token, header.clone()));
+        Ok(header)
+    }
+
+    async fn refresh(&self) {
+        match self.provider.token().await {
+            Ok(token) => match bearer(token.value()) {
+                Ok(header) => *self.token.lock().await = Some((token, header)),
+                Err(error) => tracing::warn!(%error, "refreshed token is not a valid header"),
+            },
+            Err(error) => tracing::warn!(%error, "failed to refresh token"),
+        }
+        self.refreshing.store(false, Ordering::Release);
+    }
+
+    /// Discard the token if it is still the one which was rejected.
+    async fn reject(&self, rejected: &HeaderValue) {
+        let mut current = self.token.lock().await;
+        if current
+            .as_ref()
+            .is_some_and(|(_, header)| header == rejected)
+        {
+            *current = None;
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Kind {
+    Basic(HeaderValue),
+    Header(HeaderValue),
+    Digest { username: String, password: String },
+    Token(Arc<dyn TokenProvider>),
+}
+
+/// Credentials used to authenticate requests to a host.
+#[derive(Clone)]
+pub struct Credentials {
+    kind: Kind,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match &self.kind {
+            Kind::Basic(_) => "Basic",
+            Kind::Header(_) => "Header",
+            Kind::Digest { .. } => "Digest",
+            Kind::Token(_) => "Token",
+        };
+        f.debug_tuple("Credentials").field(&kind).finish()
+    }
+}
+
+impl Credentials {
+    /// HTTP Basic authentication.
+    pub fn basic(username: &str, password: Option<&str>) -> Self {
+        Self {
+            kind: Kind::Basic(basic(username, password)),
+        }
+    }
+
+    /// A static bearer token.
+    pub fn bearer(token: &str) -> Result<Self, InvalidHeaderValue> {
+        Ok(Self {
+            kind: Kind::Header(bearer(token)?),
+        })
+    }
+
+    /// Bearer tokens fetched from a provider.
+    pub fn token(provider: impl TokenProvider) -> Self {
+        Self {
+            kind: Kind::Token(Arc::new(provider)),
+        }
+    }
+
+    /// RFC 7616 digest authentication, with the MD5 or SHA-256 algorithms.
+    pub fn digest(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            kind: Kind::Digest {
+                username: username.into(),
+                password: password.into(),
+            },
+        }
+    }
+}
+
+/// Credentials for a host, along with their state.
+#[derive(Debug, Clone)]
+enum HostAuth {
+    Basic(HeaderValue),
+    Header(HeaderValue),
+    Digest(Arc<Digest>),
+    Token(Arc<TokenCache>),
+}
+
+impl From<Credentials> for HostAuth {
+    fn from(credentials: Credentials) -> Self {
+        match credentials.kind {
+            Kind::Basic(value) => HostAuth::Basic(value),
+            Kind::Header(value) => HostAuth::Header(value),
+            Kind::Digest { username, password } => {
+                HostAuth::Digest(Arc::new(Digest::new(username, password)))
+            }
+            Kind::Token(provider) => HostAuth::Token(Arc::new(TokenCache {
+                provider,
+                token: tokio::sync::Mutex::new(None),
+                refreshing: AtomicBool::new(false),
+            })),
+        }
+    }
+}
+
+/// A layer which authenticates requests with per-host credentials.
+#[derive(Debug, Clone, Default)]
+pub struct AuthLayer {
+    hosts: Arc<HashMap<(Scheme, Authority), HostAuth>>,
+    basic_over_http: bool,
+}
+
+impl AuthLayer {
+    /// Create a new layer, with no credentials.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use these credentials for requests with this scheme and authority.
+    ///
+    /// The authority must match the request URI exactly, including the port, so
+    /// `example.com` and `example.com:8080` need their own credentials.
+    pub fn with_credentials(
+        mut self,
+        scheme: Scheme,
+        authority: Authority,
+        credentials: Credentials,
+    ) -> Self {
+        Arc::make_mut(&mut self.hosts).insert((scheme, authority), credentials.into());
+        self
+    }
+
+    /// Send HTTP Basic credentials over plain `http`.
+    ///
+    /// By default, Basic credentials configured for an `http` origin are not sent,
+    /// since anyone who can see the request can read the password.
+    pub fn allow_basic_over_http(mut self) -> Self {
+        self.basic_over_http = true;
+        self
+    }
+
+    /// Returns true if credentials are configured for any host.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    fn lookup(&self, uri: &http::Uri) -> Option<&HostAuth> {
+        let key = (uri.scheme()?.clone(), uri.authority()?.clone());
+        let auth = self.hosts.get(&key)?;
+
+        if matches!(auth, HostAuth::Basic(_)) && key.0 != Scheme::HTTPS && !self.basic_over_http {
+            tracing::warn!(
+                authority = %key.1,
+                "not sending basic credentials over {}", key.0
+            );
+            return None;
+        }
+        Some(auth)
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            auth: self.clone(),
+        }
+    }
+}
+
+/// A service which authenticates requests with per-host credentials.
+///
+/// See [`AuthLayer`].
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    auth: AuthLayer,
+}
+
+type AuthFuture<F, E> = Either<F, BoxFuture<'static, Result<http::Response<Body>, E>>>;
+
+impl<S> Service<http::Request<Body>> for AuthService<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<Error> + Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = AuthFuture<S::Future, S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<Body>) -> Self::Future {
+        if req.headers().contains_key(AUTHORIZATION) {
+            return Either::Left(self.inner.call(req));
+        }
+
+        let auth = match self.auth.lookup(req.uri()) {
+            Some(HostAuth::Basic(value) | HostAuth::Header(value)) => {
+                req.headers_mut().insert(AUTHORIZATION, value.clone());
+                return Either::Left(self.inner.call(req));
+            }
+            Some(auth) => auth.clone(),
+            None => return Either::Left(self.inner.call(req)),
+        };
+
+        // Use the service which is ready for the first request.
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+
+        Either::Right(Box::pin(async move {
+            match auth {
+                HostAuth::Token(cache) => send_token(cache, inner, req).await,
+                HostAuth::Digest(digest) => send_digest(digest, inner, req).await,
+                HostAuth::Basic(_) | HostAuth::Header(_) => {
+                    unreachable!("header credentials are sent directly")
+                }
+            }
+        }))
+    }
+}
+
+async fn send_token<S>(
+    cache: Arc<TokenCache>,
+    mut inner: S,
+    mut req: http::Request<Body>,
+) -> Result<http::Response<Body>, S::Error>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>>,
+    S::Error: From<Error>,
+{
+    let retry = clone_request(&req);
+
+    let header = cache.authorization().await.map_err(Error::Auth)?;
+    req.headers_mut().insert(AUTHORIZATION, header.clone());
+    let response = inner.call(req).await?;
+
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let Some(mut retry) = retry else {
+        return Ok(response);
+    };
+
+    // The token may have been revoked, so try once more with a new token.
+    cache.reject(&header).await;
+    let header = cache.authorization().await.map_err(Error::Auth)?;
+    retry.headers_mut().insert(AUTHORIZATION, header);
+    inner.oneshot(retry).await
+}
+
+async fn send_digest<S>(
+    digest: Arc<Digest>,
+    mut inner: S,
+    mut req: http::Request<Body>,
+) -> Result<http::Response<Body>, S::Error>
+where
+    S: Service<http::Request<Body>, Response = http::Response<Body>>,
+{
+    let retry = clone_request(&req);
+
+    let preemptive = digest.authorization(req.method(), req.uri());
+    if let Some(header) = &preemptive {
+        req.headers_mut().insert(AUTHORIZATION, header.clone());
+    }
+    let response = inner.call(req).await?;
+
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let Some(challenge) = Challenge::from_headers(response.headers()) else {
+        return Ok(response);
+    };
+
+    // The credentials were rejected, rather than the nonce having expired.
+    if preemptive.is_some() && !challenge.stale {
+        return Ok(response);
+    }
+
+    let Some(mut retry) = retry else {
+        tracing::debug!("digest challenge received, but the request body can't be replayed");
+        return Ok(response);
+    };
+
+    digest.challenge(challenge);
+    let Some(header) = digest.authorization(retry.method(), retry.uri()) else {
+        return Ok(response);
+    };
+    retry.headers_mut().insert(AUTHORIZATION, header);
+    inner.oneshot(retry).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    type Seen = Arc<Mutex<Vec<Option<String>>>>;
+
+    fn request(uri: &str) -> http::Request<Body> {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    /// A service which records the authorization header, and responds with the
+    /// status from the handler.
+    fn service<F>(
+        handler: F,
+    ) -> (
+        impl Service<
+                http::Request<Body>,
+                Response = http::Response<Body>,
+                Error = Error,
+                Future = impl Send,
+            > + Clone
+            + Send,
+        Seen,
+    )
+    where
+        F: Fn(Option<&str>) -> http::Response<Body> + Clone + Send + Sync + 'static,
+    {
+        let seen: Seen = Default::default();
+        let record = seen.clone();
+        let service = tower::service_fn(move |req: http::Request<Body>| {
+            let header = req
+                .headers()
+                .get(AUTHORIZATION)
+                .map(|value| value.to_str().unwrap().to_owned());
+            let response = handler(header.as_deref());
+            record.lock().unwrap().push(header);
+            async move { Ok::<_, Error>(response) }
+        });
+        (service, seen)
+    }
+
+    fn status(status: StatusCode) -> http::Response<Body> {
+        http::Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn basic_scoped_to_origin() {
+        let (service, seen) = service(|_| status(StatusCode::OK));
+        let layer = AuthLayer::new().with_credentials(
+            Scheme::HTTPS,
+            "example.com".parse().unwrap(),
+            Credentials::basic("Aladdin", Some("open sesame")),
+        );
+        let service = layer.layer(service);
+
+        for uri in [
+            "https://example.com/",
+            "https://EXAMPLE.com/a",
+            "http://example.com/",
+            "https://example.com:8443/",
+            "https://other.com/",
+        ] {
+            service.clone().oneshot(request(uri)).await.unwrap();
+        }
+
+        let mut explicit = request("https://example.com/");
+        explicit
+            .headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer mine"));
+        service.oneshot(explicit).await.unwrap();
+
+        let basic = Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_owned());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                basic.clone(),
+                basic,
+                None,
+                None,
+                None,
+                Some("Bearer mine".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn basic_over_http_is_opt_in() {
+        let credentials = Credentials::basic("Aladdin", Some("open sesame"));
+        let layer = AuthLayer::new().with_credentials(
+            Scheme::HTTP,
+            "example.com".parse().unwrap(),
+            credentials,
+        );
+
+        let (inner, seen) = service(|_| status(StatusCode::OK));
+        layer
+            .clone()
+            .layer(inner)
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![None]);
+
+        let (inner, seen) = service(|_| status(StatusCode::OK));
+        layer
+            .allow_basic_over_http()
+            .layer(inner)
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_owned())]
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingProvider {
+        calls: AtomicUsize,
+        expires_in: Option<Duration>,
+    }
+
+    impl TokenProvider for Arc<CountingProvider> {
+        fn token(&self) -> BoxFuture<'_, Result<Token, BoxError>> {
+            Box::pin(async move {
+                let call = self.calls.fetch_add(1, Ordering::SeqCst);
+                let token = Token::new(format!("token-{call}"));
+                Ok(match self.expires_in {
+                    Some(expires_in) => token.with_expires_in(expires_in),
+                    None => token,
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn token_rejected_is_refreshed() {
+        let (service, seen) = service(|header| match header {
+            Some("Bearer token-0") => status(StatusCode::UNAUTHORIZED),
+            _ => status(StatusCode::OK),
+        });
+        let provider = Arc::new(CountingProvider::default());
+        let layer = AuthLayer::new().with_credentials(
+            Scheme::HTTP,
+            "example.com".parse().unwrap(),
+            Credentials::token(provider.clone()),
+        );
+        let service = layer.layer(service);
+
+        let response = service
+            .clone()
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        service
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                Some("Bearer token-0".to_owned()),
+                Some("Bearer token-1".to_owned()),
+                Some("Bearer token-1".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn token_refreshed_before_expiry() {
+        let (service, seen) = service(|_| status(StatusCode::OK));
+        let provider = Arc::new(CountingProvider {
+            expires_in: Some(REFRESH_MARGIN / 2),
+            ..Default::default()
+        });
+        let layer = AuthLayer::new().with_credentials(
+            Scheme::HTTP,
+            "example.com".parse().unwrap(),
+            Credentials::token(provider.clone()),
+        );
+        let service = layer.layer(service);
+
+        service
+            .clone()
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+        service
+            .clone()
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+
+        // The second request used the current token, and started a refresh.
+        for _ in 0..100 {
+            if provider.calls.load(Ordering::SeqCst) == 2 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        service
+            .oneshot(request("http://example.com/"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                Some("Bearer token-0".to_owned()),
+                Some("Bearer token-0".to_owned()),
+                Some("Bearer token-1".to_owned())
+            ]
+        );
+    }
+
+    fn digest_challenge(header: Option<&str>) -> http::Response<Body> {
+        match header {
+            Some(header) if header.starts_with("Digest ") => status(StatusCode::OK),
+            _ => http::Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(
+                    http::header::WWW_AUTHENTICATE,
+                    r#"Digest realm="test", qop="auth", nonce="abc""#,
+                )
+                .body(Body::empty())
+                .unwrap(),
+        }
+    }
+
+    fn digest_layer() -> AuthLayer {
+        AuthLayer::new().with_credentials(
+            Scheme::HTTP,
+            "example.com".parse().unwrap(),
+            Credentials::digest("user", "password"),
+        )
+    }
+
+    #[tokio::test]
+    async fn digest_round_trip() {
+        let (service, seen) = service(digest_challenge);
+        let service = digest_layer().layer(service);
+
+        let response = service
+            .clone()
+            .oneshot(request("http://example.com/a"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The challenge is remembered, and answered without a round trip.
+        let response = service
+            .oneshot(request("http://example.com/b"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert!(seen[0].is_none());
+        assert!(seen[1].as_ref().unwrap().contains("nc=00000001"));
+        assert!(seen[2].as_ref().unwrap().contains("nc=00000002"));
+    }
+
+    #[tokio::test]
+    async fn digest_streaming_body() {
+        let (service, seen) = service(digest_challenge);
+        let service = digest_layer().layer(service);
+
+        // Streaming bodies can't be resent, so the challenge is returned.
+        let body = Body::new(http_body_util::StreamBody::new(
+            futures_util::stream::empty::<Result<http_body::Frame<bytes::Bytes>, BoxError>>(),
+        ));
+        let request = http::Request::post("http://example.com/")
+            .body(body)
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(*seen.lock().unwrap(), vec![None]);
+    }
+}
@@ -0,0 +1,575 @@
+//! Client-side load balancing across multiple endpoints.
+//!
+//! A [`LoadBalancerLayer`] maps a logical authority, such as `api.internal`, to a set of
+//! concrete endpoints, such as `10.0.0.1:8080` and `10.0.0.2:8080`. Requests to the logical
+//! authority are sent to one of the endpoints, chosen with a [`Strategy`]. The chosen
+//! endpoint is added to the request extensions as an [`Endpoint`], and the client's TCP
+//! transport connects to it instead of resolving the logical authority. Custom transports
+//! must connect to [`transport::endpoint`](crate::client::conn::transport::endpoint) to
+//! support load balancing; connections through transports which don't fail with
+//! [`UnsupportedEndpoint`](crate::client::conn::transport::UnsupportedEndpoint). The request URI is
+//! left unchanged, so the `Host` header, TLS server name and certificate verification, and
+//! HSTS and Alt-Svc caching all use the logical authority, while connections are still
+//! pooled per endpoint. The chosen endpoint is also recorded in the response extensions.
+//!
+//! Endpoints which fail repeatedly are ejected for a while, see [`OutlierDetection`].
+//! Requests to authorities which are not registered with the load balancer are sent
+//! unchanged.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http::uri::Authority;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use crate::util::random;
+
+/// How an endpoint is chosen for each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Use each endpoint in turn.
+    #[default]
+    RoundRobin,
+
+    /// Choose an endpoint at random.
+    Random,
+
+    /// Choose the endpoint with the fewest requests in flight.
+    LeastOutstanding,
+
+    /// Choose two endpoints at random, and use the one with fewer requests in flight.
+    PowerOfTwoChoices,
+}
+
+/// Temporarily eject endpoints which fail repeatedly.
+///
+/// Errors, and responses with a `5xx` status, count as failures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlierDetection {
+    consecutive_failures: usize,
+    ejection: Duration,
+    max_ejected: f64,
+}
+
+impl Default for OutlierDetection {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            ejection: Duration::from_secs(30),
+            max_ejected: 0.5,
+        }
+    }
+}
+
+impl OutlierDetection {
+    /// Create a new outlier detection configuration.
+    ///
+    /// By default, endpoints are ejected for 30 seconds after 5 consecutive failures,
+    /// and at most half of the endpoints are ejected at any time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of consecutive failures which ejects an endpoint.
+    pub fn with_consecutive_failures(mut self, failures: usize) -> Self {
+        self.consecutive_failures = failures.max(1);
+        self
+    }
+
+    /// Set how long an endpoint is ejected for.
+    pub fn with_ejection_duration(mut self, duration: Duration) -> Self {
+        self.ejection = duration;
+        self
+    }
+
+    /// Set the largest fraction of endpoints, between 0 and 1, which may be
+    /// ejected at once. At least one endpoint can always be ejected.
+    pub fn with_max_ejected(mut self, fraction: f64) -> Self {
+        self.max_ejected = fraction.clamp(0.0, 1.0);
+        self
+    }
+}
+
+/// The endpoint chosen for a request, inserted into the request and response
+/// extensions by [`LoadBalancer`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint(pub Authority);
+
+#[derive(Debug)]
+struct EndpointState {
+    authority: Authority,
+    outstanding: usize,
+    consecutive_failures: usize,
+    ejected_until: Option<Instant>,
+}
+
+impl EndpointState {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+}
+
+/// The endpoints for one logical authority.
+#[derive(Debug)]
+struct Endpoints {
+    endpoints: Mutex<Vec<EndpointState>>,
+    next: AtomicUsize,
+}
+
+impl Endpoints {
+    fn new(endpoints: Vec<Authority>) -> Self {
+        Self {
+            endpoints: Mutex::new(
+                endpoints
+                    .into_iter()
+                    .map(|authority| EndpointState {
+                        authority,
+                        outstanding: 0,
+                        consecutive_failures: 0,
+                        ejected_until: None,
+                    })
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Choose an endpoint, returning its index and authority.
+    fn choose(&self, strategy: Strategy) -> Option<(usize, Authority)> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if endpoints.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut candidates: Vec<usize> = (0..endpoints.len())
+            .filter(|&index| !endpoints[index].is_ejected(now))
+            .collect();
+        if candidates.is_empty() {
+            // Every endpoint is ejected, so fall back to using all of them.
+            candidates = (0..endpoints.len()).collect();
+        }
+
+        let random_index = |n: usize| ((random() * n as f64) as usize).min(n - 1);
+
+        let index = match strategy {
+            Strategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            Strategy::Random => candidates[random_index(candidates.len())],
+            Strategy::LeastOutstanding => {
+                // Start at a rotating offset, so that ties are spread across endpoints.
+                let offset = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..candidates.len())
+                    .map(|i| candidates[(offset + i) % candidates.len()])
+                    .min_by_key(|&index| endpoints[index].outstanding)
+                    .expect("candidates is not empty")
+            }
+            Strategy::PowerOfTwoChoices => {
+                let first = random_index(candidates.len());
+                let mut second = random_index(candidates.len().saturating_sub(1).max(1));
+                if candidates.len() > 1 && second >= first {
+                    second += 1;
+                }
+                let (a, b) = (candidates[first], candidates[second]);
+                if endpoints[b].outstanding < endpoints[a].outstanding {
+                    b
+                } else {
+                    a
+                }
+            }
+        };
+
+        let endpoint = &mut endpoints[index];
+        endpoint.outstanding += 1;
+        Some((index, endpoint.authority.clone()))
+    }
+
+    fn complete(&self, index: usize, outcome: Option<bool>, outliers: Option<&OutlierDetection>) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let total = endpoints.len();
+        let now = Instant::now();
+        let ejected = endpoints.iter().filter(|e| e.is_ejected(now)).count();
+
+        let endpoint = &mut endpoints[index];
+        endpoint.outstanding = endpoint.outstanding.saturating_sub(1);
+
+        match outcome {
+            Some(true) => endpoint.consecutive_failures = 0,
+            Some(false) => {
+                endpoint.consecutive_failures += 1;
+                if let Some(outliers) = outliers {
+                    let max_ejected = ((total as f64 * outliers.max_ejected) as usize).max(1);
+                    if endpoint.consecutive_failures >= outliers.consecutive_failures
+                        && !endpoint.is_ejected(now)
+                        && ejected < max_ejected
+                    {
+                        tracing::debug!(endpoint = %endpoint.authority, "ejecting endpoint");
+                        endpoint.ejected_until = Some(now + outliers.ejection);
+                        endpoint.consecutive_failures = 0;
+                    }
+                }
+            }
+            // The request was cancelled.
+            None => {}
+        }
+    }
+}
+
+/// Layer which balances requests across endpoints, see the [module documentation](self).
+///
+/// Clones of the layer share the state of each endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct LoadBalancerLayer {
+    services: Arc<HashMap<Authority, Arc<Endpoints>>>,
+    strategy: Strategy,
+    outliers: Option<OutlierDetection>,
+}
+
+impl LoadBalancerLayer {
+    /// Create a new load balancer with no services.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Balance requests to the `logical` authority across `endpoints`.
+    pub fn with_service<I>(mut self, logical: Authority, endpoints: I) -> Self
+    where
+        I: IntoIterator<Item = Authority>,
+    {
+        Arc::make_mut(&mut self.services).insert(
+            logical,
+            Arc::new(Endpoints::new(endpoints.into_iter().collect())),
+        );
+        self
+    }
+
+    /// Set the strategy used to choose an endpoint.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Eject endpoints which fail repeatedly.
+    pub fn with_outlier_detection(mut self, outliers: OutlierDetection) -> Self {
+        self.outliers = Some(outliers);
+        self
+    }
+
+    /// The strategy used to choose an endpoint.
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// The endpoints of the `logical` authority which are currently ejected.
+    pub fn ejected(&self, logical: &Authority) -> Vec<Authority> {
+        let now = Instant::now();
+        self.services
+            .get(logical)
+            .map(|service| {
+                service
+                    .endpoints
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|endpoint| endpoint.is_ejected(now))
+                    .map(|endpoint| endpoint.authority.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl<S> Layer<S> for LoadBalancerLayer {
+    type Service = LoadBalancer<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadBalancer {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// Service which balances requests across endpoints, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct LoadBalancer<S> {
+    inner: S,
+    config: LoadBalancerLayer,
+}
+
+impl<S> LoadBalancer<S> {
+    /// Create a new load balancer wrapping `inner`.
+    pub fn new(inner: S, config: LoadBalancerLayer) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<S, BIn, BOut> Service<http::Request<BIn>> for LoadBalancer<S>
+where
+    S: Service<http::Request<BIn>, Response = http::Response<BOut>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = LoadBalancerFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<BIn>) -> Self::Future {
+        let selected = req.uri().authority().and_then(|logical| {
+            let service = self.config.services.get(logical)?;
+            let (index, endpoint) = service.choose(self.config.strategy)?;
+            Some((logical.clone(), service.clone(), index, endpoint))
+        });
+
+        let Some((logical, service, index, endpoint)) = selected else {
+            return LoadBalancerFuture {
+                inner: self.inner.call(req),
+                guard: None,
+            };
+        };
+
+        req.extensions_mut().insert(Endpoint(endpoint.clone()));
+        tracing::trace!(%logical, %endpoint, "balanced request");
+
+        LoadBalancerFuture {
+            inner: self.inner.call(req),
+            guard: Some(Guard {
+                service,
+                index,
+                endpoint,
+                outliers: self.config.outliers,
+                completed: false,
+            }),
+        }
+    }
+}
+
+/// Releases the endpoint when the request completes or is cancelled.
+struct Guard {
+    service: Arc<Endpoints>,
+    index: usize,
+    endpoint: Authority,
+    outliers: Option<OutlierDetection>,
+    completed: bool,
+}
+
+impl Guard {
+    fn complete(mut self, success: bool) -> Endpoint {
+        self.service
+            .complete(self.index, Some(success), self.outliers.as_ref());
+        self.completed = true;
+        Endpoint(self.endpoint.clone())
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.service
+                .complete(self.index, None, self.outliers.as_ref());
+        }
+    }
+}
+
+/// Future returned by [`LoadBalancer`].
+#[pin_project::pin_project]
+pub struct LoadBalancerFuture<F> {
+    #[pin]
+    inner: F,
+    guard: Option<Guard>,
+}
+
+impl<F> fmt::Debug for LoadBalancerFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadBalancerFuture").finish()
+    }
+}
+
+impl<F, B, E> Future for LoadBalancerFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+{
+    type Output = Result<http::Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut result = futures_util::ready!(this.inner.poll(cx));
+
+        if let Some(guard) = this.guard.take() {
+            let success = result
+                .as_ref()
+                .is_ok_and(|response| !response.status().is_server_error());
+            let endpoint = guard.complete(success);
+            if let Ok(response) = result.as_mut() {
+                response.extensions_mut().insert(endpoint);
+            }
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use http::StatusCode;
+    use tower::ServiceExt as _;
+
+    type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn endpoints() -> Vec<Authority> {
+        vec![
+            Authority::from_static("10.0.0.1:80"),
+            Authority::from_static("10.0.0.2:80"),
+            Authority::from_static("10.0.0.3:80"),
+        ]
+    }
+
+    fn layer(strategy: Strategy) -> LoadBalancerLayer {
+        LoadBalancerLayer::new()
+            .with_service(Authority::from_static("api.internal"), endpoints())
+            .with_strategy(strategy)
+    }
+
+    /// A service which fails requests to `failing`, and echos the URI.
+    fn service(
+        failing: Option<Authority>,
+    ) -> impl Service<
+        http::Request<crate::Body>,
+        Response = http::Response<crate::Body>,
+        Error = BoxError,
+    > + Clone {
+        tower::service_fn(move |req: http::Request<crate::Body>| {
+            let endpoint = req.extensions().get::<Endpoint>().cloned();
+            let failed = failing.is_some() && failing == endpoint.as_ref().map(|e| e.0.clone());
+            let status = if failed {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            let uri = req.uri().clone();
+            async move {
+                let mut response = http::Response::builder()
+                    .status(status)
+                    .body(crate::Body::empty())?;
+                response.extensions_mut().insert(uri);
+                if let Some(endpoint) = endpoint {
+                    response
+                        .headers_mut()
+                        .insert("x-endpoint", endpoint.0.as_str().parse()?);
+                }
+                Ok::<_, BoxError>(response)
+            }
+        })
+    }
+
+    async fn send(
+        service: &LoadBalancer<
+            impl Service<
+                    http::Request<crate::Body>,
+                    Response = http::Response<crate::Body>,
+                    Error = BoxError,
+                > + Clone,
+        >,
+        uri: &str,
+    ) -> http::Response<crate::Body> {
+        let req = http::Request::get(uri).body(crate::Body::empty()).unwrap();
+        service.clone().oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_robin() {
+        let service = layer(Strategy::RoundRobin).layer(service(None));
+
+        let mut chosen = Vec::new();
+        for _ in 0..6 {
+            let response = send(&service, "http://api.internal/path?q=1").await;
+            let uri = response.extensions().get::<http::Uri>().unwrap();
+            assert_eq!(uri, "http://api.internal/path?q=1");
+
+            let endpoint = response.extensions().get::<Endpoint>().unwrap();
+            assert_eq!(response.headers()["x-endpoint"], endpoint.0.as_str());
+            chosen.push(endpoint.0.clone());
+        }
+
+        assert_eq!(&chosen[..3], &endpoints()[..]);
+        assert_eq!(&chosen[3..], &endpoints()[..]);
+    }
+
+    #[tokio::test]
+    async fn unknown_authority_unchanged() {
+        let service = layer(Strategy::Random).layer(service(None));
+        let response = send(&service, "http://example.com/").await;
+        let uri = response.extensions().get::<http::Uri>().unwrap();
+        assert_eq!(uri.authority().unwrap(), "example.com");
+        assert!(response.extensions().get::<Endpoint>().is_none());
+    }
+
+    #[tokio::test]
+    async fn random_strategies_use_endpoints() {
+        for strategy in [
+            Strategy::Random,
+            Strategy::LeastOutstanding,
+            Strategy::PowerOfTwoChoices,
+        ] {
+            let service = layer(strategy).layer(service(None));
+            let mut chosen = HashSet::new();
+            for _ in 0..30 {
+                let response = send(&service, "http://api.internal/").await;
+                chosen.insert(response.extensions().get::<Endpoint>().unwrap().clone());
+            }
+            assert!(chosen.len() > 1, "{strategy:?} only used {chosen:?}");
+        }
+    }
+
+    #[test]
+    fn least_outstanding() {
+        let endpoints = Endpoints::new(endpoints());
+        let (first, _) = endpoints.choose(Strategy::LeastOutstanding).unwrap();
+        let (second, _) = endpoints.choose(Strategy::LeastOutstanding).unwrap();
+        let (third, _) = endpoints.choose(Strategy::LeastOutstanding).unwrap();
+        let mut chosen = vec![first, second, third];
+        chosen.sort();
+        assert_eq!(chosen, vec![0, 1, 2]);
+
+        endpoints.complete(1, Some(true), None);
+        let (index, _) = endpoints.choose(Strategy::LeastOutstanding).unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[tokio::test]
+    async fn outlier_ejection() {
+        let failing = Authority::from_static("10.0.0.2:80");
+        let layer = layer(Strategy::RoundRobin).with_outlier_detection(
+            OutlierDetection::new()
+                .with_consecutive_failures(2)
+                .with_ejection_duration(Duration::from_secs(60)),
+        );
+        let service = layer.layer(service(Some(failing.clone())));
+        let logical = Authority::from_static("api.internal");
+
+        for _ in 0..6 {
+            send(&service, "http://api.internal/").await;
+        }
+        assert_eq!(layer.ejected(&logical), vec![failing.clone()]);
+
+        for _ in 0..6 {
+            let response = send(&service, "http://api.internal/").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_ne!(response.extensions().get::<Endpoint>().unwrap().0, failing);
+        }
+    }
+}
@@ -0,0 +1,640 @@
+//! Per-host circuit breaking for clients.
+//!
+//! A circuit breaker tracks the outcome of requests to each host, keyed by scheme and
+//! authority like the connection pool. When a host fails too often, its circuit "opens",
+//! and requests to it fail immediately with [`Error::CircuitOpen`] instead of waiting
+//! for connection timeouts. After a cool-down period, a limited number of "half-open"
+//! probe requests are let through: if they succeed the circuit closes again, and if any
+//! of them fails the circuit opens for another cool-down period.
+//!
+//! Errors, and responses with a `5xx` status, count as failures.
+//!
+//! Circuits are kept per host, not per endpoint: the client's breaker sits above its
+//! [load balancer](crate::client::balance), so a circuit covers every endpoint of a
+//! logical authority, and opens when the host as a whole is failing. Use
+//! [`OutlierDetection`](crate::client::balance::OutlierDetection) to stop sending
+//! requests to individual endpoints which fail.
+//!
+//! Closed circuits which haven't been used for a while (five minutes by default, see
+//! [`CircuitBreakerLayer::with_idle_timeout`]) are forgotten, so that contacting many
+//! hosts doesn't grow the breaker without bound. Open and half-open circuits are never
+//! forgotten, however long they go unused.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use super::pool::Key;
+use super::Error;
+
+/// The state of a host's circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CircuitState {
+    /// Requests are sent normally.
+    Closed,
+
+    /// Requests fail immediately.
+    Open,
+
+    /// A limited number of probe requests are sent, to check if the host has recovered.
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CircuitState::Closed => f.write_str("closed"),
+            CircuitState::Open => f.write_str("open"),
+            CircuitState::HalfOpen => f.write_str("half-open"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    consecutive_failures: usize,
+    error_rate: f64,
+    window: usize,
+    open_duration: Duration,
+    half_open_requests: usize,
+    idle_timeout: Duration,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { in_flight: usize, successes: usize },
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    consecutive_failures: usize,
+    outcomes: VecDeque<bool>,
+    last_used: Instant,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self {
+            state: State::Closed,
+            consecutive_failures: 0,
+            outcomes: VecDeque::new(),
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Returns true if the circuit is closed, and hasn't been used within `timeout`.
+    ///
+    /// Forgetting such a circuit only loses its recent history.
+    fn is_idle(&self, now: Instant, timeout: Duration) -> bool {
+        matches!(self.state, State::Closed) && now.duration_since(self.last_used) >= timeout
+    }
+
+    fn state(&self) -> CircuitState {
+        match self.state {
+            State::Closed => CircuitState::Closed,
+            State::Open { until } if until <= Instant::now() => CircuitState::HalfOpen,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn open(&mut self, config: &Config) {
+        self.state = State::Open {
+            until: Instant::now() + config.open_duration,
+        };
+        self.consecutive_failures = 0;
+        self.outcomes.clear();
+    }
+
+    /// Try to send a request, returning whether the request is a half-open probe.
+    fn acquire(&mut self, config: &Config) -> Option<bool> {
+        self.last_used = Instant::now();
+        if let State::Open { until } = self.state {
+            if until > Instant::now() {
+                return None;
+            }
+            self.state = State::HalfOpen {
+                in_flight: 0,
+                successes: 0,
+            };
+        }
+
+        match &mut self.state {
+            State::Closed => Some(false),
+            State::HalfOpen {
+                in_flight,
+                successes,
+            } => {
+                if *in_flight + *successes >= config.half_open_requests {
+                    None
+                } else {
+                    *in_flight += 1;
+                    Some(true)
+                }
+            }
+            State::Open { .. } => unreachable!("open circuits were handled above"),
+        }
+    }
+
+    fn record(&mut self, config: &Config, probe: bool, success: bool) {
+        self.last_used = Instant::now();
+        if probe {
+            let State::HalfOpen {
+                in_flight,
+                successes,
+            } = &mut self.state
+            else {
+                // The circuit was re-opened by another probe.
+                return;
+            };
+
+            *in_flight = in_flight.saturating_sub(1);
+            if !success {
+                self.open(config);
+            } else {
+                *successes += 1;
+                if *successes >= config.half_open_requests {
+                    self.state = State::Closed;
+                }
+            }
+            return;
+        }
+
+        if !matches!(self.state, State::Closed) {
+            return;
+        }
+
+        if self.outcomes.len() >= config.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+
+        if success {
+            self.consecutive_failures = 0;
+            return;
+        }
+
+        self.consecutive_failures += 1;
+        let failures = self.outcomes.iter().filter(|ok| !**ok).count();
+        let rate = failures as f64 / self.outcomes.len() as f64;
+        if self.consecutive_failures >= config.consecutive_failures
+            || (self.outcomes.len() >= config.window && rate >= config.error_rate)
+        {
+            self.open(config);
+        }
+    }
+
+    fn release(&mut self, probe: bool) {
+        if let (true, State::HalfOpen { in_flight, .. }) = (probe, &mut self.state) {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Layer which adds per-host circuit breaking, see the [module documentation](self).
+///
+/// Clones of the layer share their circuits, so a clone can be kept to report the
+/// state of each host with [`CircuitBreakerLayer::state`] and
+/// [`CircuitBreakerLayer::circuits`].
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerLayer {
+    config: Config,
+    circuits: Arc<Mutex<HashMap<Key, Circuit>>>,
+}
+
+impl Default for CircuitBreakerLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreakerLayer {
+    /// Create a new circuit breaker.
+    ///
+    /// By default, a circuit opens after 5 consecutive failures, or when half of the
+    /// last 20 requests failed. It stays open for 10 seconds, and then closes after
+    /// one successful probe request.
+    pub fn new() -> Self {
+        Self {
+            config: Config {
+                consecutive_failures: 5,
+                error_rate: 0.5,
+                window: 20,
+                open_duration: Duration::from_secs(10),
+                half_open_requests: 1,
+                idle_timeout: Duration::from_secs(300),
+            },
+            circuits: Default::default(),
+        }
+    }
+
+    /// Set the number of consecutive failures which opens a circuit.
+    pub fn with_consecutive_failures(mut self, failures: usize) -> Self {
+        self.config.consecutive_failures = failures.max(1);
+        self
+    }
+
+    /// Set the error rate, between 0 and 1, over the last `window` requests
+    /// which opens a circuit.
+    pub fn with_error_rate(mut self, error_rate: f64, window: usize) -> Self {
+        self.config.error_rate = error_rate.clamp(0.0, 1.0);
+        self.config.window = window.max(1);
+        self
+    }
+
+    /// Set how long a circuit stays open before probe requests are sent.
+    pub fn with_open_duration(mut self, duration: Duration) -> Self {
+        self.config.open_duration = duration;
+        self
+    }
+
+    /// Set the number of probe requests which must succeed to close a half-open circuit.
+    pub fn with_half_open_requests(mut self, requests: usize) -> Self {
+        self.config.half_open_requests = requests.max(1);
+        self
+    }
+
+    /// Set how long a closed circuit can go unused before it is forgotten.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    /// The state of the circuit for the host of `uri`.
+    ///
+    /// Hosts which have not been contacted are reported as closed.
+    pub fn state(&self, uri: &http::Uri) -> CircuitState {
+        let Ok(key) = Key::try_from(uri.clone()) else {
+            return CircuitState::Closed;
+        };
+
+        self.circuits
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map_or(CircuitState::Closed, Circuit::state)
+    }
+
+    /// The state of the circuit for every host which has been contacted,
+    /// formatted as `scheme://authority`.
+    pub fn circuits(&self) -> Vec<(String, CircuitState)> {
+        self.circuits
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, circuit)| (key.to_string(), circuit.state()))
+            .collect()
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            breaker: self.clone(),
+        }
+    }
+}
+
+/// Service which adds per-host circuit breaking, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: CircuitBreakerLayer,
+}
+
+impl<S> CircuitBreakerService<S> {
+    /// Create a new circuit breaking service wrapping `inner`.
+    pub fn new(inner: S, breaker: CircuitBreakerLayer) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+impl<S, BIn, BOut> Service<http::Request<BIn>> for CircuitBreakerService<S>
+where
+    S: Service<http::Request<BIn>, Response = http::Response<BOut>>,
+    S::Error: From<Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = CircuitBreakerFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<BIn>) -> Self::Future {
+        let Ok(key) = Key::try_from(req.uri().clone()) else {
+            return CircuitBreakerFuture {
+                inner: Some(self.inner.call(req)),
+                permit: None,
+                error: None,
+            };
+        };
+
+        let probe = {
+            let config = &self.breaker.config;
+            let mut circuits = self.breaker.circuits.lock().unwrap();
+            if !circuits.contains_key(&key) {
+                // Only prune when adding a host, so that the map is bounded by the
+                // number of hosts contacted within the idle timeout. Only closed
+                // circuits are idle, so open circuits are never forgotten.
+                let now = Instant::now();
+                circuits.retain(|_, circuit| !circuit.is_idle(now, config.idle_timeout));
+            }
+
+            circuits
+                .entry(key.clone())
+                .or_insert_with(Circuit::new)
+                .acquire(config)
+        };
+
+        match probe {
+            Some(probe) => CircuitBreakerFuture {
+                inner: Some(self.inner.call(req)),
+                permit: Some(Permit {
+                    key,
+                    probe,
+                    breaker: self.breaker.clone(),
+                    recorded: false,
+                }),
+                error: None,
+            },
+            None => {
+                tracing::debug!(host = %key, "circuit breaker open");
+                CircuitBreakerFuture {
+                    inner: None,
+                    permit: None,
+                    error: Some(Error::CircuitOpen(key.to_string())),
+                }
+            }
+        }
+    }
+}
+
+/// Records the outcome of a request on the circuit it was sent through.
+struct Permit {
+    key: Key,
+    probe: bool,
+    breaker: CircuitBreakerLayer,
+    recorded: bool,
+}
+
+impl Permit {
+    fn record(mut self, success: bool) {
+        let mut circuits = self.breaker.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(&self.key) {
+            circuit.record(&self.breaker.config, self.probe, success);
+        }
+        self.recorded = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.recorded {
+            return;
+        }
+
+        // The request was cancelled before it completed.
+        if let Ok(mut circuits) = self.breaker.circuits.lock() {
+            if let Some(circuit) = circuits.get_mut(&self.key) {
+                circuit.release(self.probe);
+            }
+        }
+    }
+}
+
+/// Future returned by [`CircuitBreakerService`].
+#[pin_project::pin_project]
+pub struct CircuitBreakerFuture<F> {
+    #[pin]
+    inner: Option<F>,
+    permit: Option<Permit>,
+    error: Option<Error>,
+}
+
+impl<F> fmt::Debug for CircuitBreakerFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreakerFuture").finish()
+    }
+}
+
+impl<F, B, E> Future for CircuitBreakerFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    E: From<Error>,
+{
+    type Output = Result<http::Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let Some(inner) = this.inner.as_pin_mut() else {
+            let error = this.error.take().expect("future polled after completion");
+            return Poll::Ready(Err(error.into()));
+        };
+
+        let result = futures_util::ready!(inner.poll(cx));
+        if let Some(permit) = this.permit.take() {
+            let success = result
+                .as_ref()
+                .is_ok_and(|response| !response.status().is_server_error());
+            permit.record(success);
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use http::StatusCode;
+    use tower::ServiceExt as _;
+
+    type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn request() -> http::Request<crate::Body> {
+        http::Request::get("http://example.com/")
+            .body(crate::Body::empty())
+            .unwrap()
+    }
+
+    fn service(
+        healthy: Arc<AtomicBool>,
+    ) -> impl Service<
+        http::Request<crate::Body>,
+        Response = http::Response<crate::Body>,
+        Error = BoxError,
+    > + Clone {
+        tower::service_fn(move |_req: http::Request<crate::Body>| {
+            let status = if healthy.load(Ordering::SeqCst) {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            async move {
+                Ok::<_, BoxError>(
+                    http::Response::builder()
+                        .status(status)
+                        .body(crate::Body::empty())
+                        .unwrap(),
+                )
+            }
+        })
+    }
+
+    fn is_circuit_open(error: &BoxError) -> bool {
+        matches!(error.downcast_ref::<Error>(), Some(Error::CircuitOpen(_)))
+    }
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let breaker = CircuitBreakerLayer::new()
+            .with_consecutive_failures(2)
+            .with_open_duration(Duration::from_secs(60));
+        let service = breaker.layer(service(healthy.clone()));
+        let uri: http::Uri = "http://example.com/".parse().unwrap();
+
+        for _ in 0..2 {
+            let response = service.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_eq!(breaker.state(&uri), CircuitState::Open);
+
+        healthy.store(true, Ordering::SeqCst);
+        let error = service.clone().oneshot(request()).await.unwrap_err();
+        assert!(is_circuit_open(&error));
+        assert_eq!(
+            breaker.circuits(),
+            vec![("http://example.com".to_owned(), CircuitState::Open)]
+        );
+    }
+
+    #[tokio::test]
+    async fn opens_on_error_rate() {
+        let healthy = Arc::new(AtomicBool::new(true));
+        let breaker = CircuitBreakerLayer::new()
+            .with_consecutive_failures(100)
+            .with_error_rate(0.5, 4);
+        let service = breaker.layer(service(healthy.clone()));
+        let uri: http::Uri = "http://example.com/".parse().unwrap();
+
+        for ok in [true, false, true, false] {
+            healthy.store(ok, Ordering::SeqCst);
+            service.clone().oneshot(request()).await.unwrap();
+        }
+        assert_eq!(breaker.state(&uri), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn half_open_probes() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let breaker = CircuitBreakerLayer::new()
+            .with_consecutive_failures(1)
+            .with_open_duration(Duration::ZERO)
+            .with_half_open_requests(2);
+        let service = breaker.layer(service(healthy.clone()));
+        let uri: http::Uri = "http://example.com/".parse().unwrap();
+
+        service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(breaker.state(&uri), CircuitState::HalfOpen);
+
+        // A failed probe re-opens the circuit.
+        service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(breaker.state(&uri), CircuitState::HalfOpen);
+
+        healthy.store(true, Ordering::SeqCst);
+        let first = service.clone().call(request());
+        let second = service.clone().call(request());
+        let error = service.clone().oneshot(request()).await.unwrap_err();
+        assert!(is_circuit_open(&error));
+
+        first.await.unwrap();
+        second.await.unwrap();
+        assert_eq!(breaker.state(&uri), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn idle_circuits_are_forgotten() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let breaker = CircuitBreakerLayer::new()
+            .with_consecutive_failures(1)
+            .with_open_duration(Duration::from_secs(600))
+            .with_idle_timeout(Duration::from_millis(20));
+
+        // This circuit's cool-down has passed, but it is still open until a probe succeeds.
+        let cooled = http::Request::get("http://example.edu/")
+            .body(crate::Body::empty())
+            .unwrap();
+        let cooled_breaker = breaker.clone().with_open_duration(Duration::ZERO);
+        let cooled_service = cooled_breaker.layer(service(healthy.clone()));
+        cooled_service.oneshot(cooled).await.unwrap();
+
+        let service = breaker.layer(service(healthy.clone()));
+        service.clone().oneshot(request()).await.unwrap();
+
+        healthy.store(true, Ordering::SeqCst);
+        let other = || {
+            http::Request::get("http://example.org/")
+                .body(crate::Body::empty())
+                .unwrap()
+        };
+        service.clone().oneshot(other()).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        let third = http::Request::get("http://example.net/")
+            .body(crate::Body::empty())
+            .unwrap();
+        service.clone().oneshot(third).await.unwrap();
+
+        // The open circuits are kept, while the idle closed circuit is forgotten.
+        let mut circuits = breaker.circuits();
+        circuits.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            circuits,
+            vec![
+                ("http://example.com".to_owned(), CircuitState::Open),
+                ("http://example.edu".to_owned(), CircuitState::HalfOpen),
+                ("http://example.net".to_owned(), CircuitState::Closed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_probe_releases_permit() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let breaker = CircuitBreakerLayer::new()
+            .with_consecutive_failures(1)
+            .with_open_duration(Duration::ZERO);
+        let service = breaker.layer(service(healthy.clone()));
+        let uri: http::Uri = "http://example.com/".parse().unwrap();
+
+        service.clone().oneshot(request()).await.unwrap();
+
+        healthy.store(true, Ordering::SeqCst);
+        let mut probe = service.clone();
+        let future = probe.call(request());
+        drop(future);
+
+        service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(breaker.state(&uri), CircuitState::Closed);
+    }
+}
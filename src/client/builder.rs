@@ -10,20 +10,29 @@ use tower_http::follow_redirect::policy;
 use tower_http::follow_redirect::FollowRedirectLayer;
 use tower_http::set_header::SetRequestHeaderLayer;
 
+use super::alt_svc::AltSvcCache;
+#[cfg(feature = "auth")]
+use super::auth::AuthLayer;
+use super::balance::LoadBalancerLayer;
+use super::breaker::CircuitBreakerLayer;
 use super::conn::protocol::auto;
 use super::conn::transport::tcp::TcpTransportConfig;
 use super::conn::transport::TransportExt;
 use super::conn::Connection;
 use super::conn::Protocol;
 use super::conn::Transport;
+use super::hsts::{HstsCache, HstsLayer};
+use super::options::{RequestDeadlineLayer, RequestOptionsLayer};
 use super::pool::PoolableConnection;
+use super::redirect::{ClientRedirectPolicy, RedirectChainLayer};
 use super::ClientService;
 use crate::client::conn::connection::ConnectionError;
 #[cfg(feature = "tls")]
 use crate::client::default_tls_config;
 use crate::client::{conn::protocol::auto::HttpConnectionBuilder, Client};
 use crate::info::HasConnectionInfo;
-use crate::service::SharedService;
+use crate::service::hedge::HedgeLayer;
+use crate::service::{RetryPolicy, SharedService};
 
 pub trait BuildProtocol<IO>
 where
@@ -67,10 +76,23 @@ pub struct Builder<T, P, RP = policy::Standard> {
     user_agent: Option<String>,
     redirect: Option<RP>,
     timeout: Option<Duration>,
-    retries: Option<usize>,
+    retries: Option<RetryPolicy>,
+    hedge: Option<HedgeLayer>,
+    breaker: Option<CircuitBreakerLayer>,
+    balancer: Option<LoadBalancerLayer>,
+    #[cfg(feature = "auth")]
+    auth: Option<AuthLayer>,
+    hsts: Option<HstsCache>,
+    alt_svc: Option<AltSvcCache>,
     #[cfg(feature = "tls")]
     tls: Option<ClientConfig>,
     pool: Option<crate::client::pool::Config>,
+    #[cfg(feature = "grpc")]
+    grpc: bool,
+    #[cfg(feature = "compression")]
+    decompression: bool,
+    #[cfg(feature = "cookies")]
+    cookies: Option<super::cookies::CookieJar>,
 }
 
 impl Builder<(), (), policy::Standard> {
@@ -83,9 +105,22 @@ impl Builder<(), (), policy::Standard> {
             redirect: None,
             timeout: None,
             retries: None,
+            hedge: None,
+            breaker: None,
+            balancer: None,
+            #[cfg(feature = "auth")]
+            auth: None,
+            hsts: None,
+            alt_svc: None,
             #[cfg(feature = "tls")]
             tls: None,
             pool: None,
+            #[cfg(feature = "grpc")]
+            grpc: false,
+            #[cfg(feature = "compression")]
+            decompression: false,
+            #[cfg(feature = "cookies")]
+            cookies: None,
         }
     }
 }
@@ -98,10 +133,23 @@ impl Default for Builder<TcpTransportConfig, HttpConnectionBuilder, policy::Stan
             user_agent: None,
             redirect: Some(policy::Standard::default()),
             timeout: Some(Duration::from_secs(30)),
-            retries: Some(3),
+            retries: Some(RetryPolicy::default()),
+            hedge: None,
+            breaker: None,
+            balancer: None,
+            #[cfg(feature = "auth")]
+            auth: None,
+            hsts: None,
+            alt_svc: None,
             #[cfg(feature = "tls")]
             tls: Some(default_tls_config()),
             pool: Some(Default::default()),
+            #[cfg(feature = "grpc")]
+            grpc: false,
+            #[cfg(feature = "compression")]
+            decompression: false,
+            #[cfg(feature = "cookies")]
+            cookies: None,
         }
     }
 }
@@ -116,9 +164,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: self.redirect,
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -131,9 +192,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: self.redirect,
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 }
@@ -207,9 +281,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: self.redirect,
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -222,9 +309,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: self.redirect,
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -249,6 +349,12 @@ impl<T, P, RP> Builder<T, P, RP> {
 
 impl<T, P, RP> Builder<T, P, RP> {
     /// Set the redirect policy. See [`policy`] for more information.
+    ///
+    /// Request bodies which can be cloned with [`Body::try_clone`](crate::Body::try_clone)
+    /// are resent when following `307` and `308` redirects.
+    ///
+    /// Use [`RedirectPolicy`](super::redirect::RedirectPolicy) to also refuse
+    /// `https` to `http` downgrades and inspect each redirect with a hook.
     pub fn with_redirect_policy<RP2>(self, policy: RP2) -> Builder<T, P, RP2> {
         Builder {
             transport: self.transport,
@@ -257,9 +363,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: Some(policy),
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -272,9 +391,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: None,
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -287,9 +419,22 @@ impl<T, P, RP> Builder<T, P, RP> {
             redirect: Some(policy::Standard::default()),
             timeout: self.timeout,
             retries: self.retries,
+            hedge: self.hedge,
+            breaker: self.breaker,
+            balancer: self.balancer,
+            #[cfg(feature = "auth")]
+            auth: self.auth,
+            hsts: self.hsts,
+            alt_svc: self.alt_svc,
             #[cfg(feature = "tls")]
             tls: self.tls,
             pool: self.pool,
+            #[cfg(feature = "grpc")]
+            grpc: self.grpc,
+            #[cfg(feature = "compression")]
+            decompression: self.decompression,
+            #[cfg(feature = "cookies")]
+            cookies: self.cookies,
         }
     }
 
@@ -301,6 +446,10 @@ impl<T, P, RP> Builder<T, P, RP> {
 
 impl<T, P, RP> Builder<T, P, RP> {
     /// Set the timeout for requests.
+    ///
+    /// The timeout applies to each attempt when requests are retried or hedged. A
+    /// deadline for the whole request can be set with
+    /// [`RequestOptions::with_deadline`](crate::client::options::RequestOptions::with_deadline).
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = Some(timeout);
         self
@@ -326,14 +475,172 @@ impl<T, P, RP> Builder<T, P, RP> {
 
 impl<T, P, RP> Builder<T, P, RP> {
     /// Set the number of retries for failed requests.
+    ///
+    /// This keeps the rest of the current retry policy, or uses the default
+    /// [`RetryPolicy`] if retries were disabled.
     pub fn with_retries(mut self, retries: usize) -> Self {
-        self.retries = Some(retries);
+        self.retries = Some(
+            self.retries
+                .take()
+                .unwrap_or_default()
+                .with_retries(retries),
+        );
         self
     }
 
     /// Get the number of retries for failed requests.
     pub fn retries(&self) -> Option<usize> {
-        self.retries
+        self.retries.as_ref().map(RetryPolicy::retries)
+    }
+
+    /// Set the policy used to retry failed requests.
+    ///
+    /// See [`RetryPolicy`] for the backoff, idempotency and budget rules.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retries = Some(policy);
+        self
+    }
+
+    /// Get the policy used to retry failed requests.
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retries.as_ref()
+    }
+
+    /// Hedge requests which are slower than recent requests.
+    ///
+    /// Each attempt made by the retry policy is hedged separately, and the timeout
+    /// applies to each hedged attempt. See [`HedgeLayer`] for more details.
+    pub fn with_hedging(mut self, hedge: HedgeLayer) -> Self {
+        self.hedge = Some(hedge);
+        self
+    }
+
+    /// Get the hedging configuration.
+    pub fn hedging(&self) -> Option<&HedgeLayer> {
+        self.hedge.as_ref()
+    }
+
+    /// Disable request hedging.
+    pub fn without_hedging(mut self) -> Self {
+        self.hedge = None;
+        self
+    }
+
+    /// Add a per-host circuit breaker.
+    ///
+    /// The circuit breaker sees each attempt made by the retry policy, and requests
+    /// rejected by an open circuit are not retried. It sits above the load balancer,
+    /// so circuits cover every endpoint of a logical authority. Keep a clone of the layer to
+    /// inspect the state of each circuit. See [`CircuitBreakerLayer`] for more details.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreakerLayer) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Get the circuit breaker.
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreakerLayer> {
+        self.breaker.as_ref()
+    }
+
+    /// Disable the circuit breaker.
+    pub fn without_circuit_breaker(mut self) -> Self {
+        self.breaker = None;
+        self
+    }
+
+    /// Balance requests to logical authorities across their endpoints.
+    ///
+    /// Load balancing happens below the redirect, cookie and retry layers, which all
+    /// see the logical URI, so each retry can be sent to a different endpoint.
+    /// See [`LoadBalancerLayer`] for more details.
+    pub fn with_load_balancer(mut self, balancer: LoadBalancerLayer) -> Self {
+        self.balancer = Some(balancer);
+        self
+    }
+
+    /// Get the load balancer.
+    pub fn load_balancer(&self) -> Option<&LoadBalancerLayer> {
+        self.balancer.as_ref()
+    }
+
+    /// Disable load balancing.
+    pub fn without_load_balancer(mut self) -> Self {
+        self.balancer = None;
+        self
+    }
+
+    /// Authenticate requests with per-host credentials.
+    ///
+    /// See [`super::auth`] for details.
+    #[cfg(feature = "auth")]
+    pub fn with_auth(mut self, auth: AuthLayer) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Get the authentication layer.
+    #[cfg(feature = "auth")]
+    pub fn auth(&self) -> Option<&AuthLayer> {
+        self.auth.as_ref()
+    }
+
+    /// Don't authenticate requests.
+    #[cfg(feature = "auth")]
+    pub fn without_auth(mut self) -> Self {
+        self.auth = None;
+        self
+    }
+
+    /// Upgrade `http` requests to known HSTS hosts to `https`, using a new cache.
+    ///
+    /// See [`super::hsts`] for details.
+    pub fn with_hsts(self) -> Self {
+        self.with_hsts_cache(HstsCache::default())
+    }
+
+    /// Upgrade `http` requests to known HSTS hosts to `https`, using the provided cache.
+    ///
+    /// The cache is shared, so it can be inspected or saved using another handle to it.
+    pub fn with_hsts_cache(mut self, cache: HstsCache) -> Self {
+        self.hsts = Some(cache);
+        self
+    }
+
+    /// Get the HSTS cache.
+    pub fn hsts(&self) -> Option<&HstsCache> {
+        self.hsts.as_ref()
+    }
+
+    /// Don't remember HSTS hosts.
+    pub fn without_hsts(mut self) -> Self {
+        self.hsts = None;
+        self
+    }
+
+    /// Record `Alt-Svc` advertisements and use them for later requests, using a new cache.
+    ///
+    /// See [`super::alt_svc`] for details.
+    pub fn with_alt_svc(self) -> Self {
+        self.with_alt_svc_cache(AltSvcCache::default())
+    }
+
+    /// Record `Alt-Svc` advertisements and use them for later requests, using the provided cache.
+    ///
+    /// The cache is shared, so it can be inspected or saved using another handle to it.
+    pub fn with_alt_svc_cache(mut self, cache: AltSvcCache) -> Self {
+        self.alt_svc = Some(cache);
+        self
+    }
+
+    /// Get the Alt-Svc cache.
+    pub fn alt_svc(&self) -> Option<&AltSvcCache> {
+        self.alt_svc.as_ref()
+    }
+
+    /// Don't use alternative services.
+    pub fn without_alt_svc(mut self) -> Self {
+        self.alt_svc = None;
+        self
     }
 
     /// Disable retries for failed requests.
@@ -343,6 +650,77 @@ impl<T, P, RP> Builder<T, P, RP> {
     }
 }
 
+#[cfg(feature = "grpc")]
+impl<T, P, RP> Builder<T, P, RP> {
+    /// Configure the client for gRPC.
+    ///
+    /// Requests are sent over HTTP/2 with gRPC headers, the request timeout is sent to
+    /// servers in the `grpc-timeout` header, and non-`OK` gRPC statuses are returned as
+    /// [`crate::grpc::Status`] errors. See [`crate::grpc::GrpcService`] for details.
+    pub fn with_grpc(mut self) -> Self {
+        self.grpc = true;
+        self
+    }
+
+    /// Is the client configured for gRPC?
+    pub fn grpc(&self) -> bool {
+        self.grpc
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<T, P, RP> Builder<T, P, RP> {
+    /// Decompress response bodies.
+    ///
+    /// This sets the `Accept-Encoding` header on requests (if it is not already present)
+    /// to the encodings enabled by cargo features (`gzip`, `deflate`, `brotli` and `zstd`),
+    /// and transparently decodes response bodies with a matching `Content-Encoding`.
+    pub fn with_decompression(mut self) -> Self {
+        self.decompression = true;
+        self
+    }
+
+    /// Do not decompress response bodies.
+    pub fn without_decompression(mut self) -> Self {
+        self.decompression = false;
+        self
+    }
+
+    /// Are response bodies decompressed?
+    pub fn decompression(&self) -> bool {
+        self.decompression
+    }
+}
+
+#[cfg(feature = "cookies")]
+impl<T, P, RP> Builder<T, P, RP> {
+    /// Store cookies from responses, and send them with later requests, using a new jar.
+    ///
+    /// See [`super::cookies`] for details.
+    pub fn with_cookie_store(self) -> Self {
+        self.with_cookie_jar(Default::default())
+    }
+
+    /// Store cookies from responses, and send them with later requests, using the provided jar.
+    ///
+    /// The jar is shared, so cookies can be inspected or saved using another handle to it.
+    pub fn with_cookie_jar(mut self, jar: super::cookies::CookieJar) -> Self {
+        self.cookies = Some(jar);
+        self
+    }
+
+    /// Disable cookie handling.
+    pub fn without_cookies(mut self) -> Self {
+        self.cookies = None;
+        self
+    }
+
+    /// The cookie jar used by the client, if cookies are enabled.
+    pub fn cookie_jar(&self) -> Option<&super::cookies::CookieJar> {
+        self.cookies.as_ref()
+    }
+}
+
 impl<T, P, RP> Builder<T, P, RP>
 where
     T: BuildTransport,
@@ -393,6 +771,28 @@ where
             ))
         };
 
+        #[cfg(feature = "grpc")]
+        let grpc = self
+            .grpc
+            .then(|| crate::grpc::GrpcLayer::new().with_timeout(self.timeout));
+        #[cfg(not(feature = "grpc"))]
+        let grpc: Option<tower::layer::util::Identity> = None;
+
+        #[cfg(feature = "compression")]
+        let decompression = self.decompression.then(decompression_layer);
+        #[cfg(not(feature = "compression"))]
+        let decompression: Option<tower::layer::util::Identity> = None;
+
+        #[cfg(feature = "cookies")]
+        let cookies = super::cookies::CookieLayer::optional(self.cookies);
+        #[cfg(not(feature = "cookies"))]
+        let cookies = tower::layer::util::Identity::new();
+
+        #[cfg(feature = "auth")]
+        let auth = self.auth.unwrap_or_default();
+        #[cfg(not(feature = "auth"))]
+        let auth = tower::layer::util::Identity::new();
+
         #[cfg(feature = "tls")]
         let transport = self
             .transport
@@ -403,19 +803,37 @@ where
 
         ServiceBuilder::new()
             .layer(SharedService::layer())
-            .option_layer(self.retries.map(|attempts| {
-                tower::retry::RetryLayer::new(crate::service::Attempts::new(attempts))
-            }))
-            .option_layer(self.timeout.map(tower::timeout::TimeoutLayer::new))
+            // Upgrade requests first, so that every layer sees the `https` URI.
+            .layer(HstsLayer::optional(self.hsts.clone()))
+            .option_layer(grpc)
+            // Request deadlines cover every attempt, so they go above retries and hedging.
+            .layer(RequestDeadlineLayer::new())
+            .option_layer(self.retries.map(tower::retry::RetryLayer::new))
+            .option_layer(self.hedge)
+            .option_layer(self.breaker)
+            .layer(RequestOptionsLayer::new(self.timeout))
             .layer(SetRequestHeaderLayer::if_not_present(
                 http::header::USER_AGENT,
                 user_agent,
             ))
-            .option_layer(self.redirect.map(FollowRedirectLayer::with_policy))
+            .option_layer(decompression)
+            .option_layer(self.redirect.map(|policy| {
+                (
+                    RedirectChainLayer,
+                    FollowRedirectLayer::with_policy(ClientRedirectPolicy::new(policy)),
+                )
+            }))
+            // Credentials are added below redirects, so they are only sent to their own host.
+            .layer(auth)
+            .layer(cookies)
+            // An empty load balancer sends requests unchanged.
+            .layer(self.balancer.unwrap_or_default())
             .service(ClientService {
                 transport,
                 protocol: self.protocol.build(),
                 pool: self.pool.map(super::pool::Pool::new),
+                hsts: self.hsts,
+                alt_svc: self.alt_svc,
                 _body: std::marker::PhantomData,
             })
     }
@@ -426,6 +844,26 @@ where
     }
 }
 
+#[cfg(feature = "compression")]
+type DecompressedResponse =
+    http::Response<tower_http::decompression::DecompressionBody<crate::Body>>;
+
+#[cfg(feature = "compression")]
+type DecompressionLayer = tower::layer::util::Stack<
+    tower_http::decompression::DecompressionLayer,
+    tower::util::MapResponseLayer<fn(DecompressedResponse) -> http::Response<crate::Body>>,
+>;
+
+#[cfg(feature = "compression")]
+fn decompression_layer() -> DecompressionLayer {
+    tower::layer::util::Stack::new(
+        tower_http::decompression::DecompressionLayer::new(),
+        tower::util::MapResponseLayer::new(|response: DecompressedResponse| {
+            response.map(crate::Body::new)
+        }),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::Builder;
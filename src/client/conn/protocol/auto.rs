@@ -220,6 +220,7 @@ mod future {
 mod tests {
     use std::fmt::Debug;
     use std::future::Future;
+    #[cfg(feature = "tls")]
     use std::io;
 
     use super::*;
@@ -229,6 +230,7 @@ mod tests {
     use crate::client::conn::{protocol::HttpProtocol, Connection as _, Stream};
     use crate::client::pool::PoolableConnection as _;
     use crate::client::Error;
+    #[cfg(feature = "tls")]
     use crate::stream::tls::TlsHandshakeStream as _;
 
     use futures_util::{stream::StreamExt as _, TryFutureExt};
@@ -349,6 +351,7 @@ mod tests {
         assert!(rrx.is_ok());
     }
 
+    #[cfg(feature = "tls")]
     #[tokio::test]
     async fn http_connector_alpn_h2() {
         let _ = tracing_subscriber::fmt::try_init();
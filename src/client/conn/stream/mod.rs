@@ -198,6 +198,7 @@ where
 
 #[cfg(feature = "stream")]
 impl From<TcpStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: TcpStream) -> Self {
         Stream {
             inner: Braid::from(stream).into(),
@@ -207,6 +208,7 @@ impl From<TcpStream> for Stream {
 
 #[cfg(feature = "stream")]
 impl From<DuplexStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: DuplexStream) -> Self {
         Stream {
             inner: Braid::from(stream).into(),
@@ -216,6 +218,7 @@ impl From<DuplexStream> for Stream {
 
 #[cfg(feature = "stream")]
 impl From<UnixStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: UnixStream) -> Self {
         Stream {
             inner: Braid::from(stream).into(),
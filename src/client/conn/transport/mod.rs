@@ -11,7 +11,6 @@ use ::http::Uri;
 use pin_project::pin_project;
 #[cfg(feature = "tls")]
 use rustls::client::ClientConfig;
-#[cfg(feature = "tls")]
 use thiserror::Error;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
@@ -100,6 +99,81 @@ where
     }
 }
 
+tokio::task_local! {
+    /// The endpoint chosen by a load balancer for the connection being made.
+    static ENDPOINT: Endpoint;
+}
+
+struct Endpoint {
+    authority: ::http::uri::Authority,
+    used: std::cell::Cell<bool>,
+}
+
+/// Error returned when a load balancer chose an endpoint, but the transport did
+/// not ask for it, and so would have connected to the URI's authority instead.
+///
+/// Transports support load balancing by connecting to [`endpoint`].
+#[derive(Debug, Error)]
+#[error("transport does not support connecting to endpoint {0}")]
+pub struct UnsupportedEndpoint(::http::uri::Authority);
+
+impl UnsupportedEndpoint {
+    /// The endpoint chosen by the load balancer.
+    pub fn endpoint(&self) -> &::http::uri::Authority {
+        &self.0
+    }
+}
+
+/// Call `connect`, directing the transport to connect to `endpoint` instead of the
+/// URI's authority.
+///
+/// The URI is left unchanged, so that it is still used for everything else about the
+/// connection, such as the TLS server name. Returns an error if the transport didn't
+/// ask for the endpoint.
+pub(crate) fn with_endpoint<F, R>(
+    endpoint: Option<::http::uri::Authority>,
+    connect: F,
+) -> Result<R, UnsupportedEndpoint>
+where
+    F: FnOnce() -> R,
+{
+    let Some(authority) = endpoint else {
+        return Ok(connect());
+    };
+
+    let endpoint = Endpoint {
+        authority,
+        used: std::cell::Cell::new(false),
+    };
+
+    ENDPOINT.sync_scope(endpoint, || {
+        let connection = connect();
+        ENDPOINT.with(|endpoint| {
+            if endpoint.used.get() {
+                Ok(connection)
+            } else {
+                Err(UnsupportedEndpoint(endpoint.authority.clone()))
+            }
+        })
+    })
+}
+
+/// The endpoint chosen by a load balancer for the connection being made, if any.
+///
+/// Transports which support [load balancing](crate::client::balance) should connect
+/// to this endpoint instead of the URI's authority, and must call this in
+/// [`Transport::connect`] itself, not in the future it returns. When an endpoint was
+/// chosen and the transport doesn't ask for it, the connection fails with
+/// [`UnsupportedEndpoint`].
+pub fn endpoint() -> Option<::http::uri::Authority> {
+    ENDPOINT
+        .try_with(|endpoint| {
+            endpoint.used.set(true);
+            endpoint.authority.clone()
+        })
+        .ok()
+}
+
 /// Extension trait for Transports to provide additional configuration options.
 pub trait TransportExt: Transport {
     #[cfg(feature = "stream")]
@@ -649,8 +723,24 @@ mod tests {
     use static_assertions::assert_impl_all;
     use tokio::net::TcpStream;
 
+    #[cfg(feature = "tls")]
     assert_impl_all!(TransportStream<Stream>: HasTlsConnectionInfo, HasConnectionInfo);
     assert_impl_all!(TransportStream<Stream>: Send, Sync, Unpin);
 
     assert_impl_all!(TransportStream<TcpStream>: HasConnectionInfo);
+
+    #[test]
+    fn unused_endpoint_is_an_error() {
+        let authority = ::http::uri::Authority::from_static("10.0.0.1:8080");
+
+        assert_eq!(with_endpoint(None, endpoint).unwrap(), None);
+        assert_eq!(
+            with_endpoint(Some(authority.clone()), endpoint).unwrap(),
+            Some(authority.clone())
+        );
+
+        let error = with_endpoint(Some(authority.clone()), || ()).unwrap_err();
+        assert_eq!(error.endpoint(), &authority);
+        assert_eq!(endpoint(), None);
+    }
 }
@@ -193,7 +193,12 @@ where
     }
 
     fn call(&mut self, req: Uri) -> Self::Future {
-        let (host, port) = match get_host_and_port(&req) {
+        let target = match super::endpoint() {
+            Some(endpoint) => endpoint_uri(&req, endpoint),
+            None => Ok(req),
+        };
+
+        let (host, port) = match target.and_then(|target| get_host_and_port(&target)) {
             Ok((host, port)) => (host, port),
             Err(e) => return Box::pin(std::future::ready(Err(e))),
         };
@@ -460,6 +465,13 @@ impl BuildTransport for TcpTransportConfig {
     }
 }
 
+/// The URI with its authority replaced by a load balanced endpoint.
+fn endpoint_uri(uri: &Uri, endpoint: http::uri::Authority) -> Result<Uri, TcpConnectionError> {
+    let mut parts = uri.clone().into_parts();
+    parts.authority = Some(endpoint);
+    Uri::from_parts(parts).map_err(|_| TcpConnectionError::uri("invalid endpoint"))
+}
+
 fn get_host_and_port(uri: &Uri) -> Result<(Box<str>, u16), TcpConnectionError> {
     let host = uri.host().ok_or(TcpConnectionError::uri("missing host"))?;
     let host = host.trim_start_matches('[').trim_end_matches(']');
@@ -733,6 +745,32 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_transport_endpoint() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let bind = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint: http::uri::Authority =
+            bind.local_addr().unwrap().to_string().parse().unwrap();
+
+        let uri: Uri = "http://api.internal".parse().unwrap();
+
+        let mut transport = TcpTransport::builder()
+            .with_config(TcpTransportConfig::default())
+            .with_gai_resolver()
+            .build::<TcpStream>();
+
+        let connect = crate::client::conn::transport::with_endpoint(Some(endpoint), || {
+            Service::call(&mut transport, uri)
+        })
+        .unwrap();
+        let (stream, _) = tokio::join!(async { connect.await.unwrap() }, async {
+            bind.accept().await.unwrap()
+        });
+
+        assert_eq!(*stream.info().remote_addr(), bind.local_addr().unwrap());
+    }
+
     #[tokio::test]
     async fn test_transport_no_candidates() {
         let _ = tracing_subscriber::fmt::try_init();
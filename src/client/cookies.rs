@@ -0,0 +1,399 @@
+//! Cookie storage for the client.
+//!
+//! A [`CookieJar`] stores cookies received in `Set-Cookie` response headers, following
+//! the rules in [RFC 6265](https://datatracker.ietf.org/doc/html/rfc6265) for domain and
+//! path matching, expiry, and the `Secure` attribute, and sends matching cookies in the
+//! `Cookie` header of later requests.
+//!
+//! The jar is applied to a client with [`Builder::with_cookie_store`](super::Builder::with_cookie_store)
+//! or [`Builder::with_cookie_jar`](super::Builder::with_cookie_jar). The cookie layer sits
+//! below the redirect layer, so cookies set by a redirect response are sent when the
+//! redirect is followed.
+//!
+//! With the `serde` feature, the jar can be saved to and loaded from a JSON file.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use cookie_store::{CookieStore, RawCookie};
+use http::header::{HeaderValue, COOKIE, SET_COOKIE};
+use http::HeaderMap;
+
+/// A shared store of cookies.
+///
+/// Cloning a `CookieJar` produces a handle to the same store, so a jar can be
+/// shared between clients, or retained to inspect or save cookies after requests.
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    store: Arc<Mutex<CookieStore>>,
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("cookies", &self.len())
+            .finish()
+    }
+}
+
+impl CookieJar {
+    /// Create a new, empty cookie jar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store the cookies from `Set-Cookie` headers in a response to a request for `uri`.
+    pub fn store_response_cookies(&self, uri: &http::Uri, headers: &HeaderMap) {
+        let Some(url) = to_url(uri) else {
+            return;
+        };
+
+        let cookies = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_owned()).ok())
+            .collect::<Vec<_>>();
+
+        if cookies.is_empty() {
+            return;
+        }
+
+        self.store
+            .lock()
+            .unwrap()
+            .store_response_cookies(cookies.into_iter(), &url);
+    }
+
+    /// Add a single cookie, as if it were received in a `Set-Cookie` header from `uri`.
+    ///
+    /// Returns `false` if the cookie could not be parsed, or was rejected (for example
+    /// because its domain does not match the URI).
+    pub fn add_cookie_str(&self, cookie: &str, uri: &http::Uri) -> bool {
+        let Some(url) = to_url(uri) else {
+            return false;
+        };
+
+        self.store.lock().unwrap().parse(cookie, &url).is_ok()
+    }
+
+    /// The `Cookie` header value to send with a request to `uri`, if any cookies match.
+    pub fn cookies(&self, uri: &http::Uri) -> Option<HeaderValue> {
+        let url = to_url(uri)?;
+        let store = self.store.lock().unwrap();
+
+        // RFC 6265 section 5.4: cookies with longer paths are listed first.
+        let mut cookies = store.matches(&url);
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.as_ref().len()));
+
+        let value = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if value.is_empty() {
+            return None;
+        }
+
+        HeaderValue::from_str(&value).ok()
+    }
+
+    /// Get the value of an unexpired cookie by domain, path and name.
+    pub fn get(&self, domain: &str, path: &str, name: &str) -> Option<String> {
+        self.store
+            .lock()
+            .unwrap()
+            .get(domain, path, name)
+            .map(|cookie| cookie.value().to_owned())
+    }
+
+    /// Remove a cookie by domain, path and name.
+    pub fn remove(&self, domain: &str, path: &str, name: &str) -> bool {
+        self.store
+            .lock()
+            .unwrap()
+            .remove(domain, path, name)
+            .is_some()
+    }
+
+    /// The number of unexpired cookies in the jar.
+    pub fn len(&self) -> usize {
+        self.store.lock().unwrap().iter_unexpired().count()
+    }
+
+    /// Returns `true` if there are no unexpired cookies in the jar.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all cookies from the jar.
+    pub fn clear(&self) {
+        self.store.lock().unwrap().clear();
+    }
+}
+
+#[cfg(feature = "serde")]
+impl CookieJar {
+    /// Load persistent cookies from JSON, as written by [`CookieJar::save_json`].
+    ///
+    /// Expired cookies are discarded.
+    pub fn load_json<R: std::io::BufRead>(reader: R) -> std::io::Result<Self> {
+        let store = cookie_store::serde::json::load(reader).map_err(std::io::Error::other)?;
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+        })
+    }
+
+    /// Save persistent, unexpired cookies as JSON.
+    ///
+    /// Session cookies (those without an `Expires` or `Max-Age` attribute) are not saved.
+    pub fn save_json<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let store = self.store.lock().unwrap();
+        cookie_store::serde::json::save(&store, writer).map_err(std::io::Error::other)
+    }
+
+    /// Load persistent cookies from a JSON file.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::load_json(std::io::BufReader::new(file))
+    }
+
+    /// Save persistent cookies to a JSON file, replacing its contents.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.save_json(&mut file)?;
+        std::io::Write::flush(&mut file)
+    }
+}
+
+fn to_url(uri: &http::Uri) -> Option<url::Url> {
+    url::Url::parse(&uri.to_string()).ok()
+}
+
+/// A layer which adds cookies from a [`CookieJar`] to requests, and stores
+/// cookies from responses.
+#[derive(Debug, Clone)]
+pub struct CookieLayer {
+    jar: Option<CookieJar>,
+}
+
+impl CookieLayer {
+    /// Create a new cookie layer using the given jar.
+    pub fn new(jar: CookieJar) -> Self {
+        Self { jar: Some(jar) }
+    }
+
+    /// Create a cookie layer which is a no-op when `jar` is `None`.
+    ///
+    /// Unlike an optional layer, this does not change the error type of the inner service.
+    pub(crate) fn optional(jar: Option<CookieJar>) -> Self {
+        Self { jar }
+    }
+}
+
+impl<S> tower::Layer<S> for CookieLayer {
+    type Service = CookieService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CookieService {
+            inner,
+            jar: self.jar.clone(),
+        }
+    }
+}
+
+/// A service which adds cookies from a [`CookieJar`] to requests, and stores
+/// cookies from responses.
+///
+/// Requests which already have a `Cookie` header are sent unchanged.
+#[derive(Debug, Clone)]
+pub struct CookieService<S> {
+    inner: S,
+    jar: Option<CookieJar>,
+}
+
+impl<S> CookieService<S> {
+    /// Create a new cookie service wrapping `inner`.
+    pub fn new(inner: S, jar: CookieJar) -> Self {
+        Self {
+            inner,
+            jar: Some(jar),
+        }
+    }
+
+    /// The cookie jar used by this service.
+    pub fn jar(&self) -> Option<&CookieJar> {
+        self.jar.as_ref()
+    }
+}
+
+impl<S, BIn, BOut> tower::Service<http::Request<BIn>> for CookieService<S>
+where
+    S: tower::Service<http::Request<BIn>, Response = http::Response<BOut>>,
+{
+    type Response = http::Response<BOut>;
+    type Error = S::Error;
+    type Future = CookieFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<BIn>) -> Self::Future {
+        let Some(jar) = self.jar.clone() else {
+            return CookieFuture {
+                inner: self.inner.call(req),
+                store: None,
+            };
+        };
+
+        if !req.headers().contains_key(COOKIE) {
+            if let Some(cookies) = jar.cookies(req.uri()) {
+                req.headers_mut().insert(COOKIE, cookies);
+            }
+        }
+
+        let uri = req.uri().clone();
+        CookieFuture {
+            inner: self.inner.call(req),
+            store: Some((uri, jar)),
+        }
+    }
+}
+
+/// Future returned by [`CookieService`].
+#[pin_project::pin_project]
+pub struct CookieFuture<F> {
+    #[pin]
+    inner: F,
+    store: Option<(http::Uri, CookieJar)>,
+}
+
+impl<F> fmt::Debug for CookieFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieFuture")
+            .field("uri", &self.store.as_ref().map(|(uri, _)| uri))
+            .finish()
+    }
+}
+
+impl<F, B, E> Future for CookieFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+{
+    type Output = Result<http::Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.inner.poll(cx).map_ok(|response| {
+            if let Some((uri, jar)) = this.store.take() {
+                jar.store_response_cookies(&uri, response.headers());
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{Layer as _, ServiceExt as _};
+
+    use super::*;
+
+    fn uri(s: &str) -> http::Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn domain_and_path_matching() {
+        let jar = CookieJar::new();
+        assert!(jar.add_cookie_str("a=1; Path=/app", &uri("http://example.com/app/login")));
+        assert!(jar.add_cookie_str("b=2; Domain=example.com", &uri("http://www.example.com/")));
+        assert!(!jar.add_cookie_str("c=3; Domain=other.com", &uri("http://example.com/")));
+
+        assert_eq!(
+            jar.cookies(&uri("http://example.com/app/home")).unwrap(),
+            "a=1; b=2"
+        );
+        assert_eq!(jar.cookies(&uri("http://api.example.com/")).unwrap(), "b=2");
+        assert_eq!(jar.cookies(&uri("http://example.com/")).unwrap(), "b=2");
+        assert!(jar.cookies(&uri("http://example.org/app")).is_none());
+    }
+
+    #[test]
+    fn secure_and_expiry() {
+        let jar = CookieJar::new();
+        jar.add_cookie_str("s=1; Secure", &uri("https://example.com/"));
+        jar.add_cookie_str("old=1; Max-Age=0", &uri("https://example.com/"));
+        jar.add_cookie_str(
+            "h=1; HttpOnly; SameSite=Strict",
+            &uri("https://example.com/"),
+        );
+
+        assert_eq!(jar.len(), 2);
+        assert!(jar
+            .cookies(&uri("http://example.com/"))
+            .is_some_and(|value| value == "h=1"));
+
+        let secure = jar.cookies(&uri("https://example.com/")).unwrap();
+        let secure = secure.to_str().unwrap();
+        assert!(secure.contains("s=1"));
+        assert!(secure.contains("h=1"));
+    }
+
+    #[tokio::test]
+    async fn service_sends_and_stores() {
+        let jar = CookieJar::new();
+        let service = CookieLayer::new(jar.clone()).layer(tower::service_fn(
+            |req: http::Request<()>| async move {
+                let mut response = http::Response::new(req.headers().get(COOKIE).cloned());
+                response
+                    .headers_mut()
+                    .insert(SET_COOKIE, HeaderValue::from_static("session=abc; Path=/"));
+                Ok::<_, Infallible>(response)
+            },
+        ));
+
+        let request = http::Request::get("http://example.com/login")
+            .body(())
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert!(response.body().is_none());
+        assert_eq!(jar.get("example.com", "/", "session").unwrap(), "abc");
+
+        let request = http::Request::get("http://example.com/home")
+            .body(())
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.body().as_ref().unwrap(), "session=abc");
+
+        let request = http::Request::get("http://example.com/home")
+            .header(COOKIE, "explicit=1")
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.body().as_ref().unwrap(), "explicit=1");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load() {
+        let jar = CookieJar::new();
+        jar.add_cookie_str("persistent=1; Max-Age=3600", &uri("http://example.com/"));
+        jar.add_cookie_str("session=1", &uri("http://example.com/"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        jar.save(&path).unwrap();
+
+        let loaded = CookieJar::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.get("example.com", "/", "persistent").unwrap(), "1");
+    }
+}
@@ -0,0 +1,136 @@
+//! A bounded cache of entries which expire, shared by the HSTS and Alt-Svc caches.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Expiry times are capped, so that huge max-age values do not overflow.
+const MAX_AGE_LIMIT: Duration = Duration::from_secs(60 * 60 * 24 * 365 * 100);
+
+/// The time an entry received at `now` with `max_age` expires, capped at
+/// [`MAX_AGE_LIMIT`].
+pub(crate) fn expires_at(now: SystemTime, max_age: Duration) -> SystemTime {
+    let max_age = max_age.min(MAX_AGE_LIMIT);
+    now.checked_add(max_age).unwrap_or(now)
+}
+
+/// A value stored in an [`ExpiringCache`].
+pub(crate) trait Expires {
+    /// When the value is no longer fresh.
+    fn expires(&self) -> SystemTime;
+}
+
+/// A map from keys to values which expire, holding at most `capacity` entries.
+///
+/// When the cache is full, expired entries are removed first, and then the entry
+/// which expires soonest is evicted. Expired entries are otherwise kept until they
+/// are evicted, so readers should check [`Expires::expires`].
+#[derive(Debug)]
+pub(crate) struct ExpiringCache<V> {
+    capacity: usize,
+    entries: HashMap<String, V>,
+}
+
+impl<V: Expires> ExpiringCache<V> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Insert or replace the value for `key`, evicting another entry if the cache is full.
+    pub(crate) fn insert(&mut self, key: String, value: V, now: SystemTime) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.entries.retain(|_, value| value.expires() > now);
+        }
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let soonest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, value)| value.expires())
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                self.entries.remove(&soonest);
+            }
+        }
+
+        if self.capacity > 0 {
+            self.entries.insert(key, value);
+        }
+    }
+
+    /// The value for `key`, which may have expired.
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        self.entries.remove(key)
+    }
+
+    /// The number of entries, including expired ones.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// All entries, including expired ones.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Expires for SystemTime {
+        fn expires(&self) -> SystemTime {
+            *self
+        }
+    }
+
+    #[test]
+    fn evicts_expired_then_soonest() {
+        let now = SystemTime::now();
+        let mut cache = ExpiringCache::with_capacity(2);
+        cache.insert("a".into(), now + Duration::from_secs(10), now);
+        cache.insert("b".into(), now + Duration::from_secs(30), now);
+
+        // Replacing an entry doesn't evict another.
+        cache.insert("b".into(), now + Duration::from_secs(20), now);
+        assert!(cache.get("a").is_some());
+
+        cache.insert("c".into(), now + Duration::from_secs(40), now);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+
+        let later = now + Duration::from_secs(25);
+        cache.insert("d".into(), now + Duration::from_secs(50), later);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("d").is_some());
+
+        let mut empty = ExpiringCache::with_capacity(0);
+        empty.insert("a".into(), now, now);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn expiry_is_capped() {
+        let now = SystemTime::now();
+        assert_eq!(expires_at(now, Duration::MAX), now + MAX_AGE_LIMIT);
+        assert_eq!(
+            expires_at(now, Duration::from_secs(1)),
+            now + Duration::from_secs(1)
+        );
+    }
+}
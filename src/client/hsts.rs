@@ -0,0 +1,480 @@
+//! HTTP Strict Transport Security for the client.
+//!
+//! An [`HstsCache`] remembers hosts which sent a `Strict-Transport-Security` header
+//! over `https`, following [RFC 6797](https://datatracker.ietf.org/doc/html/rfc6797),
+//! and upgrades later `http` requests to those hosts (and, with `includeSubDomains`,
+//! their subdomains) to `https`.
+//!
+//! The cache is applied to a client with [`Builder::with_hsts`](super::Builder::with_hsts)
+//! or [`Builder::with_hsts_cache`](super::Builder::with_hsts_cache). It holds a bounded
+//! number of hosts: when it is full, the entry which expires soonest is evicted.
+//!
+//! Requests are upgraded by an [`HstsLayer`], which the client places above its other
+//! layers, so that redirects, credentials and cookies all use the `https` URI. Redirects
+//! to known HSTS hosts are upgraded as they are followed.
+//!
+//! With the `serde` feature, the cache can be saved to and loaded from a JSON file.
+
+#[cfg(feature = "serde")]
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use http::header::STRICT_TRANSPORT_SECURITY;
+use http::uri::{Authority, Scheme};
+use http::{HeaderMap, Uri};
+
+use super::expiring::{expires_at, Expires, ExpiringCache};
+
+/// The default maximum number of hosts in an [`HstsCache`].
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+struct Entry {
+    include_subdomains: bool,
+    expires: SystemTime,
+}
+
+impl Expires for Entry {
+    fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+/// A shared cache of known HSTS hosts.
+///
+/// Cloning an `HstsCache` produces a handle to the same cache, so it can be shared
+/// between clients, or retained to inspect or save it after requests.
+#[derive(Clone)]
+pub struct HstsCache {
+    hosts: Arc<Mutex<ExpiringCache<Entry>>>,
+}
+
+impl fmt::Debug for HstsCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HstsCache")
+            .field("hosts", &self.len())
+            .finish()
+    }
+}
+
+impl Default for HstsCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl HstsCache {
+    /// Create a new, empty cache holding up to [`DEFAULT_CAPACITY`] hosts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new, empty cache holding up to `capacity` hosts.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            hosts: Arc::new(Mutex::new(ExpiringCache::with_capacity(capacity))),
+        }
+    }
+
+    /// Remember `host` as an HSTS host for `max_age`.
+    ///
+    /// A `max_age` of zero removes the host, as a `max-age=0` header does.
+    pub fn insert(&self, host: &str, max_age: Duration, include_subdomains: bool) {
+        self.insert_at(host, max_age, include_subdomains, SystemTime::now());
+    }
+
+    fn insert_at(&self, host: &str, max_age: Duration, include_subdomains: bool, now: SystemTime) {
+        let host = normalize(host);
+        let mut hosts = self.hosts.lock().unwrap();
+        if max_age.is_zero() {
+            hosts.remove(&host);
+            return;
+        }
+
+        let entry = Entry {
+            include_subdomains,
+            expires: expires_at(now, max_age),
+        };
+        hosts.insert(host, entry, now);
+    }
+
+    /// Forget `host`. Returns `true` if it was in the cache.
+    pub fn remove(&self, host: &str) -> bool {
+        self.hosts
+            .lock()
+            .unwrap()
+            .remove(&normalize(host))
+            .is_some()
+    }
+
+    /// Returns `true` if requests to `host` should only use `https`.
+    pub fn is_known(&self, host: &str) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        let host = normalize(host);
+        let now = SystemTime::now();
+        let live = |host: &str| hosts.get(host).filter(|entry| entry.expires > now);
+
+        if live(&host).is_some() {
+            return true;
+        }
+
+        // RFC 6797 section 8.2: superdomains with `includeSubDomains` also match.
+        host.match_indices('.').any(|(index, _)| {
+            live(&host[index + 1..]).is_some_and(|entry| entry.include_subdomains)
+        })
+    }
+
+    /// Update the cache from the `Strict-Transport-Security` header of a response to a
+    /// request for `uri`.
+    ///
+    /// The header is ignored unless the response was received over `https`, and for
+    /// hosts which are IP addresses.
+    pub fn store_response(&self, uri: &Uri, headers: &HeaderMap) {
+        if uri.scheme() != Some(&Scheme::HTTPS) {
+            return;
+        }
+
+        let Some(host) = uri.host().filter(|host| !is_ip_address(host)) else {
+            return;
+        };
+
+        // RFC 6797 section 8.1: only the first header is processed.
+        let Some(directives) = headers
+            .get(STRICT_TRANSPORT_SECURITY)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse)
+        else {
+            return;
+        };
+
+        self.insert(host, directives.max_age, directives.include_subdomains);
+    }
+
+    /// Returns the `https` URI to use instead of `uri`, if `uri` is an `http` URI for a
+    /// known HSTS host.
+    ///
+    /// An explicit port 80 is changed to 443, and other ports are kept.
+    pub fn upgrade(&self, uri: &Uri) -> Option<Uri> {
+        if uri.scheme() != Some(&Scheme::HTTP) {
+            return None;
+        }
+
+        let authority = uri.authority()?;
+        if !self.is_known(authority.host()) {
+            return None;
+        }
+
+        let authority = match authority.port_u16() {
+            Some(80) => Authority::try_from(authority.host()).ok()?,
+            _ => authority.clone(),
+        };
+
+        let mut parts = uri.clone().into_parts();
+        parts.scheme = Some(Scheme::HTTPS);
+        parts.authority = Some(authority);
+        Uri::from_parts(parts).ok()
+    }
+
+    /// Upgrade `req` to `https` if it is an `http` request to a known HSTS host.
+    pub(crate) fn upgrade_request<B>(&self, req: &mut http::Request<B>) {
+        if let Some(uri) = self.upgrade(req.uri()) {
+            tracing::trace!(%uri, "upgrading request to a known HSTS host");
+            *req.uri_mut() = uri;
+        }
+    }
+
+    /// The number of unexpired hosts in the cache.
+    pub fn len(&self) -> usize {
+        let now = SystemTime::now();
+        self.hosts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.expires > now)
+            .count()
+    }
+
+    /// Returns `true` if there are no unexpired hosts in the cache.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all hosts from the cache.
+    pub fn clear(&self) {
+        self.hosts.lock().unwrap().clear();
+    }
+}
+
+#[cfg(feature = "serde")]
+impl HstsCache {
+    /// Load unexpired hosts from JSON, as written by [`HstsCache::save_json`].
+    pub fn load_json<R: std::io::Read>(reader: R) -> std::io::Result<Self> {
+        let entries: HashMap<String, Entry> = serde_json::from_reader(reader)?;
+        let cache = Self::new();
+        let now = SystemTime::now();
+        {
+            let mut hosts = cache.hosts.lock().unwrap();
+            for (host, entry) in entries {
+                if entry.expires > now {
+                    hosts.insert(normalize(&host), entry, now);
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Save unexpired hosts as JSON.
+    pub fn save_json<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let now = SystemTime::now();
+        let hosts = self.hosts.lock().unwrap();
+        let entries: HashMap<&String, &Entry> = hosts
+            .iter()
+            .filter(|(_, entry)| entry.expires > now)
+            .collect();
+        serde_json::to_writer(writer, &entries).map_err(std::io::Error::other)
+    }
+
+    /// Load unexpired hosts from a JSON file.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::load_json(std::io::BufReader::new(file))
+    }
+
+    /// Save unexpired hosts to a JSON file, replacing its contents.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.save_json(&mut file)?;
+        std::io::Write::flush(&mut file)
+    }
+}
+
+/// A layer which upgrades `http` requests to known HSTS hosts to `https`.
+///
+/// The cache is also added to each request's extensions, so that the client's redirect
+/// policy can upgrade redirects to known HSTS hosts.
+#[derive(Debug, Clone)]
+pub struct HstsLayer {
+    cache: Option<HstsCache>,
+}
+
+impl HstsLayer {
+    /// Create a new HSTS layer using the given cache.
+    pub fn new(cache: HstsCache) -> Self {
+        Self { cache: Some(cache) }
+    }
+
+    /// Create an HSTS layer which is a no-op when `cache` is `None`.
+    ///
+    /// Unlike an optional layer, this does not change the error type of the inner service.
+    pub(crate) fn optional(cache: Option<HstsCache>) -> Self {
+        Self { cache }
+    }
+}
+
+impl<S> tower::Layer<S> for HstsLayer {
+    type Service = HstsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HstsService {
+            inner,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+/// A service which upgrades `http` requests to known HSTS hosts to `https`.
+#[derive(Debug, Clone)]
+pub struct HstsService<S> {
+    inner: S,
+    cache: Option<HstsCache>,
+}
+
+impl<S> HstsService<S> {
+    /// Create a new HSTS service wrapping `inner`.
+    pub fn new(inner: S, cache: HstsCache) -> Self {
+        Self {
+            inner,
+            cache: Some(cache),
+        }
+    }
+
+    /// The HSTS cache used by this service.
+    pub fn cache(&self) -> Option<&HstsCache> {
+        self.cache.as_ref()
+    }
+}
+
+impl<S, B> tower::Service<http::Request<B>> for HstsService<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if let Some(cache) = &self.cache {
+            cache.upgrade_request(&mut req);
+            req.extensions_mut().insert(cache.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Directives {
+    max_age: Duration,
+    include_subdomains: bool,
+}
+
+/// Parse a `Strict-Transport-Security` header value.
+fn parse(value: &str) -> Option<Directives> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+
+    for directive in value.split(';') {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.trim(), None),
+        };
+
+        if name.eq_ignore_ascii_case("max-age") {
+            let seconds: u64 = value?.parse().ok()?;
+            max_age = Some(Duration::from_secs(seconds));
+        } else if name.eq_ignore_ascii_case("includesubdomains") {
+            include_subdomains = true;
+        }
+    }
+
+    Some(Directives {
+        max_age: max_age?,
+        include_subdomains,
+    })
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn is_ip_address(host: &str) -> bool {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::HeaderValue;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(STRICT_TRANSPORT_SECURITY, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(
+            parse("max-age=31536000; includeSubDomains"),
+            Some(Directives {
+                max_age: Duration::from_secs(31536000),
+                include_subdomains: true
+            })
+        );
+        assert_eq!(
+            parse(r#"MAX-AGE="60"; preload"#),
+            Some(Directives {
+                max_age: Duration::from_secs(60),
+                include_subdomains: false
+            })
+        );
+        assert_eq!(parse("includeSubDomains"), None);
+        assert_eq!(parse("max-age=soon"), None);
+    }
+
+    #[test]
+    fn upgrade_known_hosts() {
+        let cache = HstsCache::new();
+        let secure: Uri = "https://Example.com/".parse().unwrap();
+        cache.store_response(&secure, &headers("max-age=60; includeSubDomains"));
+        assert_eq!(cache.len(), 1);
+
+        let upgraded = cache.upgrade(&"http://example.com/a?b".parse().unwrap());
+        assert_eq!(upgraded.unwrap(), "https://example.com/a?b");
+
+        let upgraded = cache.upgrade(&"http://www.example.com:80/".parse().unwrap());
+        assert_eq!(upgraded.unwrap(), "https://www.example.com/");
+
+        let upgraded = cache.upgrade(&"http://example.com:8080/".parse().unwrap());
+        assert_eq!(upgraded.unwrap(), "https://example.com:8080/");
+
+        assert!(cache
+            .upgrade(&"http://notexample.com/".parse().unwrap())
+            .is_none());
+        assert!(cache
+            .upgrade(&"https://example.com/".parse().unwrap())
+            .is_none());
+
+        cache.store_response(&secure, &headers("max-age=0"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn ignored_responses() {
+        let cache = HstsCache::new();
+        cache.store_response(
+            &"http://example.com/".parse().unwrap(),
+            &headers("max-age=60"),
+        );
+        cache.store_response(
+            &"https://127.0.0.1/".parse().unwrap(),
+            &headers("max-age=60"),
+        );
+        assert!(cache.is_empty());
+
+        cache.insert("example.com", Duration::from_secs(60), false);
+        assert!(!cache.is_known("www.example.com"));
+    }
+
+    #[test]
+    fn expiry_and_capacity() {
+        let cache = HstsCache::with_capacity(2);
+        let now = SystemTime::now();
+        cache.insert_at("a.com", Duration::from_secs(10), false, now);
+        cache.insert_at("b.com", Duration::from_secs(30), false, now);
+        cache.insert_at("c.com", Duration::from_secs(20), false, now);
+        assert!(!cache.is_known("a.com"));
+        assert!(cache.is_known("b.com"));
+        assert!(cache.is_known("c.com"));
+
+        let past = now - Duration::from_secs(60);
+        cache.insert_at("d.com", Duration::from_secs(1), false, past);
+        assert!(!cache.is_known("d.com"));
+        assert!(cache.is_known("b.com"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load() {
+        let cache = HstsCache::new();
+        cache.insert("example.com", Duration::from_secs(60), true);
+
+        let mut saved = Vec::new();
+        cache.save_json(&mut saved).unwrap();
+
+        let loaded = HstsCache::load_json(saved.as_slice()).unwrap();
+        assert!(loaded.is_known("www.example.com"));
+    }
+}
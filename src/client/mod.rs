@@ -25,17 +25,31 @@ pub use self::service::ClientService;
 use crate::client::conn::connection::ConnectionError;
 use crate::service::SharedService;
 
+pub mod alt_svc;
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod balance;
+pub mod breaker;
 mod builder;
 pub mod conn;
+#[cfg(feature = "cookies")]
+pub mod cookies;
+mod expiring;
+pub mod hsts;
+pub mod options;
 pub mod pool;
+pub mod redirect;
+mod request;
 mod service;
 
 pub use builder::Builder;
+pub use request::{RequestBuilder, Response};
 
 pub use pool::Config as PoolConfig;
 
 /// Client error type.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     /// Error occured with the underlying connection.
     #[error(transparent)]
@@ -60,6 +74,68 @@ pub enum Error {
     /// Protocol is not supported by this client or transport.
     #[error("unsupported protocol")]
     UnsupportedProtocol,
+
+    /// The circuit breaker for this host is open, so the request was not sent.
+    #[error("circuit breaker open for {0}")]
+    CircuitOpen(String),
+
+    /// Credentials for the request could not be obtained.
+    #[error("authentication: {0}")]
+    Auth(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// The response has a client (4xx) or server (5xx) error status.
+    #[error("HTTP status error: {0}")]
+    Status(http::StatusCode),
+}
+
+/// Error returned by [`Client::extended_connect`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ExtendedConnectError {
+    /// The request is not an extended CONNECT request.
+    #[error("not an extended CONNECT request: {0}")]
+    InvalidRequest(http::Method),
+
+    /// The request could not be sent, or no response was received.
+    #[error(transparent)]
+    Request(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// The server responded with a status other than success (2xx).
+    #[error("extended CONNECT rejected: {0}")]
+    Rejected(http::StatusCode),
+
+    /// The server accepted the request, but the stream could not be upgraded.
+    #[error("upgrade: {0}")]
+    Upgrade(#[source] std::io::Error),
+}
+
+impl Error {
+    /// Returns true if this error occured before the request was sent to the server.
+    ///
+    /// This covers errors establishing the connection, errors during the protocol
+    /// handshake, and requests which were canceled before being written to the
+    /// connection. Such requests can always be safely retried.
+    pub fn is_connect(&self) -> bool {
+        match self {
+            Error::Transport(_) => true,
+            Error::Connection(error) => {
+                if let Some(error) = error.downcast_ref::<ConnectionError>() {
+                    matches!(
+                        error,
+                        ConnectionError::Connecting(_)
+                            | ConnectionError::Handshake(_)
+                            | ConnectionError::Canceled(_)
+                            | ConnectionError::Timeout
+                    )
+                } else if let Some(error) = error.downcast_ref::<hyper::Error>() {
+                    error.is_canceled()
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
 }
 
 impl From<pool::Error<ConnectionError>> for Error {
@@ -190,7 +266,16 @@ impl Client {
         self.inner.request(request)
     }
 
+    /// Start building a request with the given method and URI.
+    ///
+    /// The returned builder can be awaited directly to send the request.
+    pub fn request_builder(&self, method: http::Method, uri: http::Uri) -> RequestBuilder {
+        RequestBuilder::new(self.clone(), method, uri)
+    }
+
     /// Make a GET request to the given URI.
+    ///
+    /// To add headers or options to the request, use [`Client::request_builder`].
     pub async fn get(
         &self,
         uri: http::Uri,
@@ -203,6 +288,63 @@ impl Client {
         let response = self.request(request).await?;
         Ok(response)
     }
+
+    /// Make a POST request to the given URI.
+    pub fn post(&self, uri: http::Uri) -> RequestBuilder {
+        self.request_builder(http::Method::POST, uri)
+    }
+
+    /// Make a PUT request to the given URI.
+    pub fn put(&self, uri: http::Uri) -> RequestBuilder {
+        self.request_builder(http::Method::PUT, uri)
+    }
+
+    /// Make a PATCH request to the given URI.
+    pub fn patch(&self, uri: http::Uri) -> RequestBuilder {
+        self.request_builder(http::Method::PATCH, uri)
+    }
+
+    /// Make a DELETE request to the given URI.
+    pub fn delete(&self, uri: http::Uri) -> RequestBuilder {
+        self.request_builder(http::Method::DELETE, uri)
+    }
+
+    /// Make a HEAD request to the given URI.
+    pub fn head(&self, uri: http::Uri) -> RequestBuilder {
+        self.request_builder(http::Method::HEAD, uri)
+    }
+
+    /// Send an extended CONNECT (RFC 8441) request, and return the upgraded stream.
+    ///
+    /// The request should be built with [`crate::upgrade::extended_connect`], and will be
+    /// sent over a (possibly pooled) HTTP/2 connection. The server must advertise
+    /// `SETTINGS_ENABLE_CONNECT_PROTOCOL`. If the server responds with a successful (2xx)
+    /// status, the response and the upgraded stream are returned. Any other status is
+    /// returned as [`ExtendedConnectError::Rejected`].
+    pub async fn extended_connect(
+        &self,
+        mut request: crate::body::Request,
+    ) -> Result<(http::Response<crate::Body>, crate::upgrade::Upgraded), ExtendedConnectError> {
+        if crate::upgrade::connect_protocol(&request).is_none() {
+            return Err(ExtendedConnectError::InvalidRequest(
+                request.method().clone(),
+            ));
+        }
+        *request.version_mut() = http::Version::HTTP_2;
+
+        let mut response = self
+            .request(request)
+            .await
+            .map_err(ExtendedConnectError::Request)?;
+        if !response.status().is_success() {
+            return Err(ExtendedConnectError::Rejected(response.status()));
+        }
+
+        let upgraded = crate::upgrade::on(&mut response)
+            .await
+            .map_err(ExtendedConnectError::Upgrade)?;
+        Ok((response, upgraded))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,351 @@
+//! Per-request configuration overrides.
+//!
+//! Insert [`RequestOptions`] into a request's extensions to override the client's
+//! configuration for that request only, so that requests with different needs can
+//! share one [`Client`](crate::Client) and its connection pool:
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use hyperdriver::client::options::RequestOptions;
+//! let mut request = http::Request::get("http://example.com/health")
+//!     .body(hyperdriver::Body::empty())
+//!     .unwrap();
+//!
+//! request.extensions_mut().insert(
+//!     RequestOptions::new()
+//!         .with_timeout(Duration::from_secs(1))
+//!         .without_retries(),
+//! );
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tower::{Layer, Service};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Configuration overrides for a single request.
+///
+/// Options which are not set use the client's configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    timeout: Option<Option<Duration>>,
+    deadline: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    retries: Option<usize>,
+    redirects: Option<usize>,
+    version: Option<http::Version>,
+}
+
+impl RequestOptions {
+    /// Create a new set of options, which doesn't override anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout for this request.
+    ///
+    /// Like the client's timeout, this applies to each attempt when the request
+    /// is retried or hedged. Use [`RequestOptions::with_deadline`] to limit the
+    /// total time spent on the request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(Some(timeout));
+        self
+    }
+
+    /// Disable the timeout for this request.
+    pub fn without_timeout(mut self) -> Self {
+        self.timeout = Some(None);
+        self
+    }
+
+    /// Set a deadline for this request, covering every attempt made when the
+    /// request is retried or hedged, and the time spent waiting between them.
+    ///
+    /// The per-attempt timeout (see [`RequestOptions::with_timeout`]) still applies,
+    /// so whichever elapses first fails the request.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set the timeout for connecting to the server, if a new connection is needed.
+    ///
+    /// The transport's own connect timeout still applies.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum number of retries for this request.
+    ///
+    /// This has no effect if the client does not retry requests.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Don't retry this request.
+    pub fn without_retries(self) -> Self {
+        self.with_retries(0)
+    }
+
+    /// Set the maximum number of redirects followed for this request.
+    ///
+    /// This has no effect if the client does not follow redirects. Only the limit
+    /// can be changed: the client's redirect policy still decides whether each
+    /// redirect is followed, and can't be replaced for a single request.
+    pub fn with_max_redirects(mut self, redirects: usize) -> Self {
+        self.redirects = Some(redirects);
+        self
+    }
+
+    /// Don't follow redirects for this request.
+    pub fn without_redirects(self) -> Self {
+        self.with_max_redirects(0)
+    }
+
+    /// Set the HTTP version for this request, which is also used to choose
+    /// the protocol for new connections.
+    pub fn with_version(mut self, version: http::Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    pub(crate) fn retries(&self) -> Option<usize> {
+        self.retries
+    }
+
+    pub(crate) fn redirects(&self) -> Option<usize> {
+        self.redirects
+    }
+}
+
+/// Applies the deadline set in [`RequestOptions`] to the whole request.
+///
+/// This belongs above the retry and hedge layers, so that the deadline covers
+/// every attempt.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestDeadlineLayer {
+    _priv: (),
+}
+
+impl RequestDeadlineLayer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for RequestDeadlineLayer {
+    type Service = RequestDeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestDeadlineService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestDeadlineService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for RequestDeadlineService<S>
+where
+    S: Service<http::Request<B>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = RequestOptionsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let deadline = req
+            .extensions()
+            .get::<RequestOptions>()
+            .and_then(|options| options.deadline);
+
+        RequestOptionsFuture {
+            inner: self.inner.call(req),
+            sleep: deadline.map(tokio::time::sleep),
+        }
+    }
+}
+
+/// Applies the request timeout and HTTP version, honoring [`RequestOptions`].
+#[derive(Debug, Clone)]
+pub(crate) struct RequestOptionsLayer {
+    timeout: Option<Duration>,
+}
+
+impl RequestOptionsLayer {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<S> Layer<S> for RequestOptionsLayer {
+    type Service = RequestOptionsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestOptionsService {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestOptionsService<S> {
+    inner: S,
+    timeout: Option<Duration>,
+}
+
+impl<S, B> Service<http::Request<B>> for RequestOptionsService<S>
+where
+    S: Service<http::Request<B>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = RequestOptionsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let mut timeout = self.timeout;
+        if let Some(options) = req.extensions().get::<RequestOptions>() {
+            timeout = options.timeout.unwrap_or(timeout);
+            if let Some(version) = options.version {
+                *req.version_mut() = version;
+            }
+        }
+
+        RequestOptionsFuture {
+            inner: self.inner.call(req),
+            sleep: timeout.map(tokio::time::sleep),
+        }
+    }
+}
+
+#[pin_project::pin_project]
+pub(crate) struct RequestOptionsFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: Option<tokio::time::Sleep>,
+}
+
+impl<F> fmt::Debug for RequestOptionsFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestOptionsFuture").finish()
+    }
+}
+
+impl<F, T, E> Future for RequestOptionsFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(result.map_err(Into::into));
+        }
+
+        if let Some(sleep) = this.sleep.as_pin_mut() {
+            futures_util::ready!(sleep.poll(cx));
+            return Poll::Ready(Err(tower::timeout::error::Elapsed::new().into()));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tower::ServiceExt as _;
+
+    fn slow(
+    ) -> impl Service<http::Request<crate::Body>, Response = http::Version, Error = BoxError> + Clone
+    {
+        tower::service_fn(|req: http::Request<crate::Body>| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok::<_, BoxError>(req.version())
+        })
+    }
+
+    fn request(options: Option<RequestOptions>) -> http::Request<crate::Body> {
+        let mut req = http::Request::new(crate::Body::empty());
+        if let Some(options) = options {
+            req.extensions_mut().insert(options);
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn default_timeout() {
+        let service = RequestOptionsLayer::new(Some(Duration::from_millis(5))).layer(slow());
+        let error = service.oneshot(request(None)).await.unwrap_err();
+        assert!(error.is::<tower::timeout::error::Elapsed>());
+    }
+
+    #[tokio::test]
+    async fn override_timeout() {
+        let service = RequestOptionsLayer::new(Some(Duration::from_millis(5))).layer(slow());
+        let options = RequestOptions::new().with_timeout(Duration::from_secs(5));
+        service
+            .clone()
+            .oneshot(request(Some(options)))
+            .await
+            .unwrap();
+
+        let options = RequestOptions::new().without_timeout();
+        service
+            .clone()
+            .oneshot(request(Some(options)))
+            .await
+            .unwrap();
+
+        let service = RequestOptionsLayer::new(None).layer(slow());
+        let options = RequestOptions::new().with_timeout(Duration::from_millis(5));
+        let error = service.oneshot(request(Some(options))).await.unwrap_err();
+        assert!(error.is::<tower::timeout::error::Elapsed>());
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        let service = RequestDeadlineLayer::new().layer(slow());
+        service.clone().oneshot(request(None)).await.unwrap();
+
+        let options = RequestOptions::new().with_deadline(Duration::from_millis(5));
+        let error = service.oneshot(request(Some(options))).await.unwrap_err();
+        assert!(error.is::<tower::timeout::error::Elapsed>());
+    }
+
+    #[tokio::test]
+    async fn override_version() {
+        let service = RequestOptionsLayer::new(None).layer(slow());
+        let options = RequestOptions::new().with_version(http::Version::HTTP_2);
+        let version = service.oneshot(request(Some(options))).await.unwrap();
+        assert_eq!(version, http::Version::HTTP_2);
+    }
+}
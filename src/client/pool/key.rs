@@ -17,8 +17,32 @@ pub enum UriError {
     MissingScheme(http::Uri),
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
-pub(crate) struct Key(http::uri::Scheme, Option<http::uri::Authority>);
+/// Identifies the connections which can be shared, by scheme and authority, and the
+/// endpoint connected to when a load balancer chose one.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub(crate) struct Key(
+    http::uri::Scheme,
+    Option<http::uri::Authority>,
+    Option<http::uri::Authority>,
+);
+
+impl Key {
+    /// Key connections made to `endpoint` instead of the URI's authority.
+    pub(crate) fn with_endpoint(self, endpoint: http::uri::Authority) -> Self {
+        Self(self.0, self.1, Some(endpoint))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tuple = f.debug_tuple("Key");
+        tuple.field(&self.0).field(&self.1);
+        if let Some(endpoint) = &self.2 {
+            tuple.field(endpoint);
+        }
+        tuple.finish()
+    }
+}
 
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -27,13 +51,17 @@ impl fmt::Display for Key {
             "{}://{}",
             self.0,
             self.1.as_ref().map_or("", |a| a.as_str())
-        )
+        )?;
+        if let Some(endpoint) = &self.2 {
+            write!(f, " via {endpoint}")?;
+        }
+        Ok(())
     }
 }
 
 impl From<(http::uri::Scheme, http::uri::Authority)> for Key {
     fn from(value: (http::uri::Scheme, http::uri::Authority)) -> Self {
-        Self(value.0, Some(value.1))
+        Self(value.0, Some(value.1), None)
     }
 }
 
@@ -48,6 +76,7 @@ impl TryFrom<http::Uri> for Key {
                 .scheme
                 .ok_or_else(|| UriError::MissingScheme(value.clone()))?,
             parts.authority,
+            None,
         ))
     }
 }
@@ -81,6 +110,7 @@ pub(crate) mod test_key {
         let key = Key(
             http::uri::Scheme::HTTP,
             Some(http::uri::Authority::from_static("localhost:8080")),
+            None,
         );
         assert_eq!(key.to_string(), "http://localhost:8080");
     }
@@ -104,7 +134,21 @@ pub(crate) mod test_key {
         let key = Key(
             http::uri::Scheme::HTTP,
             Some(http::uri::Authority::from_static("localhost:8080")),
+            None,
         );
         assert_eq!(format!("{:?}", key), "Key(\"http\", Some(localhost:8080))");
     }
+
+    #[test]
+    fn key_with_endpoint() {
+        let key: Key = "https://api.internal".parse().unwrap();
+        let endpoint = key
+            .clone()
+            .with_endpoint(http::uri::Authority::from_static("10.0.0.1:443"));
+        assert_ne!(key, endpoint);
+        assert_eq!(
+            endpoint.to_string(),
+            "https://api.internal via 10.0.0.1:443"
+        );
+    }
 }
@@ -0,0 +1,619 @@
+//! Redirect policy for the client.
+//!
+//! [`RedirectPolicy`] is a stricter alternative to tower-http's
+//! [`Standard`](tower_http::follow_redirect::policy::Standard) policy, for use with
+//! [`Builder::with_redirect_policy`](super::Builder::with_redirect_policy). It:
+//!
+//! - Limits the number of redirects followed, to 20 by default. The limit is replaced
+//!   by [`RequestOptions::with_max_redirects`] for requests which set it.
+//! - Removes sensitive headers (`Authorization`, `Cookie` and `Proxy-Authorization`)
+//!   when a redirect crosses to another origin.
+//! - Refuses to follow redirects from `https` to `http`, unless allowed. Redirects to
+//!   known HSTS hosts are upgraded to `https` when the client has an
+//!   [`HstsCache`], so they are followed.
+//! - Calls an optional hook for each redirect, which can stop following redirects.
+//!
+//! When the client follows redirects, every response has a [`RedirectChain`]
+//! extension listing the URIs which were requested.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use http::header::{HeaderName, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION};
+use http::uri::Scheme;
+use http::{StatusCode, Uri};
+use tower::{Layer, Service};
+use tower_http::follow_redirect::policy::{Action, Attempt, Policy};
+
+use super::hsts::HstsCache;
+use super::options::RequestOptions;
+use crate::Body;
+
+/// The default maximum number of redirects followed.
+pub const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+/// A redirect which the client could follow, passed to the hook set with
+/// [`RedirectPolicy::with_hook`].
+#[derive(Debug)]
+pub struct Hop<'a> {
+    status: StatusCode,
+    previous: &'a Uri,
+    location: &'a Uri,
+    redirects: usize,
+}
+
+impl Hop<'_> {
+    /// The status of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The URI of the request which was redirected.
+    pub fn previous(&self) -> &Uri {
+        self.previous
+    }
+
+    /// The URI the response redirects to.
+    pub fn location(&self) -> &Uri {
+        self.location
+    }
+
+    /// The number of redirects already followed for this request.
+    pub fn redirects(&self) -> usize {
+        self.redirects
+    }
+}
+
+type Hook = Arc<dyn Fn(&Hop<'_>) -> bool + Send + Sync + 'static>;
+
+/// The client's standard redirect policy. See the [module documentation](self).
+#[derive(Clone)]
+pub struct RedirectPolicy {
+    max: usize,
+    followed: usize,
+    allow_downgrade: bool,
+    sensitive: Arc<Vec<HeaderName>>,
+    hook: Option<Hook>,
+    previous: Option<Uri>,
+    hsts: Option<HstsCache>,
+}
+
+impl fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedirectPolicy")
+            .field("max", &self.max)
+            .field("allow_downgrade", &self.allow_downgrade)
+            .field("sensitive", &self.sensitive)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max: DEFAULT_MAX_REDIRECTS,
+            followed: 0,
+            allow_downgrade: false,
+            sensitive: Arc::new(vec![AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION]),
+            hook: None,
+            previous: None,
+            hsts: None,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Create the standard redirect policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of redirects followed for each request.
+    ///
+    /// Requests with their own limit, set with
+    /// [`RequestOptions::with_max_redirects`], use that limit instead.
+    pub fn with_max_redirects(mut self, max: usize) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Follow redirects from `https` to `http`.
+    pub fn with_downgrades(mut self) -> Self {
+        self.allow_downgrade = true;
+        self
+    }
+
+    /// Also remove this header when a redirect crosses to another origin.
+    pub fn with_sensitive_header(mut self, header: HeaderName) -> Self {
+        Arc::make_mut(&mut self.sensitive).push(header);
+        self
+    }
+
+    /// Call a hook for each redirect, before it is followed.
+    ///
+    /// Return `false` from the hook to stop following redirects, and return the
+    /// redirect response instead. The hook is not called for redirects which the
+    /// policy refuses to follow anyway.
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Hop<'_>) -> bool + Send + Sync + 'static,
+    {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// The maximum number of redirects followed for each request.
+    pub fn max_redirects(&self) -> usize {
+        self.max
+    }
+
+    /// Whether requests to `uri` are sent over `https`, including `http` URIs
+    /// which are upgraded because the host is a known HSTS host.
+    fn is_secure(&self, uri: &Uri) -> bool {
+        uri.scheme() == Some(&Scheme::HTTPS)
+            || self
+                .hsts
+                .as_ref()
+                .is_some_and(|hsts| hsts.upgrade(uri).is_some())
+    }
+}
+
+impl<B, E> Policy<B, E> for RedirectPolicy {
+    fn redirect(&mut self, attempt: &Attempt<'_>) -> Result<Action, E> {
+        if self.followed >= self.max {
+            tracing::debug!(max = self.max, "too many redirects");
+            return Ok(Action::Stop);
+        }
+
+        if !self.allow_downgrade
+            && self.is_secure(attempt.previous())
+            && !self.is_secure(attempt.location())
+        {
+            tracing::debug!(location = %attempt.location(), "refusing to downgrade from https");
+            return Ok(Action::Stop);
+        }
+
+        if let Some(hook) = &self.hook {
+            let hop = Hop {
+                status: attempt.status(),
+                previous: attempt.previous(),
+                location: attempt.location(),
+                redirects: self.followed,
+            };
+            if !hook(&hop) {
+                return Ok(Action::Stop);
+            }
+        }
+
+        self.followed += 1;
+        Ok(Action::Follow)
+    }
+
+    fn on_request(&mut self, request: &mut http::Request<B>) {
+        if let Some(max) = request
+            .extensions()
+            .get::<RequestOptions>()
+            .and_then(RequestOptions::redirects)
+        {
+            self.max = max;
+        }
+
+        if let Some(hsts) = request.extensions().get::<HstsCache>() {
+            self.hsts = Some(hsts.clone());
+        }
+
+        if let Some(previous) = &self.previous {
+            if !same_origin(previous, request.uri()) {
+                for header in self.sensitive.iter() {
+                    request.headers_mut().remove(header);
+                }
+            }
+        }
+        self.previous = Some(request.uri().clone());
+    }
+}
+
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    fn port(uri: &Uri) -> Option<u16> {
+        uri.port_u16().or_else(|| match uri.scheme_str() {
+            Some("https") => Some(443),
+            Some("http") => Some(80),
+            _ => None,
+        })
+    }
+
+    a.scheme() == b.scheme()
+        && a.host().map(str::to_ascii_lowercase) == b.host().map(str::to_ascii_lowercase)
+        && port(a) == port(b)
+}
+
+/// The URIs requested while following redirects, as a response extension.
+///
+/// The first URI is the original request, and the last is the URI which
+/// produced the response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedirectChain {
+    uris: Vec<Uri>,
+}
+
+impl RedirectChain {
+    /// The URIs requested, in order.
+    pub fn uris(&self) -> &[Uri] {
+        &self.uris
+    }
+
+    /// The number of redirects which were followed.
+    pub fn redirects(&self) -> usize {
+        self.uris.len().saturating_sub(1)
+    }
+}
+
+/// Collects the URIs requested by the redirect policy.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChainRecorder(Arc<Mutex<Vec<Uri>>>);
+
+impl ChainRecorder {
+    pub(crate) fn record(&self, uri: &Uri) {
+        self.0.lock().unwrap().push(uri.clone());
+    }
+}
+
+/// Adds the [`RedirectChain`] extension to responses.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RedirectChainLayer;
+
+impl<S> Layer<S> for RedirectChainLayer {
+    type Service = RedirectChainService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RedirectChainService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RedirectChainService<S> {
+    inner: S,
+}
+
+impl<S, B, RB> Service<http::Request<B>> for RedirectChainService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<RB>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = RedirectChainFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let recorder = ChainRecorder::default();
+        req.extensions_mut().insert(recorder.clone());
+        RedirectChainFuture {
+            inner: self.inner.call(req),
+            recorder,
+        }
+    }
+}
+
+#[pin_project::pin_project]
+#[derive(Debug)]
+pub(crate) struct RedirectChainFuture<F> {
+    #[pin]
+    inner: F,
+    recorder: ChainRecorder,
+}
+
+impl<F, RB, E> Future for RedirectChainFuture<F>
+where
+    F: Future<Output = Result<http::Response<RB>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = futures_util::ready!(this.inner.poll(cx))?;
+
+        let uris = std::mem::take(&mut *this.recorder.0.lock().unwrap());
+        response.extensions_mut().insert(RedirectChain { uris });
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Wraps the client's redirect policy so that request bodies which can be replayed
+/// are resent when following `307` and `308` redirects, so that [`RequestOptions`]
+/// apply to the whole chain of redirects, so that redirects to known HSTS hosts are
+/// upgraded, and so that the chain is recorded.
+#[derive(Debug)]
+pub(crate) struct ClientRedirectPolicy<P> {
+    inner: P,
+
+    /// Used to check that a replayable request body can still be replayed.
+    body: Option<crate::body::ReplayHandle>,
+
+    /// Options from the original request, which are not carried over to
+    /// followed requests by the redirect layer.
+    options: Option<RequestOptions>,
+
+    /// Number of redirects followed so far.
+    followed: usize,
+
+    /// Records the URIs requested, for the redirect chain extension.
+    chain: Option<ChainRecorder>,
+
+    /// Upgrades followed requests to known HSTS hosts, since they are not sent
+    /// through the HSTS layer above the redirect layer.
+    hsts: Option<HstsCache>,
+}
+
+impl<P> ClientRedirectPolicy<P> {
+    pub(crate) fn new(inner: P) -> Self {
+        Self {
+            inner,
+            body: None,
+            options: None,
+            followed: 0,
+            chain: None,
+            hsts: None,
+        }
+    }
+}
+
+impl<P: Clone> Clone for ClientRedirectPolicy<P> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+impl<P, E> Policy<Body, E> for ClientRedirectPolicy<P>
+where
+    P: Policy<Body, E>,
+{
+    fn redirect(&mut self, attempt: &Attempt<'_>) -> Result<Action, E> {
+        let resends_body = matches!(
+            attempt.status(),
+            http::StatusCode::TEMPORARY_REDIRECT | http::StatusCode::PERMANENT_REDIRECT
+        );
+
+        // The body overflowed its replay buffer while it was sent.
+        if resends_body && self.body.as_ref().is_some_and(|body| !body.is_replayable()) {
+            return Ok(Action::Stop);
+        }
+
+        let max = self.options.as_ref().and_then(RequestOptions::redirects);
+        if max.is_some_and(|max| self.followed >= max) {
+            return Ok(Action::Stop);
+        }
+
+        let action = self.inner.redirect(attempt)?;
+        if action.is_follow() {
+            self.followed += 1;
+        }
+        Ok(action)
+    }
+
+    fn on_request(&mut self, request: &mut http::Request<Body>) {
+        match request.extensions().get::<RequestOptions>() {
+            Some(options) => self.options = Some(options.clone()),
+            None => {
+                if let Some(options) = self.options.clone() {
+                    request.extensions_mut().insert(options);
+                }
+            }
+        }
+
+        match request.extensions().get::<HstsCache>() {
+            Some(hsts) => self.hsts = Some(hsts.clone()),
+            None => {
+                if let Some(hsts) = self.hsts.clone() {
+                    hsts.upgrade_request(request);
+                    request.extensions_mut().insert(hsts);
+                }
+            }
+        }
+
+        if let Some(chain) = request.extensions().get::<ChainRecorder>() {
+            self.chain = Some(chain.clone());
+        }
+        if let Some(chain) = &self.chain {
+            chain.record(request.uri());
+        }
+
+        self.body = request.body().replay_handle();
+        self.inner.on_request(request)
+    }
+
+    fn clone_body(&self, body: &Body) -> Option<Body> {
+        self.inner.clone_body(body).or_else(|| body.try_clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::HeaderValue;
+    use tower::ServiceExt as _;
+    use tower_http::follow_redirect::FollowRedirectLayer;
+
+    /// Redirects according to the path, and echoes the sensitive headers it receives.
+    fn service() -> impl Service<
+        http::Request<Body>,
+        Response = http::Response<Body>,
+        Error = std::convert::Infallible,
+        Future = impl Send,
+    > + Clone {
+        tower::service_fn(|req: http::Request<Body>| async move {
+            let location = match req.uri().path() {
+                "/same" => Some("http://example.com/done"),
+                "/other" => Some("http://other.com/done"),
+                "/downgrade" => Some("http://example.com/done"),
+                "/loop" => Some("/loop"),
+                _ => None,
+            };
+
+            let mut response = http::Response::builder();
+            if let Some(location) = location {
+                response = response
+                    .status(StatusCode::FOUND)
+                    .header(http::header::LOCATION, location);
+            }
+            let cookie = req.headers().contains_key(COOKIE).to_string();
+            Ok(response
+                .header("x-cookie", cookie)
+                .body(Body::empty())
+                .unwrap())
+        })
+    }
+
+    async fn send(policy: RedirectPolicy, uri: &str) -> http::Response<Body> {
+        send_with_options(policy, uri, None).await
+    }
+
+    async fn send_with_options(
+        policy: RedirectPolicy,
+        uri: &str,
+        options: Option<RequestOptions>,
+    ) -> http::Response<Body> {
+        let service = tower::ServiceBuilder::new()
+            .layer(RedirectChainLayer)
+            .layer(FollowRedirectLayer::with_policy(ClientRedirectPolicy::new(
+                policy,
+            )))
+            .service(service());
+
+        let mut request = http::Request::get(uri)
+            .header(COOKIE, HeaderValue::from_static("a=b"))
+            .body(Body::empty())
+            .unwrap();
+        if let Some(options) = options {
+            request.extensions_mut().insert(options);
+        }
+        service.oneshot(request).await.unwrap()
+    }
+
+    fn chain(response: &http::Response<Body>) -> Vec<String> {
+        response
+            .extensions()
+            .get::<RedirectChain>()
+            .unwrap()
+            .uris()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn same_origin_keeps_headers() {
+        let response = send(RedirectPolicy::new(), "http://example.com/same").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-cookie"], "true");
+    }
+
+    #[tokio::test]
+    async fn cross_origin_strips_headers() {
+        let response = send(RedirectPolicy::new(), "http://example.com/other").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-cookie"], "false");
+        assert_eq!(
+            chain(&response),
+            vec!["http://example.com/other", "http://other.com/done"]
+        );
+    }
+
+    #[tokio::test]
+    async fn downgrade_refused() {
+        let response = send(RedirectPolicy::new(), "https://example.com/downgrade").await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(chain(&response), vec!["https://example.com/downgrade"]);
+
+        let response = send(
+            RedirectPolicy::new().with_downgrades(),
+            "https://example.com/downgrade",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The origin changed, so the cookie was removed.
+        assert_eq!(response.headers()["x-cookie"], "false");
+    }
+
+    #[tokio::test]
+    async fn downgrade_to_hsts_host_is_upgraded() {
+        let hsts = HstsCache::new();
+        hsts.insert("example.com", std::time::Duration::from_secs(60), false);
+
+        let service = tower::ServiceBuilder::new()
+            .layer(crate::client::hsts::HstsLayer::new(hsts))
+            .layer(RedirectChainLayer)
+            .layer(FollowRedirectLayer::with_policy(ClientRedirectPolicy::new(
+                RedirectPolicy::new(),
+            )))
+            .service(service());
+
+        let request = http::Request::get("https://example.com/downgrade")
+            .header(COOKIE, HeaderValue::from_static("a=b"))
+            .body(Body::empty())
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            chain(&response),
+            vec!["https://example.com/downgrade", "https://example.com/done"]
+        );
+        // The redirect stayed on the same origin once it was upgraded.
+        assert_eq!(response.headers()["x-cookie"], "true");
+
+        let request = http::Request::get("http://example.com/other")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(chain(&response), vec!["https://example.com/other"]);
+    }
+
+    #[tokio::test]
+    async fn max_redirects() {
+        let response = send(
+            RedirectPolicy::new().with_max_redirects(3),
+            "http://example.com/loop",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let chain = response.extensions().get::<RedirectChain>().unwrap();
+        assert_eq!(chain.redirects(), 3);
+    }
+
+    #[tokio::test]
+    async fn request_max_redirects_replaces_policy_max() {
+        for max in [1, 5] {
+            let response = send_with_options(
+                RedirectPolicy::new().with_max_redirects(3),
+                "http://example.com/loop",
+                Some(RequestOptions::new().with_max_redirects(max)),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            let chain = response.extensions().get::<RedirectChain>().unwrap();
+            assert_eq!(chain.redirects(), max);
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_stops_redirects() {
+        let policy = RedirectPolicy::new().with_hook(|hop| {
+            assert_eq!(hop.status(), StatusCode::FOUND);
+            hop.location().host() == Some("example.com")
+        });
+
+        let response = send(policy.clone(), "http://example.com/same").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(policy, "http://example.com/other").await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+    }
+}
@@ -0,0 +1,490 @@
+//! Fluent request builder and response wrapper for [`Client`].
+
+use std::fmt;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
+use http_body_util::{BodyDataStream, BodyExt as _};
+
+use super::options::RequestOptions;
+use super::{Client, Error};
+use crate::body::multipart::Form;
+use crate::Body;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A builder for a request sent by a [`Client`].
+///
+/// Errors which occur while building the request are returned when it is sent.
+///
+/// # Example
+/// ```no_run
+/// # use std::time::Duration;
+/// # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let client = hyperdriver::Client::new_tcp_http();
+/// let text = client
+///     .post("http://example.com/upload".parse()?)
+///     .header("x-request-id", "1234")
+///     .body("hello")
+///     .timeout(Duration::from_secs(5))
+///     .send()
+///     .await?
+///     .error_for_status()?
+///     .text()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Awaiting the builder directly sends the request like [`Client::request`],
+/// returning the plain [`http::Response`].
+#[must_use = "requests are not sent unless they are awaited or `send` is called"]
+pub struct RequestBuilder {
+    client: Client,
+    request: http::request::Builder,
+    body: Body,
+    options: Option<RequestOptions>,
+    error: Option<BoxError>,
+}
+
+impl fmt::Debug for RequestBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestBuilder")
+            .field("method", &self.request.method_ref())
+            .field("uri", &self.request.uri_ref())
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl RequestBuilder {
+    pub(crate) fn new(client: Client, method: Method, uri: Uri) -> Self {
+        Self {
+            client,
+            request: http::Request::builder().method(method).uri(uri),
+            body: Body::empty(),
+            options: None,
+            error: None,
+        }
+    }
+
+    /// Add a header to the request.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.request = self.request.header(key, value);
+        self
+    }
+
+    /// Add all of the given headers to the request.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        if let Some(existing) = self.request.headers_mut() {
+            existing.extend(headers);
+        }
+        self
+    }
+
+    /// Set an `Authorization: Basic` header on the request.
+    ///
+    /// The header is marked as sensitive.
+    #[cfg(feature = "auth")]
+    pub fn basic_auth(mut self, username: &str, password: Option<&str>) -> Self {
+        self.request = self.request.header(
+            http::header::AUTHORIZATION,
+            super::auth::basic(username, password),
+        );
+        self
+    }
+
+    /// Set an `Authorization: Bearer` header on the request.
+    ///
+    /// The header is marked as sensitive.
+    #[cfg(feature = "auth")]
+    pub fn bearer_auth<T: fmt::Display>(mut self, token: T) -> Self {
+        match super::auth::bearer(&token.to_string()) {
+            Ok(value) => self.request = self.request.header(http::header::AUTHORIZATION, value),
+            Err(error) => self.error = Some(error.into()),
+        }
+        self
+    }
+
+    /// Set the HTTP version of the request.
+    pub fn version(mut self, version: Version) -> Self {
+        self.request = self.request.version(version);
+        self
+    }
+
+    /// Add an extension to the request.
+    pub fn extension<T>(mut self, extension: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.request = self.request.extension(extension);
+        self
+    }
+
+    /// Set the body of the request.
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set a `multipart/form-data` body, and the matching `Content-Type` header.
+    pub fn multipart(mut self, form: Form) -> Self {
+        self.request = self.request.header(CONTENT_TYPE, form.content_type());
+        self.body = form.into_body();
+        self
+    }
+
+    /// Set the timeout for this request, overriding the client's timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options = Some(self.options.unwrap_or_default().with_timeout(timeout));
+        self
+    }
+
+    /// Set the configuration overrides for this request.
+    ///
+    /// This replaces any options set by [`timeout`](Self::timeout).
+    pub fn options(mut self, options: RequestOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Append URL encoded parameters to the query string of the request URI.
+    #[cfg(feature = "serde")]
+    pub fn query<Q: serde::Serialize + ?Sized>(mut self, query: &Q) -> Self {
+        let Some(uri) = self.request.uri_ref() else {
+            return self;
+        };
+
+        let result = serde_urlencoded::to_string(query)
+            .map_err(BoxError::from)
+            .and_then(|encoded| append_query(uri, &encoded));
+
+        match result {
+            Ok(uri) => self.request = self.request.uri(uri),
+            Err(error) => self.error = Some(error),
+        }
+        self
+    }
+
+    /// Serialize the value as the JSON body of the request.
+    ///
+    /// Sets the `Content-Type` header to `application/json` unless it is already set.
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => {
+                self.body = Body::from(Bytes::from(body));
+                self.default_content_type("application/json")
+            }
+            Err(error) => {
+                self.error = Some(error.into());
+                self
+            }
+        }
+    }
+
+    /// Serialize the value as the URL encoded form body of the request.
+    ///
+    /// Sets the `Content-Type` header to `application/x-www-form-urlencoded`
+    /// unless it is already set.
+    #[cfg(feature = "serde")]
+    pub fn form<T: serde::Serialize + ?Sized>(mut self, value: &T) -> Self {
+        match serde_urlencoded::to_string(value) {
+            Ok(body) => {
+                self.body = Body::from(body);
+                self.default_content_type("application/x-www-form-urlencoded")
+            }
+            Err(error) => {
+                self.error = Some(error.into());
+                self
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn default_content_type(mut self, content_type: &'static str) -> Self {
+        if let Some(headers) = self.request.headers_mut() {
+            headers
+                .entry(CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static(content_type));
+        }
+        self
+    }
+
+    /// Build the request without sending it.
+    pub fn build(self) -> Result<crate::body::Request, BoxError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut request = self.request.body(self.body)?;
+        if let Some(options) = self.options {
+            request.extensions_mut().insert(options);
+        }
+        Ok(request)
+    }
+
+    /// Send the request, and return the response.
+    pub async fn send(self) -> Result<Response, BoxError> {
+        let client = self.client.clone();
+        let request = self.build()?;
+        let response = client.request(request).await?;
+        Ok(Response::new(response))
+    }
+}
+
+impl IntoFuture for RequestBuilder {
+    type Output = Result<crate::body::Response, BoxError>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'static>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move { self.send().await.map(Response::into_inner) })
+    }
+}
+
+#[cfg(feature = "serde")]
+fn append_query(uri: &Uri, encoded: &str) -> Result<Uri, BoxError> {
+    if encoded.is_empty() {
+        return Ok(uri.clone());
+    }
+
+    let path_and_query = match uri.query() {
+        Some(query) if !query.is_empty() => format!("{}?{query}&{encoded}", uri.path()),
+        _ => format!("{}?{encoded}", uri.path()),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+/// A response received by a [`Client`], with helpers for reading the body.
+#[derive(Debug)]
+pub struct Response {
+    inner: crate::body::Response,
+}
+
+impl Response {
+    /// Wrap an HTTP response.
+    pub fn new(inner: crate::body::Response) -> Self {
+        Self { inner }
+    }
+
+    /// The status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.inner.status()
+    }
+
+    /// The HTTP version of the response.
+    pub fn version(&self) -> Version {
+        self.inner.version()
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// A mutable reference to the headers of the response.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.inner.headers_mut()
+    }
+
+    /// The extensions of the response.
+    pub fn extensions(&self) -> &http::Extensions {
+        self.inner.extensions()
+    }
+
+    /// A mutable reference to the extensions of the response.
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        self.inner.extensions_mut()
+    }
+
+    /// Return an error if the response has a client (4xx) or server (5xx) error status.
+    pub fn error_for_status(self) -> Result<Self, Error> {
+        self.error_for_status_ref()?;
+        Ok(self)
+    }
+
+    /// Return an error if the response has a client (4xx) or server (5xx) error status,
+    /// without consuming the response.
+    pub fn error_for_status_ref(&self) -> Result<&Self, Error> {
+        let status = self.status();
+        if status.is_client_error() || status.is_server_error() {
+            Err(Error::Status(status))
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Read the whole body of the response.
+    pub async fn bytes(self) -> Result<Bytes, BoxError> {
+        Ok(self.inner.into_body().collect().await?.to_bytes())
+    }
+
+    /// Read the whole body of the response as UTF-8 text.
+    pub async fn text(self) -> Result<String, BoxError> {
+        let bytes = self.bytes().await?;
+        Ok(String::from_utf8(bytes.into())?)
+    }
+
+    /// Read the whole body of the response and deserialize it from JSON.
+    #[cfg(feature = "serde")]
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, BoxError> {
+        let bytes = self.bytes().await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Read the next chunk of data from the body of the response.
+    ///
+    /// Returns `None` once the body is complete. Trailers are skipped.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, BoxError> {
+        while let Some(frame) = self.inner.body_mut().frame().await {
+            if let Ok(data) = frame?.into_data() {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Convert the body of the response into a stream of data chunks.
+    pub fn bytes_stream(self) -> BodyDataStream<Body> {
+        self.inner.into_body().into_data_stream()
+    }
+
+    /// Consume the response, returning the body.
+    pub fn into_body(self) -> Body {
+        self.inner.into_body()
+    }
+
+    /// Consume the wrapper, returning the HTTP response.
+    pub fn into_inner(self) -> crate::body::Response {
+        self.inner
+    }
+}
+
+impl From<crate::body::Response> for Response {
+    fn from(inner: crate::body::Response) -> Self {
+        Self::new(inner)
+    }
+}
+
+impl From<Response> for crate::body::Response {
+    fn from(response: Response) -> Self {
+        response.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(method: Method, uri: &str) -> RequestBuilder {
+        RequestBuilder::new(Client::new_tcp_http(), method, uri.parse().unwrap())
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn build_request() {
+        use http::header::AUTHORIZATION;
+
+        let request = builder(Method::PUT, "http://example.com/a")
+            .header("x-test", "1")
+            .bearer_auth("secret")
+            .timeout(Duration::from_secs(1))
+            .body("hello")
+            .build()
+            .unwrap();
+
+        assert_eq!(request.method(), Method::PUT);
+        assert_eq!(request.headers()["x-test"], "1");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer secret");
+        assert!(request.headers()[AUTHORIZATION].is_sensitive());
+        assert_eq!(
+            request.extensions().get::<RequestOptions>(),
+            Some(&RequestOptions::new().with_timeout(Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn build_invalid_header() {
+        assert!(builder(Method::GET, "http://example.com/")
+            .header("x-test", "bad\nvalue")
+            .build()
+            .is_err());
+        #[cfg(feature = "auth")]
+        assert!(builder(Method::GET, "http://example.com/")
+            .bearer_auth("bad\ntoken")
+            .build()
+            .is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn build_query() {
+        let request = builder(Method::GET, "http://example.com/search?page=1")
+            .query(&[("q", "a b"), ("lang", "en")])
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.uri(),
+            "http://example.com/search?page=1&q=a+b&lang=en"
+        );
+
+        let request = builder(Method::GET, "http://example.com")
+            .query(&[("q", "x")])
+            .build()
+            .unwrap();
+        assert_eq!(request.uri(), "http://example.com/?q=x");
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn build_json_and_form() {
+        let request = builder(Method::POST, "http://example.com/")
+            .json(&serde_json::json!({"a": 1}))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/json");
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, r#"{"a":1}"#);
+
+        let request = builder(Method::POST, "http://example.com/")
+            .header(CONTENT_TYPE, "text/plain")
+            .form(&[("a", "1"), ("b", "2")])
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[CONTENT_TYPE], "text/plain");
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "a=1&b=2");
+    }
+
+    #[tokio::test]
+    async fn response_helpers() {
+        let response = Response::new(
+            http::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("missing"))
+                .unwrap(),
+        );
+
+        assert!(response.error_for_status_ref().is_err());
+        assert_eq!(response.text().await.unwrap(), "missing");
+
+        let mut response = Response::new(http::Response::new(Body::from("chunk")));
+        let response_ref = response.error_for_status_ref().unwrap();
+        assert_eq!(response_ref.status(), StatusCode::OK);
+        assert_eq!(response.chunk().await.unwrap().unwrap(), "chunk");
+        assert!(response.chunk().await.unwrap().is_none());
+    }
+}
@@ -2,9 +2,11 @@ use std::fmt;
 use std::future::poll_fn;
 use std::future::Future;
 use std::task::Poll;
+use std::time::Duration;
 
 use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
+use http::uri::Authority;
 use http::uri::Port;
 use http::uri::Scheme;
 use http::HeaderValue;
@@ -14,15 +16,20 @@ use tower::util::Oneshot;
 use tower::ServiceExt;
 use tracing::warn;
 
+use super::alt_svc::AltSvcCache;
+use super::balance::Endpoint;
 use super::conn::connection::ConnectionError;
 use super::conn::protocol::auto::HttpConnectionBuilder;
 use super::conn::protocol::HttpProtocol;
 use super::conn::transport::tcp::TcpTransport;
+use super::conn::transport::with_endpoint;
 use super::conn::transport::TransportStream;
 use super::conn::Connection;
 use super::conn::Protocol;
 use super::conn::TlsTransport;
 use super::conn::Transport;
+use super::hsts::HstsCache;
+use super::options::RequestOptions;
 use super::pool;
 use super::pool::Checkout;
 use super::pool::Connector;
@@ -50,6 +57,8 @@ where
     pub(super) transport: T,
     pub(super) protocol: P,
     pub(super) pool: Option<pool::Pool<P::Connection>>,
+    pub(super) hsts: Option<HstsCache>,
+    pub(super) alt_svc: Option<AltSvcCache>,
     pub(super) _body: std::marker::PhantomData<fn() -> BOut>,
 }
 
@@ -65,6 +74,8 @@ where
             transport,
             protocol,
             pool: Some(pool::Pool::new(pool)),
+            hsts: None,
+            alt_svc: None,
             _body: std::marker::PhantomData,
         }
     }
@@ -73,6 +84,25 @@ where
     pub fn without_pool(self) -> Self {
         Self { pool: None, ..self }
     }
+
+    /// Record `Strict-Transport-Security` headers in the cache.
+    ///
+    /// Requests are upgraded to `https` by an [`HstsLayer`](super::hsts::HstsLayer)
+    /// wrapping the client, so that the layers above this service see the upgraded URI.
+    pub fn with_hsts(self, cache: HstsCache) -> Self {
+        Self {
+            hsts: Some(cache),
+            ..self
+        }
+    }
+
+    /// Record `Alt-Svc` headers in the cache, and connect to usable alternatives.
+    pub fn with_alt_svc(self, cache: AltSvcCache) -> Self {
+        Self {
+            alt_svc: Some(cache),
+            ..self
+        }
+    }
 }
 
 impl ClientService<TlsTransport<TcpTransport>, HttpConnectionBuilder, crate::Body> {
@@ -93,6 +123,9 @@ impl ClientService<TlsTransport<TcpTransport>, HttpConnectionBuilder, crate::Bod
 
             protocol: HttpConnectionBuilder::default(),
 
+            hsts: None,
+            alt_svc: None,
+
             _body: std::marker::PhantomData,
         }
     }
@@ -109,6 +142,8 @@ where
             protocol: self.protocol.clone(),
             transport: self.transport.clone(),
             pool: self.pool.clone(),
+            hsts: self.hsts.clone(),
+            alt_svc: self.alt_svc.clone(),
             _body: std::marker::PhantomData,
         }
     }
@@ -126,10 +161,15 @@ where
     fn connect_to(
         &self,
         uri: http::Uri,
+        endpoint: Option<Authority>,
         http_protocol: HttpProtocol,
+        connect_timeout: Option<Duration>,
     ) -> Result<Checkout<P::Connection, TransportStream<T::IO>, ConnectionError>, ConnectionError>
     {
-        let key: pool::Key = uri.clone().try_into()?;
+        let mut key: pool::Key = uri.clone().try_into()?;
+        if let Some(endpoint) = endpoint.clone() {
+            key = key.with_endpoint(endpoint);
+        }
         let mut protocol = self.protocol.clone();
         let mut transport = self.transport.clone();
 
@@ -138,10 +178,15 @@ where
                 poll_fn(|cx| Transport::poll_ready(&mut transport, cx))
                     .await
                     .map_err(|error| ConnectionError::Connecting(error.into()))?;
-                transport
-                    .connect(uri)
-                    .await
-                    .map_err(|error| ConnectionError::Connecting(error.into()))
+                let connect = with_endpoint(endpoint, || transport.connect(uri))
+                    .map_err(|error| ConnectionError::Connecting(error.into()))?;
+                match connect_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, connect)
+                        .await
+                        .map_err(|_| ConnectionError::Timeout)?,
+                    None => connect.await,
+                }
+                .map_err(|error| ConnectionError::Connecting(error.into()))
             },
             Box::new(move |transport| {
                 Box::pin(async move {
@@ -185,11 +230,30 @@ where
 
     fn call(&mut self, request: http::Request<BIn>) -> Self::Future {
         let uri = request.uri().clone();
+        let alternative = self
+            .alt_svc
+            .as_ref()
+            .and_then(|alt_svc| alt_svc.alternative_uri(&uri));
+        let caches = ResponseCaches::new(self, &uri, alternative.is_some());
 
         let protocol: HttpProtocol = request.version().into();
-
-        match self.connect_to(uri, protocol) {
-            Ok(checkout) => ResponseFuture::new(checkout, request.map(Into::into)),
+        let connect_timeout = request
+            .extensions()
+            .get::<RequestOptions>()
+            .and_then(RequestOptions::connect_timeout);
+
+        let endpoint = request
+            .extensions()
+            .get::<Endpoint>()
+            .map(|endpoint| endpoint.0.clone());
+
+        match self.connect_to(
+            alternative.unwrap_or(uri),
+            endpoint,
+            protocol,
+            connect_timeout,
+        ) {
+            Ok(checkout) => ResponseFuture::new(checkout, request.map(Into::into), caches),
             Err(error) => ResponseFuture::error(error),
         }
     }
@@ -237,9 +301,17 @@ where
     C: pool::PoolableConnection,
     T: pool::PoolableTransport,
 {
-    fn new(checkout: Checkout<C, T, ConnectionError>, request: crate::body::Request) -> Self {
+    fn new(
+        checkout: Checkout<C, T, ConnectionError>,
+        request: crate::body::Request,
+        caches: Option<ResponseCaches>,
+    ) -> Self {
         Self {
-            inner: ResponseFutureState::Checkout { checkout, request },
+            inner: ResponseFutureState::Checkout {
+                checkout,
+                request,
+                caches,
+            },
             _body: std::marker::PhantomData,
         }
     }
@@ -270,16 +342,25 @@ where
                 ResponseFutureState::Checkout {
                     mut checkout,
                     request,
+                    caches,
                 } => match checkout.poll_unpin(cx) {
                     Poll::Ready(Ok(conn)) => {
-                        self.inner =
-                            ResponseFutureState::Request(execute_request(request, conn).boxed());
+                        self.inner = ResponseFutureState::Request(
+                            execute_request(request, conn, caches).boxed(),
+                        );
                     }
                     Poll::Ready(Err(error)) => {
+                        if let Some(caches) = caches {
+                            caches.connect_failed();
+                        }
                         return Poll::Ready(Err(error.into()));
                     }
                     Poll::Pending => {
-                        self.inner = ResponseFutureState::Checkout { checkout, request };
+                        self.inner = ResponseFutureState::Checkout {
+                            checkout,
+                            request,
+                            caches,
+                        };
                         return Poll::Pending;
                     }
                 },
@@ -302,11 +383,13 @@ where
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 enum ResponseFutureState<C: pool::PoolableConnection, T: pool::PoolableTransport> {
     Empty,
     Checkout {
         checkout: Checkout<C, T, ConnectionError>,
         request: crate::body::Request,
+        caches: Option<ResponseCaches>,
     },
     ConnectionError(ConnectionError),
     Request(BoxFuture<'static, Result<http::Response<crate::body::Body>, Error>>),
@@ -354,7 +437,9 @@ fn prepare_request<C: Connection + PoolableConnection>(
         } else {
             origin_form(request.uri_mut());
         }
-    } else if request.method() == http::Method::CONNECT {
+    } else if request.method() == http::Method::CONNECT
+        && crate::upgrade::connect_protocol(request).is_none()
+    {
         return Err(Error::InvalidMethod(http::Method::CONNECT));
     } else if conn.version() == Version::HTTP_2 {
         *request.version_mut() = Version::HTTP_2;
@@ -362,9 +447,58 @@ fn prepare_request<C: Connection + PoolableConnection>(
     Ok(())
 }
 
+/// Updates the HSTS and Alt-Svc caches after a request.
+#[derive(Debug)]
+struct ResponseCaches {
+    uri: Uri,
+    hsts: Option<HstsCache>,
+    alt_svc: Option<AltSvcCache>,
+
+    /// The request was sent to an alternative service.
+    alternative: bool,
+}
+
+impl ResponseCaches {
+    fn new<T, P, B>(service: &ClientService<T, P, B>, uri: &Uri, alternative: bool) -> Option<Self>
+    where
+        T: Transport,
+        P: Protocol<T::IO>,
+        P::Connection: PoolableConnection,
+    {
+        if service.hsts.is_none() && service.alt_svc.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            uri: uri.clone(),
+            hsts: service.hsts.clone(),
+            alt_svc: service.alt_svc.clone(),
+            alternative,
+        })
+    }
+
+    fn store_response(&self, headers: &http::HeaderMap) {
+        if let Some(hsts) = &self.hsts {
+            hsts.store_response(&self.uri, headers);
+        }
+        if let Some(alt_svc) = &self.alt_svc {
+            alt_svc.store_response(&self.uri, headers);
+        }
+    }
+
+    /// Forget alternatives which could not be reached, so that the origin is used instead.
+    fn connect_failed(&self) {
+        if let (true, Some(alt_svc)) = (self.alternative, &self.alt_svc) {
+            tracing::debug!(uri = %self.uri, "alternative service unavailable");
+            alt_svc.remove(&self.uri);
+        }
+    }
+}
+
 async fn execute_request<C>(
     mut request: crate::body::Request,
     mut conn: Pooled<C>,
+    caches: Option<ResponseCaches>,
 ) -> Result<http::Response<crate::body::Body>, Error>
 where
     C: Connection + PoolableConnection,
@@ -379,6 +513,10 @@ where
         .await
         .map_err(|error| Error::Connection(error.into()))?;
 
+    if let Some(caches) = caches {
+        caches.store_response(response.headers());
+    }
+
     // Shared connections are already in the pool, no need to do this.
     if !conn.can_share() {
         // Only re-insert the connection when it is ready again. Spawn
@@ -481,6 +619,7 @@ mod tests {
     #[cfg(feature = "mocks")]
     use crate::client::conn::transport::mock::{MockConnectionError, MockTransport};
 
+    #[cfg(feature = "mocks")]
     use crate::client::pool::Config as PoolConfig;
 
     use super::*;
@@ -639,6 +778,7 @@ mod tests {
             .await;
 
         let err = result.unwrap_err();
+        assert!(err.is_connect());
 
         let Error::Connection(err) = err else {
             panic!("unexpected error: {:?}", err);
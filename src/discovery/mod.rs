@@ -94,6 +94,8 @@ pub enum ConnectionError {
 pub(crate) enum InternalBindError {
     AlreadyBound,
 
+    InProcess,
+
     SocketResetError(Utf8PathBuf, io::Error),
 
     PidLockError(Utf8PathBuf, io::Error),
@@ -118,6 +120,13 @@ impl fmt::Display for BindError {
             InternalBindError::AlreadyBound => {
                 write!(f, "Service {} is already bound", self.service)
             }
+            InternalBindError::InProcess => {
+                write!(
+                    f,
+                    "Service {} is discovered in-process, and can't use an inherited socket",
+                    self.service
+                )
+            }
             InternalBindError::SocketResetError(path, error) => {
                 write!(
                     f,
@@ -140,6 +149,7 @@ impl std::error::Error for BindError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.inner {
             InternalBindError::AlreadyBound => None,
+            InternalBindError::InProcess => None,
             InternalBindError::SocketResetError(_, error) => Some(error),
             InternalBindError::PidLockError(_, error) => Some(error),
         }
@@ -275,6 +285,31 @@ impl ServiceRegistry {
             .map_err(|err| BindError::new(name.into_owned(), err))
     }
 
+    /// Bind a service by name to a listening socket which was opened elsewhere, e.g. one
+    /// inherited from systemd (see [`ListenFds`](crate::server::conn::systemd::ListenFds)).
+    ///
+    /// The service is locked just as it is by [`ServiceRegistry::bind`], but the registry
+    /// doesn't create its own `.svc` socket, so the inherited socket should be listening at
+    /// the service's path for clients to find it. Services discovered in-process can't be
+    /// bound to an inherited socket.
+    #[tracing::instrument(skip_all, fields(service=tracing::field::Empty))]
+    pub async fn bind_inherited<'a, S>(
+        &'a self,
+        service: S,
+        listener: crate::server::conn::Acceptor,
+    ) -> Result<crate::server::conn::Acceptor, BindError>
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let name = service.into();
+        let span = tracing::Span::current();
+        span.record("service", name.as_ref());
+
+        self.inner
+            .bind_inherited(&self.config, &name, listener)
+            .map_err(|err| BindError::new(name.into_owned(), err))
+    }
+
     /// Create a server which will bind to a service by name.
     pub async fn server<'a, S, M, B>(
         &'a self,
@@ -409,6 +444,18 @@ impl InnerRegistry {
 
         handle.acceptor()
     }
+
+    /// Bind to a service by name, using an existing listener.
+    fn bind_inherited(
+        &self,
+        config: &RegistryConfig,
+        service: &str,
+        listener: crate::server::conn::Acceptor,
+    ) -> Result<crate::server::conn::Acceptor, InternalBindError> {
+        let mut handle = self.get_mut(config, service);
+
+        handle.inherit(listener)
+    }
 }
 
 /// Represents a discovered service which uses a PID file to lock binding the service.
@@ -432,6 +479,28 @@ impl PidLock {
     }
 }
 
+impl PidLock {
+    /// Lock the PID file, so that no other process can bind this service.
+    fn lock(&mut self) -> Result<(), InternalBindError> {
+        tracing::trace!("Locking PID file");
+        let file = match self {
+            PidLock::Path(ref path) => PidFile::new(path.clone()).map_err(|err| {
+                tracing::warn!(
+                    "Encountered an error resetting the Pid file {path}: {}",
+                    err
+                );
+                InternalBindError::PidLockError(path.clone(), err)
+            })?,
+            PidLock::Lock(_) => {
+                tracing::warn!("Service is already bound in this process");
+                return Err(InternalBindError::AlreadyBound);
+            }
+        };
+        *self = PidLock::Lock(file);
+        Ok(())
+    }
+}
+
 /// Handle to a service for creating new connections
 ///
 /// This is the type held internally by the registry for a service.
@@ -506,21 +575,7 @@ impl ServiceHandle {
                 acceptor.take().ok_or(InternalBindError::AlreadyBound)
             }
             ServiceHandle::Unix { ref path, pidfile } => {
-                tracing::trace!("Locking PID file");
-                let file = match pidfile {
-                    PidLock::Path(ref path) => PidFile::new(path.clone()).map_err(|err| {
-                        tracing::warn!(
-                            "Encountered an error resetting the Pid file {path}: {}",
-                            err
-                        );
-                        InternalBindError::PidLockError(path.clone(), err)
-                    })?,
-                    PidLock::Lock(_) => {
-                        tracing::warn!("Service is already bound in this process");
-                        return Err(InternalBindError::AlreadyBound);
-                    }
-                };
-                *pidfile = PidLock::Lock(file);
+                pidfile.lock()?;
 
                 tracing::trace!("Binding to socket at {path}");
                 if let Err(error) = std::fs::remove_file(path) {
@@ -548,6 +603,21 @@ impl ServiceHandle {
             }
         }
     }
+
+    /// Use an existing listener as the acceptor for this service.
+    fn inherit(
+        &mut self,
+        listener: crate::server::conn::Acceptor,
+    ) -> Result<crate::server::conn::Acceptor, InternalBindError> {
+        match self {
+            ServiceHandle::Duplex { .. } => Err(InternalBindError::InProcess),
+            ServiceHandle::Unix { path, pidfile } => {
+                pidfile.lock()?;
+                tracing::trace!("Using inherited socket for {path}");
+                Ok(listener)
+            }
+        }
+    }
 }
 
 async fn connect_to_handle(
@@ -621,6 +691,37 @@ mod tests {
         assert!(matches!(pidfile, PidLock::Path(_)));
     }
 
+    #[tokio::test]
+    async fn bind_inherited_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut registry = ServiceRegistry::new();
+        registry.set_discovery(ServiceDiscovery::Unix {
+            path: Utf8PathBuf::from_path_buf(tmp.path().to_owned()).unwrap(),
+        });
+
+        let path = tmp.path().join("inherited.svc");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        registry
+            .bind_inherited("inherited", listener.into())
+            .await
+            .unwrap();
+        assert!(registry.is_available("inherited"));
+
+        let listener = tokio::net::UnixListener::bind(tmp.path().join("other.sock")).unwrap();
+        let error = registry
+            .bind_inherited("inherited", listener.into())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.inner, InternalBindError::AlreadyBound));
+
+        let (_, incoming) = crate::stream::duplex::pair();
+        let error = ServiceRegistry::new()
+            .bind_inherited("in-process", incoming.into())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.inner, InternalBindError::InProcess));
+    }
+
     #[tokio::test]
     async fn connect_to_handle_unix() {
         let tmp = tempfile::tempdir().unwrap();
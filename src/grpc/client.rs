@@ -0,0 +1,263 @@
+//! Client middleware for gRPC calls.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use http::header::{HeaderValue, CONTENT_TYPE, TE};
+
+use super::timeout::{encode_timeout, timeout_from_headers, GRPC_TIMEOUT};
+use super::{Code, Status};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A layer which prepares requests for gRPC and maps gRPC failures to errors.
+///
+/// See [`GrpcService`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcLayer {
+    timeout: Option<Duration>,
+}
+
+impl GrpcLayer {
+    /// Create a new gRPC layer with no default timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the default timeout, used for requests which don't have a `grpc-timeout` header.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<S> tower::Layer<S> for GrpcLayer {
+    type Service = GrpcService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcService {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// A service which prepares requests for gRPC and maps gRPC failures to errors.
+///
+/// Requests are sent over HTTP/2 with the `application/grpc` content type (unless
+/// another gRPC content type is already set) and `te: trailers`. If the request does not
+/// have a `grpc-timeout` header, the default timeout is sent to the server in one. The
+/// deadline from the `grpc-timeout` header is enforced on the client, and expires with
+/// a [`Code::DeadlineExceeded`] status.
+///
+/// Responses with a non-200 HTTP status, or a "trailers-only" response with a non-`OK`
+/// `grpc-status` header, are returned as a [`Status`] error. Statuses sent in the trailers
+/// of a streaming response can be read with [`Streaming`](super::Streaming).
+#[derive(Debug, Clone)]
+pub struct GrpcService<S> {
+    inner: S,
+    timeout: Option<Duration>,
+}
+
+impl<S> GrpcService<S> {
+    /// Create a new gRPC service wrapping `inner`, with an optional default timeout.
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+
+    /// Get a reference to the inner service.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, BIn, BOut> tower::Service<http::Request<BIn>> for GrpcService<S>
+where
+    S: tower::Service<http::Request<BIn>, Response = http::Response<BOut>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = http::Response<BOut>;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: http::Request<BIn>) -> Self::Future {
+        *req.version_mut() = http::Version::HTTP_2;
+
+        let headers = req.headers_mut();
+        if !super::is_grpc(headers) {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(super::CONTENT_TYPE));
+        }
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+
+        let timeout = match timeout_from_headers(headers) {
+            Some(timeout) => Some(timeout),
+            None => {
+                if let Some(timeout) = self.timeout {
+                    headers.insert(GRPC_TIMEOUT, encode_timeout(timeout));
+                }
+                self.timeout
+            }
+        };
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            deadline: timeout.map(tokio::time::sleep),
+        }
+    }
+}
+
+/// Future returned by [`GrpcService`].
+#[pin_project::pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    deadline: Option<tokio::time::Sleep>,
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("deadline", &self.deadline.as_ref().map(|d| d.deadline()))
+            .finish()
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<http::Response<B>, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(result.map_err(Into::into).and_then(check_response));
+        }
+
+        if let Some(deadline) = this.deadline.as_pin_mut() {
+            ready!(deadline.poll(cx));
+            return Poll::Ready(Err(Status::new(
+                Code::DeadlineExceeded,
+                "grpc deadline exceeded",
+            )
+            .into()));
+        }
+
+        Poll::Pending
+    }
+}
+
+fn check_response<B>(response: http::Response<B>) -> Result<http::Response<B>, BoxError> {
+    if let Some(status) = Status::from_header_map(response.headers()) {
+        if !status.is_ok() {
+            return Err(status.into());
+        }
+    }
+
+    if response.status() != http::StatusCode::OK {
+        return Err(Status::new(
+            Code::from_http(response.status()),
+            format!("unexpected http status: {}", response.status()),
+        )
+        .into());
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{Layer as _, ServiceExt as _};
+
+    use super::*;
+
+    async fn inspect(
+        req: http::Request<()>,
+    ) -> Result<http::Response<http::HeaderMap>, Infallible> {
+        assert_eq!(req.version(), http::Version::HTTP_2);
+        Ok(http::Response::new(req.headers().clone()))
+    }
+
+    #[tokio::test]
+    async fn request_headers() {
+        let service = GrpcLayer::new()
+            .with_timeout(Some(Duration::from_secs(5)))
+            .layer(tower::service_fn(inspect));
+
+        let response = service.oneshot(http::Request::new(())).await.unwrap();
+        let headers = response.into_body();
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/grpc");
+        assert_eq!(headers.get(TE).unwrap(), "trailers");
+        assert_eq!(headers.get(GRPC_TIMEOUT).unwrap(), "5000000u");
+    }
+
+    #[tokio::test]
+    async fn request_headers_preserved() {
+        let service = GrpcLayer::new()
+            .with_timeout(Some(Duration::from_secs(5)))
+            .layer(tower::service_fn(inspect));
+
+        let request = http::Request::builder()
+            .header(CONTENT_TYPE, "application/grpc+proto")
+            .header(GRPC_TIMEOUT, "1S")
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        let headers = response.into_body();
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/grpc+proto");
+        assert_eq!(headers.get(GRPC_TIMEOUT).unwrap(), "1S");
+    }
+
+    #[tokio::test]
+    async fn trailers_only_error() {
+        let service = GrpcLayer::new().layer(tower::service_fn(|_: http::Request<()>| async {
+            Ok::<_, Infallible>(Status::new(Code::NotFound, "nope").into_response())
+        }));
+
+        let error = service.oneshot(http::Request::new(())).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "nope");
+    }
+
+    #[tokio::test]
+    async fn http_status_error() {
+        let service = GrpcLayer::new().layer(tower::service_fn(|_: http::Request<()>| async {
+            let mut response = http::Response::new(());
+            *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+            Ok::<_, Infallible>(response)
+        }));
+
+        let error = service.oneshot(http::Request::new(())).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn deadline_exceeded() {
+        let service = GrpcLayer::new().layer(tower::service_fn(|_: http::Request<()>| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok::<_, Infallible>(http::Response::new(()))
+        }));
+
+        let request = http::Request::builder()
+            .header(GRPC_TIMEOUT, "10m")
+            .body(())
+            .unwrap();
+        let error = service.oneshot(request).await.unwrap_err();
+        let status = error.downcast::<Status>().unwrap();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+}
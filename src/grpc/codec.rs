@@ -0,0 +1,381 @@
+//! Length-prefixed message framing for gRPC bodies.
+//!
+//! Each gRPC message is sent as a 1-byte compression flag, followed by a 4-byte
+//! big-endian message length, followed by the message itself. Compression is not
+//! supported, so messages with the compression flag set are rejected.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::HeaderMap;
+use http_body::Frame;
+
+use super::{Code, Status};
+
+/// Length of the message prefix: one byte of flags and four bytes of length.
+const HEADER_LEN: usize = 5;
+
+/// The default maximum message size accepted by the decoder, 4MiB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Encode a single message with the gRPC length prefix.
+pub fn encode_message(message: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + message.len());
+    encode_message_into(message, &mut buf);
+    buf.freeze()
+}
+
+/// Encode a single message with the gRPC length prefix into an existing buffer.
+///
+/// # Panics
+/// Panics if the message is longer than `u32::MAX` bytes.
+pub fn encode_message_into(message: &[u8], buf: &mut BytesMut) {
+    let len = u32::try_from(message.len()).expect("grpc message too large");
+    buf.reserve(HEADER_LEN + message.len());
+    buf.put_u8(0);
+    buf.put_u32(len);
+    buf.put_slice(message);
+}
+
+/// Incremental decoder for length-prefixed gRPC messages.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    buf: BytesMut,
+    max_message_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    /// Create a new decoder with the default maximum message size.
+    pub fn new() -> Self {
+        Self::with_max_message_size(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Create a new decoder which rejects messages larger than `max_message_size`.
+    pub fn with_max_message_size(max_message_size: usize) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            max_message_size,
+        }
+    }
+
+    /// Add data to the decoder's buffer.
+    pub fn extend(&mut self, data: impl Buf) {
+        self.buf.put(data);
+    }
+
+    /// Returns `true` if there is no buffered data.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Decode the next complete message from the buffer.
+    ///
+    /// Returns `Ok(None)` if more data is required.
+    pub fn decode(&mut self) -> Result<Option<Bytes>, Status> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let compressed = self.buf[0];
+        let len = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]) as usize;
+
+        match compressed {
+            0 => {}
+            1 => {
+                return Err(Status::new(
+                    Code::Unimplemented,
+                    "compressed grpc messages are not supported",
+                ))
+            }
+            flag => {
+                return Err(Status::new(
+                    Code::Internal,
+                    format!("invalid grpc compression flag: {flag}"),
+                ))
+            }
+        }
+
+        if len > self.max_message_size {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!(
+                    "grpc message length {len} exceeds maximum of {}",
+                    self.max_message_size
+                ),
+            ));
+        }
+
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        self.buf.advance(HEADER_LEN);
+        Ok(Some(self.buf.split_to(len).freeze()))
+    }
+}
+
+/// A stream of decoded gRPC messages read from an HTTP body.
+///
+/// Once the body is complete, the `grpc-status` trailer is checked, and a non-`OK`
+/// status is returned as the final item in the stream.
+#[pin_project::pin_project]
+pub struct Streaming<B> {
+    #[pin]
+    body: B,
+    decoder: Decoder,
+    trailers: Option<HeaderMap>,
+    done: bool,
+}
+
+impl<B> fmt::Debug for Streaming<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Streaming")
+            .field("decoder", &self.decoder)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl<B> Streaming<B> {
+    /// Create a new message stream from a body.
+    pub fn new(body: B) -> Self {
+        Self::with_decoder(body, Decoder::new())
+    }
+
+    /// Create a new message stream from a body, using the provided decoder.
+    pub fn with_decoder(body: B, decoder: Decoder) -> Self {
+        Self {
+            body,
+            decoder,
+            trailers: None,
+            done: false,
+        }
+    }
+
+    /// The trailers received at the end of the body, if the body is complete.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+}
+
+impl<B> futures_core::Stream for Streaming<B>
+where
+    B: http_body::Body,
+    B::Data: Buf,
+    B::Error: fmt::Display,
+{
+    type Item = Result<Bytes, Status>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            match this.decoder.decode() {
+                Ok(Some(message)) => return Poll::Ready(Some(Ok(message))),
+                Ok(None) => {}
+                Err(status) => {
+                    *this.done = true;
+                    return Poll::Ready(Some(Err(status)));
+                }
+            }
+
+            match ready!(this.body.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => this.decoder.extend(data),
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            this.trailers
+                                .get_or_insert_with(HeaderMap::new)
+                                .extend(trailers);
+                        }
+                    }
+                },
+                Some(Err(error)) => {
+                    *this.done = true;
+                    return Poll::Ready(Some(Err(Status::new(
+                        Code::Internal,
+                        format!("error reading grpc body: {error}"),
+                    ))));
+                }
+                None => {
+                    *this.done = true;
+
+                    if !this.decoder.is_empty() {
+                        return Poll::Ready(Some(Err(Status::new(
+                            Code::Internal,
+                            "grpc body ended with a partial message",
+                        ))));
+                    }
+
+                    let status = this.trailers.as_ref().and_then(Status::from_header_map);
+                    return match status {
+                        Some(status) if !status.is_ok() => Poll::Ready(Some(Err(status))),
+                        _ => Poll::Ready(None),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// An HTTP body which sends length-prefixed gRPC messages, followed by status trailers.
+///
+/// This is intended for server responses: the messages are sent as data frames, and then
+/// the `grpc-status` and `grpc-message` trailers are sent.
+#[derive(Debug, Default)]
+pub struct MessageBody {
+    frames: VecDeque<Bytes>,
+    status: Option<Status>,
+}
+
+impl MessageBody {
+    /// Create a new body from a sequence of (unencoded) messages, with an `OK` status.
+    pub fn new<I, M>(messages: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+        M: AsRef<[u8]>,
+    {
+        Self {
+            frames: messages
+                .into_iter()
+                .map(|message| encode_message(message.as_ref()))
+                .collect(),
+            status: Some(Status::ok()),
+        }
+    }
+
+    /// Set the status sent in the trailers after the messages.
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+}
+
+impl http_body::Body for MessageBody {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(frame) = self.frames.pop_front() {
+            return Poll::Ready(Some(Ok(Frame::data(frame))));
+        }
+
+        Poll::Ready(self.status.take().map(|status| {
+            let mut trailers = HeaderMap::new();
+            status.add_header(&mut trailers);
+            Ok(Frame::trailers(trailers))
+        }))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.frames.is_empty() && self.status.is_none()
+    }
+}
+
+impl From<MessageBody> for crate::Body {
+    fn from(body: MessageBody) -> Self {
+        crate::Body::new(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt as _;
+    use http_body_util::BodyExt as _;
+
+    use super::*;
+
+    #[test]
+    fn encode_prefix() {
+        let encoded = encode_message(b"hello");
+        assert_eq!(&encoded[..], b"\x00\x00\x00\x00\x05hello");
+    }
+
+    #[test]
+    fn decode_partial() {
+        let encoded = encode_message(b"hello");
+        let mut decoder = Decoder::new();
+
+        decoder.extend(encoded.slice(..3));
+        assert_eq!(decoder.decode().unwrap(), None);
+
+        decoder.extend(encoded.slice(3..));
+        assert_eq!(decoder.decode().unwrap().unwrap(), "hello");
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn decode_too_large() {
+        let mut decoder = Decoder::with_max_message_size(4);
+        decoder.extend(encode_message(b"hello"));
+        let status = decoder.decode().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn decode_compressed() {
+        let mut decoder = Decoder::new();
+        decoder.extend(&b"\x01\x00\x00\x00\x00"[..]);
+        let status = decoder.decode().unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn message_body_roundtrip() {
+        let body = MessageBody::new(["one", "two"]);
+        let mut stream = Streaming::new(body);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "one");
+        assert_eq!(stream.next().await.unwrap().unwrap(), "two");
+        assert!(stream.next().await.is_none());
+        assert_eq!(
+            Status::from_header_map(stream.trailers().unwrap()).unwrap(),
+            Status::ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn message_body_error_status() {
+        let body = MessageBody::new(["one"]).with_status(Status::new(Code::NotFound, "gone"));
+        let mut stream = Streaming::new(body);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "one");
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "gone");
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn message_body_frames() {
+        let body = MessageBody::new(["a"]);
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+        assert_eq!(&collected.to_bytes()[..], b"\x00\x00\x00\x00\x01a");
+    }
+
+    #[tokio::test]
+    async fn streaming_partial_message() {
+        let body = http_body_util::Full::new(Bytes::from_static(b"\x00\x00\x00\x00\x05hel"));
+        let mut stream = Streaming::new(body);
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+}
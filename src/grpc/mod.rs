@@ -0,0 +1,75 @@
+//! Utilities for gRPC clients and servers.
+//!
+//! gRPC runs over HTTP/2, sending length-prefixed messages in the request and response
+//! bodies, and reporting the result of each call in the `grpc-status` and `grpc-message`
+//! trailers. This module provides:
+//!
+//! - [`Status`] and [`Code`], a typed representation of the gRPC status.
+//! - [`encode_message`], [`Decoder`], [`Streaming`] and [`MessageBody`] for message framing
+//!   on top of HTTP bodies.
+//! - [`parse_timeout`] and [`encode_timeout`] for the `grpc-timeout` header.
+//! - `GrpcLayer`, a client middleware which enforces HTTP/2, propagates timeouts, and
+//!   maps gRPC failures to [`Status`] errors. It can be enabled on a client with
+//!   `client::Builder::with_grpc`.
+//! - `DeadlineLayer`, a server middleware which enforces the deadline from the
+//!   `grpc-timeout` header.
+
+mod codec;
+mod status;
+mod timeout;
+
+#[cfg(feature = "client")]
+mod client;
+#[cfg(feature = "server")]
+mod server;
+
+#[cfg(feature = "client")]
+pub use self::client::{GrpcLayer, GrpcService, ResponseFuture};
+pub use self::codec::{
+    encode_message, encode_message_into, Decoder, MessageBody, Streaming, DEFAULT_MAX_MESSAGE_SIZE,
+};
+#[cfg(feature = "server")]
+pub use self::server::{Deadline, DeadlineFuture, DeadlineLayer, DeadlineService};
+pub use self::status::{Code, Status, GRPC_MESSAGE, GRPC_STATUS};
+pub use self::timeout::{encode_timeout, parse_timeout, timeout_from_headers, GRPC_TIMEOUT};
+
+/// The base content type for gRPC requests and responses.
+pub const CONTENT_TYPE: &str = "application/grpc";
+
+/// Returns `true` if the headers indicate a gRPC request or response.
+///
+/// This accepts `application/grpc` as well as suffixed types like `application/grpc+proto`.
+pub fn is_grpc(headers: &http::HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.strip_prefix(CONTENT_TYPE).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('+') || rest.starts_with(';')
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_grpc() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!is_grpc(&headers));
+
+        for (value, expected) in [
+            ("application/grpc", true),
+            ("application/grpc+proto", true),
+            ("application/grpc-web", false),
+            ("application/json", false),
+        ] {
+            headers.insert(
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(value),
+            );
+            assert_eq!(is_grpc(&headers), expected, "{value}");
+        }
+    }
+}
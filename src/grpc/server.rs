@@ -0,0 +1,213 @@
+//! Server middleware for gRPC calls.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::timeout::timeout_from_headers;
+use super::{Code, Status};
+
+/// The deadline for a gRPC call, inserted into request extensions by [`DeadlineService`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// The instant at which the call expires.
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// The time remaining before the call expires.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Has this deadline passed?
+    pub fn is_expired(&self) -> bool {
+        self.0 <= Instant::now()
+    }
+}
+
+/// A layer which enforces gRPC deadlines on the server.
+///
+/// See [`DeadlineService`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct DeadlineLayer {
+    max: Option<Duration>,
+}
+
+impl DeadlineLayer {
+    /// Create a new deadline layer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum deadline, which also applies to calls without a `grpc-timeout`.
+    pub fn with_max_timeout(mut self, max: Option<Duration>) -> Self {
+        self.max = max;
+        self
+    }
+}
+
+impl<S> tower::Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService {
+            inner,
+            max: self.max,
+        }
+    }
+}
+
+/// A service which enforces gRPC deadlines on the server.
+///
+/// The deadline is read from the request's `grpc-timeout` header (and limited by the
+/// configured maximum), and inserted into the request extensions as a [`Deadline`] so that
+/// handlers can observe it. If the inner service does not respond before the deadline, a
+/// trailers-only response with a [`Code::DeadlineExceeded`] status is returned instead.
+#[derive(Debug, Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    max: Option<Duration>,
+}
+
+impl<S> DeadlineService<S> {
+    /// Create a new deadline service wrapping `inner`, with an optional maximum deadline.
+    pub fn new(inner: S, max: Option<Duration>) -> Self {
+        Self { inner, max }
+    }
+}
+
+impl<S, BIn, BOut> tower::Service<http::Request<BIn>> for DeadlineService<S>
+where
+    S: tower::Service<http::Request<BIn>, Response = http::Response<BOut>>,
+    BOut: From<crate::Body>,
+{
+    type Response = http::Response<BOut>;
+    type Error = S::Error;
+    type Future = DeadlineFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<BIn>) -> Self::Future {
+        let timeout = match (timeout_from_headers(req.headers()), self.max) {
+            (Some(timeout), Some(max)) => Some(timeout.min(max)),
+            (timeout, max) => timeout.or(max),
+        };
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if let Some(deadline) = deadline {
+            req.extensions_mut().insert(Deadline(deadline));
+        }
+
+        DeadlineFuture {
+            inner: self.inner.call(req),
+            sleep: deadline.map(tokio::time::sleep_until),
+        }
+    }
+}
+
+/// Future returned by [`DeadlineService`].
+#[pin_project::pin_project]
+pub struct DeadlineFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: Option<tokio::time::Sleep>,
+}
+
+impl<F> fmt::Debug for DeadlineFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlineFuture")
+            .field("deadline", &self.sleep.as_ref().map(|s| s.deadline()))
+            .finish()
+    }
+}
+
+impl<F, B, E> Future for DeadlineFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    B: From<crate::Body>,
+{
+    type Output = Result<http::Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(result);
+        }
+
+        if let Some(sleep) = this.sleep.as_pin_mut() {
+            ready!(sleep.poll(cx));
+            let response = Status::new(Code::DeadlineExceeded, "grpc deadline exceeded")
+                .into_response()
+                .map(B::from);
+            return Poll::Ready(Ok(response));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{Layer as _, ServiceExt as _};
+
+    use super::*;
+    use crate::grpc::timeout::GRPC_TIMEOUT;
+
+    #[tokio::test]
+    async fn deadline_extension() {
+        let service =
+            DeadlineLayer::new().layer(tower::service_fn(|req: http::Request<()>| async move {
+                let deadline = req.extensions().get::<Deadline>().copied();
+                assert!(deadline.unwrap().remaining() <= Duration::from_secs(1));
+                Ok::<_, Infallible>(http::Response::new(crate::Body::empty()))
+            }));
+
+        let request = http::Request::builder()
+            .header(GRPC_TIMEOUT, "1S")
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert!(Status::from_header_map(response.headers()).is_none());
+    }
+
+    #[tokio::test]
+    async fn deadline_exceeded() {
+        let service =
+            DeadlineLayer::new().layer(tower::service_fn(|_: http::Request<()>| async move {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok::<_, Infallible>(http::Response::new(crate::Body::empty()))
+            }));
+
+        let request = http::Request::builder()
+            .header(GRPC_TIMEOUT, "10m")
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn no_deadline() {
+        let service =
+            DeadlineLayer::new().layer(tower::service_fn(|req: http::Request<()>| async move {
+                assert!(req.extensions().get::<Deadline>().is_none());
+                Ok::<_, Infallible>(http::Response::new(crate::Body::empty()))
+            }));
+
+        service.oneshot(http::Request::new(())).await.unwrap();
+    }
+}
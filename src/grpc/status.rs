@@ -0,0 +1,348 @@
+//! gRPC status codes and the `grpc-status` / `grpc-message` metadata.
+
+use std::fmt;
+
+use http::header::{HeaderMap, HeaderValue};
+
+/// Header (or trailer) carrying the numeric gRPC status code.
+pub const GRPC_STATUS: &str = "grpc-status";
+
+/// Header (or trailer) carrying the percent-encoded gRPC status message.
+pub const GRPC_MESSAGE: &str = "grpc-message";
+
+/// gRPC status codes.
+///
+/// See <https://grpc.github.io/grpc/core/md_doc_statuscodes.html> for the meaning of each code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Code {
+    /// Not an error; returned on success.
+    Ok,
+
+    /// The operation was cancelled, typically by the caller.
+    Cancelled,
+
+    /// Unknown error.
+    Unknown,
+
+    /// The client specified an invalid argument.
+    InvalidArgument,
+
+    /// The deadline expired before the operation could complete.
+    DeadlineExceeded,
+
+    /// Some requested entity was not found.
+    NotFound,
+
+    /// The entity that a client attempted to create already exists.
+    AlreadyExists,
+
+    /// The caller does not have permission to execute the operation.
+    PermissionDenied,
+
+    /// Some resource has been exhausted.
+    ResourceExhausted,
+
+    /// The system is not in a state required for the operation's execution.
+    FailedPrecondition,
+
+    /// The operation was aborted.
+    Aborted,
+
+    /// The operation was attempted past the valid range.
+    OutOfRange,
+
+    /// The operation is not implemented or supported.
+    Unimplemented,
+
+    /// Internal error.
+    Internal,
+
+    /// The service is currently unavailable.
+    Unavailable,
+
+    /// Unrecoverable data loss or corruption.
+    DataLoss,
+
+    /// The request does not have valid authentication credentials.
+    Unauthenticated,
+}
+
+impl Code {
+    /// Convert a numeric status code into a `Code`.
+    ///
+    /// Unrecognized codes are mapped to [`Code::Unknown`].
+    pub fn from_i32(code: i32) -> Self {
+        match code {
+            0 => Code::Ok,
+            1 => Code::Cancelled,
+            2 => Code::Unknown,
+            3 => Code::InvalidArgument,
+            4 => Code::DeadlineExceeded,
+            5 => Code::NotFound,
+            6 => Code::AlreadyExists,
+            7 => Code::PermissionDenied,
+            8 => Code::ResourceExhausted,
+            9 => Code::FailedPrecondition,
+            10 => Code::Aborted,
+            11 => Code::OutOfRange,
+            12 => Code::Unimplemented,
+            13 => Code::Internal,
+            14 => Code::Unavailable,
+            15 => Code::DataLoss,
+            16 => Code::Unauthenticated,
+            _ => Code::Unknown,
+        }
+    }
+
+    /// The numeric value of this status code.
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            Code::Ok => 0,
+            Code::Cancelled => 1,
+            Code::Unknown => 2,
+            Code::InvalidArgument => 3,
+            Code::DeadlineExceeded => 4,
+            Code::NotFound => 5,
+            Code::AlreadyExists => 6,
+            Code::PermissionDenied => 7,
+            Code::ResourceExhausted => 8,
+            Code::FailedPrecondition => 9,
+            Code::Aborted => 10,
+            Code::OutOfRange => 11,
+            Code::Unimplemented => 12,
+            Code::Internal => 13,
+            Code::Unavailable => 14,
+            Code::DataLoss => 15,
+            Code::Unauthenticated => 16,
+        }
+    }
+
+    /// Map a non-200 HTTP status to a gRPC status code.
+    ///
+    /// This follows the gRPC specification for HTTP to gRPC status code mapping,
+    /// which applies when a response does not carry a `grpc-status`.
+    pub fn from_http(status: http::StatusCode) -> Self {
+        match status {
+            http::StatusCode::BAD_REQUEST => Code::Internal,
+            http::StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            http::StatusCode::FORBIDDEN => Code::PermissionDenied,
+            http::StatusCode::NOT_FOUND => Code::Unimplemented,
+            http::StatusCode::TOO_MANY_REQUESTS
+            | http::StatusCode::BAD_GATEWAY
+            | http::StatusCode::SERVICE_UNAVAILABLE
+            | http::StatusCode::GATEWAY_TIMEOUT => Code::Unavailable,
+            _ => Code::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Code::Ok => "OK",
+            Code::Cancelled => "CANCELLED",
+            Code::Unknown => "UNKNOWN",
+            Code::InvalidArgument => "INVALID_ARGUMENT",
+            Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            Code::NotFound => "NOT_FOUND",
+            Code::AlreadyExists => "ALREADY_EXISTS",
+            Code::PermissionDenied => "PERMISSION_DENIED",
+            Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            Code::FailedPrecondition => "FAILED_PRECONDITION",
+            Code::Aborted => "ABORTED",
+            Code::OutOfRange => "OUT_OF_RANGE",
+            Code::Unimplemented => "UNIMPLEMENTED",
+            Code::Internal => "INTERNAL",
+            Code::Unavailable => "UNAVAILABLE",
+            Code::DataLoss => "DATA_LOSS",
+            Code::Unauthenticated => "UNAUTHENTICATED",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The status of a gRPC call, sent in the `grpc-status` and `grpc-message` metadata.
+///
+/// Non-`OK` statuses are used as the error type for gRPC calls.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("grpc status {code}: {message}")]
+pub struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    /// Create a new status with the given code and message.
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// A successful status.
+    pub fn ok() -> Self {
+        Self::new(Code::Ok, "")
+    }
+
+    /// The status code.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// The status message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Is this status `OK`?
+    pub fn is_ok(&self) -> bool {
+        self.code == Code::Ok
+    }
+
+    /// Read a status from headers or trailers.
+    ///
+    /// Returns `None` if there is no `grpc-status` entry.
+    pub fn from_header_map(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(GRPC_STATUS)?;
+        let code = value
+            .to_str()
+            .ok()
+            .and_then(|code| code.trim().parse::<i32>().ok())
+            .map(Code::from_i32)
+            .unwrap_or(Code::Unknown);
+
+        let message = headers
+            .get(GRPC_MESSAGE)
+            .map(|message| percent_decode(message.as_bytes()))
+            .unwrap_or_default();
+
+        Some(Self { code, message })
+    }
+
+    /// Write this status into headers or trailers.
+    pub fn add_header(&self, headers: &mut HeaderMap) {
+        headers.insert(GRPC_STATUS, HeaderValue::from(self.code.as_i32()));
+        if !self.message.is_empty() {
+            headers.insert(
+                GRPC_MESSAGE,
+                HeaderValue::from_str(&percent_encode(&self.message))
+                    .expect("percent-encoded message is a valid header"),
+            );
+        }
+    }
+
+    /// Build a "trailers-only" response carrying this status.
+    ///
+    /// This is the response a server sends when a call fails before any messages
+    /// have been sent.
+    pub fn into_response(self) -> crate::body::Response {
+        let mut response = http::Response::new(crate::Body::empty());
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static(super::CONTENT_TYPE),
+        );
+        self.add_header(response.headers_mut());
+        response
+    }
+}
+
+impl From<Code> for Status {
+    fn from(code: Code) -> Self {
+        Self::new(code, "")
+    }
+}
+
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(message: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(message.len());
+    let mut idx = 0;
+    while idx < message.len() {
+        if message[idx] == b'%' && idx + 2 < message.len() {
+            let hex = std::str::from_utf8(&message[idx + 1..idx + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(byte) = hex {
+                decoded.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        decoded.push(message[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_roundtrip() {
+        for code in 0..=16 {
+            assert_eq!(Code::from_i32(code).as_i32(), code);
+        }
+        assert_eq!(Code::from_i32(42), Code::Unknown);
+    }
+
+    #[test]
+    fn status_headers_roundtrip() {
+        let status = Status::new(Code::NotFound, "missing: 100% \u{1F980}");
+        let mut headers = HeaderMap::new();
+        status.add_header(&mut headers);
+
+        assert_eq!(headers.get(GRPC_STATUS).unwrap(), "5");
+        assert_eq!(
+            headers.get(GRPC_MESSAGE).unwrap(),
+            "missing: 100%25 %F0%9F%A6%80"
+        );
+
+        let parsed = Status::from_header_map(&headers).unwrap();
+        assert_eq!(parsed, status);
+    }
+
+    #[test]
+    fn status_missing() {
+        assert!(Status::from_header_map(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn status_malformed_message() {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_STATUS, HeaderValue::from_static("13"));
+        headers.insert(GRPC_MESSAGE, HeaderValue::from_static("bad %zz %4"));
+
+        let status = Status::from_header_map(&headers).unwrap();
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "bad %zz %4");
+    }
+
+    #[test]
+    fn code_from_http() {
+        assert_eq!(
+            Code::from_http(http::StatusCode::SERVICE_UNAVAILABLE),
+            Code::Unavailable
+        );
+        assert_eq!(
+            Code::from_http(http::StatusCode::NOT_FOUND),
+            Code::Unimplemented
+        );
+        assert_eq!(
+            Code::from_http(http::StatusCode::IM_A_TEAPOT),
+            Code::Unknown
+        );
+    }
+}
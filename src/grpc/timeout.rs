@@ -0,0 +1,133 @@
+//! Encoding and decoding of the `grpc-timeout` header.
+
+use std::time::Duration;
+
+use http::header::HeaderValue;
+
+/// Header carrying the deadline for a gRPC call, relative to when the request was sent.
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// The maximum number of digits allowed in a `grpc-timeout` value.
+const MAX_DIGITS: u64 = 99_999_999;
+
+/// Parse a `grpc-timeout` header value into a duration.
+///
+/// Returns `None` if the value is malformed.
+pub fn parse_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+
+    let duration = match unit {
+        "H" => Duration::from_secs(amount.checked_mul(60 * 60)?),
+        "M" => Duration::from_secs(amount.checked_mul(60)?),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+
+    Some(duration)
+}
+
+/// Encode a duration as a `grpc-timeout` header value.
+///
+/// The most precise unit which fits in the eight digits allowed by the
+/// specification is used. Durations too large to represent are clamped.
+pub fn encode_timeout(timeout: Duration) -> HeaderValue {
+    let nanos = timeout.as_nanos();
+
+    let units: [(u128, char); 6] = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60 * 1_000_000_000, 'M'),
+        (60 * 60 * 1_000_000_000, 'H'),
+    ];
+
+    for (scale, unit) in units {
+        // Round up, so that the encoded timeout is never shorter than requested.
+        let amount = nanos.div_ceil(scale);
+        if amount <= MAX_DIGITS as u128 {
+            return HeaderValue::from_str(&format!("{amount}{unit}"))
+                .expect("grpc-timeout is a valid header");
+        }
+    }
+
+    HeaderValue::from_str(&format!("{MAX_DIGITS}H")).expect("grpc-timeout is a valid header")
+}
+
+/// Read the `grpc-timeout` from a set of headers, if present and valid.
+pub fn timeout_from_headers(headers: &http::HeaderMap) -> Option<Duration> {
+    headers.get(GRPC_TIMEOUT).and_then(parse_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        let cases = [
+            ("1H", Duration::from_secs(3600)),
+            ("2M", Duration::from_secs(120)),
+            ("3S", Duration::from_secs(3)),
+            ("100m", Duration::from_millis(100)),
+            ("5u", Duration::from_micros(5)),
+            ("7n", Duration::from_nanos(7)),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(
+                parse_timeout(&HeaderValue::from_static(value)),
+                Some(expected),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for value in ["", "S", "10", "10x", "-1S", "123456789S", "1.5S"] {
+            assert_eq!(
+                parse_timeout(&HeaderValue::from_static(value)),
+                None,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        for duration in [
+            Duration::from_nanos(1),
+            Duration::from_millis(250),
+            Duration::from_secs(30),
+            Duration::from_secs(3 * 3600),
+        ] {
+            let value = encode_timeout(duration);
+            assert_eq!(parse_timeout(&value), Some(duration), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn encode_rounds_up() {
+        let value = encode_timeout(Duration::from_nanos(100_000_001));
+        assert_eq!(value, "100001u");
+    }
+
+    #[test]
+    fn encode_clamps() {
+        let value = encode_timeout(Duration::MAX);
+        assert_eq!(value, "99999999H");
+    }
+}
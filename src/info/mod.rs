@@ -4,13 +4,16 @@ use std::convert::Infallible;
 use std::fmt;
 use std::io;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use tokio::net::{TcpStream, UnixStream};
 
+pub mod proxy;
 #[cfg(feature = "tls")]
 pub mod tls;
+pub use self::proxy::ProxyInfo;
 #[cfg(feature = "tls")]
 pub use self::tls::HasTlsConnectionInfo;
 #[cfg(feature = "tls")]
@@ -295,9 +298,67 @@ impl From<DuplexAddr> for BraidAddr {
     }
 }
 
+/// Information about the listener which accepted a connection.
+///
+/// Servers which accept connections from several listeners (e.g. with
+/// `server::conn::MultiAcceptor`) name each listener, and can attach other metadata
+/// to it as extensions, so that services can tell where a connection came from.
+#[derive(Debug, Clone)]
+pub struct ListenerInfo {
+    name: Arc<str>,
+    extensions: http::Extensions,
+}
+
+impl ListenerInfo {
+    /// Create listener information with the given name.
+    pub fn new(name: impl Into<Arc<str>>) -> Self {
+        Self {
+            name: name.into(),
+            extensions: http::Extensions::new(),
+        }
+    }
+
+    /// Attach a value to this listener, which can be retrieved with [`ListenerInfo::get`].
+    pub fn with_extension<T>(mut self, value: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// The name of this listener.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get a value attached to this listener.
+    pub fn get<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions.get()
+    }
+
+    /// All values attached to this listener.
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+}
+
+impl fmt::Display for ListenerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
 /// Information about a connection to a stream.
+///
+/// Fields may be added in future releases, so construct it with
+/// [`ConnectionInfo::new`] rather than a struct literal.
 #[cfg(feature = "stream")]
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct ConnectionInfo<Addr = BraidAddr> {
     /// The local address for this connection.
     pub local_addr: Addr,
@@ -307,11 +368,21 @@ pub struct ConnectionInfo<Addr = BraidAddr> {
 
     /// Buffer size
     pub buffer_size: Option<usize>,
+
+    /// The listener which accepted this connection, when the server has more than one.
+    pub listener: Option<ListenerInfo>,
+
+    /// The PROXY protocol header sent by a proxy in front of the server, if any.
+    pub proxy: Option<ProxyInfo>,
 }
 
 /// Information about a connection to a stream.
+///
+/// Fields may be added in future releases, so construct it with
+/// [`ConnectionInfo::new`] rather than a struct literal.
 #[cfg(not(feature = "stream"))]
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct ConnectionInfo<Addr> {
     /// The local address for this connection.
     pub local_addr: Addr,
@@ -321,6 +392,12 @@ pub struct ConnectionInfo<Addr> {
 
     /// Buffer size
     pub buffer_size: Option<usize>,
+
+    /// The listener which accepted this connection, when the server has more than one.
+    pub listener: Option<ListenerInfo>,
+
+    /// The PROXY protocol header sent by a proxy in front of the server, if any.
+    pub proxy: Option<ProxyInfo>,
 }
 
 impl<Addr> Default for ConnectionInfo<Addr>
@@ -332,6 +409,8 @@ where
             local_addr: Addr::default(),
             remote_addr: Addr::default(),
             buffer_size: None,
+            listener: None,
+            proxy: None,
         }
     }
 }
@@ -343,6 +422,8 @@ impl ConnectionInfo<BraidAddr> {
             local_addr: BraidAddr::Duplex,
             remote_addr: BraidAddr::Duplex,
             buffer_size: Some(buffer_size),
+            listener: None,
+            proxy: None,
         }
     }
 }
@@ -354,11 +435,24 @@ impl ConnectionInfo<DuplexAddr> {
             local_addr: DuplexAddr::new(),
             remote_addr: DuplexAddr::new(),
             buffer_size: Some(buffer_size),
+            listener: None,
+            proxy: None,
         }
     }
 }
 
 impl<Addr> ConnectionInfo<Addr> {
+    /// Create connection info for a connection between two addresses.
+    pub fn new(local_addr: Addr, remote_addr: Addr) -> Self {
+        Self {
+            local_addr,
+            remote_addr,
+            buffer_size: None,
+            listener: None,
+            proxy: None,
+        }
+    }
+
     /// The local address for this connection
     pub fn local_addr(&self) -> &Addr {
         &self.local_addr
@@ -369,6 +463,16 @@ impl<Addr> ConnectionInfo<Addr> {
         &self.remote_addr
     }
 
+    /// The listener which accepted this connection, if it is known.
+    pub fn listener(&self) -> Option<&ListenerInfo> {
+        self.listener.as_ref()
+    }
+
+    /// The PROXY protocol header for this connection, if one was sent.
+    pub fn proxy(&self) -> Option<&ProxyInfo> {
+        self.proxy.as_ref()
+    }
+
     /// Map the addresses in this connection info to a new type.
     pub fn map<T, F>(self, f: F) -> ConnectionInfo<T>
     where
@@ -378,6 +482,8 @@ impl<Addr> ConnectionInfo<Addr> {
             local_addr: f(self.local_addr),
             remote_addr: f(self.remote_addr),
             buffer_size: self.buffer_size,
+            listener: self.listener,
+            proxy: self.proxy,
         }
     }
 }
@@ -396,6 +502,8 @@ where
             local_addr: local_addr.into(),
             remote_addr: remote_addr.into(),
             buffer_size: None,
+            listener: None,
+            proxy: None,
         })
     }
 }
@@ -423,6 +531,8 @@ where
             local_addr: local_addr.into(),
             remote_addr: remote_addr.into(),
             buffer_size: None,
+            listener: None,
+            proxy: None,
         })
     }
 }
@@ -549,6 +659,8 @@ mod tests {
             local_addr: "local",
             remote_addr: "remote",
             buffer_size: Some(1024),
+            listener: None,
+            proxy: None,
         };
 
         let mapped = info.map(|addr| addr.to_string());
@@ -0,0 +1,180 @@
+//! Information from a PROXY protocol header.
+//!
+//! When a server sits behind a load balancer or proxy which speaks the
+//! [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt),
+//! the connection's local and remote addresses in [`ConnectionInfo`](super::ConnectionInfo)
+//! are the addresses from the header. The rest of the header (the proxy's own address,
+//! and any TLV extensions) is available as [`ProxyInfo`].
+
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+
+/// The TLV type for the ALPN protocol negotiated by the proxy.
+pub const PP2_TYPE_ALPN: u8 = 0x01;
+
+/// The TLV type for the authority (usually the SNI host name) sent to the proxy.
+pub const PP2_TYPE_AUTHORITY: u8 = 0x02;
+
+/// The TLV type for a CRC32c checksum of the header.
+pub const PP2_TYPE_CRC32C: u8 = 0x03;
+
+/// The TLV type for a unique identifier for the connection.
+pub const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+
+/// The TLV type for information about the TLS connection to the proxy.
+pub const PP2_TYPE_SSL: u8 = 0x20;
+
+/// The TLV type for the network namespace the proxy accepted the connection in.
+pub const PP2_TYPE_NETNS: u8 = 0x30;
+
+/// The TLV type used by AWS Network Load Balancers, whose value starts with a subtype.
+pub const PP2_TYPE_AWS: u8 = 0xEA;
+
+/// The subtype of [`PP2_TYPE_AWS`] for the VPC endpoint ID.
+pub const PP2_SUBTYPE_AWS_VPCE_ID: u8 = 0x01;
+
+/// The version of the PROXY protocol used by a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyVersion {
+    /// The human-readable version 1 header.
+    V1,
+
+    /// The binary version 2 header.
+    V2,
+}
+
+/// A TLV (type-length-value) extension from a version 2 PROXY protocol header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    kind: u8,
+    value: Bytes,
+}
+
+impl Tlv {
+    /// Create a TLV extension.
+    pub fn new(kind: u8, value: impl Into<Bytes>) -> Self {
+        Self {
+            kind,
+            value: value.into(),
+        }
+    }
+
+    /// The type of this extension.
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    /// The value of this extension.
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+}
+
+/// Information from a PROXY protocol header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyInfo {
+    version: ProxyVersion,
+    local: bool,
+    proxy_addr: Option<SocketAddr>,
+    tlvs: Vec<Tlv>,
+}
+
+impl ProxyInfo {
+    #[cfg(any(test, all(feature = "server", feature = "stream")))]
+    pub(crate) fn new(version: ProxyVersion, local: bool, tlvs: Vec<Tlv>) -> Self {
+        Self {
+            version,
+            local,
+            proxy_addr: None,
+            tlvs,
+        }
+    }
+
+    #[cfg(all(feature = "server", feature = "stream"))]
+    pub(crate) fn with_proxy_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.proxy_addr = addr;
+        self
+    }
+
+    /// The version of the PROXY protocol header.
+    pub fn version(&self) -> ProxyVersion {
+        self.version
+    }
+
+    /// Returns `true` if the proxy opened this connection on its own behalf (e.g. for a
+    /// health check), rather than for a client. The connection's addresses are the
+    /// addresses of the connection to the proxy.
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    /// The address of the proxy which sent the header, if it connected over TCP.
+    pub fn proxy_addr(&self) -> Option<SocketAddr> {
+        self.proxy_addr
+    }
+
+    /// The TLV extensions in the header.
+    pub fn tlvs(&self) -> &[Tlv] {
+        &self.tlvs
+    }
+
+    /// The value of the first TLV extension with the given type.
+    pub fn tlv(&self, kind: u8) -> Option<&Bytes> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| &tlv.value)
+    }
+
+    /// The ALPN protocol negotiated by the proxy.
+    pub fn alpn(&self) -> Option<&[u8]> {
+        self.tlv(PP2_TYPE_ALPN).map(|value| &value[..])
+    }
+
+    /// The authority (usually the SNI host name) sent to the proxy.
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(PP2_TYPE_AUTHORITY)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The unique identifier the proxy assigned to this connection.
+    pub fn unique_id(&self) -> Option<&[u8]> {
+        self.tlv(PP2_TYPE_UNIQUE_ID).map(|value| &value[..])
+    }
+
+    /// The VPC endpoint ID sent by an AWS Network Load Balancer.
+    pub fn aws_vpce_id(&self) -> Option<&str> {
+        self.tlvs
+            .iter()
+            .filter(|tlv| tlv.kind == PP2_TYPE_AWS)
+            .find_map(|tlv| match tlv.value.split_first() {
+                Some((&PP2_SUBTYPE_AWS_VPCE_ID, id)) => std::str::from_utf8(id).ok(),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tlv_accessors() {
+        let info = ProxyInfo::new(
+            ProxyVersion::V2,
+            false,
+            vec![
+                Tlv::new(PP2_TYPE_ALPN, &b"h2"[..]),
+                Tlv::new(PP2_TYPE_AUTHORITY, &b"example.com"[..]),
+                Tlv::new(PP2_TYPE_AWS, &b"\x01vpce-0123"[..]),
+            ],
+        );
+
+        assert_eq!(info.alpn(), Some(&b"h2"[..]));
+        assert_eq!(info.authority(), Some("example.com"));
+        assert_eq!(info.aws_vpce_id(), Some("vpce-0123"));
+        assert_eq!(info.unique_id(), None);
+        assert_eq!(info.tlvs().len(), 3);
+    }
+}
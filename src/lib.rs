@@ -17,6 +17,8 @@ pub mod client;
 pub use client::Client;
 #[cfg(feature = "discovery")]
 pub mod discovery;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 #[cfg(feature = "client")]
 pub(crate) mod happy_eyeballs;
 #[cfg(feature = "client")]
@@ -32,6 +34,8 @@ pub use server::Server;
 pub mod info;
 pub mod service;
 pub mod stream;
+pub mod upgrade;
+mod util;
 
 #[allow(unused)]
 pub(crate) struct DebugLiteral<T: fmt::Display>(T);
@@ -8,6 +8,7 @@ use std::{pin::Pin, task::Poll};
 use bytes::{Buf, Bytes};
 use hyper::rt::{Read, ReadBufCursor, Write};
 
+#[derive(Debug)]
 pub struct Rewind<R> {
     inner: R,
     prefix: Option<Bytes>,
@@ -96,6 +97,72 @@ where
     }
 }
 
+impl<T> tokio::io::AsyncRead for Rewind<T>
+where
+    T: tokio::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if let Some(mut prefix) = self.prefix.take() {
+            if !prefix.is_empty() {
+                let n = std::cmp::min(prefix.len(), buf.remaining());
+
+                buf.put_slice(&prefix[..n]);
+                prefix.advance(n);
+
+                if !prefix.is_empty() {
+                    self.prefix = Some(prefix);
+                }
+                return Poll::Ready(Ok(()));
+            }
+        }
+
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T> tokio::io::AsyncWrite for Rewind<T>
+where
+    T: tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
 // These two functions expose pub(crate) functions from `ReadBufCursor`.
 fn remaining(cursor: &mut ReadBufCursor<'_>) -> usize {
     // SAFETY:
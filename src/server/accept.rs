@@ -0,0 +1,233 @@
+//! Handling errors from the acceptor.
+//!
+//! Not every error from [`Accept::poll_accept`](super::conn::Accept::poll_accept) means that
+//! the server can't continue. The server classifies each error with an [`AcceptErrorPolicy`]
+//! into one of the [`AcceptErrorKind`]s:
+//!
+//! - [`AcceptErrorKind::Connection`] errors only affect a single connection (for example,
+//!   the client reset the connection before it was accepted). They are logged and the
+//!   server continues accepting connections.
+//! - [`AcceptErrorKind::Resource`] errors indicate that the process or system has run out
+//!   of a resource, usually file descriptors (`EMFILE`) or memory. The server logs a warning,
+//!   and waits for the policy's backoff delay before accepting connections again.
+//! - [`AcceptErrorKind::Fatal`] errors stop the server, which returns
+//!   [`ServerError::Accept`](super::ServerError::Accept).
+//!
+//! The default classification, [`classify`], only knows about [`io::Error`]s. Other errors
+//! are fatal. Use [`AcceptErrorPolicy::with_classifier`] to classify errors from a custom
+//! acceptor.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default delay before accepting connections again after a resource error.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+type BoxErrorRef<'a> = &'a (dyn std::error::Error + Send + Sync + 'static);
+
+type Classifier = Arc<dyn Fn(BoxErrorRef<'_>) -> AcceptErrorKind + Send + Sync + 'static>;
+
+/// How the server should handle an error from the acceptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AcceptErrorKind {
+    /// The error only affected a single connection. Keep accepting connections.
+    Connection,
+
+    /// A resource (e.g. file descriptors) is exhausted. Wait, then keep accepting connections.
+    Resource,
+
+    /// The acceptor can't accept any more connections. Stop the server.
+    Fatal,
+}
+
+/// The policy used by the server to handle errors from the acceptor.
+///
+/// See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct AcceptErrorPolicy {
+    backoff: Duration,
+    classifier: Option<Classifier>,
+}
+
+impl fmt::Debug for AcceptErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcceptErrorPolicy")
+            .field("backoff", &self.backoff)
+            .field("classifier", &self.classifier.is_some())
+            .finish()
+    }
+}
+
+impl Default for AcceptErrorPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: DEFAULT_BACKOFF,
+            classifier: None,
+        }
+    }
+}
+
+impl AcceptErrorPolicy {
+    /// Create the default accept error policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the delay before accepting connections again after a resource error.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Classify errors with a custom function, instead of [`classify`].
+    ///
+    /// The function can call [`classify`] to fall back to the default classification.
+    pub fn with_classifier<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(BoxErrorRef<'_>) -> AcceptErrorKind + Send + Sync + 'static,
+    {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// The delay before accepting connections again after a resource error.
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    /// Classify an error from the acceptor.
+    pub fn classify(&self, error: BoxErrorRef<'_>) -> AcceptErrorKind {
+        match &self.classifier {
+            Some(classifier) => classifier(error),
+            None => classify(error),
+        }
+    }
+}
+
+/// The default classification of errors from the acceptor.
+///
+/// Errors which are not [`io::Error`]s are fatal. See [`classify_io`] for the
+/// classification of IO errors.
+pub fn classify(error: BoxErrorRef<'_>) -> AcceptErrorKind {
+    match error.downcast_ref::<io::Error>() {
+        Some(error) => classify_io(error),
+        None => AcceptErrorKind::Fatal,
+    }
+}
+
+/// The default classification of IO errors from the acceptor.
+///
+/// - Running out of file descriptors, buffer space or memory is a resource error.
+/// - Connections which were aborted, reset, refused or timed out, interrupted calls,
+///   and (on Linux) the network errors which `accept(2)` says should be treated like
+///   `EAGAIN` are connection errors.
+/// - Anything else is fatal.
+pub fn classify_io(error: &io::Error) -> AcceptErrorKind {
+    if let Some(code) = error.raw_os_error() {
+        if RESOURCE_ERRORS.contains(&code) {
+            return AcceptErrorKind::Resource;
+        }
+
+        if CONNECTION_ERRORS.contains(&code) {
+            return AcceptErrorKind::Connection;
+        }
+    }
+
+    match error.kind() {
+        io::ErrorKind::OutOfMemory => AcceptErrorKind::Resource,
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => AcceptErrorKind::Connection,
+        _ => AcceptErrorKind::Fatal,
+    }
+}
+
+#[cfg(unix)]
+const RESOURCE_ERRORS: &[i32] = &[libc::ENFILE, libc::EMFILE, libc::ENOMEM, libc::ENOBUFS];
+
+/// `WSAEMFILE` and `WSAENOBUFS`.
+#[cfg(windows)]
+const RESOURCE_ERRORS: &[i32] = &[10024, 10055];
+
+#[cfg(not(any(unix, windows)))]
+const RESOURCE_ERRORS: &[i32] = &[];
+
+/// The network errors which `accept(2)` treats like `EAGAIN`.
+#[cfg(target_os = "linux")]
+const CONNECTION_ERRORS: &[i32] = &[
+    libc::ENONET,
+    libc::EPROTO,
+    libc::ENOPROTOOPT,
+    libc::EOPNOTSUPP,
+    libc::ENETDOWN,
+    libc::ENETUNREACH,
+    libc::EHOSTDOWN,
+    libc::EHOSTUNREACH,
+];
+
+#[cfg(not(target_os = "linux"))]
+const CONNECTION_ERRORS: &[i32] = &[];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(error: io::Error) -> Box<dyn std::error::Error + Send + Sync> {
+        error.into()
+    }
+
+    #[test]
+    fn classify_io_errors() {
+        let policy = AcceptErrorPolicy::new();
+
+        let reset = boxed(io::ErrorKind::ConnectionAborted.into());
+        assert_eq!(policy.classify(&*reset), AcceptErrorKind::Connection);
+
+        let closed = boxed(io::ErrorKind::NotConnected.into());
+        assert_eq!(policy.classify(&*closed), AcceptErrorKind::Fatal);
+
+        let other = boxed(io::Error::other("other"));
+        assert_eq!(policy.classify(&*other), AcceptErrorKind::Fatal);
+
+        let message: Box<dyn std::error::Error + Send + Sync> = "not io".into();
+        assert_eq!(policy.classify(&*message), AcceptErrorKind::Fatal);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn classify_os_errors() {
+        let emfile = boxed(io::Error::from_raw_os_error(libc::EMFILE));
+        assert_eq!(classify(&*emfile), AcceptErrorKind::Resource);
+
+        let enobufs = boxed(io::Error::from_raw_os_error(libc::ENOBUFS));
+        assert_eq!(classify(&*enobufs), AcceptErrorKind::Resource);
+
+        #[cfg(target_os = "linux")]
+        {
+            let enetdown = boxed(io::Error::from_raw_os_error(libc::ENETDOWN));
+            assert_eq!(classify(&*enetdown), AcceptErrorKind::Connection);
+        }
+    }
+
+    #[test]
+    fn custom_classifier() {
+        let policy = AcceptErrorPolicy::new()
+            .with_backoff(Duration::from_millis(10))
+            .with_classifier(|error| match error.to_string().as_str() {
+                "retry" => AcceptErrorKind::Connection,
+                _ => classify(error),
+            });
+        assert_eq!(policy.backoff(), Duration::from_millis(10));
+
+        let retry: Box<dyn std::error::Error + Send + Sync> = "retry".into();
+        assert_eq!(policy.classify(&*retry), AcceptErrorKind::Connection);
+
+        let reset = boxed(io::ErrorKind::ConnectionReset.into());
+        assert_eq!(policy.classify(&*reset), AcceptErrorKind::Connection);
+    }
+}
@@ -1,19 +1,28 @@
 #[cfg(all(feature = "tls", feature = "stream"))]
 use std::sync::Arc;
+#[cfg(all(feature = "tls", feature = "stream"))]
+use std::time::Duration;
 #[cfg(feature = "stream")]
 use std::{io, net::SocketAddr};
 
 use hyper::server::conn::{http1, http2};
 use tower::make::Shared;
 
-use crate::{bridge::rt::TokioExecutor, service::MakeServiceRef};
+use crate::bridge::rt::TokioExecutor;
 
+use super::accept::AcceptErrorPolicy;
+#[cfg(feature = "compression")]
+use super::compression::{CompressionConfig, MakeServiceCompression};
 use super::conn::auto;
+#[cfg(feature = "stream")]
+use super::conn::reuseport::{ReusePort, WorkerRuntime};
+use super::conn::timeout::{ConnectionTimeouts, TimeoutProtocol};
 #[cfg(feature = "tls")]
 use super::conn::tls::info::TlsConnectionInfoService;
 #[cfg(feature = "stream")]
 use super::conn::Acceptor;
 use super::conn::MakeServiceConnectionInfoService;
+use super::limit::ConnectionLimit;
 use super::Accept;
 use super::Server;
 
@@ -45,6 +54,7 @@ impl<P, S, B> Server<NeedsAcceptor, P, S, B> {
             acceptor,
             make_service: self.make_service,
             protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
@@ -79,6 +89,56 @@ impl<P, S, B> Server<NeedsAcceptor, P, S, B> {
     ) -> Server<Acceptor, P, S, B> {
         self.with_acceptor(Acceptor::from(listener))
     }
+
+    #[cfg(feature = "stream")]
+    /// Bind `workers` TCP listeners to the provided address with `SO_REUSEPORT`, and
+    /// run one accept loop for each of them.
+    ///
+    /// The kernel spreads new connections across the listeners. The workers share
+    /// the server's [`ServerHandle`](super::ServerHandle), so a single shutdown stops
+    /// all of them and connection counts cover all of them, and they share the
+    /// server's connection limit. Workers run as tasks on the server's runtime, unless
+    /// [`Server::with_current_thread_workers`] is used. The protocol and make service
+    /// are cloned for each worker.
+    ///
+    /// See [`super::conn::reuseport`] for details.
+    pub fn with_reuse_port(
+        self,
+        addr: &SocketAddr,
+        workers: usize,
+    ) -> Result<Server<ReusePort, P, S, B>, io::Error> {
+        Ok(Server {
+            acceptor: ReusePort::bind(*addr, workers)?,
+            make_service: self.make_service,
+            protocol: self.protocol,
+            config: self.config,
+            body: self.body,
+        })
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<P, S, B> Server<ReusePort, P, S, B> {
+    /// Run each worker on its own thread, with a current-thread runtime.
+    ///
+    /// Connections are served on the runtime of the worker which accepted them. After
+    /// the server stops accepting, each worker's thread keeps running until the
+    /// connections it accepted have closed, so use
+    /// [`ServerHandle::drain`](super::ServerHandle::drain) to wait for them.
+    pub fn with_current_thread_workers(self) -> Self {
+        Server {
+            acceptor: self.acceptor.with_runtime(WorkerRuntime::CurrentThread),
+            make_service: self.make_service,
+            protocol: self.protocol,
+            config: self.config,
+            body: self.body,
+        }
+    }
+
+    /// The address the workers' listeners are bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.acceptor.local_addr()
+    }
 }
 
 impl<A, S, B> Server<A, NeedsProtocol, S, B> {
@@ -88,6 +148,7 @@ impl<A, S, B> Server<A, NeedsProtocol, S, B> {
             acceptor: self.acceptor,
             make_service: self.make_service,
             protocol,
+            config: self.config,
             body: self.body,
         }
     }
@@ -123,6 +184,7 @@ impl<A, P, B> Server<A, P, NeedsService, B> {
             acceptor: self.acceptor,
             make_service,
             protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
@@ -133,16 +195,13 @@ impl<A, P, B> Server<A, P, NeedsService, B> {
             acceptor: self.acceptor,
             make_service: Shared::new(service),
             protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
 }
 
-impl<A, P, S, B> Server<A, P, S, B>
-where
-    S: MakeServiceRef<A::Conn, B>,
-    A: Accept,
-{
+impl<A, P, S, B> Server<A, P, S, B> {
     /// Wrap the make service in a service that provides connection information.
     ///
     /// This will make `crate::info::ConnectionInfo<A>` available in the request
@@ -152,6 +211,25 @@ where
             acceptor: self.acceptor,
             make_service: MakeServiceConnectionInfoService::new(self.make_service),
             protocol: self.protocol,
+            config: self.config,
+            body: self.body,
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    /// Compress responses from each service generated by the make service.
+    ///
+    /// Responses are compressed using the best encoding accepted by the client, when
+    /// they meet the size and content type requirements in `config`.
+    pub fn with_compression(
+        self,
+        config: CompressionConfig,
+    ) -> Server<A, P, MakeServiceCompression<S>, B> {
+        Server {
+            acceptor: self.acceptor,
+            make_service: MakeServiceCompression::new(self.make_service, config),
+            protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
@@ -166,6 +244,7 @@ where
             acceptor: self.acceptor,
             make_service: TlsConnectionInfoService::new(self.make_service),
             protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
@@ -183,12 +262,46 @@ impl<P, S, B> Server<Acceptor, P, S, B> {
             acceptor: self.acceptor.with_tls(config.into()),
             make_service: self.make_service,
             protocol: self.protocol,
+            config: self.config,
+            body: self.body,
+        }
+    }
+
+    /// Close connections which don't complete the TLS handshake within `timeout`
+    /// of being accepted.
+    ///
+    /// This can be called before or after [`Server::with_tls`], and has no effect
+    /// on connections which don't use TLS.
+    pub fn with_tls_handshake_timeout(self, timeout: Duration) -> Server<Acceptor, P, S, B> {
+        Server {
+            acceptor: self.acceptor.with_tls_handshake_timeout(timeout),
+            make_service: self.make_service,
+            protocol: self.protocol,
+            config: self.config,
             body: self.body,
         }
     }
 }
 
 impl<A, P, S, B> Server<A, P, S, B> {
+    /// Apply timeouts to each connection, to close connections which are idle or
+    /// send requests too slowly.
+    ///
+    /// This wraps the protocol, so it must be called after the protocol is set.
+    /// See [`super::conn::timeout`] for details.
+    pub fn with_connection_timeouts(
+        self,
+        timeouts: ConnectionTimeouts,
+    ) -> Server<A, TimeoutProtocol<P>, S, B> {
+        Server {
+            acceptor: self.acceptor,
+            make_service: self.make_service,
+            protocol: TimeoutProtocol::new(self.protocol, timeouts),
+            config: self.config,
+            body: self.body,
+        }
+    }
+
     /// Set the body to use for handling requests.
     ///
     /// Usually this method can be called with inferred
@@ -198,7 +311,46 @@ impl<A, P, S, B> Server<A, P, S, B> {
             acceptor: self.acceptor,
             make_service: self.make_service,
             protocol: self.protocol,
+            config: self.config,
             body: Default::default(),
         }
     }
+
+    /// Set the policy used to handle errors from the acceptor.
+    ///
+    /// By default, errors which only affect a single connection are ignored, the server
+    /// waits before accepting again when it runs out of resources (e.g. file descriptors),
+    /// and other errors stop the server. See [`super::accept`] for details.
+    pub fn with_accept_error_policy(mut self, policy: AcceptErrorPolicy) -> Self {
+        self.config.accept_errors = policy;
+        self
+    }
+
+    /// Set the maximum number of connections whose services are being made at once.
+    ///
+    /// The make service is called for each connection as soon as it is accepted, and
+    /// the server keeps accepting connections while services are being made. When this
+    /// many services are being made, the server waits for one of them to finish before
+    /// accepting another connection. The limit is at least one, which makes services for
+    /// one connection at a time. The default is [`DEFAULT_MAX_PENDING_SETUPS`](super::DEFAULT_MAX_PENDING_SETUPS).
+    pub fn with_max_pending_setups(mut self, max: usize) -> Self {
+        self.config.max_pending_setups = max;
+        self
+    }
+
+    /// Serve at most `max` connections at once.
+    ///
+    /// When the limit is reached, the server stops accepting connections until one of
+    /// them finishes. See [`Server::with_connection_limit`] to reject connections instead.
+    pub fn with_max_connections(self, max: usize) -> Self {
+        self.with_connection_limit(ConnectionLimit::new(max))
+    }
+
+    /// Limit the number of connections served at once.
+    ///
+    /// See [`super::limit`] for details.
+    pub fn with_connection_limit(mut self, limit: ConnectionLimit) -> Self {
+        self.config.connection_limit = Some(limit);
+        self
+    }
 }
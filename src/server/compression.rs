@@ -0,0 +1,375 @@
+//! Response compression for servers.
+//!
+//! Compression is applied to the "make service" part of the stack with
+//! [`Server::with_compression`](crate::Server::with_compression), so that each
+//! connection's service compresses responses according to the client's `Accept-Encoding`.
+//! Only responses which are at least a minimum size, and which have a content type in
+//! the allow-list, are compressed.
+//!
+//! The supported algorithms are enabled with the `gzip`, `deflate`, `brotli` and `zstd`
+//! cargo features.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http_body::Body as HttpBody;
+use tower::Layer;
+use tower_http::compression::predicate::Predicate;
+pub use tower_http::compression::CompressionLevel;
+use tower_http::compression::{Compression, CompressionLayer, ResponseFuture};
+
+use crate::service::ServiceRef;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The default minimum response size, in bytes, for compression.
+pub const DEFAULT_MIN_SIZE: usize = 32;
+
+/// Content types which are compressed by default.
+///
+/// Entries ending in `/` match any subtype.
+pub const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Configuration for response compression.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    min_size: usize,
+    content_types: Vec<String>,
+    level: CompressionLevel,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: DEFAULT_MIN_SIZE,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|ct| ct.to_string())
+                .collect(),
+            level: CompressionLevel::Default,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Create a new compression configuration with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the minimum response size, in bytes, for compression.
+    ///
+    /// Responses whose body size is not known in advance are always compressed.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Replace the content type allow-list.
+    ///
+    /// Entries are matched case-insensitively against the start of the response's
+    /// `Content-Type`, so `text/` matches all text types, and `application/json`
+    /// matches `application/json; charset=utf-8`.
+    pub fn with_content_types<I, T>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.content_types = content_types
+            .into_iter()
+            .map(|ct| ct.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Add a content type to the allow-list.
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_types
+            .push(content_type.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Set the compression level.
+    pub fn with_level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Minimum response size for compression.
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Content types which will be compressed.
+    pub fn content_types(&self) -> &[String] {
+        &self.content_types
+    }
+
+    fn layer(&self) -> CompressionLayer<CompressionPredicate> {
+        CompressionLayer::new()
+            .quality(self.level)
+            .compress_when(CompressionPredicate {
+                min_size: self.min_size,
+                content_types: self.content_types.clone().into(),
+            })
+    }
+}
+
+/// Decides which responses are compressed, based on a [`CompressionConfig`].
+#[derive(Debug, Clone)]
+pub struct CompressionPredicate {
+    min_size: usize,
+    content_types: Arc<[String]>,
+}
+
+impl Predicate for CompressionPredicate {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        if response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size < self.min_size as u64)
+        {
+            return false;
+        }
+
+        let Some(content_type) = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let content_type = content_type.trim().to_ascii_lowercase();
+
+        // Server-sent events must be delivered as they are produced.
+        if content_type.starts_with("text/event-stream") {
+            return false;
+        }
+
+        self.content_types
+            .iter()
+            .any(|allowed| content_type.starts_with(allowed.as_str()))
+    }
+}
+
+/// A layer which applies response compression to each service made by a make service.
+#[derive(Debug, Clone, Default)]
+pub struct MakeServiceCompressionLayer {
+    config: CompressionConfig,
+}
+
+impl MakeServiceCompressionLayer {
+    /// Create a new layer with the given configuration.
+    pub fn new(config: CompressionConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> Layer<S> for MakeServiceCompressionLayer {
+    type Service = MakeServiceCompression<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MakeServiceCompression::new(inner, self.config.clone())
+    }
+}
+
+/// A make service which wraps each generated service in response compression.
+///
+/// See [`MakeServiceCompressionLayer`] and [`CompressionConfig`] for more details.
+#[derive(Debug, Clone)]
+pub struct MakeServiceCompression<C> {
+    inner: C,
+    layer: CompressionLayer<CompressionPredicate>,
+}
+
+impl<C> MakeServiceCompression<C> {
+    /// Create a new `MakeServiceCompression` wrapping `inner`.
+    pub fn new(inner: C, config: CompressionConfig) -> Self {
+        Self {
+            inner,
+            layer: config.layer(),
+        }
+    }
+}
+
+impl<C, IO> tower::Service<&IO> for MakeServiceCompression<C>
+where
+    C: ServiceRef<IO>,
+{
+    type Response = CompressionService<C::Response>;
+    type Error = C::Error;
+    type Future = MakeServiceCompressionFuture<C::Future>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, stream: &IO) -> Self::Future {
+        MakeServiceCompressionFuture {
+            inner: self.inner.call(stream),
+            layer: Some(self.layer.clone()),
+        }
+    }
+}
+
+/// Future returned by [`MakeServiceCompression`].
+#[pin_project::pin_project]
+pub struct MakeServiceCompressionFuture<F> {
+    #[pin]
+    inner: F,
+    layer: Option<CompressionLayer<CompressionPredicate>>,
+}
+
+impl<F> fmt::Debug for MakeServiceCompressionFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MakeServiceCompressionFuture").finish()
+    }
+}
+
+impl<F, S, E> Future for MakeServiceCompressionFuture<F>
+where
+    F: Future<Output = Result<S, E>>,
+{
+    type Output = Result<CompressionService<S>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.inner.poll(cx).map_ok(|service| CompressionService {
+            inner: this
+                .layer
+                .take()
+                .expect("future polled after completion")
+                .layer(service),
+        })
+    }
+}
+
+/// A service which compresses responses from the inner service.
+///
+/// Responses are returned with a [`crate::Body`], so that this service can be
+/// used with any server protocol.
+#[derive(Clone)]
+pub struct CompressionService<S> {
+    inner: Compression<S, CompressionPredicate>,
+}
+
+impl<S> fmt::Debug for CompressionService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionService").finish()
+    }
+}
+
+impl<S, BIn, BOut> tower::Service<http::Request<BIn>> for CompressionService<S>
+where
+    S: tower::Service<http::Request<BIn>, Response = http::Response<BOut>>,
+    BOut: HttpBody<Data = Bytes> + Send + 'static,
+    BOut::Error: Into<BoxError> + Send,
+{
+    type Response = crate::body::Response;
+    type Error = S::Error;
+    type Future = CompressionFuture<ResponseFuture<S::Future, CompressionPredicate>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<BIn>) -> Self::Future {
+        CompressionFuture {
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+/// Future returned by [`CompressionService`].
+#[pin_project::pin_project]
+pub struct CompressionFuture<F> {
+    #[pin]
+    inner: F,
+}
+
+impl<F> fmt::Debug for CompressionFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionFuture").finish()
+    }
+}
+
+impl<F, B, E> Future for CompressionFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError> + Send,
+{
+    type Output = Result<crate::body::Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project()
+            .inner
+            .poll(cx)
+            .map_ok(|response| response.map(crate::Body::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_type: Option<&str>, body: &'static str) -> http::Response<crate::Body> {
+        let mut response = http::Response::new(crate::Body::from(body));
+        if let Some(ct) = content_type {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, http::HeaderValue::from_str(ct).unwrap());
+        }
+        response
+    }
+
+    fn predicate(config: &CompressionConfig) -> CompressionPredicate {
+        CompressionPredicate {
+            min_size: config.min_size,
+            content_types: config.content_types.clone().into(),
+        }
+    }
+
+    #[test]
+    fn predicate_min_size() {
+        let predicate = predicate(&CompressionConfig::new().with_min_size(8));
+        assert!(!predicate.should_compress(&response(Some("text/plain"), "short")));
+        assert!(predicate.should_compress(&response(Some("text/plain"), "long enough")));
+    }
+
+    #[test]
+    fn predicate_content_types() {
+        let predicate = predicate(&CompressionConfig::new().with_min_size(0));
+        assert!(predicate.should_compress(&response(Some("text/html; charset=utf-8"), "")));
+        assert!(predicate.should_compress(&response(Some("Application/JSON"), "")));
+        assert!(!predicate.should_compress(&response(Some("image/png"), "")));
+        assert!(!predicate.should_compress(&response(Some("text/event-stream"), "")));
+        assert!(!predicate.should_compress(&response(None, "")));
+    }
+
+    #[test]
+    fn predicate_custom_content_types() {
+        let predicate = predicate(
+            &CompressionConfig::new()
+                .with_min_size(0)
+                .with_content_types(["application/grpc-web"]),
+        );
+        assert!(predicate.should_compress(&response(Some("application/grpc-web+proto"), "")));
+        assert!(!predicate.should_compress(&response(Some("text/plain"), "")));
+    }
+}
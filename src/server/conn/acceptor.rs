@@ -4,6 +4,8 @@
 use std::io;
 #[cfg(feature = "stream")]
 use std::net::SocketAddr;
+#[cfg(feature = "stream")]
+use std::os::fd::{AsFd, OwnedFd};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -36,6 +38,8 @@ use crate::server::conn::tls::TlsAcceptor as RawTlsAcceptor;
 pub struct Acceptor<A = AcceptorCore> {
     #[pin]
     inner: AcceptorInner<A>,
+    #[cfg(feature = "tls")]
+    handshake_timeout: Option<std::time::Duration>,
 }
 
 /// Accept incoming connections for streams which might
@@ -46,6 +50,8 @@ pub struct Acceptor<A = AcceptorCore> {
 pub struct Acceptor<A> {
     #[pin]
     inner: AcceptorInner<A>,
+    #[cfg(feature = "tls")]
+    handshake_timeout: Option<std::time::Duration>,
 }
 
 impl<A> Acceptor<A> {
@@ -53,6 +59,8 @@ impl<A> Acceptor<A> {
     pub fn new(accept: A) -> Self {
         Acceptor {
             inner: AcceptorInner::NoTls(accept),
+            #[cfg(feature = "tls")]
+            handshake_timeout: None,
         }
     }
 }
@@ -113,8 +121,31 @@ impl<A> Acceptor<A> {
             AcceptorInner::Tls(_) => panic!("Acceptor::tls called twice"),
         };
 
+        let mut tls = RawTlsAcceptor::new(config, core);
+        if let Some(timeout) = self.handshake_timeout {
+            tls = tls.with_handshake_timeout(timeout);
+        }
+
+        Acceptor {
+            inner: AcceptorInner::Tls(tls),
+            handshake_timeout: self.handshake_timeout,
+        }
+    }
+
+    /// Close connections which don't complete the TLS handshake within `timeout`
+    /// of being accepted.
+    ///
+    /// This can be called before or after [`Acceptor::with_tls`], and has no effect
+    /// on connections which don't use TLS.
+    pub fn with_tls_handshake_timeout(self, timeout: std::time::Duration) -> Self {
+        let inner = match self.inner {
+            AcceptorInner::Tls(tls) => AcceptorInner::Tls(tls.with_handshake_timeout(timeout)),
+            inner => inner,
+        };
+
         Acceptor {
-            inner: AcceptorInner::Tls(RawTlsAcceptor::new(config, core)),
+            inner,
+            handshake_timeout: Some(timeout),
         }
     }
 }
@@ -140,15 +171,79 @@ impl From<UnixListener> for AcceptorCore {
     }
 }
 
+#[cfg(feature = "stream")]
+impl AcceptorCore {
+    /// Create an acceptor from a listening socket which was opened elsewhere,
+    /// e.g. inherited from systemd or from a previous server process.
+    ///
+    /// The socket must be a listening TCP or Unix stream socket. It is put into
+    /// non-blocking mode, and must be used from within a tokio runtime.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self, io::Error> {
+        let socket = socket2::Socket::from(fd);
+
+        if socket.r#type()? != socket2::Type::STREAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket is not a stream socket",
+            ));
+        }
+
+        #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+        if !socket.is_listener()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket is not listening",
+            ));
+        }
+
+        socket.set_nonblocking(true)?;
+        let addr = socket.local_addr()?;
+        if addr.is_unix() {
+            let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+            Ok(UnixListener::from_std(listener)?.into())
+        } else if addr.as_socket().is_some() {
+            let listener = std::net::TcpListener::from(socket);
+            Ok(TcpListener::from_std(listener)?.into())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket is not a TCP or Unix socket",
+            ))
+        }
+    }
+
+    /// Duplicate the listening socket, e.g. to hand it off to another process
+    /// while this one keeps accepting connections.
+    ///
+    /// Duplex listeners don't have a socket, and return an error.
+    pub fn try_clone_fd(&self) -> Result<OwnedFd, io::Error> {
+        match self {
+            AcceptorCore::Tcp(listener) => listener.as_fd().try_clone_to_owned(),
+            AcceptorCore::Unix(listener) => listener.as_fd().try_clone_to_owned(),
+            AcceptorCore::Duplex(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "duplex listeners don't have a socket",
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl TryFrom<OwnedFd> for AcceptorCore {
+    type Error = io::Error;
+
+    fn try_from(fd: OwnedFd) -> Result<Self, Self::Error> {
+        AcceptorCore::from_fd(fd)
+    }
+}
+
 #[cfg(feature = "stream")]
 impl<T> From<T> for Acceptor
 where
     T: Into<AcceptorCore>,
 {
     fn from(value: T) -> Self {
-        Acceptor {
-            inner: AcceptorInner::NoTls(value.into()),
-        }
+        Acceptor::new(value.into())
     }
 }
 
@@ -65,6 +65,16 @@ impl<E> Builder<E> {
         &mut self.http2
     }
 
+    /// Advertise `SETTINGS_ENABLE_CONNECT_PROTOCOL` on HTTP/2 connections.
+    ///
+    /// This allows clients to send extended CONNECT requests (RFC 8441), e.g. to
+    /// tunnel WebSockets over HTTP/2. See [`crate::upgrade`] for handling these
+    /// requests in a service.
+    pub fn enable_connect_protocol(&mut self) -> &mut Self {
+        self.http2.enable_connect_protocol();
+        self
+    }
+
     /// Serve a connection with automatic protocol detection.
     pub fn serve_connection_with_upgrades<I, S, B>(
         &self,
@@ -189,6 +199,7 @@ where
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[pin_project(project = ConnectionStateProject)]
 enum ConnectionState<'b, I, S, E>
 where
@@ -0,0 +1,499 @@
+//! Zero-downtime restarts, by handing listening sockets from one process to the next.
+//!
+//! The running process offers its listening sockets with a [`HandoffSender`], which
+//! listens on a Unix socket. When the replacement process starts, it connects with a
+//! [`HandoffReceiver`] and receives copies of the sockets (with `SCM_RIGHTS`), so both
+//! processes accept from the same listeners and no connections are refused. Once the
+//! new process is serving, it calls [`HandoffReceiver::complete`], and the old process
+//! releases its [`PidFile`] and gracefully shuts down.
+//!
+//! # Example
+//! ```no_run
+//! # use std::time::Duration;
+//! # use hyperdriver::Body;
+//! # use hyperdriver::Server;
+//! # use hyperdriver::pidfile::PidFile;
+//! # use hyperdriver::server::conn::{Acceptor, AcceptorCore};
+//! # use hyperdriver::server::conn::handoff::{HandoffReceiver, HandoffSender};
+//! # use tower::service_fn;
+//! # type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+//! # async fn example() -> std::io::Result<()> {
+//! // Take over the listener from the running process, or bind a new one.
+//! let receiver = HandoffReceiver::connect("/run/app/handoff.sock").await?;
+//! let core = match receiver {
+//!     Some(mut receiver) => {
+//!         let listener = receiver.take("http").expect("the http listener");
+//!         receiver.complete().await?;
+//!         listener.into_core()?
+//!     }
+//!     None => tokio::net::TcpListener::bind("[::]:8080").await?.into(),
+//! };
+//! let pidfile = PidFile::new("/run/app/app.pid".into())?;
+//!
+//! // Offer the listener to the next process.
+//! let sender = HandoffSender::bind("/run/app/handoff.sock")?
+//!     .with_listener("http", core.try_clone_fd()?)
+//!     .with_pidfile(pidfile);
+//!
+//! let server = Server::builder()
+//!     .with_acceptor(Acceptor::from(core))
+//!     .with_auto_http()
+//!     .with_shared_service(service_fn(|_| async move {
+//!         Ok::<_, BoxError>(http::Response::new(Body::empty()))
+//!     }));
+//!
+//! let handle = server.handle();
+//! let shutdown = async move {
+//!     sender.send().await?;
+//!     handle.drain(Duration::from_secs(30)).await;
+//!     Ok::<_, std::io::Error>(())
+//! };
+//!
+//! let (result, shutdown) = tokio::join!(async { server.await }, shutdown);
+//! result.unwrap();
+//! shutdown
+//! # }
+//! ```
+//!
+//! The new process should complete the handoff as soon as it is ready to accept
+//! connections: the old process doesn't stop accepting until it does, and if the new
+//! process exits before completing the handoff, the old process keeps serving and waits
+//! for another process to take over.
+
+use std::fmt;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt as _;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use nix::sys::socket::{ControlMessage, ControlMessageOwned, MsgFlags};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, Interest};
+use tokio::net::{UnixListener, UnixStream};
+
+use super::systemd::{ListenFd, ListenFds};
+use crate::pidfile::PidFile;
+
+/// The default time allowed for a new process to take over the sockets.
+pub const DEFAULT_HANDOFF_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most sockets which can be passed in one message.
+const MAX_FDS: usize = 253;
+
+/// Sent with the sockets, to identify the protocol.
+const MAGIC: u8 = b'H';
+const VERSION: u8 = 1;
+
+/// Sent by the new process when it has taken over the sockets.
+const ACK: u8 = b'A';
+
+/// Sent by the old process once it has released its PID file.
+const RELEASED: u8 = b'R';
+
+/// Offers this process's listening sockets to a replacement process.
+pub struct HandoffSender {
+    listener: UnixListener,
+    path: Utf8PathBuf,
+    listeners: Vec<(String, OwnedFd)>,
+    pidfile: Option<PidFile>,
+    timeout: Duration,
+    removed: bool,
+}
+
+impl HandoffSender {
+    /// Listen for a replacement process on a Unix socket at `path`.
+    ///
+    /// Any existing socket at `path` is removed, so this should be called after
+    /// a handoff from a previous process has completed. The socket is removed when
+    /// the sender is dropped.
+    ///
+    /// The socket is only accessible by the owner (mode `0600`), and only processes
+    /// running as the same user as this process are sent the sockets.
+    pub fn bind(path: impl Into<Utf8PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+        match std::fs::remove_file(&path) {
+            Ok(()) => tracing::debug!(%path, "removed stale handoff socket"),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        // Processes which connect before the mode is set are still checked in `offer`.
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Self {
+            listener,
+            path,
+            listeners: Vec::new(),
+            pidfile: None,
+            timeout: DEFAULT_HANDOFF_TIMEOUT,
+            removed: false,
+        })
+    }
+
+    /// Offer a listening socket with the given name.
+    ///
+    /// Use [`AcceptorCore::try_clone_fd`](super::AcceptorCore::try_clone_fd) to get
+    /// a socket from an acceptor before serving it.
+    pub fn with_listener(mut self, name: impl Into<String>, fd: OwnedFd) -> Self {
+        self.push(name, fd);
+        self
+    }
+
+    /// Offer a listening socket with the given name.
+    pub fn push(&mut self, name: impl Into<String>, fd: OwnedFd) {
+        self.listeners.push((name.into(), fd));
+    }
+
+    /// Release this PID file once the replacement process has taken over,
+    /// so that the new process can lock it.
+    pub fn with_pidfile(mut self, pidfile: PidFile) -> Self {
+        self.pidfile = Some(pidfile);
+        self
+    }
+
+    /// The time allowed for a replacement process to take over after it connects.
+    ///
+    /// A process which takes longer is disconnected, and the sockets are offered
+    /// to the next process which connects. Defaults to [`DEFAULT_HANDOFF_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The path of the Unix socket which replacement processes connect to.
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Wait for a replacement process to take over the sockets.
+    ///
+    /// This resolves once a process has received the sockets and completed the
+    /// handoff, at which point the PID file has been released and this process
+    /// should shut down. Failed handoffs are logged, and the sockets are offered
+    /// again to the next process which connects.
+    pub async fn send(mut self) -> Result<(), io::Error> {
+        if self.listeners.len() > MAX_FDS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("at most {MAX_FDS} sockets can be handed off"),
+            ));
+        }
+
+        let mut stream = loop {
+            let (mut stream, _) = self.listener.accept().await?;
+            tracing::debug!(path = %self.path, "replacement process connected");
+
+            match tokio::time::timeout(self.timeout, self.offer(&mut stream)).await {
+                Ok(Ok(())) => break stream,
+                Ok(Err(error)) => {
+                    tracing::warn!("handoff to replacement process failed: {error}");
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        "replacement process didn't take over within {:?}",
+                        self.timeout
+                    );
+                }
+            }
+        };
+
+        // The new process has taken over: release the PID file and the handoff socket
+        // before telling it, so that it can lock them for itself.
+        drop(self.pidfile.take());
+        self.remove_socket();
+        if let Err(error) = stream.write_u8(RELEASED).await {
+            tracing::debug!("unable to notify replacement process: {error}");
+        }
+
+        tracing::info!("handed off {} listening sockets", self.listeners.len());
+        Ok(())
+    }
+
+    /// Send the sockets to a replacement process, and wait for it to take over.
+    async fn offer(&self, stream: &mut UnixStream) -> Result<(), io::Error> {
+        check_peer(stream)?;
+
+        let mut names = Vec::new();
+        for (name, _) in &self.listeners {
+            let len = u16::try_from(name.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name is too long"))?;
+            names.extend_from_slice(&len.to_be_bytes());
+            names.extend_from_slice(name.as_bytes());
+        }
+
+        let [high, low] = (self.listeners.len() as u16).to_be_bytes();
+        let header = [MAGIC, VERSION, high, low];
+        let fds: Vec<RawFd> = self
+            .listeners
+            .iter()
+            .map(|(_, fd)| fd.as_raw_fd())
+            .collect();
+
+        let sent = stream
+            .async_io(Interest::WRITABLE, || {
+                send_with_fds(stream.as_raw_fd(), &header, &fds)
+            })
+            .await?;
+        stream.write_all(&header[sent..]).await?;
+        stream.write_all(&names).await?;
+
+        match stream.read_u8().await? {
+            ACK => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected handoff response",
+            )),
+        }
+    }
+
+    fn remove_socket(&mut self) {
+        // Only remove the socket once, since the new process may bind its own.
+        if mem::replace(&mut self.removed, true) {
+            return;
+        }
+
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            if error.kind() != io::ErrorKind::NotFound {
+                tracing::warn!(path = %self.path, "unable to remove handoff socket: {error}");
+            }
+        }
+    }
+}
+
+impl Drop for HandoffSender {
+    fn drop(&mut self) {
+        self.remove_socket();
+    }
+}
+
+impl fmt::Debug for HandoffSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandoffSender")
+            .field("path", &self.path)
+            .field(
+                "listeners",
+                &self
+                    .listeners
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("pidfile", &self.pidfile)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Takes over the listening sockets of a running process.
+#[derive(Debug)]
+pub struct HandoffReceiver {
+    stream: UnixStream,
+    listeners: ListenFds,
+}
+
+impl HandoffReceiver {
+    /// Connect to a running process's [`HandoffSender`] at `path`, and receive its sockets.
+    ///
+    /// Returns `None` if there is no process to take over from.
+    pub async fn connect(path: impl AsRef<Utf8Path>) -> Result<Option<Self>, io::Error> {
+        let path = path.as_ref();
+        let mut stream = match UnixStream::connect(path).await {
+            Ok(stream) => stream,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                tracing::debug!(%path, "no running process to take over from");
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        check_peer(&stream)?;
+
+        let mut header = [0u8; 4];
+        let (received, fds) = stream
+            .async_io(Interest::READABLE, || {
+                recv_with_fds(stream.as_raw_fd(), &mut header)
+            })
+            .await?;
+        if received == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        stream.read_exact(&mut header[received..]).await?;
+
+        let [magic, version, high, low] = header;
+        if magic != MAGIC || version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported handoff protocol",
+            ));
+        }
+
+        let count = u16::from_be_bytes([high, low]) as usize;
+        if fds.len() != count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {count} sockets, received {}", fds.len()),
+            ));
+        }
+
+        let mut listeners = Vec::with_capacity(count);
+        for fd in fds {
+            let len = stream.read_u16().await? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await?;
+            let name = String::from_utf8(name)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid socket name"))?;
+            socket2::SockRef::from(&fd).set_cloexec(true)?;
+            tracing::debug!(%name, ?fd, "received listening socket");
+            listeners.push(ListenFd::new(name, fd));
+        }
+
+        Ok(Some(Self {
+            stream,
+            listeners: ListenFds::new(listeners),
+        }))
+    }
+
+    /// The names of the sockets which haven't been taken yet.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.listeners.names()
+    }
+
+    /// Take the first socket with the given name.
+    pub fn take(&mut self, name: &str) -> Option<ListenFd> {
+        self.listeners.take(name)
+    }
+
+    /// Take all of the sockets which haven't been taken yet.
+    pub fn take_listeners(&mut self) -> ListenFds {
+        mem::take(&mut self.listeners)
+    }
+
+    /// Tell the old process that this process has taken over, and wait for it to
+    /// release its PID file.
+    ///
+    /// Once this returns, the old process is shutting down, and this process can lock
+    /// the PID file and bind its own [`HandoffSender`].
+    pub async fn complete(mut self) -> Result<(), io::Error> {
+        self.stream.write_u8(ACK).await?;
+        match self.stream.read_u8().await? {
+            RELEASED => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected handoff response",
+            )),
+        }
+    }
+}
+
+/// Check that the process at the other end of `stream` is running as the same user
+/// as this process, since the sockets give it control over this process's listeners.
+fn check_peer(stream: &UnixStream) -> Result<(), io::Error> {
+    let peer = stream.peer_cred()?.uid();
+    let uid = nix::unistd::getuid().as_raw();
+    if peer != uid {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("handoff peer is running as uid {peer}, not {uid}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Send `data`, along with copies of `fds`, on a Unix stream socket.
+fn send_with_fds(socket: RawFd, data: &[u8], fds: &[RawFd]) -> Result<usize, io::Error> {
+    let iov = [IoSlice::new(data)];
+    let rights = [ControlMessage::ScmRights(fds)];
+    let cmsgs: &[ControlMessage<'_>] = if fds.is_empty() { &[] } else { &rights };
+
+    nix::sys::socket::sendmsg::<()>(socket, &iov, cmsgs, SEND_FLAGS, None).map_err(Into::into)
+}
+
+/// Receive into `buf` from a Unix stream socket, along with any file descriptors
+/// which were sent with the data. The file descriptors are close-on-exec where
+/// `MSG_CMSG_CLOEXEC` is supported.
+fn recv_with_fds(socket: RawFd, buf: &mut [u8]) -> Result<(usize, Vec<OwnedFd>), io::Error> {
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut control = nix::cmsg_space!([RawFd; MAX_FDS]);
+
+    let msg = nix::sys::socket::recvmsg::<()>(socket, &mut iov, Some(&mut control), RECV_FLAGS)?;
+
+    let mut fds = Vec::new();
+    let cmsgs = msg
+        .cmsgs()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "too many sockets were sent"))?;
+    for cmsg in cmsgs {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // SAFETY: Each file descriptor received with SCM_RIGHTS is new to this
+            // process, and isn't owned by anything else.
+            #[allow(unsafe_code)]
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+
+    Ok((msg.bytes, fds))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS: MsgFlags = MsgFlags::MSG_NOSIGNAL;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS: MsgFlags = MsgFlags::empty();
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: MsgFlags = MsgFlags::MSG_CMSG_CLOEXEC;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: MsgFlags = MsgFlags::empty();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_without_sender() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(tmp.path().join("handoff.sock")).unwrap();
+        assert!(HandoffReceiver::connect(&path).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn abandoned_handoff_is_offered_again() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(tmp.path().join("handoff.sock")).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = HandoffSender::bind(path.clone())
+            .unwrap()
+            .with_listener("http", OwnedFd::from(listener));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let sending = tokio::spawn(sender.send());
+
+        // The first process disconnects without completing the handoff.
+        let receiver = HandoffReceiver::connect(&path).await.unwrap().unwrap();
+        assert_eq!(receiver.names().collect::<Vec<_>>(), vec!["http"]);
+        drop(receiver);
+
+        let mut receiver = HandoffReceiver::connect(&path).await.unwrap().unwrap();
+        let fd = receiver.take("http").unwrap();
+        receiver.complete().await.unwrap();
+        sending.await.unwrap().unwrap();
+        assert!(!path.exists());
+
+        let core = fd.into_core().unwrap();
+        let crate::server::conn::AcceptorCore::Tcp(listener) = core else {
+            panic!("expected a TCP listener");
+        };
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+}
@@ -20,14 +20,25 @@ pub use acceptor::AcceptorCore;
 pub use info::{
     ConnectionWithInfo, MakeServiceConnectionInfoLayer, MakeServiceConnectionInfoService,
 };
+pub use multi::{ListenerStream, MultiAcceptor};
 pub use stream::{Accept, AcceptExt, AcceptOne, Stream};
 
 mod acceptor;
 /// HTTP connection builder with automatic protocol detection.
 pub mod auto;
 mod connecting;
+#[cfg(feature = "handoff")]
+pub mod handoff;
 mod info;
+mod multi;
+#[cfg(feature = "stream")]
+pub mod proxy;
+#[cfg(feature = "stream")]
+pub mod reuseport;
 mod stream;
+#[cfg(feature = "stream")]
+pub mod systemd;
+pub mod timeout;
 #[cfg(feature = "tls")]
 pub mod tls;
 
@@ -0,0 +1,304 @@
+//! Accept connections from several listeners with a single server.
+//!
+//! A [`MultiAcceptor`] merges several acceptors, so that one [`Server`](crate::server::Server)
+//! (and one graceful shutdown) serves all of them. Each listener is named, and the
+//! [`ListenerInfo`] for the listener which accepted a connection is available in the
+//! connection's [`ConnectionInfo`].
+//!
+//! Listeners must share an acceptor type. [`Acceptor`](super::Acceptor) can wrap TCP,
+//! Unix and duplex listeners, with or without TLS, so it can be used to serve a mix
+//! of listeners, each with its own TLS configuration.
+
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "tls")]
+use std::io;
+
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::Accept;
+#[cfg(feature = "tls")]
+use crate::info::tls::TlsConnectionInfoReciever;
+use crate::info::{ConnectionInfo, HasConnectionInfo, ListenerInfo};
+#[cfg(feature = "tls")]
+use crate::stream::tls::{TlsHandshakeInfo, TlsHandshakeStream};
+
+/// An acceptor which accepts connections from several listeners.
+///
+/// Listeners are polled in turn, starting after the one which accepted the
+/// last connection, so that a busy listener can't starve the others. An error
+/// from any listener is returned from the combined acceptor, and handled by the
+/// server's [`AcceptErrorPolicy`](crate::server::accept::AcceptErrorPolicy).
+///
+/// An acceptor without any listeners never accepts a connection.
+pub struct MultiAcceptor<A> {
+    listeners: Vec<Listener<A>>,
+    next: usize,
+}
+
+struct Listener<A> {
+    info: ListenerInfo,
+    acceptor: Pin<Box<A>>,
+}
+
+impl<A> MultiAcceptor<A> {
+    /// Create an acceptor without any listeners.
+    pub fn new() -> Self {
+        Self {
+            listeners: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Add a listener with the given name.
+    pub fn with_listener(self, name: impl Into<std::sync::Arc<str>>, acceptor: A) -> Self {
+        self.with_listener_info(ListenerInfo::new(name), acceptor)
+    }
+
+    /// Add a listener, described by `info`.
+    pub fn with_listener_info(mut self, info: ListenerInfo, acceptor: A) -> Self {
+        self.push(info, acceptor);
+        self
+    }
+
+    /// Add a listener, described by `info`.
+    pub fn push(&mut self, info: ListenerInfo, acceptor: A) {
+        self.listeners.push(Listener {
+            info,
+            acceptor: Box::pin(acceptor),
+        });
+    }
+
+    /// The listeners in this acceptor.
+    pub fn listeners(&self) -> impl Iterator<Item = &ListenerInfo> {
+        self.listeners.iter().map(|listener| &listener.info)
+    }
+
+    /// The number of listeners in this acceptor.
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    /// Returns `true` if this acceptor has no listeners.
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+impl<A> Default for MultiAcceptor<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> fmt::Debug for MultiAcceptor<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiAcceptor")
+            .field("listeners", &self.listeners().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<N, A> FromIterator<(N, A)> for MultiAcceptor<A>
+where
+    N: Into<std::sync::Arc<str>>,
+{
+    fn from_iter<T: IntoIterator<Item = (N, A)>>(iter: T) -> Self {
+        let mut acceptor = Self::new();
+        for (name, listener) in iter {
+            acceptor.push(ListenerInfo::new(name), listener);
+        }
+        acceptor
+    }
+}
+
+impl<A> Accept for MultiAcceptor<A>
+where
+    A: Accept,
+    <A::Conn as HasConnectionInfo>::Addr: Clone,
+{
+    type Conn = ListenerStream<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Conn, Self::Error>> {
+        let this = &mut *self;
+        let count = this.listeners.len();
+
+        for offset in 0..count {
+            let index = (this.next + offset) % count;
+            let listener = &mut this.listeners[index];
+
+            if let Poll::Ready(result) = listener.acceptor.as_mut().poll_accept(cx) {
+                this.next = (index + 1) % count;
+                return Poll::Ready(match result {
+                    Ok(conn) => Ok(ListenerStream::new(conn, listener.info.clone())),
+                    Err(error) => {
+                        tracing::debug!(listener = %listener.info, "error accepting connection");
+                        Err(error)
+                    }
+                });
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// A connection accepted by a [`MultiAcceptor`].
+///
+/// The connection info for this stream includes the listener which accepted it.
+#[derive(Debug)]
+#[pin_project]
+pub struct ListenerStream<IO> {
+    #[pin]
+    inner: IO,
+    listener: ListenerInfo,
+}
+
+impl<IO> ListenerStream<IO> {
+    /// Wrap a connection accepted by `listener`.
+    pub fn new(inner: IO, listener: ListenerInfo) -> Self {
+        Self { inner, listener }
+    }
+
+    /// The listener which accepted this connection.
+    pub fn listener(&self) -> &ListenerInfo {
+        &self.listener
+    }
+
+    /// Get a reference to the inner connection.
+    pub fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+
+    /// Unwrap the inner connection.
+    pub fn into_inner(self) -> IO {
+        self.inner
+    }
+}
+
+impl<IO> HasConnectionInfo for ListenerStream<IO>
+where
+    IO: HasConnectionInfo,
+{
+    type Addr = IO::Addr;
+
+    fn info(&self) -> ConnectionInfo<Self::Addr> {
+        let mut info = self.inner.info();
+        info.listener = Some(self.listener.clone());
+        info
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<IO> TlsHandshakeStream for ListenerStream<IO>
+where
+    IO: TlsHandshakeStream,
+{
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.inner.poll_handshake(cx)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<IO> TlsHandshakeInfo for ListenerStream<IO>
+where
+    IO: TlsHandshakeInfo,
+{
+    fn recv(&self) -> TlsConnectionInfoReciever {
+        self.inner.recv()
+    }
+}
+
+impl<IO> AsyncRead for ListenerStream<IO>
+where
+    IO: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<IO> AsyncWrite for ListenerStream<IO>
+where
+    IO: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(all(test, feature = "stream"))]
+mod tests {
+    use super::*;
+
+    use crate::stream::duplex;
+
+    #[tokio::test]
+    async fn accepts_from_each_listener() {
+        let (first, first_incoming) = duplex::pair();
+        let (second, second_incoming) = duplex::pair();
+
+        let mut acceptor = MultiAcceptor::new()
+            .with_listener("first", first_incoming)
+            .with_listener_info(
+                ListenerInfo::new("second").with_extension(8080u16),
+                second_incoming,
+            );
+        assert_eq!(acceptor.len(), 2);
+
+        let (_client, conn) = tokio::try_join!(
+            second.connect(1024),
+            std::future::poll_fn(|cx| Pin::new(&mut acceptor).poll_accept(cx))
+        )
+        .unwrap();
+        let info = conn.info();
+        let listener = info.listener().unwrap();
+        assert_eq!(listener.name(), "second");
+        assert_eq!(listener.get::<u16>(), Some(&8080));
+
+        let (_client, conn) = tokio::try_join!(
+            first.connect(1024),
+            std::future::poll_fn(|cx| Pin::new(&mut acceptor).poll_accept(cx))
+        )
+        .unwrap();
+        assert_eq!(conn.listener().name(), "first");
+    }
+}
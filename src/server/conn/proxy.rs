@@ -0,0 +1,939 @@
+//! Accept connections from a proxy which speaks the PROXY protocol.
+//!
+//! Load balancers such as HAProxy and AWS Network Load Balancers can send a
+//! [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt) header
+//! at the start of each connection, which describes the client's original connection.
+//! [`ProxyAcceptor`] reads version 1 and version 2 headers from each accepted connection,
+//! and uses the addresses from the header as the connection's
+//! [`remote_addr`](crate::info::ConnectionInfo::remote_addr) and
+//! [`local_addr`](crate::info::ConnectionInfo::local_addr). The rest of the header,
+//! including any TLV extensions, is available as [`ConnectionInfo::proxy`](crate::info::ConnectionInfo::proxy).
+//!
+//! The header comes before any TLS handshake, so the proxy acceptor should wrap the
+//! listener, and TLS should wrap the proxy acceptor:
+//!
+//! ```rust
+//! # use hyperdriver::server::conn::{Acceptor, AcceptorCore};
+//! # use hyperdriver::server::conn::proxy::{ProxyAcceptor, ProxyProtocol};
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+//! let config = ProxyProtocol::required().with_trusted_network("10.0.0.0/8".parse()?);
+//! let acceptor = Acceptor::new(ProxyAcceptor::new(AcceptorCore::from(listener), config));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Headers are read concurrently with accepting more connections, and connections which
+//! don't send a valid header within the [header timeout](ProxyProtocol::with_header_timeout)
+//! are closed.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::{Buf as _, Bytes, BytesMut};
+use futures_util::stream::{FuturesUnordered, StreamExt as _};
+use pin_project::pin_project;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite};
+
+use super::Accept;
+use crate::info::proxy::{ProxyVersion, Tlv};
+use crate::info::{BraidAddr, ConnectionInfo, HasConnectionInfo, ProxyInfo, UnixAddr};
+use crate::rewind::Rewind;
+
+/// How long to wait for a PROXY protocol header by default.
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum number of connections whose headers are read at once.
+const MAX_PENDING_HEADERS: usize = 256;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// Whether connections must start with a PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyMode {
+    /// Connections must start with a header, and are closed if they don't.
+    Required,
+
+    /// Headers are read if they are present, otherwise connections are served as-is.
+    Optional,
+
+    /// Headers are not read, and connections are served as-is.
+    Disabled,
+}
+
+/// Configuration for reading PROXY protocol headers.
+///
+/// Headers are only trusted from peers in the trusted networks. When the peer isn't
+/// trusted, connections are closed in [`ProxyMode::Required`] mode, and served without
+/// reading a header in [`ProxyMode::Optional`] mode. Without any trusted networks, no TCP
+/// peer is trusted; use [`ProxyProtocol::trust_all`] to trust every peer. Connections which
+/// aren't over TCP (e.g. Unix sockets) are always trusted.
+#[derive(Debug, Clone)]
+pub struct ProxyProtocol {
+    mode: ProxyMode,
+    trusted: Vec<TrustedNetwork>,
+    trust_all: bool,
+    header_timeout: Duration,
+}
+
+impl ProxyProtocol {
+    /// Read PROXY protocol headers in the given mode.
+    pub fn new(mode: ProxyMode) -> Self {
+        Self {
+            mode,
+            trusted: Vec::new(),
+            trust_all: false,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+        }
+    }
+
+    /// Require a PROXY protocol header on every connection.
+    pub fn required() -> Self {
+        Self::new(ProxyMode::Required)
+    }
+
+    /// Read PROXY protocol headers when connections start with one.
+    pub fn optional() -> Self {
+        Self::new(ProxyMode::Optional)
+    }
+
+    /// Don't read PROXY protocol headers.
+    pub fn disabled() -> Self {
+        Self::new(ProxyMode::Disabled)
+    }
+
+    /// Trust headers from peers in `network`.
+    pub fn with_trusted_network(mut self, network: TrustedNetwork) -> Self {
+        self.trusted.push(network);
+        self
+    }
+
+    /// Trust headers from peers in each of `networks`.
+    pub fn with_trusted_networks<I>(mut self, networks: I) -> Self
+    where
+        I: IntoIterator<Item = TrustedNetwork>,
+    {
+        self.trusted.extend(networks);
+        self
+    }
+
+    /// Trust headers from every peer, e.g. when the listener is only reachable
+    /// through the proxy.
+    pub fn trust_all(mut self) -> Self {
+        self.trust_all = true;
+        self
+    }
+
+    /// Close connections which don't send a complete header within `timeout` of
+    /// being accepted. The default is [`DEFAULT_HEADER_TIMEOUT`].
+    pub fn with_header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Whether connections must start with a PROXY protocol header.
+    pub fn mode(&self) -> ProxyMode {
+        self.mode
+    }
+
+    /// The networks which headers are trusted from.
+    pub fn trusted_networks(&self) -> &[TrustedNetwork] {
+        &self.trusted
+    }
+
+    /// How long to wait for a header.
+    pub fn header_timeout(&self) -> Duration {
+        self.header_timeout
+    }
+
+    fn trusts(&self, peer: &BraidAddr) -> bool {
+        match peer.tcp() {
+            Some(addr) => {
+                self.trust_all
+                    || self
+                        .trusted
+                        .iter()
+                        .any(|network| network.contains(addr.ip()))
+            }
+            None => true,
+        }
+    }
+}
+
+impl Default for ProxyProtocol {
+    fn default() -> Self {
+        Self::required()
+    }
+}
+
+/// An IP network (e.g. `10.0.0.0/8`) which PROXY protocol headers are trusted from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TrustedNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl TrustedNetwork {
+    /// The network of addresses which share the first `prefix` bits with `addr`.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, InvalidNetwork> {
+        let addr = canonical(addr);
+        let addr = match addr {
+            IpAddr::V4(ip) if prefix <= 32 => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & v4_mask(prefix)))
+            }
+            IpAddr::V6(ip) if prefix <= 128 => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & v6_mask(prefix)))
+            }
+            _ => {
+                return Err(InvalidNetwork(format!(
+                    "prefix /{prefix} is too long for {addr}"
+                )))
+            }
+        };
+
+        Ok(Self { addr, prefix })
+    }
+
+    /// A network containing a single address.
+    pub fn host(addr: IpAddr) -> Self {
+        let addr = canonical(addr);
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix }
+    }
+
+    /// Returns `true` if `ip` is in this network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                u32::from(ip) & v4_mask(self.prefix) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                u128::from(ip) & v6_mask(self.prefix) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Convert IPv4-mapped IPv6 addresses to IPv4 addresses.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+impl fmt::Display for TrustedNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<IpAddr> for TrustedNetwork {
+    fn from(addr: IpAddr) -> Self {
+        Self::host(addr)
+    }
+}
+
+impl FromStr for TrustedNetwork {
+    type Err = InvalidNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidNetwork(s.to_owned());
+        match s.split_once('/') {
+            Some((addr, prefix)) => Self::new(
+                addr.parse().map_err(|_| invalid())?,
+                prefix.parse().map_err(|_| invalid())?,
+            ),
+            None => Ok(Self::host(s.parse().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+/// An invalid [`TrustedNetwork`].
+#[derive(Debug, Error)]
+#[error("invalid network: {0}")]
+pub struct InvalidNetwork(String);
+
+/// An acceptor which reads a PROXY protocol header from each connection.
+///
+/// See the [module documentation](self) for details.
+#[pin_project]
+pub struct ProxyAcceptor<A>
+where
+    A: Accept,
+{
+    #[pin]
+    inner: A,
+    config: Arc<ProxyProtocol>,
+    pending: FuturesUnordered<ReadHeader<A::Conn>>,
+}
+
+type ReadHeader<IO> = Pin<Box<dyn std::future::Future<Output = Option<ProxyStream<IO>>> + Send>>;
+
+impl<A> ProxyAcceptor<A>
+where
+    A: Accept,
+{
+    /// Read PROXY protocol headers from connections accepted by `inner`.
+    pub fn new(inner: A, config: ProxyProtocol) -> Self {
+        Self {
+            inner,
+            config: Arc::new(config),
+            pending: FuturesUnordered::new(),
+        }
+    }
+
+    /// The configuration for reading headers.
+    pub fn config(&self) -> &ProxyProtocol {
+        &self.config
+    }
+}
+
+impl<A> fmt::Debug for ProxyAcceptor<A>
+where
+    A: Accept + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyAcceptor")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl<A> Accept for ProxyAcceptor<A>
+where
+    A: Accept,
+    A::Conn: HasConnectionInfo<Addr = BraidAddr>,
+{
+    type Conn = ProxyStream<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Conn, Self::Error>> {
+        let mut this = self.project();
+
+        loop {
+            while this.pending.len() < MAX_PENDING_HEADERS {
+                match this.inner.as_mut().poll_accept(cx) {
+                    Poll::Ready(Ok(stream)) => {
+                        if this.config.mode == ProxyMode::Disabled {
+                            return Poll::Ready(Ok(ProxyStream::passthrough(stream, Bytes::new())));
+                        }
+
+                        let config = this.config.clone();
+                        this.pending.push(Box::pin(async move {
+                            match tokio::time::timeout(
+                                config.header_timeout,
+                                read_header(stream, &config),
+                            )
+                            .await
+                            {
+                                Ok(Ok(stream)) => Some(stream),
+                                Ok(Err(error)) => {
+                                    tracing::debug!("closing connection: {error}");
+                                    None
+                                }
+                                Err(_) => {
+                                    tracing::debug!(
+                                        "closing connection: timed out reading PROXY protocol header"
+                                    );
+                                    None
+                                }
+                            }
+                        }));
+                    }
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => break,
+                }
+            }
+
+            match this.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Some(stream))) => return Poll::Ready(Ok(stream)),
+                Poll::Ready(Some(None)) => continue,
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Read a PROXY protocol header from the start of `stream`.
+async fn read_header<IO>(mut stream: IO, config: &ProxyProtocol) -> io::Result<ProxyStream<IO>>
+where
+    IO: HasConnectionInfo<Addr = BraidAddr> + AsyncRead + Unpin,
+{
+    let mut info = stream.info();
+    if !config.trusts(&info.remote_addr) {
+        if config.mode == ProxyMode::Required {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "PROXY protocol header from untrusted peer {}",
+                    info.remote_addr
+                ),
+            ));
+        }
+
+        return Ok(ProxyStream::passthrough(stream, Bytes::new()));
+    }
+
+    let mut buf = BytesMut::with_capacity(V1_MAX_LEN);
+    loop {
+        match parse(&buf).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))? {
+            Parsed::Incomplete => {
+                if stream.read_buf(&mut buf).await? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            Parsed::NotProxy if config.mode == ProxyMode::Required => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing PROXY protocol header",
+                ));
+            }
+            Parsed::NotProxy => return Ok(ProxyStream::passthrough(stream, buf.freeze())),
+            Parsed::Header(header, len) => {
+                buf.advance(len);
+
+                let proxy = header.info.with_proxy_addr(info.remote_addr.tcp());
+                if let Some((source, destination)) = header.addresses {
+                    info.remote_addr = source;
+                    info.local_addr = destination;
+                }
+                info.proxy = Some(proxy);
+
+                return Ok(ProxyStream {
+                    inner: Rewind::new(stream, buf.freeze()),
+                    info,
+                });
+            }
+        }
+    }
+}
+
+/// A connection accepted by a [`ProxyAcceptor`].
+///
+/// The connection info for this stream uses the addresses from the PROXY protocol header.
+#[derive(Debug)]
+pub struct ProxyStream<IO>
+where
+    IO: HasConnectionInfo,
+{
+    inner: Rewind<IO>,
+    info: ConnectionInfo<IO::Addr>,
+}
+
+impl<IO> ProxyStream<IO>
+where
+    IO: HasConnectionInfo,
+{
+    fn passthrough(stream: IO, prefix: Bytes) -> Self {
+        let info = stream.info();
+        Self {
+            inner: Rewind::new(stream, prefix),
+            info,
+        }
+    }
+
+    /// The PROXY protocol header sent on this connection, if any.
+    pub fn proxy(&self) -> Option<&ProxyInfo> {
+        self.info.proxy.as_ref()
+    }
+}
+
+impl<IO> HasConnectionInfo for ProxyStream<IO>
+where
+    IO: HasConnectionInfo,
+    IO::Addr: Clone,
+{
+    type Addr = IO::Addr;
+
+    fn info(&self) -> ConnectionInfo<Self::Addr> {
+        self.info.clone()
+    }
+}
+
+impl<IO> AsyncRead for ProxyStream<IO>
+where
+    IO: HasConnectionInfo + AsyncRead + Unpin,
+    IO::Addr: Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<IO> AsyncWrite for ProxyStream<IO>
+where
+    IO: HasConnectionInfo + AsyncWrite + Unpin,
+    IO::Addr: Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+/// A parsed PROXY protocol header.
+#[derive(Debug)]
+struct Header {
+    info: ProxyInfo,
+
+    /// The source and destination addresses, if the connection is proxied for a client.
+    addresses: Option<(BraidAddr, BraidAddr)>,
+}
+
+#[derive(Debug)]
+enum Parsed {
+    /// More bytes are needed to parse the header.
+    Incomplete,
+
+    /// The connection doesn't start with a header.
+    NotProxy,
+
+    /// A header, and its length in bytes.
+    Header(Header, usize),
+}
+
+#[derive(Debug, Error)]
+#[error("invalid PROXY protocol header: {0}")]
+struct HeaderError(&'static str);
+
+fn parse(buf: &[u8]) -> Result<Parsed, HeaderError> {
+    if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if V2_SIGNATURE.starts_with(buf) || V1_PREFIX.starts_with(buf) {
+        Ok(Parsed::Incomplete)
+    } else {
+        Ok(Parsed::NotProxy)
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Parsed, HeaderError> {
+    let Some(end) = buf.windows(2).position(|window| window == b"\r\n") else {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(HeaderError("version 1 header is too long"));
+        }
+        return Ok(Parsed::Incomplete);
+    };
+
+    if end + 2 > V1_MAX_LEN {
+        return Err(HeaderError("version 1 header is too long"));
+    }
+
+    let line = std::str::from_utf8(&buf[V1_PREFIX.len()..end])
+        .map_err(|_| HeaderError("version 1 header is not ASCII"))?;
+    let mut parts = line.split(' ');
+
+    let addresses = match parts.next() {
+        Some("UNKNOWN") => None,
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let mut next = || parts.next().ok_or(HeaderError("missing address"));
+            let source: IpAddr = next()?
+                .parse()
+                .map_err(|_| HeaderError("invalid source address"))?;
+            let destination: IpAddr = next()?
+                .parse()
+                .map_err(|_| HeaderError("invalid destination address"))?;
+            let source_port: u16 = next()?
+                .parse()
+                .map_err(|_| HeaderError("invalid source port"))?;
+            let destination_port: u16 = next()?
+                .parse()
+                .map_err(|_| HeaderError("invalid destination port"))?;
+
+            if parts.next().is_some() {
+                return Err(HeaderError("unexpected data after addresses"));
+            }
+
+            let matches_family = match family {
+                "TCP4" => source.is_ipv4() && destination.is_ipv4(),
+                _ => source.is_ipv6() && destination.is_ipv6(),
+            };
+            if !matches_family {
+                return Err(HeaderError("addresses don't match the protocol family"));
+            }
+
+            Some((
+                BraidAddr::from(SocketAddr::new(source, source_port)),
+                BraidAddr::from(SocketAddr::new(destination, destination_port)),
+            ))
+        }
+        _ => return Err(HeaderError("unknown protocol family")),
+    };
+
+    Ok(Parsed::Header(
+        Header {
+            info: ProxyInfo::new(ProxyVersion::V1, false, Vec::new()),
+            addresses,
+        },
+        end + 2,
+    ))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Parsed, HeaderError> {
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(Parsed::Incomplete);
+    }
+
+    let version = buf[12] >> 4;
+    if version != 2 {
+        return Err(HeaderError("unsupported version"));
+    }
+
+    let local = match buf[12] & 0x0F {
+        0x0 => true,
+        0x1 => false,
+        _ => return Err(HeaderError("unknown command")),
+    };
+
+    let family = buf[13] >> 4;
+    let len = usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if buf.len() < V2_HEADER_LEN + len {
+        return Ok(Parsed::Incomplete);
+    }
+    let body = &buf[V2_HEADER_LEN..V2_HEADER_LEN + len];
+
+    let (addresses, tlvs) = match family {
+        // AF_UNSPEC
+        0x0 => (None, body),
+
+        // AF_INET
+        0x1 => {
+            let (addresses, tlvs) = split(body, 12)?;
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&addresses[at..at + 4]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            (
+                Some((
+                    BraidAddr::from(SocketAddr::new(ip(0), port(8))),
+                    BraidAddr::from(SocketAddr::new(ip(4), port(10))),
+                )),
+                tlvs,
+            )
+        }
+
+        // AF_INET6
+        0x2 => {
+            let (addresses, tlvs) = split(body, 36)?;
+            let ip =
+                |at: usize| IpAddr::from(<[u8; 16]>::try_from(&addresses[at..at + 16]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            (
+                Some((
+                    BraidAddr::from(SocketAddr::new(ip(0), port(32))),
+                    BraidAddr::from(SocketAddr::new(ip(16), port(34))),
+                )),
+                tlvs,
+            )
+        }
+
+        // AF_UNIX
+        0x3 => {
+            let (addresses, tlvs) = split(body, 216)?;
+            (
+                Some((unix_addr(&addresses[..108])?, unix_addr(&addresses[108..])?)),
+                tlvs,
+            )
+        }
+
+        _ => return Err(HeaderError("unknown address family")),
+    };
+
+    Ok(Parsed::Header(
+        Header {
+            info: ProxyInfo::new(ProxyVersion::V2, local, parse_tlvs(tlvs)?),
+            // Local connections use the addresses of the connection to the proxy.
+            addresses: addresses.filter(|_| !local),
+        },
+        V2_HEADER_LEN + len,
+    ))
+}
+
+fn split(body: &[u8], at: usize) -> Result<(&[u8], &[u8]), HeaderError> {
+    if body.len() < at {
+        return Err(HeaderError("addresses are truncated"));
+    }
+    Ok(body.split_at(at))
+}
+
+fn unix_addr(path: &[u8]) -> Result<BraidAddr, HeaderError> {
+    let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+    if end == 0 {
+        return Ok(BraidAddr::Unix(UnixAddr::unnamed()));
+    }
+
+    let path = std::str::from_utf8(&path[..end])
+        .map_err(|_| HeaderError("unix socket path is not UTF-8"))?;
+    Ok(BraidAddr::Unix(UnixAddr::from_pathbuf(path.into())))
+}
+
+fn parse_tlvs(mut buf: &[u8]) -> Result<Vec<Tlv>, HeaderError> {
+    let mut tlvs = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < 3 {
+            return Err(HeaderError("TLV is truncated"));
+        }
+
+        let kind = buf[0];
+        let len = usize::from(u16::from_be_bytes([buf[1], buf[2]]));
+        if buf.len() < 3 + len {
+            return Err(HeaderError("TLV is truncated"));
+        }
+
+        tlvs.push(Tlv::new(kind, Bytes::copy_from_slice(&buf[3..3 + len])));
+        buf = &buf[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::info::proxy::{PP2_TYPE_AUTHORITY, PP2_TYPE_AWS};
+
+    fn header(buf: &[u8]) -> (Header, usize) {
+        match parse(buf).unwrap() {
+            Parsed::Header(header, len) => (header, len),
+            parsed => panic!("expected a header, got {parsed:?}"),
+        }
+    }
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(0x20 | command);
+        buf.push(family);
+        buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let buf = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET / HTTP/1.1\r\n";
+        let (header, len) = header(buf);
+        assert_eq!(&buf[len..], b"GET / HTTP/1.1\r\n");
+        assert_eq!(header.info.version(), ProxyVersion::V1);
+
+        let (source, destination) = header.addresses.unwrap();
+        assert_eq!(source, BraidAddr::Tcp("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            destination,
+            BraidAddr::Tcp("198.51.100.2:443".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v1_tcp6_and_unknown() {
+        let (header, _) = header(b"PROXY TCP6 2001:db8::1 2001:db8::2 1234 80\r\n");
+        let (source, _) = header.addresses.unwrap();
+        assert_eq!(
+            source,
+            BraidAddr::Tcp("[2001:db8::1]:1234".parse().unwrap())
+        );
+
+        let (header, len) = self::header(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n");
+        assert!(header.addresses.is_none());
+        assert_eq!(len, 35);
+    }
+
+    #[test]
+    fn v1_invalid() {
+        assert!(parse(b"PROXY TCP4 192.0.2.1 2001:db8::2 1234 80\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.2 1234\r\n").is_err());
+        assert!(parse(b"PROXY UDP4 192.0.2.1 198.51.100.2 1234 80\r\n").is_err());
+        assert!(parse(&[b"PROXY ".as_slice(), &[b'a'; 120]].concat()).is_err());
+    }
+
+    #[test]
+    fn v2_tcp4_with_tlvs() {
+        let mut body = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        body.extend_from_slice(&56324u16.to_be_bytes());
+        body.extend_from_slice(&443u16.to_be_bytes());
+        body.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0, 11]);
+        body.extend_from_slice(b"example.com");
+        body.extend_from_slice(&[PP2_TYPE_AWS, 0, 5, 0x01]);
+        body.extend_from_slice(b"vpce");
+
+        let mut buf = v2(0x1, 0x11, &body);
+        buf.extend_from_slice(b"rest");
+
+        let (header, len) = header(&buf);
+        assert_eq!(&buf[len..], b"rest");
+        assert_eq!(header.info.version(), ProxyVersion::V2);
+        assert!(!header.info.is_local());
+        assert_eq!(header.info.authority(), Some("example.com"));
+        assert_eq!(header.info.aws_vpce_id(), Some("vpce"));
+
+        let (source, destination) = header.addresses.unwrap();
+        assert_eq!(source, BraidAddr::Tcp("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            destination,
+            BraidAddr::Tcp("198.51.100.2:443".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v2_local_and_unix() {
+        let (header, _) = header(&v2(0x0, 0x00, &[]));
+        assert!(header.info.is_local());
+        assert!(header.addresses.is_none());
+
+        let mut body = vec![0u8; 216];
+        body[..9].copy_from_slice(b"/tmp/a.sk");
+        let (header, _) = self::header(&v2(0x1, 0x31, &body));
+        let (source, destination) = header.addresses.unwrap();
+        assert_eq!(source.path().unwrap(), "/tmp/a.sk");
+        assert_eq!(destination, BraidAddr::Unix(UnixAddr::unnamed()));
+    }
+
+    #[test]
+    fn incomplete_and_missing_headers() {
+        assert!(matches!(parse(b""), Ok(Parsed::Incomplete)));
+        assert!(matches!(parse(b"PROX"), Ok(Parsed::Incomplete)));
+        assert!(matches!(
+            parse(b"PROXY TCP4 192.0.2.1"),
+            Ok(Parsed::Incomplete)
+        ));
+        assert!(matches!(parse(&V2_SIGNATURE[..5]), Ok(Parsed::Incomplete)));
+        assert!(matches!(
+            parse(&v2(0x1, 0x11, &[0; 12])[..20]),
+            Ok(Parsed::Incomplete)
+        ));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\n"), Ok(Parsed::NotProxy)));
+        assert!(parse(&v2(0x1, 0x11, &[0; 4])).is_err());
+    }
+
+    #[test]
+    fn trusted_networks() {
+        let network: TrustedNetwork = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        assert_eq!(network.to_string(), "10.1.0.0/16");
+
+        let network: TrustedNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!network.contains("10.1.2.3".parse().unwrap()));
+
+        let host: TrustedNetwork = "192.0.2.1".parse().unwrap();
+        assert!(host.contains("192.0.2.1".parse().unwrap()));
+        assert!(!host.contains("192.0.2.2".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<TrustedNetwork>()
+            .unwrap()
+            .contains("192.0.2.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<TrustedNetwork>().is_err());
+        assert!("example.com/8".parse::<TrustedNetwork>().is_err());
+
+        let config = ProxyProtocol::required().with_trusted_network(network);
+        assert!(config.trusts(&BraidAddr::Tcp("[2001:db8::1]:80".parse().unwrap())));
+        assert!(!config.trusts(&BraidAddr::Tcp("192.0.2.1:80".parse().unwrap())));
+        assert!(config.trusts(&BraidAddr::Duplex));
+
+        let peer = BraidAddr::Tcp("192.0.2.1:80".parse().unwrap());
+        assert!(!ProxyProtocol::required().trusts(&peer));
+        assert!(ProxyProtocol::required().trust_all().trusts(&peer));
+    }
+
+    #[tokio::test]
+    async fn untrusted_peer_header_is_rejected() {
+        use crate::stream::Braid;
+        use tokio::io::AsyncWriteExt as _;
+        use tokio::net::{TcpListener, TcpStream};
+
+        const HEADER: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n";
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let connect = || async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(HEADER).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            (client, Braid::from(stream))
+        };
+
+        let (_client, stream) = connect().await;
+        let error = read_header(stream, &ProxyProtocol::required())
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        // In optional mode, the header is left unread and the peer's address is kept.
+        let (_client, stream) = connect().await;
+        let mut stream = read_header(stream, &ProxyProtocol::optional())
+            .await
+            .unwrap();
+        assert!(stream.proxy().is_none());
+        assert_eq!(
+            stream.info().remote_addr().tcp().unwrap().ip(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        );
+        let mut buf = vec![0; HEADER.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, HEADER);
+
+        let (_client, stream) = connect().await;
+        let stream = read_header(stream, &ProxyProtocol::required().trust_all())
+            .await
+            .unwrap();
+        assert!(stream.proxy().is_some());
+        assert_eq!(
+            stream.info().remote_addr(),
+            &BraidAddr::Tcp("192.0.2.1:56324".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Shard accepting connections across workers, with `SO_REUSEPORT`.
+//!
+//! [`ReusePort`] binds several TCP listeners to the same address, with the
+//! `SO_REUSEPORT` socket option set, so that the kernel spreads new connections
+//! across them. A [`Server`](crate::server::Server) built with
+//! [`Server::with_reuse_port`](crate::server::Server::with_reuse_port) runs one
+//! accept loop for each listener, optionally each on its own thread with a
+//! current-thread runtime, while sharing a single
+//! [`ServerHandle`](crate::server::ServerHandle) and connection limit.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// The backlog of pending connections for each listener.
+const BACKLOG: i32 = 1024;
+
+/// How the workers serving a [`ReusePort`] are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkerRuntime {
+    /// Run each worker as a task on the runtime which is serving the server.
+    #[default]
+    Spawn,
+
+    /// Run each worker on its own thread, with a current-thread runtime. Connections
+    /// accepted by a worker are served on the worker's runtime.
+    CurrentThread,
+}
+
+/// Several TCP listeners bound to the same address with `SO_REUSEPORT`.
+///
+/// The listeners are kept as standard library listeners (in non-blocking mode) until
+/// a worker starts serving them, so that each is registered with the runtime of the
+/// worker which serves it.
+#[derive(Debug)]
+pub struct ReusePort {
+    listeners: Vec<TcpListener>,
+    runtime: WorkerRuntime,
+}
+
+impl ReusePort {
+    /// Bind `workers` listeners to `addr`.
+    ///
+    /// If `addr` has port 0, the first listener is bound to an unused port, and the
+    /// rest are bound to the same port. At least one listener is bound.
+    pub fn bind(addr: SocketAddr, workers: usize) -> Result<Self, io::Error> {
+        let first = bind_reuse_port(addr)?;
+        let addr = first.local_addr()?;
+
+        let mut listeners = Vec::with_capacity(workers.max(1));
+        listeners.push(first);
+        for _ in 1..workers {
+            listeners.push(bind_reuse_port(addr)?);
+        }
+
+        tracing::debug!(%addr, workers = listeners.len(), "bound listeners with SO_REUSEPORT");
+        Ok(Self {
+            listeners,
+            runtime: WorkerRuntime::default(),
+        })
+    }
+
+    /// Set how the workers are run. The default is [`WorkerRuntime::Spawn`].
+    pub fn with_runtime(mut self, runtime: WorkerRuntime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// How the workers are run.
+    pub fn runtime(&self) -> WorkerRuntime {
+        self.runtime
+    }
+
+    /// The address the listeners are bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listeners[0].local_addr()
+    }
+
+    /// The number of listeners, and so the number of workers.
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    /// Returns `true` if there are no listeners.
+    ///
+    /// [`ReusePort::bind`] always binds at least one listener.
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Unwrap the listeners. They are in non-blocking mode.
+    pub fn into_listeners(self) -> Vec<TcpListener> {
+        self.listeners
+    }
+}
+
+fn bind_reuse_port(addr: SocketAddr) -> Result<TcpListener, io::Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_listeners_to_one_port() {
+        let listeners = ReusePort::bind("127.0.0.1:0".parse().unwrap(), 4).unwrap();
+        assert_eq!(listeners.len(), 4);
+
+        let addr = listeners.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        for listener in listeners.into_listeners() {
+            assert_eq!(listener.local_addr().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn binds_at_least_one_listener() {
+        let listeners = ReusePort::bind("127.0.0.1:0".parse().unwrap(), 0).unwrap();
+        assert_eq!(listeners.len(), 1);
+    }
+}
@@ -215,6 +215,7 @@ where
 
 #[cfg(feature = "stream")]
 impl From<TcpStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: TcpStream) -> Self {
         Stream {
             info: stream.info().map(Into::into),
@@ -227,6 +228,7 @@ impl From<TcpStream> for Stream {
 
 #[cfg(feature = "stream")]
 impl From<DuplexStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: DuplexStream) -> Self {
         Stream {
             info: stream.info().map(Into::into),
@@ -239,6 +241,7 @@ impl From<DuplexStream> for Stream {
 
 #[cfg(feature = "stream")]
 impl From<UnixStream> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: UnixStream) -> Self {
         Stream {
             info: stream.info().map(Into::into),
@@ -251,6 +254,7 @@ impl From<UnixStream> for Stream {
 
 #[cfg(feature = "stream")]
 impl From<Braid> for Stream {
+    #[cfg_attr(not(feature = "tls"), allow(clippy::useless_conversion))]
     fn from(stream: Braid) -> Self {
         Stream {
             info: stream.info(),
@@ -0,0 +1,327 @@
+//! Socket activation, using listening sockets passed to the process by systemd.
+//!
+//! When a service is started by a systemd `.socket` unit (or by any service manager
+//! which implements the same protocol), the listening sockets are passed to the
+//! process as file descriptors starting at 3. The number of sockets is in the
+//! `LISTEN_FDS` environment variable, and their names (from `FileDescriptorName=`)
+//! are in `LISTEN_FDNAMES`.
+//!
+//! # Example
+//! ```no_run
+//! # use hyperdriver::server::conn::systemd::ListenFds;
+//! # async fn example() -> std::io::Result<()> {
+//! let mut fds = ListenFds::from_env()?;
+//! let acceptor = match fds.take("http") {
+//!     Some(fd) => fd.into_acceptor()?,
+//!     None => hyperdriver::server::conn::Acceptor::bind(&"[::]:8080".parse().unwrap()).await?,
+//! };
+//! # drop(acceptor);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Once;
+
+use super::{Acceptor, AcceptorCore, MultiAcceptor};
+
+/// The first file descriptor passed by the service manager.
+pub const SD_LISTEN_FDS_START: RawFd = 3;
+
+const LISTEN_PID: &str = "LISTEN_PID";
+const LISTEN_FDS: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+
+/// The name systemd uses for sockets without a `FileDescriptorName=`.
+const UNKNOWN_NAME: &str = "unknown";
+
+/// Run once, when the sockets are taken, so that they can't be owned twice.
+static TAKEN: Once = Once::new();
+
+/// A listening socket passed to this process by the service manager, or
+/// handed off by another process.
+pub struct ListenFd {
+    name: String,
+    fd: OwnedFd,
+}
+
+impl ListenFd {
+    #[cfg(feature = "handoff")]
+    pub(crate) fn new(name: String, fd: OwnedFd) -> Self {
+        Self { name, fd }
+    }
+
+    /// The name of the socket, from `LISTEN_FDNAMES`.
+    ///
+    /// Sockets without a name are called `"unknown"`, as they are by systemd.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Create an acceptor core for this socket.
+    ///
+    /// See [`AcceptorCore::from_fd`] for the sockets which are supported.
+    pub fn into_core(self) -> Result<AcceptorCore, io::Error> {
+        AcceptorCore::from_fd(self.fd)
+    }
+
+    /// Create an acceptor for this socket.
+    pub fn into_acceptor(self) -> Result<Acceptor, io::Error> {
+        self.into_core().map(Acceptor::from)
+    }
+
+    /// Unwrap the file descriptor for this socket.
+    pub fn into_fd(self) -> OwnedFd {
+        self.fd
+    }
+}
+
+impl AsFd for ListenFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl fmt::Debug for ListenFd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListenFd")
+            .field("name", &self.name)
+            .field("fd", &self.fd)
+            .finish()
+    }
+}
+
+/// The listening sockets passed to this process by the service manager.
+#[derive(Debug, Default)]
+pub struct ListenFds {
+    fds: Vec<ListenFd>,
+}
+
+impl ListenFds {
+    #[cfg(feature = "handoff")]
+    pub(crate) fn new(fds: Vec<ListenFd>) -> Self {
+        Self { fds }
+    }
+
+    /// Take ownership of the listening sockets passed to this process.
+    ///
+    /// If the process wasn't socket activated, or the sockets were passed to another
+    /// process (`LISTEN_PID` doesn't match this process), there are no sockets.
+    ///
+    /// The sockets can only be taken once: later calls (including calls to
+    /// [`ListenFds::from_env_and_unset`]) return no sockets. The sockets are marked
+    /// close-on-exec. The `LISTEN_*` environment variables are left in place; child
+    /// processes ignore them, since `LISTEN_PID` won't match.
+    pub fn from_env() -> Result<Self, io::Error> {
+        Self::take_from_env(false)
+    }
+
+    /// Take ownership of the listening sockets passed to this process, and remove
+    /// the `LISTEN_*` environment variables so that they aren't inherited by child
+    /// processes.
+    ///
+    /// This is the equivalent of calling `sd_listen_fds` with `unset_environment`.
+    /// Modifying the environment isn't thread-safe, so this must be called before
+    /// any other threads are started (including the tokio runtime's worker threads),
+    /// e.g. at the start of `main`. Otherwise, use [`ListenFds::from_env`].
+    pub fn from_env_and_unset() -> Result<Self, io::Error> {
+        Self::take_from_env(true)
+    }
+
+    fn take_from_env(unset_environment: bool) -> Result<Self, io::Error> {
+        let mut taken = None;
+        TAKEN.call_once(|| taken = Some(Self::read_env(unset_environment)));
+        taken.unwrap_or_else(|| {
+            tracing::debug!("listening sockets have already been taken");
+            Ok(Self::default())
+        })
+    }
+
+    /// Read the `LISTEN_*` environment variables and take the sockets. Must only be
+    /// called once per process, which is guarded by `TAKEN`.
+    fn read_env(unset_environment: bool) -> Result<Self, io::Error> {
+        let pid = std::env::var(LISTEN_PID).ok();
+        let count = std::env::var(LISTEN_FDS).ok();
+        let names = std::env::var(LISTEN_FDNAMES).ok();
+
+        if unset_environment {
+            std::env::remove_var(LISTEN_PID);
+            std::env::remove_var(LISTEN_FDS);
+            std::env::remove_var(LISTEN_FDNAMES);
+        }
+
+        let Some(names) = parse(
+            pid.as_deref(),
+            count.as_deref(),
+            names.as_deref(),
+            std::process::id(),
+        )?
+        else {
+            return Ok(Self::default());
+        };
+
+        let mut fds = Vec::with_capacity(names.len());
+        for (fd, name) in (SD_LISTEN_FDS_START..).zip(names) {
+            // SAFETY: `parse` only returns names when `LISTEN_PID` is this process, so
+            // the service manager passed `LISTEN_FDS` open file descriptors, starting at
+            // `SD_LISTEN_FDS_START`, to this process rather than to a parent which
+            // left the variables in the environment. `TAKEN` ensures this runs at most
+            // once per process, so no other `OwnedFd` is created for these descriptors
+            // here, and nothing else in this crate takes them.
+            #[allow(unsafe_code)]
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            socket2::SockRef::from(&fd).set_cloexec(true)?;
+            tracing::debug!(%name, ?fd, "inherited listening socket");
+            fds.push(ListenFd { name, fd });
+        }
+
+        Ok(Self { fds })
+    }
+
+    /// The number of sockets.
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Returns `true` if there are no sockets.
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// The names of the sockets, in the order they were passed.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fds.iter().map(|fd| fd.name())
+    }
+
+    /// Take the first socket with the given name.
+    pub fn take(&mut self, name: &str) -> Option<ListenFd> {
+        let index = self.fds.iter().position(|fd| fd.name == name)?;
+        Some(self.fds.remove(index))
+    }
+
+    /// Create an acceptor which accepts connections from all of the sockets,
+    /// with each listener named after its socket.
+    pub fn into_multi_acceptor(self) -> Result<MultiAcceptor<Acceptor>, io::Error> {
+        self.fds
+            .into_iter()
+            .map(|fd| {
+                let name = fd.name.clone();
+                fd.into_acceptor().map(|acceptor| (name, acceptor))
+            })
+            .collect()
+    }
+}
+
+impl IntoIterator for ListenFds {
+    type Item = ListenFd;
+    type IntoIter = std::vec::IntoIter<ListenFd>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fds.into_iter()
+    }
+}
+
+/// Parse the `LISTEN_*` environment variables, returning the names of the sockets
+/// if they were passed to this process.
+fn parse(
+    pid: Option<&str>,
+    count: Option<&str>,
+    names: Option<&str>,
+    current: u32,
+) -> Result<Option<Vec<String>>, io::Error> {
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Ok(None);
+    };
+
+    let pid: u32 = pid
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid LISTEN_PID"))?;
+    if pid != current {
+        tracing::debug!(pid, "listening sockets were passed to another process");
+        return Ok(None);
+    }
+
+    let count: usize = count
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid LISTEN_FDS"))?;
+
+    match names {
+        Some(names) => {
+            let names: Vec<String> = names.split(':').map(String::from).collect();
+            if names.len() != count {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LISTEN_FDNAMES doesn't match LISTEN_FDS",
+                ));
+            }
+            Ok(Some(names))
+        }
+        None => Ok(Some(vec![UNKNOWN_NAME.to_owned(); count])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::info::HasConnectionInfo;
+    use crate::server::conn::Accept;
+
+    #[test]
+    fn parse_environment() {
+        assert_eq!(parse(None, None, None, 42).unwrap(), None);
+        assert_eq!(parse(Some("41"), Some("1"), None, 42).unwrap(), None);
+        assert_eq!(
+            parse(Some("42"), Some("2"), None, 42).unwrap(),
+            Some(vec!["unknown".to_owned(), "unknown".to_owned()])
+        );
+        assert_eq!(
+            parse(Some("42"), Some("2"), Some("http:admin"), 42).unwrap(),
+            Some(vec!["http".to_owned(), "admin".to_owned()])
+        );
+        assert!(parse(Some("42"), Some("2"), Some("http"), 42).is_err());
+        assert!(parse(Some("42"), Some("two"), None, 42).is_err());
+    }
+
+    #[tokio::test]
+    async fn acceptor_from_tcp_fd() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut acceptor = AcceptorCore::from_fd(OwnedFd::from(listener)).unwrap();
+        assert!(matches!(acceptor, AcceptorCore::Tcp(_)));
+
+        let (_client, conn) = tokio::try_join!(
+            tokio::net::TcpStream::connect(addr),
+            std::future::poll_fn(|cx| std::pin::Pin::new(&mut acceptor).poll_accept(cx))
+        )
+        .unwrap();
+        assert_eq!(conn.info().local_addr().clone(), addr.into());
+    }
+
+    #[tokio::test]
+    async fn acceptor_from_unix_fd() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("test.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let mut acceptor = AcceptorCore::from_fd(OwnedFd::from(listener)).unwrap();
+        assert!(matches!(acceptor, AcceptorCore::Unix(_)));
+
+        tokio::try_join!(
+            tokio::net::UnixStream::connect(&path),
+            std::future::poll_fn(|cx| std::pin::Pin::new(&mut acceptor).poll_accept(cx))
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn acceptor_from_non_listening_fd() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(AcceptorCore::from_fd(OwnedFd::from(socket)).is_err());
+    }
+}
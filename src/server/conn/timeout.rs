@@ -0,0 +1,545 @@
+//! Timeouts for server connections.
+//!
+//! [`ConnectionTimeouts`] protects the server from clients which hold connections open
+//! without using them, such as slowloris clients which send request headers very slowly.
+//! It is applied by wrapping any [`Protocol`] in a [`TimeoutProtocol`], usually with
+//! [`Server::with_connection_timeouts`](crate::server::Server::with_connection_timeouts),
+//! so it works the same way for HTTP/1, HTTP/2 and automatic protocol detection.
+//!
+//! A connection is in one of three phases:
+//!
+//! - *Idle*: no request is in flight, and nothing has been read since the connection was
+//!   opened or the previous request finished. The [idle timeout](ConnectionTimeouts::with_idle)
+//!   applies, and gracefully shuts down the connection.
+//! - *Reading*: no request is in flight, but some bytes have been read. The
+//!   [header read timeout](ConnectionTimeouts::with_header_read) applies from the first byte,
+//!   and closes the connection. HTTP/2 connections (recognized by their connection preface)
+//!   don't enter this phase, since clients send frames such as pings and window updates
+//!   while no request is in flight: they stay idle until a request starts.
+//! - *Active*: at least one request is being handled by the service, or its response body
+//!   is still being sent. No timeout applies, other than the connection lifetime.
+//!
+//! The [lifetime](ConnectionTimeouts::with_lifetime) applies from when the connection is
+//! served, and gracefully shuts down the connection, letting requests in flight finish.
+//!
+//! The TLS handshake happens before the protocol sees the connection, so its timeout is set
+//! on the acceptor instead, see
+//! [`Server::with_tls_handshake_timeout`](crate::server::Server::with_tls_handshake_timeout).
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::task::AtomicWaker;
+use http_body::Frame;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use super::Connection;
+use crate::server::Protocol;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Timeouts applied to each connection served by a [`TimeoutProtocol`].
+///
+/// No timeouts are set by default. See the [module documentation](self) for details.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    header_read: Option<Duration>,
+    idle: Option<Duration>,
+    lifetime: Option<Duration>,
+}
+
+impl ConnectionTimeouts {
+    /// Create a new set of timeouts, with no timeouts set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Close connections which don't send complete request headers within `timeout`
+    /// of sending the first byte of the request.
+    ///
+    /// This only applies to HTTP/1 connections, and to the HTTP/2 connection preface.
+    pub fn with_header_read(mut self, timeout: Duration) -> Self {
+        self.header_read = Some(timeout);
+        self
+    }
+
+    /// Shut down connections which send nothing for `timeout` while no request is in flight.
+    pub fn with_idle(mut self, timeout: Duration) -> Self {
+        self.idle = Some(timeout);
+        self
+    }
+
+    /// Shut down connections `timeout` after they are served.
+    pub fn with_lifetime(mut self, timeout: Duration) -> Self {
+        self.lifetime = Some(timeout);
+        self
+    }
+
+    /// The header read timeout.
+    pub fn header_read(&self) -> Option<Duration> {
+        self.header_read
+    }
+
+    /// The idle timeout.
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle
+    }
+
+    /// The maximum connection lifetime.
+    pub fn lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle(Instant),
+    Reading(Instant),
+    Active,
+}
+
+/// The HTTP/2 connection preface, sent by clients before any frames.
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How much of the HTTP/2 connection preface has been read, until the protocol is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Preface {
+    Partial(usize),
+    Http1,
+    Http2,
+}
+
+impl Preface {
+    fn update(&mut self, bytes: &[u8]) {
+        if let Preface::Partial(matched) = *self {
+            let expected = &H2_PREFACE[matched..];
+            let len = expected.len().min(bytes.len());
+            *self = if bytes[..len] != expected[..len] {
+                Preface::Http1
+            } else if matched + len == H2_PREFACE.len() {
+                Preface::Http2
+            } else {
+                Preface::Partial(matched + len)
+            };
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Phase,
+    in_flight: usize,
+    preface: Preface,
+}
+
+/// Tracks the phase of a connection, shared between the stream, service and connection.
+#[derive(Debug)]
+struct Activity {
+    state: Mutex<State>,
+    waker: AtomicWaker,
+}
+
+impl Activity {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State {
+                phase: Phase::Idle(Instant::now()),
+                in_flight: 0,
+                preface: Preface::Partial(0),
+            }),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    fn read(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.preface.update(bytes);
+        match (state.phase, state.preface) {
+            // Only a request ends the idle phase of an HTTP/2 connection.
+            (Phase::Idle(_), Preface::Http2) => {}
+            (Phase::Idle(_), _) => {
+                state.phase = Phase::Reading(Instant::now());
+                self.waker.wake();
+            }
+            // The rest of the preface arrived after its first bytes.
+            (Phase::Reading(_), Preface::Http2) => {
+                state.phase = Phase::Idle(Instant::now());
+                self.waker.wake();
+            }
+            _ => {}
+        }
+    }
+
+    fn start_request(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight += 1;
+        state.phase = Phase::Active;
+        self.waker.wake();
+    }
+
+    fn finish_request(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            state.phase = Phase::Idle(Instant::now());
+            self.waker.wake();
+        }
+    }
+
+    fn phase(&self) -> Phase {
+        self.state.lock().unwrap().phase
+    }
+}
+
+/// A [`Protocol`] which applies [`ConnectionTimeouts`] to each connection served by
+/// the wrapped protocol.
+#[derive(Debug, Clone)]
+pub struct TimeoutProtocol<P> {
+    inner: P,
+    timeouts: ConnectionTimeouts,
+}
+
+impl<P> TimeoutProtocol<P> {
+    /// Apply `timeouts` to connections served by `inner`.
+    pub fn new(inner: P, timeouts: ConnectionTimeouts) -> Self {
+        Self { inner, timeouts }
+    }
+
+    /// The wrapped protocol.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The timeouts applied to each connection.
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+}
+
+impl<P, S, IO> Protocol<S, IO> for TimeoutProtocol<P>
+where
+    P: Protocol<TimeoutService<S>, TimeoutStream<IO>>,
+{
+    type Error = BoxError;
+    type Connection = TimeoutConnection<P::Connection>;
+
+    fn serve_connection_with_upgrades(&self, stream: IO, service: S) -> Self::Connection {
+        let activity = Activity::new();
+        let stream = TimeoutStream {
+            inner: stream,
+            activity: activity.clone(),
+        };
+        let service = TimeoutService {
+            inner: service,
+            activity: activity.clone(),
+        };
+
+        TimeoutConnection {
+            inner: self.inner.serve_connection_with_upgrades(stream, service),
+            activity,
+            timeouts: self.timeouts,
+            timer: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            deadline: None,
+            lifetime: self
+                .timeouts
+                .lifetime
+                .map(|lifetime| Box::pin(tokio::time::sleep(lifetime))),
+            shutdown: false,
+        }
+    }
+}
+
+/// A connection served by a [`TimeoutProtocol`].
+#[pin_project]
+pub struct TimeoutConnection<C> {
+    #[pin]
+    inner: C,
+    activity: Arc<Activity>,
+    timeouts: ConnectionTimeouts,
+    timer: Pin<Box<Sleep>>,
+    deadline: Option<Instant>,
+    lifetime: Option<Pin<Box<Sleep>>>,
+    shutdown: bool,
+}
+
+impl<C> fmt::Debug for TimeoutConnection<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutConnection")
+            .field("timeouts", &self.timeouts)
+            .field("phase", &self.activity.phase())
+            .finish()
+    }
+}
+
+impl<C> Connection for TimeoutConnection<C>
+where
+    C: Connection,
+{
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        let this = self.project();
+        *this.shutdown = true;
+        this.inner.graceful_shutdown();
+    }
+}
+
+impl<C, E> Future for TimeoutConnection<C>
+where
+    C: Connection + Future<Output = Result<(), E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<(), BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let mut this = self.as_mut().project();
+            if let Poll::Ready(result) = this.inner.as_mut().poll(cx) {
+                return Poll::Ready(result.map_err(Into::into));
+            }
+
+            this.activity.waker.register(cx.waker());
+
+            if let Some(lifetime) = this.lifetime.as_mut() {
+                if lifetime.as_mut().poll(cx).is_ready() {
+                    *this.lifetime = None;
+                    if !*this.shutdown {
+                        tracing::debug!("connection reached its maximum lifetime");
+                        *this.shutdown = true;
+                        this.inner.as_mut().graceful_shutdown();
+                        continue;
+                    }
+                }
+            }
+
+            let phase = this.activity.phase();
+            let deadline = match phase {
+                Phase::Idle(since) if !*this.shutdown => {
+                    this.timeouts.idle.map(|timeout| since + timeout)
+                }
+                Phase::Reading(since) => this.timeouts.header_read.map(|timeout| since + timeout),
+                _ => None,
+            };
+
+            let Some(deadline) = deadline else {
+                *this.deadline = None;
+                return Poll::Pending;
+            };
+
+            if *this.deadline != Some(deadline) {
+                this.timer.as_mut().reset(deadline);
+                *this.deadline = Some(deadline);
+            }
+
+            if this.timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            *this.deadline = None;
+            match phase {
+                Phase::Reading(_) => {
+                    tracing::debug!("connection timed out reading request headers");
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "timed out reading request headers",
+                    )
+                    .into()));
+                }
+                _ => {
+                    tracing::debug!("connection timed out while idle");
+                    *this.shutdown = true;
+                    this.inner.as_mut().graceful_shutdown();
+                }
+            }
+        }
+    }
+}
+
+/// The stream passed to the protocol wrapped by a [`TimeoutProtocol`], which records
+/// when bytes are read.
+#[pin_project]
+pub struct TimeoutStream<IO> {
+    #[pin]
+    inner: IO,
+    activity: Arc<Activity>,
+}
+
+impl<IO: fmt::Debug> fmt::Debug for TimeoutStream<IO> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TimeoutStream").field(&self.inner).finish()
+    }
+}
+
+impl<IO> TimeoutStream<IO> {
+    /// The wrapped stream.
+    pub fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+}
+
+impl<IO> AsyncRead for TimeoutStream<IO>
+where
+    IO: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let result = this.inner.poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            this.activity.read(&buf.filled()[filled..]);
+        }
+        result
+    }
+}
+
+impl<IO> AsyncWrite for TimeoutStream<IO>
+where
+    IO: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+/// The service passed to the protocol wrapped by a [`TimeoutProtocol`], which records
+/// when requests are in flight.
+///
+/// A request is in flight until its response body has been sent, or dropped.
+#[derive(Clone)]
+pub struct TimeoutService<S> {
+    inner: S,
+    activity: Arc<Activity>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for TimeoutService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TimeoutService").field(&self.inner).finish()
+    }
+}
+
+impl<S, R> tower::Service<R> for TimeoutService<S>
+where
+    S: tower::Service<R, Response = crate::body::Response>,
+{
+    type Response = crate::body::Response;
+    type Error = S::Error;
+    type Future = TimeoutServiceFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        self.activity.start_request();
+        TimeoutServiceFuture {
+            inner: self.inner.call(req),
+            guard: Some(RequestGuard(self.activity.clone())),
+        }
+    }
+}
+
+/// Marks a request as finished when dropped.
+#[derive(Debug)]
+struct RequestGuard(Arc<Activity>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.finish_request();
+    }
+}
+
+/// The response future of a [`TimeoutService`].
+#[derive(Debug)]
+#[pin_project]
+pub struct TimeoutServiceFuture<F> {
+    #[pin]
+    inner: F,
+    guard: Option<RequestGuard>,
+}
+
+impl<F, E> Future for TimeoutServiceFuture<F>
+where
+    F: Future<Output = Result<crate::body::Response, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = futures_core::ready!(this.inner.poll(cx))?;
+        let guard = this.guard.take();
+
+        if http_body::Body::is_end_stream(response.body()) {
+            return Poll::Ready(Ok(response));
+        }
+        Poll::Ready(Ok(
+            response.map(|body| crate::Body::new(GuardedBody { inner: body, guard }))
+        ))
+    }
+}
+
+/// A response body which keeps its request in flight until it ends or is dropped.
+#[pin_project]
+struct GuardedBody {
+    #[pin]
+    inner: crate::Body,
+    guard: Option<RequestGuard>,
+}
+
+impl http_body::Body for GuardedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = futures_core::ready!(this.inner.as_mut().poll_frame(cx));
+        if !matches!(frame, Some(Ok(_))) || this.inner.is_end_stream() {
+            this.guard.take();
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -3,6 +3,7 @@
 use core::task::{Context, Poll};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures_core::ready;
 use pin_project::pin_project;
@@ -21,6 +22,7 @@ use crate::server::conn::Accept;
 #[pin_project]
 pub struct TlsAcceptor<A> {
     config: Arc<ServerConfig>,
+    handshake_timeout: Option<Duration>,
     #[pin]
     incoming: A,
 }
@@ -30,7 +32,18 @@ pub(super) use super::TlsStream;
 impl<A> TlsAcceptor<A> {
     /// Create a new TLS Acceptor with the given [rustls::ServerConfig] and [tokio::net::TcpListener].
     pub fn new(config: Arc<ServerConfig>, incoming: A) -> Self {
-        TlsAcceptor { config, incoming }
+        TlsAcceptor {
+            config,
+            handshake_timeout: None,
+            incoming,
+        }
+    }
+
+    /// Close connections which don't complete the TLS handshake within `timeout`
+    /// of being accepted.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
     }
 }
 
@@ -54,7 +67,11 @@ where
             Ok(stream) => {
                 let accept =
                     tokio_rustls::TlsAcceptor::from(Arc::clone(this.config)).accept(stream);
-                Poll::Ready(Ok(TlsStream::new(accept)))
+                let stream = TlsStream::new(accept);
+                Poll::Ready(Ok(match this.handshake_timeout {
+                    Some(timeout) => stream.with_handshake_timeout(*timeout),
+                    None => stream,
+                }))
             }
 
             // An error occurred while accepting a new TCP connection.
@@ -3,11 +3,13 @@
 //! handshake has been completed.
 
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{fmt, io};
 use std::{future::Future, pin::Pin};
 
 use futures_core::ready;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
 use tokio_rustls::Accept;
 
 use crate::info::tls::{channel, TlsConnectionInfoReciever, TlsConnectionInfoSender};
@@ -45,6 +47,7 @@ where
     IO: HasConnectionInfo,
 {
     state: TlsState<IO>,
+    timeout: Option<Pin<Box<Sleep>>>,
     tx: TlsConnectionInfoSender,
     pub(crate) rx: TlsConnectionInfoReciever,
 }
@@ -78,8 +81,19 @@ where
         F: FnOnce(&mut tokio_rustls::server::TlsStream<IO>, &mut Context) -> Poll<io::Result<R>>,
     {
         match self.state {
-            TlsState::Handshake(ref mut accept) => match ready!(Pin::new(accept).poll(cx)) {
-                Ok(mut stream) => {
+            TlsState::Handshake(ref mut accept) => match Pin::new(accept).poll(cx) {
+                Poll::Pending => {
+                    if let Some(timeout) = self.timeout.as_mut() {
+                        ready!(timeout.as_mut().poll(cx));
+                        tracing::debug!("TLS handshake timed out");
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "TLS handshake timed out",
+                        )));
+                    }
+                    Poll::Pending
+                }
+                Poll::Ready(Ok(mut stream)) => {
                     // Take some action here when the handshake happens
 
                     let (io, server_info) = stream.get_ref();
@@ -92,9 +106,10 @@ where
                     // Back to processing the stream
                     let result = action(&mut stream, cx);
                     self.state = TlsState::Streaming(stream);
+                    self.timeout = None;
                     result
                 }
-                Err(err) => Poll::Ready(Err(err)),
+                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
             },
             TlsState::Streaming(ref mut stream) => action(stream, cx),
         }
@@ -114,10 +129,19 @@ where
 
         Self {
             state: TlsState::Handshake(accept),
+            timeout: None,
             tx,
             rx,
         }
     }
+
+    /// Fail the TLS handshake if it doesn't complete within `timeout`.
+    ///
+    /// The timeout starts when this method is called.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(Box::pin(tokio::time::sleep(timeout)));
+        self
+    }
 }
 
 impl<IO> HasConnectionInfo for TlsStream<IO>
@@ -0,0 +1,260 @@
+//! A handle for controlling a running server from outside of it.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::debug;
+
+/// How the server has been asked to shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Shutdown {
+    /// The server is running normally.
+    Running,
+
+    /// The server should stop accepting connections, and gracefully shut down
+    /// the connections it is serving.
+    Graceful,
+
+    /// The server should stop accepting connections, and abort the connections
+    /// it is serving.
+    Immediate,
+}
+
+/// The number of connections being served.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    active: usize,
+    draining: usize,
+}
+
+struct Shared {
+    shutdown: watch::Sender<Shutdown>,
+    counts: watch::Sender<Counts>,
+    accepting: watch::Sender<Option<bool>>,
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared").finish()
+    }
+}
+
+/// A cloneable handle to a [`Server`](super::Server), which can shut the server down
+/// and report on the connections it is serving.
+///
+/// Get a handle with [`Server::handle`](super::Server::handle) before starting the server.
+/// Shutting down with the handle works whether the server is awaited directly or with
+/// [`Server::with_graceful_shutdown`](super::Server::with_graceful_shutdown), and in either
+/// case the server's future resolves with `Ok(())` once it stops accepting connections.
+/// Connections are served in their own tasks, so they are still draining when the
+/// server's future resolves. Use [`ServerHandle::drain`] to wait for them.
+///
+/// # Example
+/// ```rust
+/// # use std::time::Duration;
+/// # use hyperdriver::Body;
+/// # use hyperdriver::Server;
+/// # use hyperdriver::stream::duplex;
+/// # use tower::service_fn;
+/// # type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+/// # async fn example() {
+/// let (_, incoming) = duplex::pair();
+/// let server = Server::builder()
+///     .with_acceptor(incoming)
+///     .with_auto_http()
+///     .with_shared_service(service_fn(|_| async move {
+///         Ok::<_, BoxError>(http::Response::new(Body::empty()))
+///     }));
+///
+/// let handle = server.handle();
+/// let shutdown = async move {
+///     // Stop accepting connections, and give open connections 30 seconds to finish.
+///     handle.drain(Duration::from_secs(30)).await
+/// };
+///
+/// let (result, aborted) = tokio::join!(async { server.await }, shutdown);
+/// result.unwrap();
+/// println!("aborted {aborted} connections");
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ServerHandle {
+    shared: Arc<Shared>,
+}
+
+impl ServerHandle {
+    pub(super) fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                shutdown: watch::channel(Shutdown::Running).0,
+                counts: watch::channel(Counts::default()).0,
+                accepting: watch::channel(None).0,
+            }),
+        }
+    }
+
+    /// Stop accepting connections, and gracefully shut down the connections being served.
+    pub fn shutdown(&self) {
+        self.signal(Shutdown::Graceful);
+    }
+
+    /// Stop accepting connections, and abort the connections being served.
+    ///
+    /// This can also be used during a graceful shutdown to abort the connections
+    /// which are still draining.
+    pub fn shutdown_now(&self) {
+        self.signal(Shutdown::Immediate);
+    }
+
+    fn signal(&self, shutdown: Shutdown) {
+        self.shared.shutdown.send_if_modified(|current| {
+            if *current < shutdown {
+                debug!(?shutdown, "server shutdown requested");
+                *current = shutdown;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// Returns `true` once a shutdown has been requested.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shared.shutdown.borrow() != Shutdown::Running
+    }
+
+    /// The number of connections being served, which haven't been asked to shut down.
+    pub fn active_connections(&self) -> usize {
+        self.shared.counts.borrow().active
+    }
+
+    /// The number of connections which are gracefully shutting down.
+    pub fn draining_connections(&self) -> usize {
+        self.shared.counts.borrow().draining
+    }
+
+    /// The number of connections being served, including those which are draining.
+    pub fn connections(&self) -> usize {
+        let counts = self.shared.counts.borrow();
+        counts.active + counts.draining
+    }
+
+    /// Returns `true` while the server is accepting connections.
+    pub fn is_accepting(&self) -> bool {
+        *self.shared.accepting.borrow() == Some(true)
+    }
+
+    /// Wait until the server has stopped accepting connections.
+    ///
+    /// This resolves once the server's future has finished or been dropped, whether
+    /// because of a shutdown or an error. If the server has never been started, this
+    /// waits for it to start and then stop.
+    pub async fn stopped_accepting(&self) {
+        let mut accepting = self.shared.accepting.subscribe();
+        let _ = accepting.wait_for(|state| *state == Some(false)).await;
+    }
+
+    /// Start a graceful shutdown (if one hasn't been started), and wait for the
+    /// connections being served to finish.
+    ///
+    /// Connections which are still open after `timeout` are aborted. Returns the
+    /// number of connections which were aborted.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.shutdown();
+
+        if tokio::time::timeout(timeout, self.connections_closed())
+            .await
+            .is_ok()
+        {
+            return 0;
+        }
+
+        let remaining = self.connections();
+        tracing::warn!(
+            remaining,
+            "connections did not drain before the deadline, aborting"
+        );
+        self.shutdown_now();
+        self.connections_closed().await;
+        remaining
+    }
+
+    /// Wait until there are no connections being served.
+    async fn connections_closed(&self) {
+        let mut counts = self.shared.counts.subscribe();
+        let _ = counts
+            .wait_for(|counts| counts.active + counts.draining == 0)
+            .await;
+    }
+
+    pub(super) fn subscribe(&self) -> watch::Receiver<Shutdown> {
+        self.shared.shutdown.subscribe()
+    }
+
+    pub(super) fn set_accepting(&self, accepting: bool) {
+        self.shared.accepting.send_if_modified(|state| {
+            // Once the server has stopped accepting, it doesn't start again.
+            if *state == Some(false) || *state == Some(accepting) {
+                false
+            } else {
+                *state = Some(accepting);
+                true
+            }
+        });
+    }
+
+    /// Count a connection as active until the returned guard is dropped.
+    pub(super) fn track(&self) -> Tracked {
+        self.shared.counts.send_modify(|counts| counts.active += 1);
+        Tracked {
+            shared: self.shared.clone(),
+            draining: false,
+        }
+    }
+}
+
+impl fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = *self.shared.counts.borrow();
+        f.debug_struct("ServerHandle")
+            .field("shutdown", &*self.shared.shutdown.borrow())
+            .field("active", &counts.active)
+            .field("draining", &counts.draining)
+            .finish()
+    }
+}
+
+/// Counts a connection being served by the server.
+#[derive(Debug)]
+pub(super) struct Tracked {
+    shared: Arc<Shared>,
+    draining: bool,
+}
+
+impl Tracked {
+    /// Count the connection as draining instead of active.
+    pub(super) fn draining(&mut self) {
+        if !self.draining {
+            self.draining = true;
+            self.shared.counts.send_modify(|counts| {
+                counts.active -= 1;
+                counts.draining += 1;
+            });
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let draining = self.draining;
+        self.shared.counts.send_modify(|counts| {
+            if draining {
+                counts.draining -= 1;
+            } else {
+                counts.active -= 1;
+            }
+        });
+    }
+}
@@ -0,0 +1,229 @@
+//! Limiting the number of connections served at once.
+//!
+//! A [`ConnectionLimit`] sets the maximum number of connections the server serves at once,
+//! including connections whose services are still being made. When the limit is reached, the
+//! server either:
+//!
+//! - stops accepting connections until one finishes, so that new connections wait in the
+//!   listener's backlog (the default), or
+//! - keeps accepting connections, and responds to each new connection with an HTTP response
+//!   (by default `503 Service Unavailable`) before closing it. Use
+//!   [`ConnectionLimit::with_rejection`] or [`ConnectionLimit::with_rejection_response`]
+//!   for this behavior.
+//!
+//! The rejection response is always sent as HTTP/1.1, since it is written before the
+//! connection's protocol is known. Only a few rejection responses are written at once:
+//! while they are in progress, further connections over the limit are closed immediately.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut as _, Bytes, BytesMut};
+use http::header::{CONNECTION, CONTENT_LENGTH};
+use http::{Response, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
+use tokio::sync::Semaphore;
+
+/// How long the server waits to send a rejection response before giving up.
+const REJECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most rejection responses written at once.
+const MAX_REJECTIONS: usize = 32;
+
+/// The maximum number of connections served at once, and what to do with new connections
+/// when the limit is reached.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    max: usize,
+    rejection: Option<Bytes>,
+}
+
+impl ConnectionLimit {
+    /// Serve at most `max` connections at once. The limit is at least one connection.
+    ///
+    /// When the limit is reached, the server stops accepting connections.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            rejection: None,
+        }
+    }
+
+    /// Accept and close connections over the limit, after responding with
+    /// `503 Service Unavailable`.
+    pub fn with_rejection(self) -> Self {
+        let mut response = Response::new(Bytes::new());
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        self.with_rejection_response(response)
+    }
+
+    /// Accept and close connections over the limit, after responding with `response`.
+    ///
+    /// The `content-length` header is set from the body, and `connection: close` is added.
+    pub fn with_rejection_response(mut self, response: Response<Bytes>) -> Self {
+        self.rejection = Some(serialize(response));
+        self
+    }
+
+    /// Stop accepting connections when the limit is reached, instead of rejecting them.
+    pub fn without_rejection(mut self) -> Self {
+        self.rejection = None;
+        self
+    }
+
+    /// The maximum number of connections served at once.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Returns `true` if connections over the limit are accepted and rejected.
+    pub fn rejects(&self) -> bool {
+        self.rejection.is_some()
+    }
+
+    pub(super) fn rejection(&self) -> Option<&Bytes> {
+        self.rejection.as_ref()
+    }
+}
+
+/// Writes the rejection response to connections over the limit, a few at a time.
+#[derive(Debug, Clone)]
+pub(super) struct Rejections {
+    response: Bytes,
+    writers: Arc<Semaphore>,
+}
+
+impl Rejections {
+    pub(super) fn new(response: Bytes) -> Self {
+        Self {
+            response,
+            writers: Arc::new(Semaphore::new(MAX_REJECTIONS)),
+        }
+    }
+
+    /// Respond to a connection over the limit in the background and close it, or close
+    /// it now if too many rejections are already in progress.
+    pub(super) fn reject<IO>(&self, stream: IO)
+    where
+        IO: AsyncWrite + Unpin + Send + 'static,
+    {
+        let Ok(permit) = self.writers.clone().try_acquire_owned() else {
+            tracing::debug!("too many rejections in progress, closing connection");
+            return;
+        };
+
+        let response = self.response.clone();
+        tokio::spawn(async move {
+            reject(stream, response).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Write a rejection response to a connection, and close it.
+async fn reject<IO>(mut stream: IO, rejection: Bytes)
+where
+    IO: AsyncWrite + Unpin,
+{
+    let write = async {
+        stream.write_all(&rejection).await?;
+        stream.shutdown().await
+    };
+
+    match tokio::time::timeout(REJECTION_TIMEOUT, write).await {
+        Ok(Ok(())) => tracing::trace!("rejected connection over the limit"),
+        Ok(Err(error)) => tracing::debug!("error rejecting connection: {error}"),
+        Err(_) => tracing::debug!("timed out rejecting connection"),
+    }
+}
+
+/// Serialize a response as HTTP/1.1.
+fn serialize(response: Response<Bytes>) -> Bytes {
+    let (parts, body) = response.into_parts();
+
+    let mut buf = BytesMut::new();
+    let reason = parts.status.canonical_reason().unwrap_or("");
+    buf.put_slice(format!("HTTP/1.1 {} {}\r\n", parts.status.as_str(), reason).as_bytes());
+    for (name, value) in parts.headers.iter() {
+        if name == CONTENT_LENGTH || name == CONNECTION {
+            continue;
+        }
+        buf.put_slice(name.as_str().as_bytes());
+        buf.put_slice(b": ");
+        buf.put_slice(value.as_bytes());
+        buf.put_slice(b"\r\n");
+    }
+
+    buf.put_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+    buf.put_slice(b"connection: close\r\n\r\n");
+    buf.put_slice(&body);
+    buf.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rejection() {
+        let limit = ConnectionLimit::new(1).with_rejection();
+        assert!(limit.rejects());
+        assert_eq!(
+            limit.rejection().unwrap(),
+            &Bytes::from_static(
+                b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn custom_rejection() {
+        let response = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("retry-after", "1")
+            .header(CONNECTION, "keep-alive")
+            .body(Bytes::from_static(b"busy"))
+            .unwrap();
+
+        let limit = ConnectionLimit::new(1).with_rejection_response(response);
+        assert_eq!(
+            limit.rejection().unwrap(),
+            &Bytes::from_static(
+                b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 1\r\ncontent-length: 4\r\nconnection: close\r\n\r\nbusy"
+            )
+        );
+
+        assert!(!limit.without_rejection().rejects());
+    }
+
+    #[tokio::test]
+    async fn rejections_are_bounded() {
+        use tokio::io::AsyncReadExt as _;
+
+        let rejections =
+            Rejections::new(ConnectionLimit::new(1).with_rejection().rejection.unwrap());
+
+        // Peers which never read keep their rejections in progress.
+        let mut stalled = Vec::new();
+        for _ in 0..MAX_REJECTIONS {
+            let (client, server) = tokio::io::duplex(1);
+            rejections.reject(server);
+            stalled.push(client);
+        }
+
+        let (mut client, server) = tokio::io::duplex(1);
+        rejections.reject(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.is_empty());
+
+        drop(stalled);
+        let (mut client, server) = tokio::io::duplex(1024);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        rejections.reject(server);
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 503"));
+    }
+}
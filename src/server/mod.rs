@@ -38,6 +38,7 @@
 //!     server.await.unwrap();
 //! }
 
+use std::convert::Infallible;
 use std::future::{Future, IntoFuture};
 use std::marker::PhantomData;
 use std::pin::{pin, Pin};
@@ -46,24 +47,41 @@ use std::task::{ready, Context, Poll};
 use std::{fmt, io};
 
 use builder::{NeedsAcceptor, NeedsProtocol, NeedsService};
-use futures_util::future::FutureExt as _;
+use futures_util::stream::{FuturesUnordered, StreamExt as _};
 use http_body::Body;
 #[cfg(feature = "stream")]
 use tokio::net::ToSocketAddrs;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 use tracing::instrument::Instrumented;
 use tracing::{debug, Instrument};
 
+use self::accept::{AcceptErrorKind, AcceptErrorPolicy};
 pub use self::conn::auto::Builder as AutoBuilder;
 pub use self::conn::Accept;
 #[cfg(feature = "stream")]
 use self::conn::Acceptor;
 use self::conn::Connection;
+pub use self::handle::ServerHandle;
+use self::handle::Shutdown;
+use self::limit::ConnectionLimit;
+#[cfg(feature = "stream")]
+pub use self::workers::ServingWorkers;
 #[cfg(feature = "stream")]
 use crate::bridge::rt::TokioExecutor;
 use crate::service::MakeServiceRef;
 
+pub mod accept;
 mod builder;
+#[cfg(feature = "compression")]
+pub mod compression;
 pub mod conn;
+mod handle;
+pub mod limit;
+#[cfg(feature = "stream")]
+mod workers;
+
+/// The default maximum number of connections whose services are being made at once.
+pub const DEFAULT_MAX_PENDING_SETUPS: usize = 64;
 
 type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
@@ -90,13 +108,17 @@ pub trait Protocol<S, IO> {
 /// using a [tower::Service].
 ///
 /// To use the server, call `.await` on it. This will start the server
-/// and serve until the future is cancelled or the acceptor encounters an
-/// error. To cancel the server, drop the future.
+/// and serve until the future is cancelled or the acceptor encounters a
+/// fatal error. To cancel the server, drop the future. Errors from the acceptor
+/// are handled using an [`AcceptErrorPolicy`], which can be set with
+/// [`Server::with_accept_error_policy`].
 ///
 /// The server also supports graceful shutdown. Provide a future which will
 /// resolve when the server should shut down to [`Server::with_graceful_shutdown`]
 /// to enable this behavior. In graceful shutdown mode, individual connections
 /// will have an opportunity to finish processing before the server stops.
+/// For more control over shutdown, and to monitor the connections being served,
+/// use a [`ServerHandle`] from [`Server::handle`].
 ///
 /// The generic parameters can be a bit tricky. They are as follows:
 ///
@@ -110,9 +132,32 @@ pub struct Server<A, P, S, B> {
     acceptor: A,
     protocol: P,
     make_service: S,
+    config: Config,
     body: PhantomData<fn(B) -> ()>,
 }
 
+/// Server-wide settings, which are independent of the acceptor, protocol and service.
+#[derive(Debug, Clone)]
+struct Config {
+    accept_errors: AcceptErrorPolicy,
+    max_pending_setups: usize,
+    connection_limit: Option<ConnectionLimit>,
+
+    /// Shared with the handles returned by [`Server::handle`].
+    handle: ServerHandle,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            accept_errors: AcceptErrorPolicy::default(),
+            max_pending_setups: DEFAULT_MAX_PENDING_SETUPS,
+            connection_limit: None,
+            handle: ServerHandle::new(),
+        }
+    }
+}
+
 impl<A, P, S, B> fmt::Debug for Server<A, P, S, B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Server").finish()
@@ -158,6 +203,7 @@ impl Server<(), (), (), ()> {
             acceptor: Default::default(),
             protocol: Default::default(),
             make_service: Default::default(),
+            config: Default::default(),
             body: Default::default(),
         }
     }
@@ -173,10 +219,18 @@ impl<A, P, S, B> Server<A, P, S, B> {
             acceptor,
             protocol,
             make_service,
+            config: Config::default(),
             body: PhantomData,
         }
     }
 
+    /// A handle which can shut down this server, and report on its connections.
+    ///
+    /// See [`ServerHandle`] for details.
+    pub fn handle(&self) -> ServerHandle {
+        self.config.handle.clone()
+    }
+
     /// Shutdown the server gracefully when the given future resolves.
     ///
     ///
@@ -246,16 +300,16 @@ where
     type Output = Result<(), ServerError>;
 
     fn into_future(self) -> Self::IntoFuture {
-        Serving {
-            server: self,
-            state: State::Preparing,
-        }
+        let connections = Connections::new(self.config.connection_limit.as_ref());
+        Serving::new(self, connections)
     }
 }
 
 /// A future that drives the server to accept connections.
+///
+/// The future resolves with `Ok(())` when the server is shut down with a [`ServerHandle`].
 #[derive(Debug)]
-#[pin_project::pin_project]
+#[pin_project::pin_project(PinnedDrop)]
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct Serving<A, P, S, B>
 where
@@ -265,21 +319,141 @@ where
     server: Server<A, P, S, B>,
 
     #[pin]
-    state: State<A::Conn, S::Future>,
+    state: State,
+
+    connections: Connections,
+    permit: Option<OwnedSemaphorePermit>,
+    making: FuturesUnordered<Making<A::Conn, S::Future>>,
+    signal: ShutdownSignal,
+}
+
+#[pin_project::pinned_drop]
+impl<A, P, S, B> PinnedDrop for Serving<A, P, S, B>
+where
+    S: MakeServiceRef<A::Conn, B>,
+    A: Accept,
+{
+    fn drop(self: Pin<&mut Self>) {
+        self.server.config.handle.set_accepting(false);
+    }
 }
 
 #[derive(Debug)]
-#[pin_project::pin_project(project = StateProj, project_replace = StateProjOwn)]
-enum State<S, F> {
+#[pin_project::pin_project(project = StateProj)]
+enum State {
     Preparing,
+    Waiting {
+        acquire: Acquire,
+    },
     Accepting,
-    Making {
+    Backoff {
         #[pin]
-        future: F,
-        stream: S,
+        sleep: tokio::time::Sleep,
     },
 }
 
+/// Waits for a connection to finish when the server is at its connection limit.
+struct Acquire(BoxFuture<'static, OwnedSemaphorePermit>);
+
+impl fmt::Debug for Acquire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acquire").finish()
+    }
+}
+
+/// Resolves when a shutdown is requested with a [`ServerHandle`].
+struct ShutdownSignal(BoxFuture<'static, ()>);
+
+impl ShutdownSignal {
+    fn new(mut signal: watch::Receiver<Shutdown>) -> Self {
+        Self(Box::pin(async move {
+            let _ = signal
+                .wait_for(|shutdown| *shutdown != Shutdown::Running)
+                .await;
+        }))
+    }
+}
+
+impl fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownSignal").finish()
+    }
+}
+
+/// Counts the connections being served, and limits them to the [`ConnectionLimit`].
+///
+/// Each connection holds a permit from the semaphore until it is finished.
+#[derive(Debug, Clone)]
+struct Connections {
+    semaphore: Arc<Semaphore>,
+    rejections: Option<limit::Rejections>,
+
+    /// Held by each connection accepted by a worker, so that the worker can wait
+    /// for its own connections to close.
+    worker: Option<mpsc::Sender<Infallible>>,
+}
+
+impl Connections {
+    fn new(limit: Option<&ConnectionLimit>) -> Self {
+        let max = limit.map_or(Semaphore::MAX_PERMITS, |limit| {
+            limit.max().clamp(1, Semaphore::MAX_PERMITS)
+        });
+
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            rejections: limit
+                .and_then(|limit| limit.rejection().cloned())
+                .map(limit::Rejections::new),
+            worker: None,
+        }
+    }
+
+    fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+
+    fn acquire(&self) -> Acquire {
+        let semaphore = self.semaphore.clone();
+        Acquire(Box::pin(async move {
+            semaphore
+                .acquire_owned()
+                .await
+                .expect("connection semaphore is never closed")
+        }))
+    }
+}
+
+/// A future which makes the service for a single accepted connection.
+#[derive(Debug)]
+#[pin_project::pin_project]
+struct Making<IO, F> {
+    #[pin]
+    future: F,
+    stream: Option<IO>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<IO, F, Svc, E> Future for Making<IO, F>
+where
+    F: Future<Output = Result<Svc, E>>,
+{
+    type Output = Result<(IO, Svc, OwnedSemaphorePermit), E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let service = ready!(this.future.poll(cx))?;
+        let stream = this
+            .stream
+            .take()
+            .expect("making future polled after completion");
+        let permit = this
+            .permit
+            .take()
+            .expect("making future polled after completion");
+        Poll::Ready(Ok((stream, service, permit)))
+    }
+}
+
 impl<A, P, S, B> Serving<A, P, S, B>
 where
     S: MakeServiceRef<A::Conn, B>,
@@ -288,51 +462,130 @@ where
 {
     /// Polls the server to accept a single new connection.
     ///
-    /// The returned connection should be spawned on the runtime.
+    /// Services for accepted connections are made concurrently with accepting
+    /// more connections, up to the limit set by [`Server::with_max_pending_setups`].
+    /// The returned connection should be spawned on the runtime, and the permit
+    /// held until the connection is finished.
     #[allow(clippy::type_complexity)]
     fn poll_once(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-    ) -> Poll<Result<Option<Instrumented<P::Connection>>, ServerError>> {
+    ) -> Poll<Result<Option<(Instrumented<P::Connection>, OwnedSemaphorePermit)>, ServerError>>
+    {
         let mut me = self.as_mut().project();
 
+        if let Poll::Ready(Some(made)) = me.making.poll_next_unpin(cx) {
+            let (stream, service, permit) = made.map_err(ServerError::make)?;
+            let span = tracing::span!(tracing::Level::TRACE, "connection");
+            let conn = me
+                .server
+                .protocol
+                .serve_connection_with_upgrades(stream, service)
+                .instrument(span);
+
+            return Poll::Ready(Ok(Some((conn, permit))));
+        }
+
+        if me.making.len() >= me.server.config.max_pending_setups.max(1) {
+            // The pending setups will wake the task when one of them finishes.
+            return Poll::Pending;
+        }
+
         match me.state.as_mut().project() {
             StateProj::Preparing => {
                 ready!(me.server.make_service.poll_ready_ref(cx)).map_err(ServerError::ready)?;
+
+                // Without a rejection response, wait for a connection slot before accepting,
+                // so that new connections wait in the listener's backlog.
+                if me.connections.rejections.is_none() && me.permit.is_none() {
+                    *me.permit = me.connections.try_acquire();
+                    if me.permit.is_none() {
+                        debug!("connection limit reached, waiting for a connection to finish");
+                        me.state.set(State::Waiting {
+                            acquire: me.connections.acquire(),
+                        });
+                        return Poll::Ready(Ok(None));
+                    }
+                }
+
+                me.state.set(State::Accepting);
+            }
+            StateProj::Waiting { acquire } => {
+                *me.permit = Some(ready!(acquire.0.as_mut().poll(cx)));
                 me.state.set(State::Accepting);
             }
             StateProj::Accepting => match ready!(Pin::new(&mut me.server.acceptor).poll_accept(cx))
             {
                 Ok(stream) => {
+                    let Some(permit) = me.permit.take().or_else(|| me.connections.try_acquire())
+                    else {
+                        debug!("connection limit reached, rejecting connection");
+                        if let Some(rejections) = &me.connections.rejections {
+                            rejections.reject(stream);
+                        }
+                        return Poll::Ready(Ok(None));
+                    };
+
                     let future = me.server.make_service.make_service_ref(&stream);
-                    me.state.set(State::Making { future, stream });
+                    me.making.push(Making {
+                        future,
+                        stream: Some(stream),
+                        permit: Some(permit),
+                    });
+                    me.state.set(State::Preparing);
                 }
                 Err(e) => {
-                    return Poll::Ready(Err(ServerError::accept(e)));
+                    let error = e.into();
+                    match me.server.config.accept_errors.classify(&*error) {
+                        AcceptErrorKind::Connection => {
+                            debug!("accept error: {}", error);
+                        }
+                        AcceptErrorKind::Resource => {
+                            let backoff = me.server.config.accept_errors.backoff();
+                            tracing::warn!(?backoff, "accept error: {}", error);
+                            me.state.set(State::Backoff {
+                                sleep: tokio::time::sleep(backoff),
+                            });
+                        }
+                        AcceptErrorKind::Fatal => {
+                            return Poll::Ready(Err(ServerError::accept(error)));
+                        }
+                    }
                 }
             },
-            StateProj::Making { future, .. } => {
-                let service = ready!(future.poll(cx)).map_err(ServerError::make)?;
-                if let StateProjOwn::Making { stream, .. } =
-                    me.state.project_replace(State::Preparing)
-                {
-                    let span = tracing::span!(tracing::Level::TRACE, "connection");
-                    let conn = me
-                        .server
-                        .protocol
-                        .serve_connection_with_upgrades(stream, service)
-                        .instrument(span);
-
-                    return Poll::Ready(Ok(Some(conn)));
-                } else {
-                    unreachable!("state must still be accepting");
-                }
+            StateProj::Backoff { sleep } => {
+                ready!(sleep.poll(cx));
+                me.state.set(State::Accepting);
             }
         };
         Poll::Ready(Ok(None))
     }
 }
 
+impl<A, P, S, B> Serving<A, P, S, B>
+where
+    S: MakeServiceRef<A::Conn, B>,
+    A: Accept,
+{
+    /// Start serving, counting connections with `connections`, which may be
+    /// shared with other accept loops.
+    fn new(server: Server<A, P, S, B>, connections: Connections) -> Self {
+        server.config.handle.set_accepting(true);
+        Serving {
+            connections,
+            signal: ShutdownSignal::new(server.config.handle.subscribe()),
+            server,
+            state: State::Preparing,
+            permit: None,
+            making: FuturesUnordered::new(),
+        }
+    }
+
+    fn handle(&self) -> &ServerHandle {
+        &self.server.config.handle
+    }
+}
+
 impl<A, P, S, B> Future for Serving<A, P, S, B>
 where
     S: MakeServiceRef<A::Conn, B>,
@@ -344,60 +597,93 @@ where
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
+            if self
+                .as_mut()
+                .project()
+                .signal
+                .0
+                .as_mut()
+                .poll(cx)
+                .is_ready()
+            {
+                debug!("server shutting down, no longer accepting connections");
+                self.handle().set_accepting(false);
+                return Poll::Ready(Ok(()));
+            }
+
             match self.as_mut().poll_once(cx) {
-                Poll::Ready(Ok(Some(conn))) => {
-                    tokio::spawn(async move {
-                        if let Err(error) = conn.await {
-                            debug!("connection error: {:?}", error.into());
-                        }
-                    });
+                Poll::Ready(Ok(Some((conn, permit)))) => {
+                    let worker = self.connections.worker.clone();
+                    serve_connection(conn, (permit, worker), self.handle());
                 }
                 Poll::Ready(Ok(None)) => {}
-                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Err(e)) => {
+                    self.handle().set_accepting(false);
+                    return Poll::Ready(Err(e));
+                }
                 Poll::Pending => return Poll::Pending,
             }
         }
     }
 }
 
-#[derive(Debug, Clone)]
-struct CloseSender(Option<tokio::sync::watch::Receiver<()>>);
-
-impl CloseSender {
-    fn send(&mut self) {
-        let _ = self.0.take();
-        tracing::trace!("sending close signal");
-    }
-}
-
-#[derive(Debug, Clone)]
-struct CloseReciever(Arc<tokio::sync::watch::Sender<()>>);
+/// Spawn a task to serve a connection, which shuts the connection down
+/// when a shutdown is requested with a [`ServerHandle`].
+///
+/// The permit is held until the connection is finished.
+fn serve_connection<C, E>(
+    conn: Instrumented<C>,
+    permit: (OwnedSemaphorePermit, Option<mpsc::Sender<Infallible>>),
+    handle: &ServerHandle,
+) where
+    C: Connection + Future<Output = Result<(), E>> + Send + 'static,
+    E: Into<BoxError>,
+{
+    let mut tracked = handle.track();
+    let mut signal = handle.subscribe();
+
+    tokio::spawn(async move {
+        {
+            let mut conn = pin!(conn);
+            let mut next = Shutdown::Graceful;
+            loop {
+                tokio::select! {
+                    rv = conn.as_mut() => {
+                        if let Err(error) = rv {
+                            debug!("connection error: {}", error.into());
+                        }
+                        debug!("connection closed");
+                        break;
+                    },
+                    shutdown = shutdown_signal(&mut signal, next) => {
+                        if shutdown == Shutdown::Immediate {
+                            debug!("connection aborted");
+                            break;
+                        }
 
-impl IntoFuture for CloseReciever {
-    type IntoFuture = CloseFuture;
-    type Output = ();
+                        debug!("connection received shutdown signal");
+                        conn.as_mut().inner_pin_mut().graceful_shutdown();
+                        tracked.draining();
+                        next = Shutdown::Immediate;
+                    },
+                }
+            }
+        }
 
-    fn into_future(self) -> Self::IntoFuture {
-        CloseFuture(Box::pin(async move {
-            self.0.closed().await;
-        }))
-    }
+        drop(tracked);
+        drop(permit);
+        tracing::trace!("finished serving connection");
+    });
 }
 
-#[pin_project::pin_project]
-struct CloseFuture(#[pin] BoxFuture<'static, ()>);
-
-impl Future for CloseFuture {
-    type Output = ();
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project().0.poll(cx)
-    }
-}
-
-fn close() -> (CloseSender, CloseReciever) {
-    let (tx, rx) = tokio::sync::watch::channel(());
-    (CloseSender(Some(rx)), CloseReciever(Arc::new(tx)))
+/// Wait for a shutdown at least as urgent as `at_least`.
+async fn shutdown_signal(signal: &mut watch::Receiver<Shutdown>, at_least: Shutdown) -> Shutdown {
+    // The sender is owned by the server handle, which outlives each connection.
+    signal
+        .wait_for(|shutdown| *shutdown >= at_least)
+        .await
+        .map(|shutdown| *shutdown)
+        .unwrap_or(Shutdown::Immediate)
 }
 
 /// A server that can accept connections, and run each connection, and can
@@ -415,13 +701,6 @@ where
 
     #[pin]
     signal: F,
-
-    channel: CloseReciever,
-    shutdown: CloseSender,
-
-    #[pin]
-    finished: CloseFuture,
-    connection: CloseSender,
 }
 
 impl<A, P, S, B, F> GracefulShutdown<A, P, S, B, F>
@@ -433,15 +712,9 @@ where
     F: Future<Output = ()>,
 {
     fn new(server: Server<A, P, S, B>, signal: F) -> Self {
-        let (tx, rx) = close();
-        let (tx2, rx2) = close();
         Self {
             server: server.into_future(),
             signal,
-            channel: rx,
-            shutdown: tx,
-            finished: rx2.into_future(),
-            connection: tx2,
         }
     }
 }
@@ -461,66 +734,22 @@ where
     S: MakeServiceRef<A::Conn, Body>,
     P: Protocol<S::Service, A::Conn>,
     A: Accept + Unpin,
+    Body: http_body::Body,
     F: Future<Output = ()>,
 {
     type Output = Result<(), ServerError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut this = self.project();
-
-        loop {
-            match this.signal.as_mut().poll(cx) {
-                Poll::Ready(()) => {
-                    debug!("received shutdown signal");
-                    this.shutdown.send();
-                    return Poll::Ready(Ok(()));
-                }
-                Poll::Pending => {}
-            }
-
-            match this.server.as_mut().poll_once(cx) {
-                Poll::Ready(Ok(Some(conn))) => {
-                    let shutdown_rx = this.channel.clone();
-                    let mut finished_tx = this.connection.clone();
-
-                    tokio::spawn(async move {
-                        let mut shutdown = pin!(shutdown_rx
-                            .into_future()
-                            .fuse()
-                            .instrument(conn.span().clone()));
-                        let mut conn = pin!(conn);
-                        loop {
-                            tokio::select! {
-                                rv = &mut conn.as_mut() => {
-                                    if let Err(error) = rv {
-                                        debug!("connection error: {}", error.into());
-                                    }
-                                    debug!("connection closed");
-                                    break;
-                                },
-                                _ = &mut shutdown => {
-                                    debug!("connection received shutdown signal");
-                                    conn.as_mut().inner_pin_mut().graceful_shutdown();
-                                },
-                            }
-                        }
-                        finished_tx.send();
-                        tracing::trace!("finished serving connection");
-                    });
-                }
-                Poll::Ready(Ok(None)) => {}
-                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-                Poll::Pending => return Poll::Pending,
-            }
+        let this = self.project();
 
-            match this.finished.as_mut().poll(cx) {
-                Poll::Ready(()) => {
-                    debug!("all connections closed");
-                    return Poll::Ready(Ok(()));
-                }
-                Poll::Pending => {}
-            }
+        if this.signal.poll(cx).is_ready() {
+            debug!("received shutdown signal");
+            this.server.handle().shutdown();
+            this.server.handle().set_accepting(false);
+            return Poll::Ready(Ok(()));
         }
+
+        this.server.poll(cx)
     }
 }
 
@@ -0,0 +1,234 @@
+//! Serve a [`ReusePort`] with one accept loop per worker.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::{Future, IntoFuture};
+use std::io;
+use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::stream::{FuturesUnordered, StreamExt as _};
+use http_body::Body;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{debug, Instrument as _};
+
+use super::conn::reuseport::{ReusePort, WorkerRuntime};
+use super::conn::{Acceptor, Stream};
+use super::{BoxFuture, Config, Connections, Protocol, Server, ServerError, ServerHandle, Serving};
+use crate::service::MakeServiceRef;
+
+/// The connections accepted by each worker.
+type Conn = Stream;
+
+impl<P, S, B> IntoFuture for Server<ReusePort, P, S, B>
+where
+    S: MakeServiceRef<Conn, B> + Clone + Send + 'static,
+    P: Protocol<S::Service, Conn> + Clone + Send + 'static,
+    B: Body + 'static,
+    Serving<Acceptor, P, S, B>: Send,
+{
+    type IntoFuture = ServingWorkers;
+    type Output = Result<(), ServerError>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        // Workers share the connection limit, as well as the handle in `config`.
+        let connections = Connections::new(self.config.connection_limit.as_ref());
+        let runtime = self.acceptor.runtime();
+        let handle = self.config.handle.clone();
+
+        // Nothing is sent on this channel: workers stop when it closes.
+        let (stop, stopped) = watch::channel(());
+
+        let workers: Vec<_> = self
+            .acceptor
+            .into_listeners()
+            .into_iter()
+            .map(|listener| Worker {
+                listener,
+                protocol: self.protocol.clone(),
+                make_service: self.make_service.clone(),
+                config: self.config.clone(),
+                connections: connections.clone(),
+                stopped: stopped.clone(),
+                body: PhantomData,
+            })
+            .collect();
+
+        ServingWorkers {
+            inner: Box::pin(async move {
+                let mut running: FuturesUnordered<_> = workers
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, worker)| start(index, worker, runtime))
+                    .collect();
+
+                while let Some(result) = running.next().await {
+                    if let Err(error) = result {
+                        debug!("worker stopped with an error, stopping all workers");
+                        drop(stop);
+                        return Err(error);
+                    }
+                }
+
+                drop(stop);
+                Ok(())
+            }),
+            handle,
+        }
+    }
+}
+
+/// A future which drives the workers of a [`Server`] built with
+/// [`Server::with_reuse_port`] to accept connections.
+///
+/// Like [`Serving`], the future resolves with `Ok(())` when the server is shut down
+/// with a [`ServerHandle`]. If any worker stops with an error, the other workers stop
+/// accepting connections, and the error is returned. Dropping the future stops all of
+/// the workers, and gracefully shuts down their connections.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ServingWorkers {
+    inner: BoxFuture<'static, Result<(), ServerError>>,
+    handle: ServerHandle,
+}
+
+impl Drop for ServingWorkers {
+    fn drop(&mut self) {
+        // Workers on their own threads keep running until their connections close.
+        self.handle.shutdown();
+    }
+}
+
+impl fmt::Debug for ServingWorkers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServingWorkers").finish()
+    }
+}
+
+impl Future for ServingWorkers {
+    type Output = Result<(), ServerError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+/// The parts of the server needed to run one accept loop.
+struct Worker<P, S, B> {
+    listener: std::net::TcpListener,
+    protocol: P,
+    make_service: S,
+    config: Config,
+    connections: Connections,
+    stopped: watch::Receiver<()>,
+    body: PhantomData<fn(B) -> ()>,
+}
+
+impl<P, S, B> Worker<P, S, B>
+where
+    S: MakeServiceRef<Conn, B>,
+    P: Protocol<S::Service, Conn>,
+    B: Body,
+{
+    /// Serve the listener until the server shuts down, or the workers are stopped.
+    ///
+    /// This must be run on the runtime which will serve the worker's connections.
+    async fn run(self) -> Result<(), ServerError> {
+        let listener = tokio::net::TcpListener::from_std(self.listener)?;
+        let server = Server {
+            acceptor: Acceptor::from(listener),
+            protocol: self.protocol,
+            make_service: self.make_service,
+            config: self.config,
+            body: PhantomData,
+        };
+
+        let mut stopped = self.stopped;
+        tokio::select! {
+            rv = Serving::new(server, self.connections) => rv,
+            _ = stopped.changed() => Ok(()),
+        }
+    }
+}
+
+/// Start a worker, returning a future which resolves when it finishes.
+fn start<P, S, B>(
+    index: usize,
+    mut worker: Worker<P, S, B>,
+    runtime: WorkerRuntime,
+) -> BoxFuture<'static, Result<(), ServerError>>
+where
+    S: MakeServiceRef<Conn, B> + Send + 'static,
+    P: Protocol<S::Service, Conn> + Send + 'static,
+    B: Body + 'static,
+    Serving<Acceptor, P, S, B>: Send,
+{
+    let span = tracing::debug_span!("worker", index);
+
+    match runtime {
+        WorkerRuntime::Spawn => {
+            let task = tokio::spawn(worker.run().instrument(span));
+            Box::pin(async move {
+                match task.await {
+                    Ok(result) => result,
+                    Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+                    // The runtime is shutting down.
+                    Err(_) => Ok(()),
+                }
+            })
+        }
+        WorkerRuntime::CurrentThread => {
+            let (tx, rx) = oneshot::channel();
+
+            // Each connection accepted by the worker holds a sender, so the receiver
+            // is closed once they have all finished.
+            let (tracker, mut closed) = mpsc::channel::<Infallible>(1);
+            worker.connections.worker = Some(tracker);
+
+            let thread = std::thread::Builder::new()
+                .name(format!("server-worker-{index}"))
+                .spawn(move || {
+                    let runtime = match tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                    {
+                        Ok(runtime) => runtime,
+                        Err(error) => {
+                            let _ = tx.send(Err(error.into()));
+                            return;
+                        }
+                    };
+
+                    let run = AssertUnwindSafe(|| {
+                        runtime.block_on(worker.run().instrument(span.clone()))
+                    });
+                    let result = std::panic::catch_unwind(run).unwrap_or_else(|panic| {
+                        let message = panic
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown panic");
+                        Err(io::Error::other(format!("server worker panicked: {message}")).into())
+                    });
+                    let _ = tx.send(result);
+
+                    // The worker's connections are served on this runtime, and dropping
+                    // it would abort them, so keep it running until they have closed.
+                    runtime.block_on(
+                        async move {
+                            let _ = closed.recv().await;
+                        }
+                        .instrument(span),
+                    );
+                });
+
+            Box::pin(async move {
+                thread?;
+                rx.await.unwrap_or_else(|_| {
+                    Err(io::Error::other("server worker thread exited unexpectedly").into())
+                })
+            })
+        }
+    }
+}
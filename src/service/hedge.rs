@@ -0,0 +1,465 @@
+//! Hedged requests, to reduce tail latency.
+//!
+//! A hedged request is sent a second time when the first attempt has not responded
+//! within a delay chosen from recent request latencies. Whichever attempt responds
+//! first is returned, and the other attempt is cancelled. The winning attempt is
+//! recorded in the response extensions as a [`HedgeAttempt`].
+//!
+//! Only requests with idempotent methods, and whose body can be cloned with
+//! [`Body::try_clone`](crate::Body::try_clone), are hedged.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tower::util::Oneshot;
+use tower::{Layer, Service};
+
+use super::is_idempotent;
+use crate::util::clone_request;
+
+/// The attempt which produced a response, inserted into the response extensions
+/// by [`HedgeService`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HedgeAttempt {
+    /// The response came from the original request.
+    Original,
+
+    /// The response came from the hedged copy of the request.
+    Hedged,
+}
+
+/// Recent request latencies, shared by every service created by a [`HedgeLayer`].
+///
+/// Samples are kept in the order they were recorded, so that the oldest can be
+/// evicted, and in sorted order, so that percentiles can be read directly.
+#[derive(Debug)]
+struct Latencies {
+    samples: VecDeque<Duration>,
+    sorted: Vec<Duration>,
+    capacity: usize,
+}
+
+impl Latencies {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            sorted: Vec::new(),
+            capacity,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.samples.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        if self.samples.len() >= self.capacity {
+            self.evict();
+        }
+        self.samples.push_back(latency);
+        let index = self.sorted.partition_point(|sample| *sample < latency);
+        self.sorted.insert(index, latency);
+    }
+
+    fn evict(&mut self) {
+        if let Some(oldest) = self.samples.pop_front() {
+            let index = self.sorted.partition_point(|sample| *sample < oldest);
+            self.sorted.remove(index);
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> Duration {
+        let index = ((self.sorted.len() as f64 - 1.0) * percentile).round() as usize;
+        self.sorted[index]
+    }
+}
+
+/// Layer which hedges requests, see the [module documentation](self).
+#[derive(Clone)]
+pub struct HedgeLayer {
+    percentile: f64,
+    min_samples: usize,
+    min_delay: Duration,
+    latencies: Arc<Mutex<Latencies>>,
+}
+
+impl fmt::Debug for HedgeLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HedgeLayer")
+            .field("percentile", &self.percentile)
+            .field("min_samples", &self.min_samples)
+            .field("min_delay", &self.min_delay)
+            .finish()
+    }
+}
+
+impl Default for HedgeLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HedgeLayer {
+    /// Create a new hedging layer.
+    ///
+    /// By default, requests are hedged once they have been outstanding longer than
+    /// 95% of the last 1000 requests, and only after at least 20 latencies have
+    /// been recorded.
+    pub fn new() -> Self {
+        Self {
+            percentile: 0.95,
+            min_samples: 20,
+            min_delay: Duration::ZERO,
+            latencies: Arc::new(Mutex::new(Latencies::new(1000))),
+        }
+    }
+
+    /// Set the latency percentile, between 0 and 1, after which requests are hedged.
+    pub fn with_percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the number of latencies which must be recorded before requests are hedged.
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Set the number of recent latencies used to compute the hedging delay.
+    pub fn with_history(self, capacity: usize) -> Self {
+        self.latencies.lock().unwrap().set_capacity(capacity.max(1));
+        self
+    }
+
+    /// Set the minimum delay before a request is hedged.
+    pub fn with_min_delay(mut self, min_delay: Duration) -> Self {
+        self.min_delay = min_delay;
+        self
+    }
+
+    /// The delay before a request is hedged, or `None` if too few latencies
+    /// have been recorded.
+    pub fn delay(&self) -> Option<Duration> {
+        let latencies = self.latencies.lock().unwrap();
+        if latencies.len() == 0 || latencies.len() < self.min_samples {
+            return None;
+        }
+
+        Some(latencies.percentile(self.percentile).max(self.min_delay))
+    }
+
+    fn record(&self, latency: Duration) {
+        self.latencies.lock().unwrap().record(latency);
+    }
+}
+
+impl<S> Layer<S> for HedgeLayer {
+    type Service = HedgeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HedgeService {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// Service which hedges requests, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct HedgeService<S> {
+    inner: S,
+    config: HedgeLayer,
+}
+
+impl<S> HedgeService<S> {
+    /// Create a new hedging service wrapping `inner`.
+    pub fn new(inner: S, config: HedgeLayer) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<S> Service<http::Request<crate::Body>> for HedgeService<S>
+where
+    S: Service<http::Request<crate::Body>, Response = http::Response<crate::Body>> + Clone,
+{
+    type Response = http::Response<crate::Body>;
+    type Error = S::Error;
+    type Future = HedgeFuture<S>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<crate::Body>) -> Self::Future {
+        let hedge = if is_idempotent(req.method()) {
+            self.config
+                .delay()
+                .and_then(|delay| Some((delay, clone_request(&req)?)))
+        } else {
+            None
+        };
+
+        let service = self.inner.clone();
+        let mut service = std::mem::replace(&mut self.inner, service);
+        let now = Instant::now();
+
+        let hedge = hedge.map(|(delay, req)| Hedge {
+            sleep: Box::pin(tokio::time::sleep(delay)),
+            service: service.clone(),
+            request: req,
+        });
+
+        HedgeFuture {
+            original: Some(Attempt {
+                future: Box::pin(service.call(req)),
+                started: now,
+            }),
+            hedged: None,
+            hedge,
+            error: None,
+            config: self.config.clone(),
+        }
+    }
+}
+
+struct Attempt<F> {
+    future: Pin<Box<F>>,
+    started: Instant,
+}
+
+struct Hedge<S> {
+    sleep: Pin<Box<tokio::time::Sleep>>,
+    service: S,
+    request: http::Request<crate::Body>,
+}
+
+/// Future returned by [`HedgeService`].
+#[pin_project::pin_project]
+pub struct HedgeFuture<S>
+where
+    S: Service<http::Request<crate::Body>>,
+{
+    original: Option<Attempt<S::Future>>,
+    hedged: Option<Attempt<Oneshot<S, http::Request<crate::Body>>>>,
+    hedge: Option<Hedge<S>>,
+    error: Option<S::Error>,
+    config: HedgeLayer,
+}
+
+impl<S> fmt::Debug for HedgeFuture<S>
+where
+    S: Service<http::Request<crate::Body>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HedgeFuture")
+            .field("original", &self.original.is_some())
+            .field("hedged", &self.hedged.is_some())
+            .finish()
+    }
+}
+
+impl<S> Future for HedgeFuture<S>
+where
+    S: Service<http::Request<crate::Body>, Response = http::Response<crate::Body>>,
+{
+    type Output = Result<http::Response<crate::Body>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Some(hedge) = this.hedge.as_mut() {
+            if this.original.is_some() && hedge.sleep.as_mut().poll(cx).is_ready() {
+                let hedge = this.hedge.take().expect("hedge is present");
+                tracing::trace!("hedging request");
+                *this.hedged = Some(Attempt {
+                    future: Box::pin(Oneshot::new(hedge.service, hedge.request)),
+                    started: Instant::now(),
+                });
+            }
+        }
+
+        if let Some(attempt) = this.original.as_mut() {
+            if let Poll::Ready(result) = attempt.future.as_mut().poll(cx) {
+                let started = attempt.started;
+                *this.original = None;
+                match result {
+                    Ok(mut response) => {
+                        this.config.record(started.elapsed());
+                        response.extensions_mut().insert(HedgeAttempt::Original);
+                        return Poll::Ready(Ok(response));
+                    }
+                    Err(error) => {
+                        // Don't wait to hedge a request which has already failed.
+                        *this.hedge = None;
+                        *this.error = Some(error);
+                    }
+                }
+            }
+        }
+
+        if let Some(attempt) = this.hedged.as_mut() {
+            if let Poll::Ready(result) = attempt.future.as_mut().poll(cx) {
+                let started = attempt.started;
+                *this.hedged = None;
+                match result {
+                    Ok(mut response) => {
+                        this.config.record(started.elapsed());
+                        response.extensions_mut().insert(HedgeAttempt::Hedged);
+                        return Poll::Ready(Ok(response));
+                    }
+                    Err(error) => {
+                        this.error.get_or_insert(error);
+                    }
+                }
+            }
+        }
+
+        if this.original.is_none() && this.hedged.is_none() {
+            let error = this.error.take().expect("future polled after completion");
+            return Poll::Ready(Err(error));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use http::Method;
+    use tower::ServiceExt as _;
+
+    type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn trained(samples: usize, latency: Duration) -> HedgeLayer {
+        let layer = HedgeLayer::new().with_min_samples(samples);
+        for _ in 0..samples {
+            layer.record(latency);
+        }
+        layer
+    }
+
+    fn request(method: Method) -> http::Request<crate::Body> {
+        http::Request::builder()
+            .method(method)
+            .uri("http://example.com/")
+            .body(crate::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn delay_percentile() {
+        let layer = HedgeLayer::new().with_min_samples(10).with_percentile(0.9);
+        assert_eq!(layer.delay(), None);
+
+        for ms in 1..=10 {
+            layer.record(Duration::from_millis(ms));
+        }
+        assert_eq!(layer.delay(), Some(Duration::from_millis(9)));
+
+        let layer = layer.with_min_delay(Duration::from_millis(50));
+        assert_eq!(layer.delay(), Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let layer = HedgeLayer::new().with_min_samples(1).with_history(2);
+        layer.record(Duration::from_secs(10));
+        layer.record(Duration::from_millis(1));
+        layer.record(Duration::from_millis(1));
+        assert_eq!(layer.delay(), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn sorted_samples_follow_history() {
+        let mut latencies = Latencies::new(3);
+        for ms in [5, 1, 3, 2] {
+            latencies.record(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            latencies.sorted,
+            [1, 2, 3].map(Duration::from_millis).to_vec()
+        );
+
+        latencies.set_capacity(1);
+        assert_eq!(latencies.sorted, vec![Duration::from_millis(2)]);
+    }
+
+    /// A service where the first call is slow, and later calls are fast.
+    fn slow_then_fast(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<
+        http::Request<crate::Body>,
+        Response = http::Response<crate::Body>,
+        Error = BoxError,
+        Future = impl Send,
+    > + Clone {
+        tower::service_fn(move |_req: http::Request<crate::Body>| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call == 0 {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Ok::<_, BoxError>(http::Response::new(crate::Body::empty()))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn hedged_attempt_wins() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = trained(1, Duration::from_millis(1)).layer(slow_then_fast(calls.clone()));
+
+        let response = service.oneshot(request(Method::GET)).await.unwrap();
+        assert_eq!(
+            response.extensions().get::<HedgeAttempt>(),
+            Some(&HedgeAttempt::Hedged)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_not_hedged() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = trained(1, Duration::from_millis(1)).layer(slow_then_fast(calls.clone()));
+
+        let result = tokio::time::timeout(
+            Duration::from_millis(50),
+            service.oneshot(request(Method::POST)),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn untrained_not_hedged() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        calls.store(1, Ordering::SeqCst);
+        let service = HedgeLayer::new().layer(slow_then_fast(calls.clone()));
+
+        let response = service.oneshot(request(Method::GET)).await.unwrap();
+        assert_eq!(
+            response.extensions().get::<HedgeAttempt>(),
+            Some(&HedgeAttempt::Original)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
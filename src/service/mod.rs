@@ -1,6 +1,8 @@
 //! A collection of utilities for working with `Service` types and Servers.
 
 mod adapt;
+#[cfg(feature = "client")]
+pub mod hedge;
 mod http;
 #[cfg(feature = "incoming")]
 mod incoming;
@@ -17,7 +19,11 @@ pub use self::http::HttpService;
 pub use self::incoming::{AdaptIncomingLayer, AdaptIncomingService};
 pub use self::make::{make_service_fn, BoxMakeServiceLayer, BoxMakeServiceRef, MakeServiceRef};
 #[cfg(feature = "client")]
-pub use self::retry::{Attempts, Retry, RetryLayer};
+pub(crate) use self::retry::is_idempotent;
+#[cfg(feature = "client")]
+pub use self::retry::{
+    Attempts, Backoff, BackoffFuture, Budget, Retry, RetryLayer, RetryPolicy, RetryableError,
+};
 pub use serviceref::ServiceRef;
 pub use shared::SharedService;
 pub use tower::{service_fn, Service, ServiceBuilder, ServiceExt};
@@ -1,7 +1,31 @@
+//! Retry policies for HTTP clients.
+//!
+//! [`RetryPolicy`] is the policy used by [`Client`](crate::Client): it retries failed
+//! requests with exponential backoff and jitter, honors `Retry-After` responses, only
+//! retries idempotent requests unless the request never reached the server, and
+//! draws retries from a [`Budget`] shared by every request made with the policy.
+//!
+//! [`Attempts`] is a simpler policy which retries errors and server errors immediately.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use http::header::RETRY_AFTER;
+use http::{Method, StatusCode};
 use tower::retry::Policy;
 
+use crate::client::options::RequestOptions;
+use crate::util::random;
+
+pub use tower::retry::budget::Budget;
 pub use tower::retry::{Retry, RetryLayer};
 
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 /// A policy for retrying requests.
 #[derive(Debug, Clone)]
 pub struct Attempts(usize);
@@ -18,14 +42,17 @@ impl<E> Policy<http::Request<crate::Body>, http::Response<crate::Body>, E> for A
 
     fn retry(
         &self,
-        _req: &http::Request<crate::Body>,
+        req: &http::Request<crate::Body>,
         result: Result<&http::Response<crate::Body>, &E>,
     ) -> Option<Self::Future> {
+        if self.0 == 0 || !req.body().is_replayable() {
+            return None;
+        }
+
         match result {
             Ok(res) if res.status().is_server_error() => Some(std::future::ready(Self(self.0 - 1))),
             Ok(_) => None,
-            Err(_) if self.0 > 0 => Some(std::future::ready(Self(self.0 - 1))),
-            Err(_) => None,
+            Err(_) => Some(std::future::ready(Self(self.0 - 1))),
         }
     }
 
@@ -33,19 +60,551 @@ impl<E> Policy<http::Request<crate::Body>, http::Response<crate::Body>, E> for A
         &self,
         req: &http::Request<crate::Body>,
     ) -> Option<http::Request<crate::Body>> {
-        if let Some(body) = req.body().try_clone() {
-            let mut new_req = http::Request::builder()
-                .uri(req.uri().clone())
-                .method(req.method().clone())
-                .version(req.version());
+        crate::util::clone_request(req)
+    }
+}
+
+/// Errors which can tell whether the request was sent before the error occured.
+///
+/// Errors which happen before the request is sent are always safe to retry,
+/// regardless of the request method.
+pub trait RetryableError {
+    /// Returns true if the error occured before the request was sent to the server.
+    fn is_connect(&self) -> bool;
 
-            if let Some(headers) = new_req.headers_mut() {
-                *headers = req.headers().clone();
-            };
+    /// Returns false if the request should not be retried at all, for example
+    /// because a circuit breaker rejected it.
+    fn is_retryable(&self) -> bool {
+        true
+    }
+}
+
+impl RetryableError for crate::client::Error {
+    fn is_connect(&self) -> bool {
+        crate::client::Error::is_connect(self)
+    }
 
-            new_req.body(body).ok()
+    fn is_retryable(&self) -> bool {
+        !matches!(self, crate::client::Error::CircuitOpen(_))
+    }
+}
+
+impl RetryableError for BoxError {
+    fn is_connect(&self) -> bool {
+        self.downcast_ref::<crate::client::Error>()
+            .is_some_and(|error| error.is_connect())
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.downcast_ref::<crate::client::Error>()
+            .map_or(true, RetryableError::is_retryable)
+    }
+}
+
+/// Exponential backoff between retries, with jitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl Backoff {
+    /// Create a new backoff starting at `initial`, and never exceeding `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            ..Default::default()
+        }
+    }
+
+    /// A backoff which retries immediately.
+    pub fn none() -> Self {
+        Self::new(Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Set the factor by which the delay grows after each attempt.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Set the fraction of each delay which is randomized, between 0 and 1.
+    ///
+    /// With a jitter of 0.5, each delay is chosen at random between half
+    /// of the computed delay and the full computed delay.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The delay before the first retry.
+    pub fn initial(&self) -> Duration {
+        self.initial
+    }
+
+    /// The maximum delay between retries.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The delay before retry number `attempt`, counting from zero, without jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max.as_secs_f64() {
+            self.max
         } else {
-            None
+            Duration::from_secs_f64(delay)
+        }
+    }
+
+    fn jittered(&self, attempt: u32) -> Duration {
+        let delay = self.delay(attempt);
+        delay.mul_f64(1.0 - self.jitter * random())
+    }
+}
+
+/// The default number of retries for [`RetryPolicy`].
+const DEFAULT_RETRIES: usize = 3;
+
+/// A configurable policy for retrying requests.
+///
+/// Requests are retried when:
+/// - the request could not be sent to the server, because connecting or the handshake failed.
+/// - the request is idempotent, and failed after being sent, or the response status
+///   was one of `408`, `429`, `500`, `502`, `503` or `504`.
+///
+/// Only requests whose body can be cloned (see [`Body::try_clone`](crate::Body::try_clone))
+/// are retried. Streaming bodies can be made replayable with
+/// [`Body::replayable`](crate::Body::replayable).
+///
+/// Between attempts, the policy waits according to its [`Backoff`], or for the duration
+/// of the response's `Retry-After` header when present. If `Retry-After` is longer than
+/// [`RetryPolicy::with_max_retry_after`], the response is returned instead.
+///
+/// Each request deposits into the policy's [`Budget`], and each retry withdraws from it,
+/// so that clones of the policy limit retries to a fraction of the overall traffic.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    retries: usize,
+    attempt: u32,
+    backoff: Backoff,
+    max_retry_after: Duration,
+    non_idempotent: bool,
+    budget: Option<Arc<Budget>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRIES)
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new policy which retries a request at most `retries` times.
+    ///
+    /// The policy uses the default [`Backoff`], and the default [`Budget`], which limits
+    /// retries to 20% of requests over the last 10 seconds, with a minimum of 10 retries
+    /// per second.
+    pub fn new(retries: usize) -> Self {
+        Self {
+            retries,
+            attempt: 0,
+            backoff: Backoff::default(),
+            max_retry_after: Duration::from_secs(30),
+            non_idempotent: false,
+            budget: Some(Arc::new(Budget::default())),
+        }
+    }
+
+    /// Set the maximum number of retries for a request.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set the backoff between retries.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the longest `Retry-After` which will be waited for before retrying.
+    pub fn with_max_retry_after(mut self, max: Duration) -> Self {
+        self.max_retry_after = max;
+        self
+    }
+
+    /// Retry requests with non-idempotent methods, such as `POST`, on any retryable failure.
+    ///
+    /// By default, non-idempotent requests are only retried when they were never sent.
+    pub fn with_non_idempotent(mut self) -> Self {
+        self.non_idempotent = true;
+        self
+    }
+
+    /// Set the retry budget shared by all clones of this policy.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(Arc::new(budget));
+        self
+    }
+
+    /// Do not limit retries with a budget.
+    pub fn without_budget(mut self) -> Self {
+        self.budget = None;
+        self
+    }
+
+    /// The maximum number of retries for a request.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// The backoff between retries.
+    pub fn backoff(&self) -> &Backoff {
+        &self.backoff
+    }
+
+    fn is_idempotent(&self, method: &Method) -> bool {
+        self.non_idempotent || is_idempotent(method)
+    }
+
+    /// Decide how long to wait before retrying, if at all.
+    fn delay<E>(
+        &self,
+        req: &http::Request<crate::Body>,
+        result: Result<&http::Response<crate::Body>, &E>,
+    ) -> Option<Duration>
+    where
+        E: RetryableError,
+    {
+        let delay = self.backoff.jittered(self.attempt);
+        match result {
+            Ok(res) => {
+                if !is_retryable_status(res.status()) || !self.is_idempotent(req.method()) {
+                    return None;
+                }
+
+                match retry_after(res.headers()) {
+                    Some(after) if after > self.max_retry_after => None,
+                    Some(after) => Some(after),
+                    None => Some(delay),
+                }
+            }
+            Err(error) if !error.is_retryable() => None,
+            Err(error) if error.is_connect() || self.is_idempotent(req.method()) => Some(delay),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<E> Policy<http::Request<crate::Body>, http::Response<crate::Body>, E> for RetryPolicy
+where
+    E: RetryableError,
+{
+    type Future = BackoffFuture;
+
+    fn retry(
+        &self,
+        req: &http::Request<crate::Body>,
+        result: Result<&http::Response<crate::Body>, &E>,
+    ) -> Option<Self::Future> {
+        if let Some(budget) = &self.budget {
+            if self.attempt == 0 {
+                budget.deposit();
+            }
+        }
+
+        // The request may override the number of retries, which is only
+        // read on the first attempt and then counted down by the policy.
+        let mut retries = self.retries;
+        if self.attempt == 0 {
+            if let Some(options) = req.extensions().get::<RequestOptions>() {
+                retries = options.retries().unwrap_or(retries);
+            }
+        }
+
+        // A replayable body may have overflowed its buffer while it was sent.
+        if retries == 0 || !req.body().is_replayable() {
+            return None;
+        }
+
+        let delay = self.delay(req, result)?;
+
+        if let Some(budget) = &self.budget {
+            if budget.withdraw().is_err() {
+                tracing::debug!("retry budget exhausted");
+                return None;
+            }
+        }
+
+        tracing::trace!(attempt = self.attempt + 1, ?delay, "retrying request");
+
+        let mut policy = self.clone();
+        policy.retries = retries - 1;
+        policy.attempt = policy.attempt.saturating_add(1);
+
+        Some(BackoffFuture {
+            sleep: (!delay.is_zero()).then(|| Box::pin(tokio::time::sleep(delay))),
+            policy: Some(policy),
+        })
+    }
+
+    fn clone_request(
+        &self,
+        req: &http::Request<crate::Body>,
+    ) -> Option<http::Request<crate::Body>> {
+        crate::util::clone_request(req)
+    }
+}
+
+/// Future which waits for the backoff delay before a retry.
+pub struct BackoffFuture {
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    policy: Option<RetryPolicy>,
+}
+
+impl fmt::Debug for BackoffFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackoffFuture").finish()
+    }
+}
+
+impl Future for BackoffFuture {
+    type Output = RetryPolicy;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(sleep) = self.sleep.as_mut() {
+            futures_util::ready!(sleep.as_mut().poll(cx));
+            self.sleep = None;
+        }
+
+        Poll::Ready(self.policy.take().expect("future polled after completion"))
+    }
+}
+
+/// Returns true for methods which RFC 9110 defines as idempotent.
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Parse the `Retry-After` header, as either a number of seconds or an HTTP date.
+fn retry_after(headers: &http::HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestError(bool);
+
+    impl RetryableError for TestError {
+        fn is_connect(&self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn circuit_open_not_retried() {
+        let error: BoxError = crate::client::Error::CircuitOpen("http://example.com".into()).into();
+        assert!(!error.is_retryable());
+        assert!(!error.is_connect());
+    }
+
+    type Result<'a> = std::result::Result<&'a http::Response<crate::Body>, &'a TestError>;
+
+    fn request(method: Method) -> http::Request<crate::Body> {
+        http::Request::builder()
+            .method(method)
+            .uri("http://example.com/")
+            .body(crate::Body::empty())
+            .unwrap()
+    }
+
+    fn response(status: StatusCode) -> http::Response<crate::Body> {
+        http::Response::builder()
+            .status(status)
+            .body(crate::Body::empty())
+            .unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(2).with_backoff(Backoff::none())
+    }
+
+    #[test]
+    fn attempts_exhausted() {
+        let policy = Attempts::new(0);
+        let res = response(StatusCode::INTERNAL_SERVER_ERROR);
+        let result: Result = Ok(&res);
+        assert!(policy.retry(&request(Method::GET), result).is_none());
+    }
+
+    #[test]
+    fn backoff_grows_to_max() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(800));
+        assert_eq!(backoff.delay(4), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_jitter() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = backoff.jittered(0);
+            assert!(delay >= Duration::from_millis(50));
+            assert!(delay <= Duration::from_millis(100));
         }
     }
+
+    #[test]
+    fn retry_idempotent_statuses() {
+        let policy = policy();
+        let res = response(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(policy
+            .retry(&request(Method::GET), Result::Ok(&res))
+            .is_some());
+        assert!(policy
+            .retry(&request(Method::POST), Result::Ok(&res))
+            .is_none());
+
+        let res = response(StatusCode::NOT_IMPLEMENTED);
+        assert!(policy
+            .retry(&request(Method::GET), Result::Ok(&res))
+            .is_none());
+
+        let policy = policy.with_non_idempotent();
+        let res = response(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(policy
+            .retry(&request(Method::POST), Result::Ok(&res))
+            .is_some());
+    }
+
+    #[test]
+    fn retry_connect_errors() {
+        let policy = policy();
+        assert!(policy
+            .retry(&request(Method::POST), Result::Err(&TestError(true)))
+            .is_some());
+        assert!(policy
+            .retry(&request(Method::POST), Result::Err(&TestError(false)))
+            .is_none());
+        assert!(policy
+            .retry(&request(Method::GET), Result::Err(&TestError(false)))
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn retry_exhausted() {
+        let policy = policy();
+        let req = request(Method::GET);
+        let error = TestError(true);
+
+        let policy = policy.retry(&req, Result::Err(&error)).unwrap().await;
+        let policy = policy.retry(&req, Result::Err(&error)).unwrap().await;
+        assert_eq!(policy.retries(), 0);
+        assert!(policy.retry(&req, Result::Err(&error)).is_none());
+    }
+
+    #[tokio::test]
+    async fn retry_request_override() {
+        let error = TestError(true);
+
+        let mut req = request(Method::GET);
+        req.extensions_mut()
+            .insert(RequestOptions::new().without_retries());
+        assert!(policy().retry(&req, Result::Err(&error)).is_none());
+
+        let mut req = request(Method::GET);
+        req.extensions_mut()
+            .insert(RequestOptions::new().with_retries(5));
+        let policy = policy().retry(&req, Result::Err(&error)).unwrap().await;
+        assert_eq!(policy.retries(), 4);
+        let policy = policy.retry(&req, Result::Err(&error)).unwrap().await;
+        assert_eq!(policy.retries(), 3);
+    }
+
+    #[test]
+    fn retry_after_limit() {
+        let policy = policy().with_max_retry_after(Duration::from_secs(5));
+        let req = request(Method::GET);
+
+        let mut res = response(StatusCode::TOO_MANY_REQUESTS);
+        res.headers_mut()
+            .insert(RETRY_AFTER, http::HeaderValue::from_static("2"));
+        assert_eq!(
+            policy.delay(&req, Result::Ok(&res)),
+            Some(Duration::from_secs(2))
+        );
+
+        res.headers_mut()
+            .insert(RETRY_AFTER, http::HeaderValue::from_static("60"));
+        assert!(policy.delay(&req, Result::Ok(&res)).is_none());
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            RETRY_AFTER,
+            http::HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, http::HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn budget_limits_retries() {
+        let policy = policy().with_budget(Budget::new(Duration::from_secs(10), 0, 0.0));
+        let res = response(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(policy
+            .retry(&request(Method::GET), Result::Ok(&res))
+            .is_none());
+    }
 }
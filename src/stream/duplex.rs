@@ -233,7 +233,8 @@ impl Accept for DuplexIncoming {
             let stream = request.ack(self.max_buf_size)?;
             Poll::Ready(Ok(stream))
         } else {
-            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+            // Every client has been dropped, so no more connections can arrive.
+            Poll::Ready(Err(io::ErrorKind::NotConnected.into()))
         }
     }
 }
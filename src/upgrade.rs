@@ -0,0 +1,177 @@
+//! Extended CONNECT (RFC 8441) support, for tunnelling protocols like WebSockets over HTTP/2.
+//!
+//! An extended CONNECT request is an HTTP/2 `CONNECT` request which carries a `:protocol`
+//! pseudo-header (represented by [`ConnectProtocol`] in the request extensions). When the
+//! server accepts the request with a successful (2xx) response, the HTTP/2 stream becomes a
+//! bidirectional byte stream for the requested protocol.
+//!
+//! Servers must advertise `SETTINGS_ENABLE_CONNECT_PROTOCOL` to receive these requests, see
+//! [`AutoBuilder::enable_connect_protocol`](crate::server::AutoBuilder::enable_connect_protocol).
+//! Handlers can check for the requested protocol with [`connect_protocol`], and then use
+//! [`on`] to get the upgraded stream once the response has been sent.
+//!
+//! Clients can build requests with [`extended_connect`], and send them with
+//! [`Client::extended_connect`](crate::client::Client::extended_connect).
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::bridge::io::TokioIo;
+
+pub use hyper::ext::Protocol as ConnectProtocol;
+
+/// The protocol name used to tunnel WebSockets over HTTP/2 (RFC 8441).
+pub const WEBSOCKET: &str = "websocket";
+
+/// An upgraded stream, which implements [`tokio::io::AsyncRead`] and [`tokio::io::AsyncWrite`].
+pub type Upgraded = TokioIo<hyper::upgrade::Upgraded>;
+
+/// Build an extended CONNECT request to the given URI for the given protocol.
+///
+/// The request uses HTTP/2, the `CONNECT` method, and carries the protocol as a
+/// [`ConnectProtocol`] extension so that it is sent as the `:protocol` pseudo-header.
+/// Additional headers (e.g. `sec-websocket-version`) can be added to the returned builder.
+pub fn extended_connect<U>(uri: U, protocol: &str) -> http::request::Builder
+where
+    http::Uri: TryFrom<U>,
+    <http::Uri as TryFrom<U>>::Error: Into<http::Error>,
+{
+    http::Request::builder()
+        .method(http::Method::CONNECT)
+        .version(http::Version::HTTP_2)
+        .uri(uri)
+        .extension(ConnectProtocol::from(protocol))
+}
+
+/// Returns the protocol requested by an extended CONNECT request, if any.
+///
+/// This returns `None` for any request which is not a `CONNECT` request, or which
+/// does not carry a `:protocol` pseudo-header.
+pub fn connect_protocol<B>(request: &http::Request<B>) -> Option<&ConnectProtocol> {
+    if request.method() != http::Method::CONNECT {
+        return None;
+    }
+
+    request.extensions().get::<ConnectProtocol>()
+}
+
+/// Returns `true` if the request is an extended CONNECT request for a WebSocket.
+pub fn is_websocket<B>(request: &http::Request<B>) -> bool {
+    connect_protocol(request).is_some_and(|protocol| protocol.as_str() == WEBSOCKET)
+}
+
+mod sealed {
+    pub trait Upgradeable {
+        fn on_upgrade(self) -> Option<hyper::upgrade::OnUpgrade>;
+    }
+}
+
+impl<B> sealed::Upgradeable for http::Request<B> {
+    fn on_upgrade(mut self) -> Option<hyper::upgrade::OnUpgrade> {
+        self.extensions_mut().remove()
+    }
+}
+
+impl<B> sealed::Upgradeable for &mut http::Request<B> {
+    fn on_upgrade(self) -> Option<hyper::upgrade::OnUpgrade> {
+        self.extensions_mut().remove()
+    }
+}
+
+impl<B> sealed::Upgradeable for http::Response<B> {
+    fn on_upgrade(mut self) -> Option<hyper::upgrade::OnUpgrade> {
+        self.extensions_mut().remove()
+    }
+}
+
+impl<B> sealed::Upgradeable for &mut http::Response<B> {
+    fn on_upgrade(self) -> Option<hyper::upgrade::OnUpgrade> {
+        self.extensions_mut().remove()
+    }
+}
+
+/// Get the pending upgrade from a request or response.
+///
+/// On the server, call this with the request, and then respond with a successful
+/// status. The returned future resolves once the response has been sent and the
+/// stream has been handed off. On the client, call this with the response.
+pub fn on<M>(message: M) -> OnUpgrade
+where
+    M: sealed::Upgradeable,
+{
+    OnUpgrade {
+        inner: message.on_upgrade(),
+    }
+}
+
+/// A future which resolves to the [`Upgraded`] stream.
+///
+/// If the message did not support an upgrade, or the upgrade failed, this
+/// resolves to an error.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct OnUpgrade {
+    inner: Option<hyper::upgrade::OnUpgrade>,
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnUpgrade")
+            .field("pending", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl Future for OnUpgrade {
+    type Output = Result<Upgraded, io::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.inner.as_mut() {
+            Some(upgrade) => Pin::new(upgrade)
+                .poll(cx)
+                .map_ok(TokioIo::new)
+                .map_err(io::Error::other),
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no upgrade available for this message",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_connect_request() {
+        let request = extended_connect("https://example.com/chat", WEBSOCKET)
+            .body(())
+            .unwrap();
+
+        assert_eq!(request.method(), http::Method::CONNECT);
+        assert_eq!(request.version(), http::Version::HTTP_2);
+        assert_eq!(connect_protocol(&request).unwrap().as_str(), "websocket");
+        assert!(is_websocket(&request));
+    }
+
+    #[test]
+    fn connect_protocol_requires_connect() {
+        let request = http::Request::get("https://example.com/chat")
+            .extension(ConnectProtocol::from(WEBSOCKET))
+            .body(())
+            .unwrap();
+
+        assert!(connect_protocol(&request).is_none());
+        assert!(!is_websocket(&request));
+    }
+
+    #[tokio::test]
+    async fn on_without_upgrade() {
+        let request = http::Request::new(());
+        let error = on(request).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
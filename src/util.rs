@@ -0,0 +1,106 @@
+//! Small helpers shared by the client, server and body modules.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::SystemTime;
+
+/// A random number in `[0, 1)`, good enough to spread out retries or choose endpoints.
+///
+/// This is not cryptographically secure.
+#[cfg(feature = "client")]
+pub(crate) fn random() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A random `u64`, from the standard library's randomly seeded hasher.
+///
+/// This is not cryptographically secure.
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default(),
+    );
+    hasher.finish()
+}
+
+/// Copy a request, if its body can be cloned.
+#[cfg(feature = "client")]
+pub(crate) fn clone_request(
+    req: &http::Request<crate::Body>,
+) -> Option<http::Request<crate::Body>> {
+    let mut clone = http::Request::new(req.body().try_clone()?);
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.version_mut() = req.version();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.extensions_mut() = req.extensions().clone();
+    Some(clone)
+}
+
+/// Parse the `key=value` parameters of a header value, split by `separator`.
+///
+/// Separators inside quoted strings are ignored, and quotes and escapes are removed
+/// from values. Keys are lowercased. Segments without an `=`, such as the media type
+/// of a `Content-Type` header, are skipped.
+pub(crate) fn parameters(value: &str, separator: char) -> Vec<(String, String)> {
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => {
+                segment.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if c == separator && !quoted => segments.push(std::mem::take(&mut segment)),
+            _ => segment.push(c),
+        }
+    }
+    segments.push(segment);
+
+    segments
+        .into_iter()
+        .filter_map(|segment| {
+            let (key, value) = segment.split_once('=')?;
+            Some((key.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_parameters() {
+        assert_eq!(
+            parameters(r#"form-data; name="a; b"; FileName="\"x\".txt""#, ';'),
+            vec![
+                ("name".to_owned(), "a; b".to_owned()),
+                ("filename".to_owned(), r#""x".txt"#.to_owned()),
+            ]
+        );
+        assert_eq!(
+            parameters(r#"realm="a, b", qop=auth"#, ','),
+            vec![
+                ("realm".to_owned(), "a, b".to_owned()),
+                ("qop".to_owned(), "auth".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn random_is_in_range() {
+        for _ in 0..100 {
+            let value = random();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+}
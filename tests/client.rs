@@ -1,8 +1,11 @@
+//! Integration tests for the client.
+
 use futures_util::StreamExt;
 use http::StatusCode;
 use hyperdriver::body::Response;
 use hyperdriver::bridge::io::TokioIo;
 use hyperdriver::bridge::rt::TokioExecutor;
+use std::future::IntoFuture as _;
 use std::pin::pin;
 
 use hyperdriver::client::conn::protocol::auto::HttpConnectionBuilder;
@@ -61,6 +64,248 @@ async fn client_h2() -> Result<(), BoxError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn client_extended_connect() -> Result<(), BoxError> {
+    use hyperdriver::upgrade;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let mut protocol = hyperdriver::server::AutoBuilder::default();
+    protocol.enable_connect_protocol();
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_protocol(protocol)
+        .with_shared_service(tower::service_fn(service_tunnel));
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::client::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .build();
+
+    let request = upgrade::extended_connect("http://test/chat", upgrade::WEBSOCKET)
+        .body(hyperdriver::body::Body::empty())?;
+    let (response, mut stream) = client.extended_connect(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    stream.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+
+    let request = upgrade::extended_connect("http://test/chat", "other")
+        .body(hyperdriver::body::Body::empty())?;
+    let error = client.extended_connect(request).await.unwrap_err();
+    assert!(matches!(
+        error,
+        hyperdriver::client::ExtendedConnectError::Rejected(StatusCode::BAD_REQUEST)
+    ));
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+#[cfg(feature = "auth")]
+#[tokio::test]
+async fn client_request_builder() -> Result<(), BoxError> {
+    use http_body_util::BodyExt as _;
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        if req.uri().path() == "/missing" {
+            return http::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(hyperdriver::body::Body::empty());
+        }
+
+        let auth = req.headers()[http::header::AUTHORIZATION].clone();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        http::Response::builder()
+            .header(http::header::AUTHORIZATION, auth)
+            .body(hyperdriver::body::Body::from(body))
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::client::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .build();
+
+    let response = client
+        .post("http://test/echo".parse()?)
+        .bearer_auth("token")
+        .body("hello")
+        .send()
+        .await?
+        .error_for_status()?;
+    assert_eq!(
+        response.headers()[http::header::AUTHORIZATION],
+        "Bearer token"
+    );
+    assert_eq!(response.text().await?, "hello");
+
+    let response = client
+        .request_builder(http::Method::GET, "http://test/missing".parse()?)
+        .send()
+        .await?;
+    let error = response.error_for_status().unwrap_err();
+    assert!(matches!(
+        error,
+        hyperdriver::client::Error::Status(StatusCode::NOT_FOUND)
+    ));
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_multipart() -> Result<(), BoxError> {
+    use hyperdriver::body::multipart::{Form, Multipart, Part};
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let mut multipart = Multipart::from_request(req)?.with_part_limit(1024);
+        let mut summary = Vec::new();
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_owned();
+            let file_name = field.file_name().unwrap_or_default().to_owned();
+            let size = field.bytes().await?.len();
+            summary.push(format!("{name}:{file_name}:{size}"));
+        }
+
+        Ok::<_, BoxError>(http::Response::new(hyperdriver::body::Body::from(
+            summary.join(","),
+        )))
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::client::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .build();
+
+    let form = Form::new().text("title", "hello").part(
+        "data",
+        Part::bytes(vec![0u8; 512]).with_file_name("data.bin"),
+    );
+    let response = client
+        .post("http://test/upload".parse()?)
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?;
+    assert_eq!(response.text().await?, "title::5,data:data.bin:512");
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+#[cfg(feature = "auth")]
+#[tokio::test]
+async fn client_digest_auth() -> Result<(), BoxError> {
+    use hyperdriver::client::auth::{AuthLayer, Credentials};
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let authorized = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("Digest username=\"user\""));
+
+        let response = if authorized {
+            http::Response::builder().status(StatusCode::OK)
+        } else {
+            http::Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(
+                    http::header::WWW_AUTHENTICATE,
+                    r#"Digest realm="test", qop="auth", algorithm=SHA-256, nonce="n""#,
+                )
+        };
+        response.body(hyperdriver::body::Body::empty())
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::client::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_auth(AuthLayer::new().with_credentials(
+            http::uri::Scheme::HTTP,
+            "test".parse()?,
+            Credentials::digest("user", "password"),
+        ))
+        .build();
+
+    let response = client
+        .post("http://test/".parse()?)
+        .body("data")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .request_builder(http::Method::GET, "http://other/".parse()?)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+async fn service_tunnel(
+    mut req: hyperdriver::body::Request,
+) -> Result<hyperdriver::body::Response, BoxError> {
+    if !hyperdriver::upgrade::is_websocket(&req) {
+        return Ok(http::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(hyperdriver::body::Body::empty())?);
+    }
+
+    let upgrade = hyperdriver::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        let stream = upgrade.await?;
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        tokio::io::copy(&mut reader, &mut writer).await?;
+        Ok::<_, BoxError>(())
+    });
+
+    Ok(http::Response::new(hyperdriver::body::Body::empty()))
+}
+
 async fn service_ok(
     req: http::Request<hyper::body::Incoming>,
 ) -> Result<hyperdriver::body::Response, BoxError> {
@@ -103,3 +348,60 @@ async fn serve_one_h2(acceptor: hyperdriver::server::conn::Acceptor) -> Result<(
 
     Ok(())
 }
+
+#[tokio::test]
+async fn client_hsts_and_alt_svc() -> Result<(), BoxError> {
+    use hyperdriver::client::alt_svc::AltSvcCache;
+    use hyperdriver::client::hsts::HstsCache;
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    // HTTP/2 sends the scheme and authority, so the server can see upgraded requests.
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let response = http::Response::builder()
+            .header("x-uri", req.uri().to_string())
+            .header(http::header::STRICT_TRANSPORT_SECURITY, "max-age=60")
+            .header(http::header::ALT_SVC, r#"h2=":8443"; ma=60"#);
+        response.body(hyperdriver::Body::empty())
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_http2()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let hsts = HstsCache::new();
+    let alt_svc = AltSvcCache::new();
+    let client = hyperdriver::client::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_hsts_cache(hsts.clone())
+        .with_alt_svc_cache(alt_svc.clone())
+        .build();
+
+    let get = |uri: &str| {
+        http::Request::get(uri)
+            .version(http::Version::HTTP_2)
+            .body(hyperdriver::body::Body::empty())
+    };
+
+    let response = client.request(get("http://test/plain")?).await?;
+    assert_eq!(response.headers()["x-uri"], "http://test/plain");
+    assert!(hsts.is_empty());
+    assert!(alt_svc.is_empty());
+
+    let response = client.request(get("https://test/secure")?).await?;
+    assert_eq!(response.headers()["x-uri"], "https://test/secure");
+    assert!(hsts.is_known("test"));
+    assert_eq!(alt_svc.alternatives(&"https://test/".parse()?).len(), 1);
+
+    let response = client.request(get("http://test/upgraded")?).await?;
+    assert_eq!(response.headers()["x-uri"], "https://test/upgraded");
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
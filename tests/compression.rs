@@ -0,0 +1,92 @@
+//! Integration tests for response compression and decompression.
+
+use std::future::IntoFuture as _;
+
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use http_body_util::BodyExt as _;
+use hyperdriver::client::conn::protocol::auto::HttpConnectionBuilder;
+use hyperdriver::client::conn::transport::duplex::DuplexTransport;
+use hyperdriver::server::compression::CompressionConfig;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog. \
+    The quick brown fox jumps over the lazy dog. \
+    The quick brown fox jumps over the lazy dog.";
+
+async fn text(req: hyperdriver::body::Request) -> Result<hyperdriver::body::Response, BoxError> {
+    let content_type = match req.uri().path() {
+        "/image" => "image/png",
+        _ => "text/plain",
+    };
+
+    Ok(http::Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(hyperdriver::Body::from(TEXT))?)
+}
+
+fn serve(incoming: hyperdriver::stream::duplex::DuplexIncoming) -> tokio::task::JoinHandle<()> {
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(tower::service_fn(text))
+        .with_compression(CompressionConfig::new().with_min_size(16));
+
+    tokio::spawn(async move {
+        let _ = server.into_future().await;
+    })
+}
+
+#[tokio::test]
+async fn compression_roundtrip() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+    let server = serve(incoming);
+
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_decompression()
+        .build();
+
+    let response = client.get("http://test/".parse()?).await?;
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(body, TEXT);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn compression_without_decompression() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+    let server = serve(incoming);
+
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .build();
+
+    let request = http::Request::get("http://test/")
+        .header(ACCEPT_ENCODING, "gzip")
+        .body(hyperdriver::Body::empty())?;
+    let response = client.request(request).await?;
+    assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_ne!(body, TEXT);
+
+    let request = http::Request::get("http://test/image")
+        .header(ACCEPT_ENCODING, "gzip")
+        .body(hyperdriver::Body::empty())?;
+    let response = client.request(request).await?;
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(body, TEXT);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Integration tests for client cookie handling.
+
+use std::future::IntoFuture as _;
+
+use http::header::{COOKIE, LOCATION, SET_COOKIE};
+use http::StatusCode;
+use hyperdriver::client::conn::protocol::auto::HttpConnectionBuilder;
+use hyperdriver::client::conn::transport::duplex::DuplexTransport;
+use hyperdriver::client::cookies::CookieJar;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+async fn login(req: hyperdriver::body::Request) -> Result<hyperdriver::body::Response, BoxError> {
+    match req.uri().path() {
+        "/login" => Ok(http::Response::builder()
+            .status(StatusCode::FOUND)
+            .header(LOCATION, "/home")
+            .header(SET_COOKIE, "session=abc123; Path=/; HttpOnly")
+            .body(hyperdriver::Body::empty())?),
+        "/home" => {
+            let authorized = req
+                .headers()
+                .get(COOKIE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.contains("session=abc123"));
+
+            let status = if authorized {
+                StatusCode::OK
+            } else {
+                StatusCode::UNAUTHORIZED
+            };
+
+            Ok(http::Response::builder()
+                .status(status)
+                .body(hyperdriver::Body::empty())?)
+        }
+        _ => Ok(http::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(hyperdriver::Body::empty())?),
+    }
+}
+
+#[tokio::test]
+async fn cookies_persist_across_redirects() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(tower::service_fn(login));
+    let server = tokio::spawn(server.into_future());
+
+    let jar = CookieJar::new();
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_standard_redirect_policy()
+        .with_cookie_jar(jar.clone())
+        .build();
+
+    let response = client.get("http://test/login".parse()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(jar.get("test", "/", "session").unwrap(), "abc123");
+
+    let response = client.get("http://test/home".parse()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    jar.clear();
+    let response = client.get("http://test/home".parse()?).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
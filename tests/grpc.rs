@@ -0,0 +1,128 @@
+//! Integration tests for gRPC clients and servers.
+
+use std::future::IntoFuture as _;
+use std::time::Duration;
+
+use futures_util::StreamExt as _;
+use hyperdriver::client::conn::protocol::auto::HttpConnectionBuilder;
+use hyperdriver::client::conn::transport::duplex::DuplexTransport;
+use hyperdriver::grpc::{self, Code, MessageBody, Status, Streaming};
+use tower::ServiceBuilder;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+async fn echo(req: hyperdriver::body::Request) -> Result<hyperdriver::body::Response, BoxError> {
+    assert!(grpc::is_grpc(req.headers()));
+
+    match req.uri().path() {
+        "/test.Echo/Unary" => {
+            let mut messages = Vec::new();
+            let mut stream = Streaming::new(req.into_body());
+            while let Some(message) = stream.next().await {
+                messages.push(message?);
+            }
+
+            let mut response = http::Response::new(MessageBody::new(messages).into());
+            response.headers_mut().insert(
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(grpc::CONTENT_TYPE),
+            );
+            Ok(response)
+        }
+        "/test.Echo/Slow" => {
+            let deadline = req.extensions().get::<grpc::Deadline>().copied();
+            assert!(deadline.is_some());
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(http::Response::new(MessageBody::new(["late"]).into()))
+        }
+        _ => Ok(Status::new(Code::Unimplemented, "unknown method").into_response()),
+    }
+}
+
+fn client(tx: hyperdriver::stream::duplex::DuplexClient) -> hyperdriver::Client {
+    hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_timeout(Duration::from_secs(5))
+        .with_grpc()
+        .build()
+}
+
+fn request(path: &str, messages: &[&str]) -> hyperdriver::body::Request {
+    http::Request::post(format!("http://test{path}"))
+        .body(MessageBody::new(messages).into())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn grpc_unary() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(
+            ServiceBuilder::new()
+                .layer(grpc::DeadlineLayer::new())
+                .service(tower::service_fn(echo)),
+        );
+    let server = tokio::spawn(server.into_future());
+
+    let client = client(tx);
+
+    let response = client
+        .request(request("/test.Echo/Unary", &["hello", "world"]))
+        .await?;
+    assert_eq!(response.version(), http::Version::HTTP_2);
+
+    let mut stream = Streaming::new(response.into_body());
+    assert_eq!(stream.next().await.unwrap()?, "hello");
+    assert_eq!(stream.next().await.unwrap()?, "world");
+    assert!(stream.next().await.is_none());
+
+    let error = client
+        .request(request("/test.Echo/Missing", &[]))
+        .await
+        .unwrap_err();
+    let status = error.downcast::<Status>().unwrap();
+    assert_eq!(status.code(), Code::Unimplemented);
+    assert_eq!(status.message(), "unknown method");
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn grpc_deadline() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(
+            ServiceBuilder::new()
+                .layer(grpc::DeadlineLayer::new())
+                .service(tower::service_fn(echo)),
+        );
+    let server = tokio::spawn(server.into_future());
+
+    let client = client(tx);
+
+    let mut req = request("/test.Echo/Slow", &[]);
+    req.headers_mut().insert(
+        grpc::GRPC_TIMEOUT,
+        grpc::encode_timeout(Duration::from_millis(50)),
+    );
+
+    let error = client.request(req).await.unwrap_err();
+    let status = error.downcast::<Status>().unwrap();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+//! Integration tests against httpbin.org.
+
 use http_body_util::BodyExt as _;
 use hyperdriver::client::{conn::transport::tcp::TcpTransportConfig, Client};
 
@@ -0,0 +1,228 @@
+//! Integration tests for client retries.
+
+use std::future::IntoFuture as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::StatusCode;
+use http_body_util::BodyExt as _;
+use hyperdriver::client::conn::protocol::auto::HttpConnectionBuilder;
+use hyperdriver::client::conn::transport::duplex::DuplexTransport;
+use hyperdriver::service::{Backoff, RetryPolicy};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::test]
+async fn retry_unavailable() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let service = tower::service_fn(move |_: hyperdriver::body::Request| {
+        let call = counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let status = if call % 3 == 2 {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+
+            http::Response::builder()
+                .status(status)
+                .header(http::header::RETRY_AFTER, "0")
+                .body(hyperdriver::Body::empty())
+        }
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_retry_policy(RetryPolicy::new(2).with_backoff(Backoff::new(
+            Duration::from_millis(1),
+            Duration::from_millis(10),
+        )))
+        .build();
+
+    let response = client.get("http://test/".parse()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let request = http::Request::post("http://test/").body(hyperdriver::Body::empty())?;
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
+
+fn streaming(chunks: &'static [&'static str]) -> hyperdriver::Body {
+    let frames = chunks.iter().map(|chunk| {
+        Ok::<_, BoxError>(http_body::Frame::data(bytes::Bytes::from_static(
+            chunk.as_bytes(),
+        )))
+    });
+    hyperdriver::Body::new(http_body_util::StreamBody::new(futures_util::stream::iter(
+        frames,
+    )))
+}
+
+#[tokio::test]
+async fn replay_streaming_body() -> Result<(), BoxError> {
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let service = tower::service_fn(move |req: hyperdriver::body::Request| {
+        let call = counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let path = req.uri().path().to_owned();
+            let body = req.into_body().collect().await?.to_bytes();
+            assert_eq!(body, "hello world");
+
+            let response = match (path.as_str(), call) {
+                ("/upload", 0) => http::Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(hyperdriver::Body::empty())?,
+                ("/upload", _) => http::Response::builder()
+                    .status(StatusCode::TEMPORARY_REDIRECT)
+                    .header(http::header::LOCATION, "/target")
+                    .body(hyperdriver::Body::empty())?,
+                _ => http::Response::new(hyperdriver::Body::from(body)),
+            };
+            Ok::<_, BoxError>(response)
+        }
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_standard_redirect_policy()
+        .with_retry_policy(RetryPolicy::new(2).with_backoff(Backoff::none()))
+        .build();
+
+    let request = http::Request::put("http://test/upload")
+        .body(streaming(&["hello", " ", "world"]).replayable(1024))?;
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(body, "hello world");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let request = http::Request::put("http://test/upload")
+        .body(streaming(&["hello", " ", "world"]).replayable(4))?;
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_options() -> Result<(), BoxError> {
+    use hyperdriver::client::options::RequestOptions;
+
+    let (tx, incoming) = hyperdriver::stream::duplex::pair();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let service = tower::service_fn(move |req: hyperdriver::body::Request| {
+        let counter = counter.clone();
+        async move {
+            let response = http::Response::builder();
+            let response = match req.uri().path() {
+                "/a" => response
+                    .status(StatusCode::FOUND)
+                    .header(http::header::LOCATION, "/b"),
+                "/b" => response
+                    .status(StatusCode::FOUND)
+                    .header(http::header::LOCATION, "/c"),
+                "/c" => response.status(StatusCode::OK),
+                "/slow" => {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    response.status(StatusCode::OK)
+                }
+                _ => {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    response.status(StatusCode::SERVICE_UNAVAILABLE)
+                }
+            };
+            response.body(hyperdriver::Body::empty())
+        }
+    });
+
+    let server = hyperdriver::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(service);
+    let server = tokio::spawn(server.into_future());
+
+    let client = hyperdriver::Client::builder()
+        .with_protocol(HttpConnectionBuilder::default())
+        .with_transport(DuplexTransport::new(1024, tx))
+        .with_default_pool()
+        .with_standard_redirect_policy()
+        .with_timeout(Duration::from_millis(50))
+        .with_retry_policy(RetryPolicy::new(3).with_backoff(Backoff::none()))
+        .build();
+
+    let response = client.get("http://test/a".parse()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let chain = response
+        .extensions()
+        .get::<hyperdriver::client::redirect::RedirectChain>()
+        .expect("redirect chain");
+    assert_eq!(chain.redirects(), 2);
+    assert_eq!(chain.uris().last().unwrap(), "http://test/c");
+
+    let mut request = http::Request::get("http://test/a").body(hyperdriver::Body::empty())?;
+    request
+        .extensions_mut()
+        .insert(RequestOptions::new().with_max_redirects(1));
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(response.headers()[http::header::LOCATION], "/c");
+
+    let mut request = http::Request::get("http://test/flaky").body(hyperdriver::Body::empty())?;
+    request
+        .extensions_mut()
+        .insert(RequestOptions::new().with_retries(1));
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let error = client
+        .get("http://test/slow".parse()?)
+        .await
+        .expect_err("default timeout");
+    assert!(error.is::<tower::timeout::error::Elapsed>());
+
+    let mut request = http::Request::get("http://test/slow").body(hyperdriver::Body::empty())?;
+    request
+        .extensions_mut()
+        .insert(RequestOptions::new().with_timeout(Duration::from_secs(5)));
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server.abort();
+    let _ = server.await;
+    Ok(())
+}
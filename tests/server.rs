@@ -1,3 +1,5 @@
+//! Integration tests for the server.
+
 use std::future::Future;
 use std::future::IntoFuture;
 use std::pin::pin;
@@ -75,7 +77,7 @@ where
     async move {
         tracing::trace!("sending shutdown signal");
         let _ = tx.send(());
-        handle.await.unwrap().map_err(Into::into)
+        handle.await.unwrap()
     }
 }
 
@@ -245,3 +247,869 @@ async fn echo_auto_h2() {
 
     handle.await.unwrap();
 }
+
+/// An acceptor which returns some errors before accepting connections.
+struct FlakyAcceptor<A> {
+    errors: std::collections::VecDeque<std::io::Error>,
+    inner: A,
+}
+
+impl<A> Accept for FlakyAcceptor<A>
+where
+    A: Accept<Error = std::io::Error> + Unpin,
+{
+    type Conn = A::Conn;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<Self::Conn, Self::Error>> {
+        if let Some(error) = self.errors.pop_front() {
+            return std::task::Poll::Ready(Err(error));
+        }
+        std::pin::Pin::new(&mut self.inner).poll_accept(cx)
+    }
+}
+
+#[tokio::test]
+async fn accept_errors_are_survivable() {
+    use hyper::client::conn::http1::Builder;
+    use hyperdriver::server::accept::AcceptErrorPolicy;
+    use std::io::ErrorKind;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+    let acceptor = FlakyAcceptor {
+        errors: [ErrorKind::ConnectionAborted, ErrorKind::OutOfMemory]
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        inner: incoming,
+    };
+
+    let server = hyperdriver::server::Server::builder()
+        .with_acceptor(hyperdriver::server::conn::Acceptor::new(acceptor))
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo))
+        .with_accept_error_policy(
+            AcceptErrorPolicy::new().with_backoff(std::time::Duration::from_millis(10)),
+        );
+
+    let handle = serve_gracefully(server);
+
+    let mut conn = connection(&client, Builder::new()).await.unwrap();
+    let response = conn.send_request(hello_world()).await.unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"hello world");
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn accept_errors_can_be_fatal() {
+    use hyperdriver::server::accept::{AcceptErrorKind, AcceptErrorPolicy};
+    use hyperdriver::server::ServerError;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (_client, incoming) = hyperdriver::stream::duplex::pair();
+    let acceptor = FlakyAcceptor {
+        errors: [std::io::ErrorKind::ConnectionAborted.into()]
+            .into_iter()
+            .collect(),
+        inner: incoming,
+    };
+
+    let server = hyperdriver::server::Server::builder()
+        .with_acceptor(hyperdriver::server::conn::Acceptor::new(acceptor))
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo))
+        .with_accept_error_policy(
+            AcceptErrorPolicy::new().with_classifier(|_| AcceptErrorKind::Fatal),
+        );
+
+    let result = server.await;
+    assert!(matches!(result, Err(ServerError::Accept(_))));
+}
+
+#[tokio::test]
+async fn slow_make_service_does_not_block_accept() {
+    use hyper::client::conn::http1::Builder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    // The service for the first connection isn't ready until `release` is notified.
+    let release = Arc::new(tokio::sync::Notify::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let make_service = {
+        let release = release.clone();
+        hyperdriver::service::make_service_fn(move |_: &hyperdriver::server::conn::Stream<_>| {
+            let first = calls.fetch_add(1, Ordering::SeqCst) == 0;
+            let release = release.clone();
+            async move {
+                if first {
+                    release.notified().await;
+                }
+                Ok::<_, BoxError>(tower::service_fn(echo))
+            }
+        })
+    };
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http1()
+        .with_make_service(make_service)
+        .with_max_pending_setups(4);
+
+    let handle = serve_gracefully(server);
+
+    let mut slow = connection(&client, Builder::new()).await.unwrap();
+    let slow = tokio::spawn(async move { slow.send_request(hello_world()).await });
+
+    let mut fast = connection(&client, Builder::new()).await.unwrap();
+    let response = fast.send_request(hello_world()).await.unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"hello world");
+    assert!(!slow.is_finished());
+
+    release.notify_one();
+    let response = slow.await.unwrap().unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"hello world");
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_limit_applies_backpressure() {
+    use hyper::client::conn::http1::Builder;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo))
+        .with_max_connections(1);
+
+    let handle = serve_gracefully(server);
+
+    let mut first = connection(&client, Builder::new()).await.unwrap();
+    let response = first.send_request(hello_world()).await.unwrap();
+    response.into_body().collect().await.unwrap();
+
+    // The second connection isn't accepted while the first is open.
+    let second = client.connect(1024);
+    let mut second = pin!(second);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), second.as_mut())
+            .await
+            .is_err()
+    );
+
+    drop(first);
+    tokio::time::timeout(Duration::from_secs(1), second)
+        .await
+        .expect("connection accepted after the first closed")
+        .unwrap();
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_limit_rejects_connections() {
+    use hyper::client::conn::http1::Builder;
+    use hyperdriver::server::limit::ConnectionLimit;
+    use tokio::io::AsyncReadExt as _;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo))
+        .with_connection_limit(ConnectionLimit::new(1).with_rejection());
+
+    let handle = serve_gracefully(server);
+
+    let mut first = connection(&client, Builder::new()).await.unwrap();
+    let response = first.send_request(hello_world()).await.unwrap();
+    response.into_body().collect().await.unwrap();
+
+    let mut second = client.connect(1024).await.unwrap();
+    let mut rejection = String::new();
+    second.read_to_string(&mut rejection).await.unwrap();
+    assert!(rejection.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    drop(first);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_timeouts_close_slow_connections() {
+    use hyperdriver::server::conn::timeout::ConnectionTimeouts;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_auto_http()
+        .with_shared_service(tower::service_fn(echo))
+        .with_connection_timeouts(
+            ConnectionTimeouts::new()
+                .with_header_read(Duration::from_millis(50))
+                .with_idle(Duration::from_millis(50)),
+        );
+
+    let handle = serve_gracefully(server);
+
+    // A connection which never sends anything is closed when idle.
+    let mut idle = client.connect(1024).await.unwrap();
+    let mut buf = Vec::new();
+    tokio::time::timeout(Duration::from_secs(1), idle.read_to_end(&mut buf))
+        .await
+        .expect("idle connection closed")
+        .unwrap();
+
+    // A connection which never finishes its request headers is closed.
+    let mut slow = client.connect(1024).await.unwrap();
+    slow.write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n")
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(1), slow.read_to_end(&mut buf))
+        .await
+        .expect("slow connection closed");
+    assert!(buf.is_empty());
+
+    // Connections which send requests promptly are served.
+    let mut conn = connection(&client, hyper::client::conn::http1::Builder::new())
+        .await
+        .unwrap();
+    let response = conn.send_request(hello_world()).await.unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"hello world");
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_timeouts_allow_slow_responses() {
+    use hyper::client::conn::http2::Builder;
+    use hyperdriver::server::conn::timeout::ConnectionTimeouts;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    // The response takes longer than the timeouts, and is large enough that the
+    // client sends window updates while it is being read.
+    let service = tower::service_fn(|_: hyperdriver::body::Request| async move {
+        let chunks = futures_util::stream::unfold(0, |sent| async move {
+            if sent == 8 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+            let chunk = bytes::Bytes::from(vec![b'x'; 32 * 1024]);
+            Some((Ok::<_, BoxError>(http_body::Frame::data(chunk)), sent + 1))
+        });
+        Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::new(
+            http_body_util::StreamBody::new(chunks),
+        )))
+    });
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http2()
+        .with_shared_service(service)
+        .with_connection_timeouts(
+            ConnectionTimeouts::new()
+                .with_header_read(Duration::from_millis(50))
+                .with_idle(Duration::from_millis(50)),
+        );
+
+    let handle = serve_gracefully(server);
+
+    let mut conn = connection(&client, Builder::new(TokioExecutor::new()))
+        .await
+        .unwrap();
+    let response = conn.send_request(hello_world()).await.unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(data.len(), 8 * 32 * 1024);
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_timeouts_allow_idle_http2_pings() {
+    use hyperdriver::server::conn::timeout::ConnectionTimeouts;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    const PING: u8 = 0x6;
+    const GOAWAY: u8 = 0x7;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http2()
+        .with_shared_service(tower::service_fn(echo))
+        .with_connection_timeouts(
+            ConnectionTimeouts::new()
+                .with_header_read(Duration::from_millis(50))
+                .with_idle(Duration::from_millis(300)),
+        );
+
+    let handle = serve_gracefully(server);
+
+    let stream = client.connect(1024).await.unwrap();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Collect the types of the frames sent by the server, until it sends a GOAWAY.
+    let reading = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let mut types = Vec::new();
+        loop {
+            while buf.len() >= 9 {
+                let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize;
+                if buf.len() < 9 + len {
+                    break;
+                }
+                types.push(buf[3]);
+                buf.drain(..9 + len);
+            }
+            if types.contains(&GOAWAY) || reader.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                return types;
+            }
+        }
+    });
+
+    writer
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        .await
+        .unwrap();
+    writer
+        .write_all(&[0, 0, 0, 0x4, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+
+    // Keepalive pings, for longer than the header read timeout, without a request.
+    for _ in 0..8 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        writer
+            .write_all(&[0, 0, 8, PING, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+    }
+
+    let types = tokio::time::timeout(Duration::from_secs(2), reading)
+        .await
+        .expect("idle connection shut down")
+        .unwrap();
+    assert!(
+        types.contains(&PING),
+        "pings were not acknowledged: {types:?}"
+    );
+    assert!(
+        types.contains(&GOAWAY),
+        "connection closed without a GOAWAY: {types:?}"
+    );
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_lifetime_shuts_down_connections() {
+    use hyper::client::conn::http2::Builder;
+    use hyperdriver::server::conn::timeout::ConnectionTimeouts;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http2()
+        .with_shared_service(tower::service_fn(echo))
+        .with_connection_timeouts(
+            ConnectionTimeouts::new().with_lifetime(Duration::from_millis(50)),
+        );
+
+    let handle = serve_gracefully(server);
+
+    let mut conn = connection(&client, Builder::new(TokioExecutor::new()))
+        .await
+        .unwrap();
+    let response = conn.send_request(hello_world()).await.unwrap();
+    response.into_body().collect().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(conn.send_request(hello_world()).await.is_err());
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn multiple_listeners_share_a_server() {
+    use hyper::client::conn::http1::Builder;
+    use hyperdriver::info::{BraidAddr, ConnectionInfo};
+    use hyperdriver::server::conn::{Acceptor, MultiAcceptor};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (public, public_incoming) = hyperdriver::stream::duplex::pair();
+    let (admin, admin_incoming) = hyperdriver::stream::duplex::pair();
+
+    let acceptor = MultiAcceptor::new()
+        .with_listener("public", Acceptor::from(public_incoming))
+        .with_listener("admin", Acceptor::from(admin_incoming));
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let info = req
+            .extensions()
+            .get::<ConnectionInfo<BraidAddr>>()
+            .expect("connection info");
+        let name = info.listener().expect("listener info").name().to_owned();
+        Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from(name)))
+    });
+
+    let server = hyperdriver::server::Server::builder()
+        .with_acceptor(acceptor)
+        .with_http1()
+        .with_shared_service(service)
+        .with_connection_info();
+
+    let handle = serve_gracefully(server);
+
+    for (client, name) in [(&admin, "admin"), (&public, "public"), (&admin, "admin")] {
+        let mut conn = connection(client, Builder::new()).await.unwrap();
+        let response = conn.send_request(hello_world()).await.unwrap();
+        let data = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&*data, name.as_bytes());
+    }
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn server_handle_shuts_down_gracefully() {
+    use hyper::client::conn::http1::Builder;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo));
+
+    let handle = server.handle();
+    let serving = tokio::spawn(server.into_future());
+
+    let mut conn = connection(&client, Builder::new()).await.unwrap();
+    let response = conn.send_request(hello_world()).await.unwrap();
+    response.into_body().collect().await.unwrap();
+
+    assert!(handle.is_accepting());
+    assert_eq!(handle.active_connections(), 1);
+    assert_eq!(handle.draining_connections(), 0);
+
+    let stopped = handle.clone();
+    let stopped = tokio::spawn(async move { stopped.stopped_accepting().await });
+
+    handle.shutdown();
+    serving.await.unwrap().unwrap();
+    tokio::time::timeout(Duration::from_secs(1), stopped)
+        .await
+        .expect("subscribers are notified when the server stops accepting")
+        .unwrap();
+    assert!(!handle.is_accepting());
+
+    assert_eq!(handle.drain(Duration::from_secs(1)).await, 0);
+    assert_eq!(handle.connections(), 0);
+}
+
+#[tokio::test]
+async fn server_handle_aborts_connections_after_deadline() {
+    use hyper::client::conn::http1::Builder;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    // Requests are never answered, so connections can't drain.
+    let started = Arc::new(tokio::sync::Notify::new());
+    let service = {
+        let started = started.clone();
+        tower::service_fn(move |_: hyperdriver::body::Request| {
+            started.notify_one();
+            std::future::pending::<Result<hyperdriver::body::Response, BoxError>>()
+        })
+    };
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        .with_http1()
+        .with_shared_service(service);
+
+    let handle = server.handle();
+    let serving = tokio::spawn(server.into_future());
+
+    let mut conn = connection(&client, Builder::new()).await.unwrap();
+    let request = tokio::spawn(async move { conn.send_request(hello_world()).await });
+    started.notified().await;
+
+    handle.shutdown();
+    serving.await.unwrap().unwrap();
+
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while handle.draining_connections() != 1 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("connection is draining");
+    assert_eq!(handle.active_connections(), 0);
+
+    assert_eq!(handle.drain(Duration::from_millis(50)).await, 1);
+    assert_eq!(handle.connections(), 0);
+    assert!(request.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn proxy_protocol_sets_client_address() {
+    use hyperdriver::bridge::io::TokioIo;
+    use hyperdriver::info::{BraidAddr, ConnectionInfo};
+    use hyperdriver::server::conn::proxy::{ProxyAcceptor, ProxyProtocol};
+    use hyperdriver::server::conn::{Acceptor, AcceptorCore};
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let acceptor = Acceptor::new(ProxyAcceptor::new(
+        AcceptorCore::from(incoming),
+        ProxyProtocol::required(),
+    ));
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let info = req
+            .extensions()
+            .get::<ConnectionInfo<BraidAddr>>()
+            .expect("connection info");
+        let body = format!("{} -> {}", info.remote_addr(), info.local_addr());
+        Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from(body)))
+    });
+
+    let server = hyperdriver::server::Server::builder()
+        .with_acceptor(acceptor)
+        .with_http1()
+        .with_shared_service(service)
+        .with_connection_info();
+
+    let handle = serve_gracefully(server);
+
+    // Connections without a header are closed.
+    let mut stream = client.connect(1024).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+
+    let mut stream = client.connect(1024).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n")
+        .await
+        .unwrap();
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+
+    let response = sender.send_request(hello_world()).await.unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"192.0.2.1:56324 -> 198.51.100.2:443");
+
+    handle.await.unwrap();
+}
+
+#[cfg(feature = "handoff")]
+#[tokio::test]
+async fn listener_handoff_between_servers() {
+    use camino::Utf8PathBuf;
+    use hyperdriver::bridge::io::TokioIo;
+    use hyperdriver::pidfile::PidFile;
+    use hyperdriver::server::conn::handoff::{HandoffReceiver, HandoffSender};
+    use hyperdriver::server::conn::{Acceptor, AcceptorCore};
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    async fn get(addr: std::net::SocketAddr) -> bytes::Bytes {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let response = sender.send_request(hello_world()).await.unwrap();
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let socket = Utf8PathBuf::from_path_buf(tmp.path().join("handoff.sock")).unwrap();
+    let pidfile = Utf8PathBuf::from_path_buf(tmp.path().join("server.pid")).unwrap();
+
+    // The old server.
+    let core = AcceptorCore::from(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+    let AcceptorCore::Tcp(listener) = &core else {
+        unreachable!()
+    };
+    let addr = listener.local_addr().unwrap();
+
+    let sender = HandoffSender::bind(socket.clone())
+        .unwrap()
+        .with_listener("http", core.try_clone_fd().unwrap())
+        .with_pidfile(PidFile::new(pidfile.clone()).unwrap());
+
+    let old = hyperdriver::server::Server::builder()
+        .with_acceptor(Acceptor::from(core))
+        .with_http1()
+        .with_shared_service(tower::service_fn(|_| async {
+            Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from("old")))
+        }));
+    let old_handle = old.handle();
+    let old_serving = tokio::spawn(old.into_future());
+    let sending = tokio::spawn(sender.send());
+
+    assert_eq!(&*get(addr).await, b"old");
+
+    // The new server takes over the listener.
+    let mut receiver = HandoffReceiver::connect(&socket)
+        .await
+        .unwrap()
+        .expect("a server to take over from");
+    let acceptor = receiver.take("http").unwrap().into_acceptor().unwrap();
+
+    let new = hyperdriver::server::Server::builder()
+        .with_acceptor(acceptor)
+        .with_http1()
+        .with_shared_service(tower::service_fn(|_| async {
+            Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from("new")))
+        }));
+    let new_handle = new.handle();
+    let new_serving = tokio::spawn(new.into_future());
+
+    receiver.complete().await.unwrap();
+    sending.await.unwrap().unwrap();
+    let _pidfile = PidFile::new(pidfile).expect("the old server released the PID file");
+
+    assert_eq!(old_handle.drain(Duration::from_secs(1)).await, 0);
+    old_serving.await.unwrap().unwrap();
+
+    assert_eq!(&*get(addr).await, b"new");
+
+    new_handle.shutdown();
+    new_serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn reuse_port_workers_share_a_handle() {
+    use hyperdriver::bridge::io::TokioIo;
+    use hyperdriver::info::{BraidAddr, ConnectionInfo};
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let service = tower::service_fn(|req: hyperdriver::body::Request| async move {
+        let info = req
+            .extensions()
+            .get::<ConnectionInfo<BraidAddr>>()
+            .expect("connection info");
+        Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from(
+            info.local_addr().to_string(),
+        )))
+    });
+
+    for current_thread in [false, true] {
+        let server = hyperdriver::server::Server::builder()
+            .with_reuse_port(&"127.0.0.1:0".parse().unwrap(), 4)
+            .unwrap()
+            .with_http1()
+            .with_shared_service(service)
+            .with_connection_info();
+        let server = if current_thread {
+            server.with_current_thread_workers()
+        } else {
+            server
+        };
+
+        let addr = server.local_addr().unwrap();
+        let handle = server.handle();
+        let serving = tokio::spawn(server.into_future());
+
+        let mut senders = Vec::new();
+        for _ in 0..8 {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                .await
+                .unwrap();
+            tokio::spawn(conn);
+
+            let response = sender.send_request(hello_world()).await.unwrap();
+            let data = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&*data, addr.to_string().as_bytes());
+            senders.push(sender);
+        }
+
+        assert!(handle.is_accepting());
+        assert_eq!(handle.active_connections(), 8);
+
+        drop(senders);
+        assert_eq!(handle.drain(Duration::from_secs(1)).await, 0);
+        serving.await.unwrap().unwrap();
+        assert!(!handle.is_accepting());
+    }
+}
+
+#[tokio::test]
+async fn current_thread_workers_finish_requests_after_shutdown() {
+    use hyperdriver::bridge::io::TokioIo;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (started, mut requests) = tokio::sync::mpsc::unbounded_channel();
+    let service = tower::service_fn(move |_: hyperdriver::body::Request| {
+        let started = started.clone();
+        async move {
+            let _ = started.send(());
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, BoxError>(Response::new(hyperdriver::body::Body::from("done")))
+        }
+    });
+
+    let server = hyperdriver::server::Server::builder()
+        .with_reuse_port(&"127.0.0.1:0".parse().unwrap(), 2)
+        .unwrap()
+        .with_http1()
+        .with_shared_service(service)
+        .with_current_thread_workers();
+
+    let addr = server.local_addr().unwrap();
+    let handle = server.handle();
+    let serving = tokio::spawn(server.into_future());
+
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let response = tokio::spawn(sender.send_request(hello_world()));
+
+    // Shut down while the request is being served, and once the workers have stopped
+    // accepting, the request should still finish on the worker's runtime.
+    requests.recv().await.unwrap();
+    handle.shutdown();
+    serving.await.unwrap().unwrap();
+
+    let response = response.await.unwrap().unwrap();
+    let data = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&*data, b"done");
+    assert_eq!(handle.drain(Duration::from_secs(1)).await, 0);
+}
+
+#[tokio::test]
+async fn dropping_current_thread_workers_shuts_down_connections() {
+    use hyperdriver::bridge::io::TokioIo;
+    use std::time::Duration;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_reuse_port(&"127.0.0.1:0".parse().unwrap(), 2)
+        .unwrap()
+        .with_http1()
+        .with_shared_service(tower::service_fn(echo))
+        .with_current_thread_workers();
+
+    let addr = server.local_addr().unwrap();
+    let handle = server.handle();
+    let serving = tokio::spawn(server.into_future());
+
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    let conn = tokio::spawn(conn);
+    let response = sender.send_request(hello_world()).await.unwrap();
+    response.into_body().collect().await.unwrap();
+
+    // Dropping the server without a shutdown closes the idle keep-alive connection,
+    // rather than leaving the worker serving it.
+    serving.abort();
+    let _ = serving.await;
+    assert!(handle.is_shutting_down());
+    tokio::time::timeout(Duration::from_secs(1), conn)
+        .await
+        .expect("connection closed")
+        .unwrap()
+        .unwrap();
+    assert_eq!(handle.drain(Duration::from_secs(1)).await, 0);
+}
+
+#[tokio::test]
+async fn current_thread_worker_panics_are_errors() {
+    use hyperdriver::server::conn::Stream;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let make_service = hyperdriver::service::make_service_fn(|_: &Stream| {
+        if true {
+            panic!("unable to make service");
+        }
+        std::future::ready(Ok::<_, BoxError>(tower::service_fn(echo)))
+    });
+
+    let server = hyperdriver::server::Server::builder()
+        .with_reuse_port(&"127.0.0.1:0".parse().unwrap(), 1)
+        .unwrap()
+        .with_http1()
+        .with_make_service(make_service)
+        .with_current_thread_workers();
+
+    let addr = server.local_addr().unwrap();
+    let serving = tokio::spawn(server.into_future());
+    let _stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+    let error = serving.await.unwrap().unwrap_err();
+    assert!(error.to_string().contains("unable to make service"), "{error}");
+}
@@ -1,3 +1,5 @@
+//! Tests for serving with a custom body type.
+
 use bytes::Bytes;
 use futures_util::FutureExt;
 use http_body::Body;
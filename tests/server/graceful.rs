@@ -1,3 +1,5 @@
+//! Tests for graceful server shutdown.
+
 use std::future::Future;
 use std::pin::{pin, Pin};
 use std::task::Context;
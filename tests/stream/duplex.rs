@@ -1,3 +1,5 @@
+//! Tests for braided duplex streams.
+
 #[tokio::test]
 async fn braided_duplex() {
     use futures_util::StreamExt;
@@ -1,3 +1,5 @@
+//! Tests for braided TCP streams.
+
 use std::net::Ipv4Addr;
 
 #[tokio::test]
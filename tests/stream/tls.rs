@@ -1,3 +1,5 @@
+//! Tests for braided TLS streams.
+
 use std::{net::Ipv4Addr, sync::Arc};
 
 use rustls::ServerConfig;
@@ -1,3 +1,5 @@
+//! Tests for braided Unix streams.
+
 #[tokio::test]
 async fn braided_unix() {
     use futures_util::StreamExt;
@@ -1,3 +1,5 @@
+//! Integration tests for TLS clients and servers.
+
 use std::future::Future;
 
 use http::Request;
@@ -177,3 +179,32 @@ async fn tls_echo_h2() {
 
     guard.await.unwrap();
 }
+
+#[tokio::test]
+async fn tls_handshake_timeout() {
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt as _;
+
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let (duplex_client, incoming) = hyperdriver::stream::duplex::pair();
+
+    let server = hyperdriver::server::Server::builder()
+        .with_incoming(incoming)
+        // The timeout is kept until TLS is configured.
+        .with_tls_handshake_timeout(Duration::from_millis(50))
+        .with_tls(tls_config())
+        .with_shared_service(tower::service_fn(echo))
+        .with_http1();
+
+    let handle = serve_gracefully(server);
+
+    // A client which never starts the handshake is disconnected.
+    let mut stream = duplex_client.connect(1024).await.unwrap();
+    let mut buf = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut buf))
+        .await
+        .expect("connection closed after the handshake timeout");
+
+    handle.await.unwrap();
+}